@@ -0,0 +1,73 @@
+use rand::{Rng, SeedableRng};
+use rand_xoshiro::Xoshiro256PlusPlus;
+use rays::aabb::{SlabRay, AABB};
+use rays::ray::Ray;
+use rays::ray_stats::count_box_test;
+use rays::vec3::Vec3;
+use std::hint::black_box;
+use std::time::Instant;
+
+// times AABB::hit_slabs against the per-axis box test it replaced, which
+// divided by the direction on every box. run with `cargo bench --bench slabs`.
+// each ray is tested against every box, as on the way down a tree
+
+const RAYS: usize = 2_000;
+const BOXES: usize = 1_000;
+const ROUNDS: usize = 5;
+
+// the old test: one slab at a time, narrowing the range as it goes. it
+// counts itself for --stats as hit_slabs does, so only the tests differ
+fn per_axis(aabb: &AABB, ray: &Ray, min_t: f64, max_t: f64) -> Option<(f64, f64)> {
+    count_box_test();
+    let mut range = (min_t, max_t);
+    for axis in 0..3 {
+        let inverse = 1.0 / ray.direction[axis];
+        let mut t0 = (aabb.minimum[axis] - ray.origin[axis]) * inverse;
+        let mut t1 = (aabb.maximum[axis] - ray.origin[axis]) * inverse;
+        if inverse < 0.0 {
+            std::mem::swap(&mut t0, &mut t1);
+        }
+        range = (if t0 > range.0 { t0 } else { range.0 }, if t1 < range.1 { t1 } else { range.1 });
+        if range.1 <= range.0 {
+            return None
+        }
+    }
+    Some(range)
+}
+
+// the fastest of a few rounds, in nanoseconds per box test, and how many hit
+fn time(mut round: impl FnMut() -> usize) -> (f64, usize) {
+    let mut best = f64::INFINITY;
+    let mut hits = 0;
+    for _ in 0..ROUNDS {
+        let start = Instant::now();
+        hits = black_box(round());
+        best = best.min(start.elapsed().as_nanos() as f64 / (RAYS * BOXES) as f64);
+    }
+    (best, hits)
+}
+
+fn main() {
+    let mut rng = Xoshiro256PlusPlus::seed_from_u64(1);
+    let mut random = |min: f64, max: f64| rng.gen_range(min..max);
+    let boxes: Vec<AABB> = (0..BOXES).map(|_| {
+        let corner = Vec3::new(random(-10.0, 10.0), random(-10.0, 10.0), random(-10.0, 10.0));
+        AABB::new(corner, corner + Vec3::new(random(0.1, 3.0), random(0.1, 3.0), random(0.1, 3.0)))
+    }).collect();
+    let rays: Vec<Ray> = (0..RAYS).map(|_| {
+        let origin = Vec3::new(random(-15.0, 15.0), random(-15.0, 15.0), random(-15.0, 15.0));
+        Ray::new(origin, Vec3::new(random(-1.0, 1.0), random(-1.0, 1.0), random(-1.0, 1.0)), None)
+    }).collect();
+
+    let (slabs, slab_hits) = time(|| rays.iter().map(|ray| {
+        let slab_ray = SlabRay::new(ray);
+        boxes.iter().filter(|aabb| black_box(aabb).hit_slabs(&slab_ray, 0.001, f64::INFINITY).is_some()).count()
+    }).sum());
+    let (axes, axis_hits) = time(|| rays.iter().map(|ray| {
+        boxes.iter().filter(|aabb| per_axis(black_box(aabb), ray, 0.001, f64::INFINITY).is_some()).count()
+    }).sum());
+    assert_eq!(slab_hits, axis_hits, "the two tests disagree");
+    println!("hit_slabs: {:.2} ns per box", slabs);
+    println!("per axis:  {:.2} ns per box", axes);
+    println!("{:.2}x faster, {} hits", axes / slabs, slab_hits);
+}
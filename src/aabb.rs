@@ -1,5 +1,6 @@
-use crate::Vec3;
+use crate::vec3::{Scalar, Vec3};
 use crate::Ray;
+use crate::ray_stats::count_box_test;
 
 // axis-aligned bounding boxes.
 // idea is to optimize the check for a ray intersecting a list of
@@ -8,53 +9,38 @@ use crate::Ray;
 // which may be an object, or another aabb and so on.
 // ideally, these aabb's are grouped into a hierarchical tree to achieve
 // logarithmic performance
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Copy, Clone)]
-pub struct AABB {
+pub struct AABB<T = f64> {
     // the minimum bounds of each plane (x, y, z)
-    pub minimum: Vec3,
+    pub minimum: Vec3<T>,
     // the maximum bounds of each plane (x, y, z)
-    pub maximum: Vec3
+    pub maximum: Vec3<T>
 }
 
-impl AABB {
-    pub fn new(minimum: Vec3, maximum: Vec3) -> AABB {
+impl<T: Scalar> AABB<T> {
+    pub fn new(minimum: Vec3<T>, maximum: Vec3<T>) -> AABB<T> {
         AABB {
             minimum,
             maximum
         }
     }
 
-    pub fn hit(&self, ray: &Ray, min_t: f64, max_t: f64) -> bool {
-        // check intersection in each dimension
-        self.computeIntersection(ray, min_t, max_t, 0) && 
-        self.computeIntersection(ray, min_t, max_t, 1) &&
-        self.computeIntersection(ray, min_t, max_t, 2)
+    pub fn hit(&self, ray: &Ray<T>, min_t: T, max_t: T) -> bool {
+        self.hit_range(ray, min_t, max_t).is_some()
     }
 
-    // combines two given boxes
-    pub fn surrounding_box(first: AABB, second: AABB) -> AABB {
-        let small: Vec3 = Vec3::new(
-            first.minimum.x().min(second.minimum.x()),
-            first.minimum.y().min(second.minimum.y()),
-            first.minimum.z().min(second.minimum.z()),
-        );
-        let big: Vec3 = Vec3::new(
-            first.maximum.x().max(second.maximum.x()),
-            first.maximum.y().max(second.maximum.y()),
-            first.maximum.z().max(second.maximum.z()),
-        );
-        AABB::new(small, big)
+    // the part of [min_t, max_t] where the ray is inside the box, if any
+    pub fn hit_range(&self, ray: &Ray<T>, min_t: T, max_t: T) -> Option<(T, T)> {
+        self.hit_slabs(&SlabRay::new(ray), min_t, max_t)
     }
 
     /*
         reference: https://www.scratchapixel.com/lessons/3d-basic-rendering/minimal-ray-tracer-rendering-simple-shapes/ray-box-intersection
 
         this is much easier to see when visualized, strongly recommend the above
-        link. it doesn't help that this method is using an optimized hit method
-        from Andrew Kensler (https://www.researchgate.net/publication/232618536_Optimizing_Ray-Triangle_Intersection_via_Automated_Search)
-        
-        essentially we're checking the start and end of where a ray intersects a box,
-        if at all
+        link. the box is the overlap of 3 slabs, one per axis (the space between
+        two planes), and the ray is inside the box where it's inside all 3:
     
                t0______t1 (the point in the line, e.g. the t in y(t) = mt + b)
                 |      | 
@@ -67,54 +53,164 @@ impl AABB {
         just the intersection values for the x plane is not enough, the t values
         for in the y plane must intersect as well. in 3D, the same goes for z
 
-        ray: ray to check intersection in min_t and max_t
-        min_t: minimum t to check for intersection
-        max_t: maximum t to check for intersection
-        dimension: 0 -> x, 1 -> y, 2 -> z
+        all 3 slabs are worked out at once with the same operations on each lane
+        and no branches (min/max instead of swapping when the ray goes the
+        negative way), which the compiler turns into simd instructions (2 lanes
+        at a time with x86-64's baseline sse2). the divisions are done once per
+        ray in SlabRay rather than once per box. all told it's only ~10% faster
+        than testing one slab at a time, see benches/slabs.rs
+
+        returns the part of [min_t, max_t] where the ray is inside the box, if any
     */
-    fn computeIntersection(&self, ray: &Ray, min_t: f64, max_t: f64, dimension: u32) -> bool {
-        let mut t0: f64;
-        let mut t1: f64;
-        let inv_d: f64;
-        if dimension == 0 {
-            inv_d = 1.0 / ray.direction.x();
-            t0 = (self.minimum.x() - ray.origin.x()) * inv_d;
-            t1 = (self.maximum.x() - ray.origin.x()) * inv_d;
-        } else if dimension == 1 {
-            inv_d = 1.0 / ray.direction.y();
-            t0 = (self.minimum.y() - ray.origin.y()) * inv_d;
-            t1 = (self.maximum.y() - ray.origin.y()) * inv_d;
-        } else {
-            inv_d = 1.0 / ray.direction.z();
-            t0 = (self.minimum.z() - ray.origin.z()) * inv_d;
-            t1 = (self.maximum.z() - ray.origin.z()) * inv_d;
+    pub fn hit_slabs(&self, ray: &SlabRay<T>, min_t: T, max_t: T) -> Option<(T, T)> {
+        count_box_test();
+        let minimum = [self.minimum.x(), self.minimum.y(), self.minimum.z(), self.minimum.z()];
+        let maximum = [self.maximum.x(), self.maximum.y(), self.maximum.z(), self.maximum.z()];
+        let mut near = [min_t; 4];
+        let mut far = [max_t; 4];
+        for lane in 0..4 {
+            let t0 = (minimum[lane] - ray.origin[lane]) * ray.inverse_direction[lane];
+            let t1 = (maximum[lane] - ray.origin[lane]) * ray.inverse_direction[lane];
+            near[lane] = min(t0, t1);
+            far[lane] = max(t0, t1);
         }
-
-        let orig_t0: f64 = t0;
-        if inv_d < 0.0 {
-            t0 = t1;
-            t1 = orig_t0;
+        let start = max(max(near[0], near[1]), max(near[2], min_t));
+        let end = min(min(far[0], far[1]), min(far[2], max_t));
+        if end > start {
+            Some((start, end))
+        } else {
+            None
         }
+    }
 
-        let min_computed_t: f64;
-        let max_computed_t: f64;
+    // combines two given boxes
+    pub fn surrounding_box(first: AABB<T>, second: AABB<T>) -> AABB<T> {
+        AABB::new(first.minimum.min_components(&second.minimum), first.maximum.max_components(&second.maximum))
+    }
 
-        if t0 > min_t {
-            min_computed_t = t0;
-        } else {
-            min_computed_t = min_t;
+    // the overlapping part of two boxes, if they overlap at all. boxes that
+    // only touch (sharing a face, edge or corner) don't
+    pub fn intersection(first: AABB<T>, second: AABB<T>) -> Option<AABB<T>> {
+        let small = first.minimum.max_components(&second.minimum);
+        let big = first.maximum.min_components(&second.maximum);
+        if small.x() >= big.x() || small.y() >= big.y() || small.z() >= big.z() {
+            return None
         }
+        Some(AABB::new(small, big))
+    }
 
-        if t1 < max_t {
-            max_computed_t = t1;
+    // 0 (x), 1 (y) or 2 (z), whichever the box is longest along. the first on a tie
+    pub fn longest_axis(&self) -> u32 {
+        let d = self.maximum - self.minimum;
+        if d.x() >= d.y() && d.x() >= d.z() {
+            0
+        } else if d.y() >= d.z() {
+            1
         } else {
-            max_computed_t = max_t;
+            2
         }
+    }
 
-        if max_computed_t <= min_computed_t {
-            false
-        } else {
-            true
+    // the chance a random ray hitting a parent box also hits this one is
+    // proportional to this, which is what the surface area heuristic builds on
+    pub fn surface_area(&self) -> T {
+        let d = self.maximum - self.minimum;
+        T::from_f64(2.0) * (d.x() * d.y() + d.y() * d.z() + d.z() * d.x())
+    }
+}
+
+// f64::min and max also pick the number out of a number and a nan, which
+// costs extra instructions per lane. these are single simd instructions, and
+// a nan (a ray along a slab's plane) just makes the test fail or pass
+fn min<T: Scalar>(a: T, b: T) -> T {
+    if a < b { a } else { b }
+}
+
+fn max<T: Scalar>(a: T, b: T) -> T {
+    if a > b { a } else { b }
+}
+
+// a ray set up for testing against lots of boxes, e.g. all the ones on the way
+// down a tree: 1 / direction is worked out once up front, and each vector is
+// padded to 4 lanes (the last repeats z) so the 3 slabs fit simd registers
+#[derive(Copy, Clone)]
+pub struct SlabRay<T = f64> {
+    origin: [T; 4],
+    inverse_direction: [T; 4]
+}
+
+impl<T: Scalar> SlabRay<T> {
+    pub fn new(ray: &Ray<T>) -> SlabRay<T> {
+        let (origin, direction) = (ray.origin, ray.direction);
+        let one = T::from_f64(1.0);
+        SlabRay {
+            origin: [origin.x(), origin.y(), origin.z(), origin.z()],
+            inverse_direction: [one / direction.x(), one / direction.y(), one / direction.z(), one / direction.z()]
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vec3::Vec3;
+
+    #[test]
+    fn test_slab_ranges() {
+        let unit = AABB::new(Vec3::new(-1.0, -1.0, -1.0), Vec3::new(1.0, 1.0, 1.0));
+        let range = |origin: Vec3, direction: Vec3| unit.hit_range(&Ray::new(origin, direction, None), 0.0, 100.0);
+        // along an axis (the other slabs divide by 0), backwards and diagonally
+        assert_eq!(range(Vec3::new(-5.0, 0.5, 0.0), Vec3::new(1.0, 0.0, 0.0)), Some((4.0, 6.0)));
+        assert_eq!(range(Vec3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -2.0)), Some((2.0, 3.0)));
+        assert_eq!(range(Vec3::new(-3.0, -3.0, 0.0), Vec3::new(1.0, 1.0, 0.0)), Some((2.0, 4.0)));
+        // starting inside, passing by and pointing away
+        assert_eq!(range(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0)), Some((0.0, 1.0)));
+        assert_eq!(range(Vec3::new(-5.0, 1.5, 0.0), Vec3::new(1.0, 0.0, 0.0)), None);
+        assert_eq!(range(Vec3::new(-5.0, 0.0, 0.0), Vec3::new(-1.0, 0.0, 0.0)), None);
+        // the range is cut to what's asked for
+        assert_eq!(unit.hit_range(&Ray::new(Vec3::new(-5.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0), None), 4.5, 5.0), Some((4.5, 5.0)));
+    }
+
+    #[test]
+    fn test_single_precision() {
+        // the same box and ray in f32 give the same range, to f32's precision
+        let unit = AABB::new(Vec3::new(-1.0, -1.0, -1.0), Vec3::new(1.0, 1.0, 1.0));
+        let ray = Ray::new(Vec3::new(-3.0, -2.5, 0.25), Vec3::new(1.0, 1.0, 0.1).unit_vector(), None);
+        let single = AABB::new(unit.minimum.cast::<f32>(), unit.maximum.cast::<f32>());
+        let single_ray: Ray<f32> = Ray::new(ray.origin.cast(), ray.direction.cast(), None);
+        let (start, end) = unit.hit_range(&ray, 0.0, 100.0).unwrap();
+        let (single_start, single_end) = single.hit_range(&single_ray, 0.0, 100.0).unwrap();
+        assert!((single_start as f64 - start).abs() < 1e-5 && (single_end as f64 - end).abs() < 1e-5);
+        assert!((single_ray.at(single_start) - single.minimum).cast::<f64>().x().abs() < 1e-5);
+        assert_eq!(std::mem::size_of::<Vec3<f32>>(), std::mem::size_of::<Vec3>() / 2);
+    }
+
+    #[test]
+    fn test_longest_axis() {
+        let longest = |x: f64, y: f64, z: f64| AABB::new(Vec3::new(-1.0, -1.0, -1.0), Vec3::new(x, y, z)).longest_axis();
+        assert_eq!((longest(3.0, 1.0, 2.0), longest(1.0, 3.0, 2.0), longest(1.0, 2.0, 3.0)), (0, 1, 2));
+        assert_eq!((longest(1.0, 1.0, 1.0), longest(0.0, 1.0, 1.0)), (0, 1));
+    }
+
+    #[test]
+    fn test_touching_boxes_dont_overlap() {
+        let unit = |x: f64| AABB::new(Vec3::new(x, 0.0, 0.0), Vec3::new(x + 1.0, 1.0, 1.0));
+        let overlap = AABB::intersection(unit(0.0), unit(0.5)).unwrap();
+        assert_eq!((overlap.minimum.x(), overlap.maximum.x()), (0.5, 1.0));
+        // sharing a face, as a kd-tree's children do, isn't overlapping
+        assert!(AABB::intersection(unit(0.0), unit(1.0)).is_none());
+        assert!(AABB::intersection(unit(0.0), unit(2.0)).is_none());
+    }
+
+    #[test]
+    fn test_slabs_have_to_overlap_at_once() {
+        let unit = AABB::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 1.0, 1.0));
+        // passing by a corner, the ray is between the x planes for t in
+        // [2.5, 3.5] and between the y planes for t in [1, 2], but never both
+        let by_the_corner = Ray::new(Vec3::new(-2.5, 2.0, 0.5), Vec3::new(1.0, -1.0, 0.0), None);
+        assert!(!unit.hit(&by_the_corner, 0.0, 100.0));
+        // a little further in they overlap for t in [2, 2.5]
+        let through = Ray::new(Vec3::new(-2.0, 2.5, 0.5), Vec3::new(1.0, -1.0, 0.0), None);
+        assert_eq!(unit.hit_range(&through, 0.0, 100.0), Some((2.0, 2.5)));
+    }
+}
@@ -0,0 +1,82 @@
+use crate::hittable::Hittable;
+use crate::bvh::BVH;
+use crate::flat_bvh::FlatBVH;
+use crate::kd_tree::KdTree;
+use crate::hittable_list::HittableList;
+
+// a structure that speeds up finding the closest hit among a list of objects.
+// they're all Hittable themselves, so they can be nested or added to a scene
+// like any other object
+pub trait Accelerator: Hittable + Sized {
+    // t0/t1 is the shutter interval the bounding boxes have to cover
+    fn construct(list: Vec<Box<dyn Hittable>>, t0: f64, t1: f64) -> Self;
+}
+
+impl Accelerator for BVH {
+    fn construct(list: Vec<Box<dyn Hittable>>, t0: f64, t1: f64) -> BVH {
+        BVH::construct(list, t0, t1)
+    }
+}
+
+impl Accelerator for FlatBVH {
+    fn construct(list: Vec<Box<dyn Hittable>>, t0: f64, t1: f64) -> FlatBVH {
+        FlatBVH::construct(list, t0, t1)
+    }
+}
+
+impl Accelerator for KdTree {
+    fn construct(list: Vec<Box<dyn Hittable>>, t0: f64, t1: f64) -> KdTree {
+        KdTree::construct(list, t0, t1)
+    }
+}
+
+// picks an accelerator at runtime, e.g. to compare them on the same scene
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum AcceleratorKind {
+    // the pointer based tree, simplest to follow
+    Bvh,
+    // the same tree stored in one array, faster to traverse
+    FlatBvh,
+    // a flat tree built from morton codes, faster to build
+    Lbvh,
+    // splits space instead of objects, good for axis-aligned scenes
+    KdTree
+}
+
+impl AcceleratorKind {
+    pub fn parse(name: &str) -> Option<AcceleratorKind> {
+        match name {
+            "bvh" => Some(AcceleratorKind::Bvh),
+            "flat-bvh" => Some(AcceleratorKind::FlatBvh),
+            "lbvh" => Some(AcceleratorKind::Lbvh),
+            "kd-tree" => Some(AcceleratorKind::KdTree),
+            _ => None
+        }
+    }
+
+    // objects without a bounding box (e.g. a Plane) can't go in a tree, so
+    // they're kept next to it and tested on every ray
+    pub fn build(self, list: Vec<Box<dyn Hittable>>, t0: f64, t1: f64) -> Box<dyn Hittable> {
+        let (bounded, unbounded): (Vec<_>, Vec<_>) = list.into_iter().partition(|object| object.bounding_box(t0, t1).is_some());
+        if unbounded.is_empty() {
+            return self.build_tree(bounded, t0, t1)
+        }
+        let mut objects = HittableList::new();
+        for object in unbounded {
+            objects.add_boxed(object);
+        }
+        if !bounded.is_empty() {
+            objects.add_boxed(self.build_tree(bounded, t0, t1));
+        }
+        Box::new(objects)
+    }
+
+    fn build_tree(self, list: Vec<Box<dyn Hittable>>, t0: f64, t1: f64) -> Box<dyn Hittable> {
+        match self {
+            AcceleratorKind::Bvh => Box::new(<BVH as Accelerator>::construct(list, t0, t1)),
+            AcceleratorKind::FlatBvh => Box::new(<FlatBVH as Accelerator>::construct(list, t0, t1)),
+            AcceleratorKind::Lbvh => Box::new(FlatBVH::construct_lbvh(list, t0, t1)),
+            AcceleratorKind::KdTree => Box::new(<KdTree as Accelerator>::construct(list, t0, t1))
+        }
+    }
+}
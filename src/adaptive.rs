@@ -0,0 +1,209 @@
+use crate::vec3::*;
+
+// pixels at least this dark count as this bright when measuring relative
+// error, otherwise a black pixel would never look converged
+const DARKEST_PERCEIVED: f64 = 0.02;
+// convergence is checked every this many samples
+const BATCH_SIZE: u64 = 4;
+
+// adaptive sampling.
+// instead of the same number of samples everywhere, keep sampling a pixel only
+// while its estimate is still noisy. the noise is measured on how the pixel is
+// going to look rather than on its raw value: absolute variance is tiny in dark
+// areas (which stop too early and stay grainy) and huge in bright ones (which
+// keep sampling noise nobody can see once it's clamped and gamma corrected)
+pub struct AdaptiveSampling {
+    pub min_samples: u64,
+    // the standard error allowed, relative to the pixel's perceived brightness
+    pub threshold: f64
+}
+
+impl AdaptiveSampling {
+    pub fn new(min_samples: u64, threshold: f64) -> AdaptiveSampling {
+        AdaptiveSampling {
+            min_samples: min_samples.max(2),
+            threshold
+        }
+    }
+
+    pub fn is_converged(&self, estimate: &PixelEstimate) -> bool {
+        if estimate.count < self.min_samples || !estimate.count.is_multiple_of(BATCH_SIZE) {
+            return false
+        }
+        estimate.relative_error() <= self.threshold
+    }
+}
+
+// how bright a colour looks on screen: luminance squashed into [0, 1) by a
+// reinhard tonemap, then gamma corrected (a square root is close enough to the
+// srgb curve the image gets, and cheaper)
+pub fn perceived_brightness(colour: &Color) -> f64 {
+    let luminance = 0.2126 * colour.x() + 0.7152 * colour.y() + 0.0722 * colour.z();
+    let luminance = luminance.max(0.0);
+    (luminance / (1.0 + luminance)).sqrt()
+}
+
+// the running sum of a pixel's samples plus the mean and variance of their
+// perceived brightness (welford's algorithm, so no samples need to be kept)
+pub struct PixelEstimate {
+    pub sum: Color,
+    pub count: u64,
+    // for compositing: the sum of the samples' coverage (alpha) and of their
+    // colour without the background, i.e. premultiplied by the coverage
+    pub alpha: f64,
+    pub foreground: Color,
+    // the sum of the samples' pixel filter weights (see filter.rs), which the
+    // sums are divided by rather than the count. for the box filter every
+    // weight is 1, so it's the count
+    pub weight: f64,
+    // kept public so estimates can be saved and picked up again, see restart.rs
+    pub mean: f64,
+    pub m2: f64,
+    // samples outlier rejection threw away (see IntegratorSettings). they're
+    // in count, so the sample numbers move on, but in none of the sums or
+    // the brightness statistics
+    pub rejected: u64
+}
+
+impl PixelEstimate {
+    pub fn new() -> PixelEstimate {
+        PixelEstimate {
+            sum: Color::new(0.0, 0.0, 0.0),
+            count: 0,
+            alpha: 0.0,
+            foreground: Color::new(0.0, 0.0, 0.0),
+            weight: 0.0,
+            mean: 0.0,
+            m2: 0.0,
+            rejected: 0
+        }
+    }
+
+    // a sample of something solid, covering the pixel completely
+    pub fn add(&mut self, sample: Color) {
+        self.add_layered(sample, sample, 1.0);
+    }
+
+    pub fn add_layered(&mut self, sample: Color, foreground: Color, alpha: f64) {
+        self.add_filtered(sample, foreground, alpha, 1.0);
+    }
+
+    // a sample the pixel filter gives weight. the brightness statistics are
+    // of the sample as it is, so how noisy the pixel is doesn't depend on the filter
+    pub fn add_filtered(&mut self, sample: Color, foreground: Color, alpha: f64, weight: f64) {
+        self.alpha += alpha * weight;
+        self.foreground = self.foreground + foreground * weight;
+        self.sum = self.sum + sample * weight;
+        self.weight += weight;
+        self.count += 1;
+        let value = perceived_brightness(&sample);
+        let delta = value - self.mean;
+        self.mean += delta / self.measured() as f64;
+        self.m2 += delta * (value - self.mean);
+    }
+
+    // a sample that was taken but thrown away
+    pub fn reject(&mut self) {
+        self.count += 1;
+        self.rejected += 1;
+    }
+
+    // the samples the brightness statistics are of
+    pub fn measured(&self) -> u64 {
+        self.count - self.rejected
+    }
+
+    // what the sums are divided by. weights can be negative, so in the
+    // unlikely case they add up to nothing or less, it falls back on the count
+    pub fn total_weight(&self) -> f64 {
+        if self.weight > 0.0 { self.weight } else { self.measured().max(1) as f64 }
+    }
+
+    // what an rgba image stores for the pixel: the colour without the
+    // background, premultiplied by the coverage unless straight, and the coverage
+    pub fn rgba(&self, straight: bool) -> (Color, f64) {
+        let weight = self.total_weight();
+        let alpha = (self.alpha / weight).clamp(0.0, 1.0);
+        let foreground = self.foreground / weight;
+        // straight alpha is the colour of what's there, however little of it
+        (if straight && alpha > 0.0 { foreground / alpha } else { foreground }, alpha)
+    }
+
+    // the pixel's colour so far
+    pub fn colour(&self) -> Color {
+        self.sum / self.total_weight()
+    }
+
+    // standard error of the mean brightness, relative to the brightness
+    pub fn relative_error(&self) -> f64 {
+        self.relative_deviation() / (self.measured() as f64).sqrt()
+    }
+
+    // how noisy a single sample is, relative to the brightness. unlike the
+    // error this doesn't shrink with more samples
+    pub fn relative_deviation(&self) -> f64 {
+        if self.measured() < 2 {
+            return f64::INFINITY
+        }
+        let variance = self.m2 / (self.measured() - 1) as f64;
+        variance.sqrt() / self.mean.max(DARKEST_PERCEIVED)
+    }
+}
+
+impl Default for PixelEstimate {
+    fn default() -> PixelEstimate {
+        PixelEstimate::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_is_exposure_invariant_in_the_dark() {
+        // the same relative noise on a dark and a mid grey pixel should
+        // converge about equally, even though their absolute variance differs a lot
+        let mut dark = PixelEstimate::new();
+        let mut mid = PixelEstimate::new();
+        for i in 0..64 {
+            let jitter = if i % 2 == 0 { 0.8 } else { 1.2 };
+            dark.add(Color::new(0.01, 0.01, 0.01) * jitter);
+            mid.add(Color::new(0.2, 0.2, 0.2) * jitter);
+        }
+        let ratio = dark.relative_error() / mid.relative_error();
+        assert!(ratio > 0.5 && ratio < 2.0, "ratio was {}", ratio);
+    }
+
+    #[test]
+    fn test_filter_weights_normalise_flat_pixels() {
+        // however a filter with negative lobes weights samples of a flat
+        // colour, the pixel comes out that colour
+        let mut estimate = PixelEstimate::new();
+        let grey = Color::new(0.4, 0.4, 0.4);
+        for weight in [1.3, -0.3, 1.1, 0.9, -0.2].iter() {
+            estimate.add_filtered(grey, grey, 1.0, *weight);
+        }
+        assert!((estimate.colour() - grey).length() < 1e-12);
+        assert!((estimate.alpha / estimate.total_weight() - 1.0).abs() < 1e-12);
+        assert_eq!(estimate.count, 5);
+    }
+
+    #[test]
+    fn test_rgba_is_premultiplied() {
+        // half red sphere, half sky
+        let mut estimate = PixelEstimate::new();
+        let (red, sky) = (Color::new(0.8, 0.0, 0.0), Color::new(0.5, 0.7, 1.0));
+        estimate.add_layered(red, red, 1.0);
+        estimate.add_layered(sky, Color::new(0.0, 0.0, 0.0), 0.0);
+        let (premultiplied, alpha) = estimate.rgba(false);
+        assert!((premultiplied - Color::new(0.4, 0.0, 0.0)).near_zero() && (alpha - 0.5).abs() < 1e-12);
+        let (straight, _) = estimate.rgba(true);
+        assert!((straight - red).near_zero());
+        // only sky has nothing to divide by
+        let mut empty = PixelEstimate::new();
+        empty.add_layered(sky, Color::new(0.0, 0.0, 0.0), 0.0);
+        let (colour, alpha) = empty.rgba(true);
+        assert!(colour.near_zero() && alpha == 0.0);
+    }
+}
@@ -0,0 +1,327 @@
+use crate::vec3::*;
+use crate::Ray;
+use crate::aabb::AABB;
+use crate::hittable::*;
+use crate::export::ExportMesh;
+use crate::material::Material;
+use crate::transform::Transform;
+use std::sync::Arc;
+
+// things that change over time, given as keyframes: values at a few times,
+// blended in between. time is in seconds, the same as rays' time, so a frame
+// rendered with its shutter open from t0 to t1 (see FrameTiming) sees
+// everything move while it's open, and comes out motion blurred
+
+// values that can be blended between keyframes, t from 0 (self) to 1 (other)
+pub trait Interpolate: Copy {
+    fn interpolate(&self, other: &Self, t: f64) -> Self;
+}
+
+impl Interpolate for f64 {
+    fn interpolate(&self, other: &f64, t: f64) -> f64 {
+        self + (other - self) * t
+    }
+}
+
+impl Interpolate for Vec3 {
+    fn interpolate(&self, other: &Vec3, t: f64) -> Vec3 {
+        *self + (*other - *self) * t
+    }
+}
+
+// where an object is: scaled, then rotated about x, y and z (in degrees, in
+// that order), then moved by the translation
+#[derive(Copy, Clone, Debug)]
+pub struct Pose {
+    pub translation: Vec3,
+    pub rotation: Vec3,
+    pub scale: Vec3
+}
+
+impl Pose {
+    pub fn new(translation: Vec3) -> Pose {
+        Pose {
+            translation,
+            rotation: Vec3::new(0.0, 0.0, 0.0),
+            scale: Vec3::new(1.0, 1.0, 1.0)
+        }
+    }
+
+    pub fn with_rotation(mut self, degrees: Vec3) -> Pose {
+        self.rotation = degrees;
+        self
+    }
+
+    pub fn with_scale(mut self, scale: Vec3) -> Pose {
+        self.scale = scale;
+        self
+    }
+
+    pub fn transform(&self) -> Transform {
+        Transform::scaling(self.scale)
+            .then(&Transform::rotation_x(self.rotation.x()))
+            .then(&Transform::rotation_y(self.rotation.y()))
+            .then(&Transform::rotation_z(self.rotation.z()))
+            .then(&Transform::translation(self.translation))
+    }
+}
+
+// the angles are blended as they are, so a turn from 0 to 720 spins twice
+impl Interpolate for Pose {
+    fn interpolate(&self, other: &Pose, t: f64) -> Pose {
+        Pose {
+            translation: self.translation.interpolate(&other.translation, t),
+            rotation: self.rotation.interpolate(&other.rotation, t),
+            scale: self.scale.interpolate(&other.scale, t)
+        }
+    }
+}
+
+// where a camera is, what it's looking at and how far it's zoomed in, as
+// for Camera::moved
+#[derive(Copy, Clone, Debug)]
+pub struct CameraPose {
+    pub lookfrom: Vec3,
+    pub lookat: Vec3,
+    pub vertical_fov: f64
+}
+
+impl Interpolate for CameraPose {
+    fn interpolate(&self, other: &CameraPose, t: f64) -> CameraPose {
+        CameraPose {
+            lookfrom: self.lookfrom.interpolate(&other.lookfrom, t),
+            lookat: self.lookat.interpolate(&other.lookat, t),
+            vertical_fov: self.vertical_fov.interpolate(&other.vertical_fov, t)
+        }
+    }
+}
+
+// how values get from one key to the next
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Easing {
+    // at a steady speed, changing speed suddenly at each key
+    Linear,
+    // speeding up out of each key and slowing down into the next (smoothstep)
+    Smooth
+}
+
+// a value over time. before the first key it stays at the first, after the
+// last at the last
+pub struct Keyframes<T> {
+    // in order of time
+    keys: Vec<(f64, T)>,
+    easing: Easing
+}
+
+impl<T: Interpolate> Keyframes<T> {
+    pub fn new(time: f64, value: T) -> Keyframes<T> {
+        Keyframes {
+            keys: vec![(time, value)],
+            easing: Easing::Linear
+        }
+    }
+
+    pub fn with_key(mut self, time: f64, value: T) -> Keyframes<T> {
+        if self.keys.iter().any(|(key_time, _)| *key_time == time) {
+            panic!("There's already a keyframe at {}s", time);
+        }
+        let position = self.keys.partition_point(|(key_time, _)| *key_time < time);
+        self.keys.insert(position, (time, value));
+        self
+    }
+
+    pub fn with_easing(mut self, easing: Easing) -> Keyframes<T> {
+        self.easing = easing;
+        self
+    }
+
+    // the times of the first and last keys
+    pub fn span(&self) -> (f64, f64) {
+        (self.keys[0].0, self.keys[self.keys.len() - 1].0)
+    }
+
+    pub fn at(&self, time: f64) -> T {
+        let next = self.keys.partition_point(|(key_time, _)| *key_time <= time);
+        if next == 0 {
+            return self.keys[0].1
+        }
+        if next == self.keys.len() {
+            return self.keys[next - 1].1
+        }
+        let ((from_time, from), (to_time, to)) = (&self.keys[next - 1], &self.keys[next]);
+        let t = (time - from_time) / (to_time - from_time);
+        let t = match self.easing {
+            Easing::Linear => t,
+            Easing::Smooth => t * t * (3.0 - 2.0 * t)
+        };
+        from.interpolate(to, t)
+    }
+}
+
+// how finely the path between keys is followed for an animated instance's
+// box, since rotating moves corners off the straight line between keys
+const BOX_STEPS: usize = 16;
+
+// like Instance, but with its transform keyframed, so the geometry moves
+// (and turns and grows) over time
+pub struct AnimatedInstance {
+    object: Arc<dyn Hittable>,
+    path: Keyframes<Pose>,
+    // around everywhere the geometry goes, so acceleration structures built
+    // once hold for every frame
+    bounding_box: Option<AABB>
+}
+
+impl AnimatedInstance {
+    pub fn new(object: Arc<dyn Hittable>, path: Keyframes<Pose>) -> AnimatedInstance {
+        let (start, end) = path.span();
+        let steps = BOX_STEPS * path.keys.len();
+        let bounding_box = object.bounding_box(start, end).map(|local| {
+            (0..=steps).map(|step| {
+                let time = start + (end - start) * step as f64 / steps as f64;
+                path.at(time).transform().bounding_box(&local)
+            }).reduce(AABB::surrounding_box).unwrap()
+        });
+        AnimatedInstance {
+            object,
+            path,
+            bounding_box
+        }
+    }
+}
+
+impl Hittable for AnimatedInstance {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let transform = self.path.at(ray.time).transform();
+        let local_ray = Ray::new(transform.inverse_point(&ray.origin), transform.inverse_vector(&ray.direction), Some(ray.time));
+        let mut record = self.object.hit(&local_ray, t_min, t_max)?;
+        let local_outward = if record.front_face { record.normal } else { record.normal * -1.0 };
+        let outward_normal = transform.normal(&local_outward).unit_vector();
+        record.point = transform.point(&record.point);
+        record.set_face_normal(ray, &outward_normal);
+        record.tangent = record.tangent.map(|tangent| transform.vector(&tangent));
+        Some(record)
+    }
+
+    fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<AABB> {
+        self.bounding_box
+    }
+
+    // where it is at the first key
+    fn tessellate(&self) -> Vec<ExportMesh> {
+        let transform = self.path.at(self.path.span().0).transform();
+        let mut meshes = self.object.tessellate();
+        for mesh in meshes.iter_mut() {
+            for position in mesh.positions.iter_mut() {
+                *position = transform.point(position);
+            }
+            for normal in mesh.normals.iter_mut() {
+                *normal = transform.normal(normal).unit_vector();
+            }
+        }
+        meshes
+    }
+
+    // as for Instance, only when the geometry isn't shared
+    fn materials_mut(&mut self) -> Vec<&mut Arc<Material>> {
+        Arc::get_mut(&mut self.object).map_or_else(Vec::new, |object| object.materials_mut())
+    }
+}
+
+// when each frame of an animation is taken
+#[derive(Copy, Clone, Debug)]
+pub struct FrameTiming {
+    pub fps: f64,
+    // how long the shutter is open for each frame, as a film camera's rotary
+    // shutter: 360 degrees is the whole frame, 180 (the default) half of it,
+    // which is what film has always used and looks natural
+    pub shutter_angle: f64
+}
+
+impl FrameTiming {
+    pub fn new(fps: f64) -> FrameTiming {
+        if fps <= 0.0 {
+            panic!("Animations need more than 0 frames per second, got {}", fps);
+        }
+        FrameTiming {
+            fps,
+            shutter_angle: 180.0
+        }
+    }
+
+    pub fn with_shutter_angle(mut self, degrees: f64) -> FrameTiming {
+        if degrees <= 0.0 || degrees > 360.0 {
+            panic!("A shutter angle has to be over 0 and at most 360 degrees, got {}", degrees);
+        }
+        self.shutter_angle = degrees;
+        self
+    }
+
+    // when the shutter opens and closes for frame (from 0), in seconds
+    pub fn shutter(&self, frame: u64) -> (f64, f64) {
+        let open = frame as f64 / self.fps;
+        (open, open + self.shutter_angle / 360.0 / self.fps)
+    }
+}
+
+// a file path for one frame: each run of #s in it is replaced by the frame
+// number, padded with 0s to as many digits, e.g. frame-####.png becomes
+// frame-0012.png. anything else is left alone
+pub fn frame_path(pattern: &str, frame: u64) -> String {
+    let mut path = String::with_capacity(pattern.len());
+    let mut run = 0;
+    for character in pattern.chars() {
+        if character == '#' {
+            run += 1;
+            continue
+        }
+        if run > 0 {
+            path.push_str(&format!("{:0width$}", frame, width = run));
+            run = 0;
+        }
+        path.push(character);
+    }
+    if run > 0 {
+        path.push_str(&format!("{:0width$}", frame, width = run));
+    }
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sphere::Sphere;
+    use crate::material::Material;
+
+    #[test]
+    fn test_keyframed_ball_moves_over_time() {
+        let path = Keyframes::new(0.0, Pose::new(Vec3::new(0.0, 0.0, 0.0)))
+            .with_key(2.0, Pose::new(Vec3::new(4.0, 0.0, 0.0)).with_rotation(Vec3::new(0.0, 0.0, 90.0)))
+            .with_key(1.0, Pose::new(Vec3::new(1.0, 0.0, 0.0)));
+        assert_eq!(path.span(), (0.0, 2.0));
+        assert!(path.at(-1.0).translation.equal_to(&Vec3::new(0.0, 0.0, 0.0)));
+        assert!(path.at(1.5).translation.equal_to(&Vec3::new(2.5, 0.0, 0.0)));
+        assert!((path.at(1.5).rotation.z() - 45.0).abs() < 1e-9);
+        assert!(path.at(5.0).translation.equal_to(&Vec3::new(4.0, 0.0, 0.0)));
+        let eased = Keyframes::new(0.0, 0.0).with_key(1.0, 1.0).with_easing(Easing::Smooth);
+        assert!(eased.at(0.25) < 0.25 && (eased.at(0.5) - 0.5).abs() < 1e-12);
+
+        let glass = Material::Dielectric{index_of_refraction: 1.5, absorption: Color::new(0.0, 0.0, 0.0)};
+        let ball = AnimatedInstance::new(Arc::new(Sphere::new(Vec3::new(0.0, 0.0, 0.0), 0.5, glass)), path);
+        let bounding_box = ball.bounding_box(0.0, 1.0).unwrap();
+        assert!(bounding_box.minimum.x() <= -0.5 && bounding_box.maximum.x() >= 4.5);
+        // a ray down onto x = 2.5 only finds the ball at 1.5s
+        let down = |time: f64| Ray::new(Vec3::new(2.5, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0), Some(time));
+        assert!(ball.hit(&down(0.0), 0.001, f64::INFINITY).is_none());
+        let hit = ball.hit(&down(1.5), 0.001, f64::INFINITY).unwrap();
+        assert!((hit.point - Vec3::new(2.5, 0.5, 0.0)).length() < 1e-9);
+
+        assert_eq!(frame_path("renders/frame-####.png", 12), "renders/frame-0012.png");
+        assert_eq!(frame_path("#-#.ppm", 123), "123-123.ppm");
+        assert_eq!(frame_path("--spp", 3), "--spp");
+
+        // 24 fps with a 180 degree shutter is open for the first 1/48s of each frame
+        let (open, close) = FrameTiming::new(24.0).shutter(12);
+        assert!((open - 0.5).abs() < 1e-12 && (close - open - 1.0 / 48.0).abs() < 1e-12);
+    }
+}
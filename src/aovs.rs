@@ -0,0 +1,171 @@
+use crate::vec3::*;
+use crate::ray::Ray;
+use crate::hittable::{HitRecord, Hittable};
+use crate::material::Material;
+use crate::layers::write_pfm;
+use crate::CameraSample;
+use std::collections::HashMap;
+use std::io::Result;
+use std::sync::Arc;
+
+// arbitrary output variables (aovs): images of what the camera rays hit first
+// rather than of the light, written next to the render. denoisers take the
+// albedo and normal to tell noise from detail, and compositing uses the depth
+// (e.g. for fog or defocus) and the material ids (to pick out one material)
+
+// what a camera ray hit first
+#[derive(Copy, Clone, Debug)]
+pub struct FirstHit {
+    pub albedo: Color,
+    // the shading normal, facing the ray
+    pub normal: Vec3,
+    // the distance from the camera
+    pub depth: f64,
+    // which material, by its address, see MaterialIds
+    pub material: usize
+}
+
+impl FirstHit {
+    pub fn new(ray: &Ray, record: &HitRecord) -> FirstHit {
+        FirstHit {
+            albedo: record.material.albedo(record),
+            normal: record.normal,
+            depth: record.t * ray.direction.length(),
+            material: record.material as *const Material as usize
+        }
+    }
+}
+
+// numbers the scene's materials 1, 2, 3... in the order the scene has them,
+// so the same scene gets the same ids every render. 0 is the sky, and
+// materials the scene can't hand out (e.g. an instance's) are 0 too
+pub struct MaterialIds {
+    ids: HashMap<usize, u32>
+}
+
+impl MaterialIds {
+    pub fn new(world: &mut dyn Hittable) -> MaterialIds {
+        let mut ids = HashMap::new();
+        for material in world.materials_mut() {
+            let next = ids.len() as u32 + 1;
+            ids.entry(Arc::as_ptr(material) as usize).or_insert(next);
+        }
+        MaterialIds {ids}
+    }
+
+    pub fn id(&self, material: usize) -> u32 {
+        self.ids.get(&material).copied().unwrap_or(0)
+    }
+}
+
+// per pixel sums of the aovs, like LayerFilm
+pub struct AovFilm {
+    width: u32,
+    height: u32,
+    material_ids: MaterialIds,
+    counts: Vec<u64>,
+    // samples that hit something, which the normal and depth are averaged over
+    hits: Vec<u64>,
+    albedo: Vec<Color>,
+    normal: Vec<Vec3>,
+    depth: Vec<f64>,
+    // ids can't be averaged, so each pixel keeps its first sample's
+    material: Vec<Option<u32>>
+}
+
+impl AovFilm {
+    pub fn new(width: u32, height: u32, material_ids: MaterialIds) -> AovFilm {
+        let pixels = (width * height) as usize;
+        AovFilm {
+            width,
+            height,
+            material_ids,
+            counts: vec![0; pixels],
+            hits: vec![0; pixels],
+            albedo: vec![Color::new(0.0, 0.0, 0.0); pixels],
+            normal: vec![Vec3::new(0.0, 0.0, 0.0); pixels],
+            depth: vec![0.0; pixels],
+            material: vec![None; pixels]
+        }
+    }
+
+    // pixel counts rows from the top
+    pub fn add(&mut self, pixel: usize, sample: &CameraSample) {
+        self.counts[pixel] += 1;
+        match &sample.first_hit {
+            Some(hit) => {
+                self.hits[pixel] += 1;
+                self.albedo[pixel] = self.albedo[pixel] + hit.albedo;
+                self.normal[pixel] = self.normal[pixel] + hit.normal;
+                self.depth[pixel] += hit.depth;
+                self.material[pixel].get_or_insert(self.material_ids.id(hit.material));
+            },
+            // the sky's albedo is its colour, as bright as it gets
+            None => {
+                let sky = sample.colour;
+                self.albedo[pixel] = self.albedo[pixel] + sky / sky.max_component().max(1.0);
+                self.material[pixel].get_or_insert(0);
+            }
+        }
+    }
+
+    // the pixel's average albedo
+    pub fn albedo(&self, pixel: usize) -> Color {
+        self.albedo[pixel] / self.counts[pixel].max(1) as f64
+    }
+
+    // the pixel's average normal, unit length, or 0 if nothing was hit
+    pub fn normal(&self, pixel: usize) -> Vec3 {
+        let normal = self.normal[pixel];
+        if normal.near_zero() { normal } else { normal.unit_vector() }
+    }
+
+    // writes prefix-albedo.pfm, prefix-normal.pfm (world space, -1 to 1),
+    // prefix-depth.pfm (0 where nothing was hit) and prefix-material.pfm,
+    // whose values are the ids themselves
+    pub fn write(&self, prefix: &str) -> Result<()> {
+        let pixels = 0..self.counts.len();
+        let grey = |value: f64| Color::new(value, value, value);
+        write_pfm(&format!("{}-albedo.pfm", prefix), self.width, self.height, pixels.clone().map(|pixel| self.albedo(pixel)))?;
+        write_pfm(&format!("{}-normal.pfm", prefix), self.width, self.height, pixels.clone().map(|pixel| self.normal(pixel)))?;
+        write_pfm(&format!("{}-depth.pfm", prefix), self.width, self.height,
+            pixels.clone().map(|pixel| grey(self.depth[pixel] / self.hits[pixel].max(1) as f64)))?;
+        write_pfm(&format!("{}-material.pfm", prefix), self.width, self.height,
+            pixels.map(|pixel| grey(self.material[pixel].unwrap_or(0) as f64)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittable_list::HittableList;
+    use crate::sphere::Sphere;
+    use crate::texture::SolidTexture;
+
+    #[test]
+    fn test_first_hits_fill_the_aovs() {
+        let red = Arc::new(Material::Lambertian{albedo: Box::new(SolidTexture::new(Color::new(0.8, 0.1, 0.1))), normal_map: None});
+        let mut world = HittableList::new();
+        world.add(Sphere::new(Vec3::new(0.0, 0.0, -2.0), 0.5, Material::Dielectric{index_of_refraction: 1.5, absorption: Color::new(0.0, 0.0, 0.0)}));
+        world.add(Sphere::new(Vec3::new(0.0, 0.0, -5.0), 0.5, red.clone()));
+        world.add(Sphere::new(Vec3::new(3.0, 0.0, -5.0), 0.5, red));
+        let ids = MaterialIds::new(&mut world);
+
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -2.0), None);
+        let glass = FirstHit::new(&ray, &world.hit(&ray, 0.001, f64::INFINITY).unwrap());
+        assert!((glass.depth - 1.5).abs() < 1e-9);
+        assert!(glass.albedo.equal_to(&Color::new(1.0, 1.0, 1.0)));
+        assert!(glass.normal.equal_to(&Vec3::new(0.0, 0.0, 1.0)));
+        assert_eq!(ids.id(glass.material), 1);
+        let beside = Ray::new(Vec3::new(3.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0), None);
+        assert_eq!(ids.id(FirstHit::new(&beside, &world.hit(&beside, 0.001, f64::INFINITY).unwrap()).material), 2);
+
+        let mut film = AovFilm::new(1, 1, ids);
+        let sample = |first_hit| CameraSample{colour: Color::new(2.0, 1.0, 0.0), foreground: Color::new(0.0, 0.0, 0.0), alpha: 0.0, layer: None, first_hit};
+        film.add(0, &sample(None));
+        film.add(0, &sample(Some(glass)));
+        // half sky (as bright as albedo goes), half glass
+        assert!((film.albedo[0] / 2.0).equal_to(&Color::new(1.0, 0.75, 0.5)));
+        assert_eq!((film.depth[0], film.hits[0], film.material[0]), (1.5, 1, Some(0)));
+    }
+}
@@ -0,0 +1,76 @@
+// the render as text, each character standing for a block of pixels by how
+// bright it is. small enough to paste into a terminal, a commit message or a
+// test's expected output, where an image file can't go
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum AsciiRamp {
+    // plain ascii, for anywhere
+    Ascii,
+    // unicode shading blocks, smoother but needs a font that has them
+    Blocks
+}
+
+impl AsciiRamp {
+    pub fn parse(name: &str) -> Option<AsciiRamp> {
+        match name {
+            "ascii" => Some(AsciiRamp::Ascii),
+            "blocks" => Some(AsciiRamp::Blocks),
+            _ => None
+        }
+    }
+
+    // darkest to brightest
+    fn characters(&self) -> Vec<char> {
+        match self {
+            AsciiRamp::Ascii => " .:-=+*#%@".chars().collect(),
+            AsciiRamp::Blocks => " ░▒▓█".chars().collect()
+        }
+    }
+}
+
+// luminance is 0 to 1 per pixel, as displayed (after tonemapping and gamma),
+// in rows from the top of the image down. characters are about twice as tall
+// as they're wide, so there are half as many rows as keep the image's shape
+pub fn ascii_art(luminance: &[f64], width: usize, height: usize, columns: usize, ramp: AsciiRamp) -> String {
+    if luminance.len() != width * height {
+        panic!("Image needs {}x{} pixels, got {}", width, height, luminance.len());
+    }
+    let columns = columns.clamp(1, width);
+    let rows = ((columns as f64 * height as f64 / width as f64 / 2.0).round() as usize).clamp(1, height);
+    let characters = ramp.characters();
+
+    let mut art = String::with_capacity((columns + 1) * rows);
+    for row in 0..rows {
+        let (top, bottom) = (row * height / rows, (row + 1) * height / rows);
+        for column in 0..columns {
+            let (left, right) = (column * width / columns, (column + 1) * width / columns);
+            // the average over the block the character covers
+            let mut total = 0.0;
+            for y in top..bottom {
+                total += luminance[y * width + left..y * width + right].iter().map(|l| l.clamp(0.0, 1.0)).sum::<f64>();
+            }
+            let average = total / ((bottom - top) * (right - left)) as f64;
+            let index = ((average * characters.len() as f64) as usize).min(characters.len() - 1);
+            art.push(characters[index]);
+        }
+        art.push('\n');
+    }
+    art
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gradient_art() {
+        // dark on the left, bright on the right
+        let (width, height) = (40, 10);
+        let luminance: Vec<f64> = (0..width * height).map(|pixel| (pixel % width) as f64 / (width - 1) as f64).collect();
+        let art = ascii_art(&luminance, width, height, 10, AsciiRamp::Ascii);
+        assert_eq!(art, " .:-=+*#%@\n");
+        let art = ascii_art(&luminance, width, height, 20, AsciiRamp::Blocks);
+        assert_eq!(art.lines().count(), 3);
+        assert!(art.lines().all(|line| line.starts_with(' ') && line.ends_with('█')), "{}", art);
+    }
+}
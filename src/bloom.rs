@@ -0,0 +1,126 @@
+use crate::vec3::*;
+use rayon::prelude::*;
+
+// the glow around very bright things: a real lens and eye scatter a little of
+// the light reaching them sideways, which only shows around lights and hot
+// highlights, the light being so much brighter than what's around it. without
+// it they end in hard edges no matter how bright they are. done on the render
+// before tone mapping, so it knows how bright things really are: what's over
+// the threshold is picked out, blurred with a wide gaussian and added back on
+pub struct Bloom {
+    // how bright (in luminance) a pixel has to be to glow, 1 being white
+    pub threshold: f64,
+    // how much of the light over the threshold is spread out
+    pub strength: f64,
+    // the blur's standard deviation, as a fraction of the image's width so it
+    // looks the same at any resolution
+    pub radius: f64
+}
+
+impl Bloom {
+    pub fn new() -> Bloom {
+        Bloom {
+            threshold: 1.0,
+            strength: 0.2,
+            radius: 0.01
+        }
+    }
+
+    pub fn with_threshold(mut self, threshold: f64) -> Bloom {
+        self.threshold = threshold;
+        self
+    }
+
+    pub fn with_strength(mut self, strength: f64) -> Bloom {
+        self.strength = strength;
+        self
+    }
+
+    pub fn with_radius(mut self, radius: f64) -> Bloom {
+        if radius <= 0.0 {
+            panic!("Bloom needs a radius over 0, got {}", radius);
+        }
+        self.radius = radius;
+        self
+    }
+
+    // the pixels (rows from the top) with the glow added
+    pub fn apply(&self, pixels: &[Color], width: u32, height: u32) -> Vec<Color> {
+        let (width, height) = (width as usize, height as usize);
+        // the bright pass, keeping the colour of what's over the threshold
+        let bright: Vec<Color> = pixels.iter().map(|colour| {
+            let luminance = colour.luminance();
+            if luminance <= self.threshold {
+                Color::new(0.0, 0.0, 0.0)
+            } else {
+                *colour * ((luminance - self.threshold) / luminance)
+            }
+        }).collect();
+
+        let sigma = (self.radius * width as f64).max(0.5);
+        let reach = (3.0 * sigma).ceil() as i64;
+        let kernel: Vec<f64> = (-reach..=reach).map(|offset| (-(offset * offset) as f64 / (2.0 * sigma * sigma)).exp()).collect();
+        // a gaussian is separable, so it's blurred along the rows then down the
+        // columns. near the edges only the part of the kernel inside the image
+        // counts, so they don't come out darker
+        let blur = |source: &[Color], pixel: &(dyn Fn(usize, i64) -> Option<usize> + Sync), lines: usize, length: usize| -> Vec<Color> {
+            (0..lines * length).into_par_iter().map(|index| {
+                let (line, along) = (index / length, (index % length) as i64);
+                let mut total = Color::new(0.0, 0.0, 0.0);
+                let mut total_weight = 0.0;
+                for (weight, offset) in kernel.iter().zip(-reach..=reach) {
+                    if let Some(neighbour) = pixel(line, along + offset) {
+                        total = total + source[neighbour] * *weight;
+                        total_weight += weight;
+                    }
+                }
+                total / total_weight
+            }).collect()
+        };
+        let along_rows = |row: usize, x: i64| if x < 0 || x >= width as i64 { None } else { Some(row * width + x as usize) };
+        let rows = blur(&bright, &along_rows, height, width);
+        let down_columns = |column: usize, y: i64| if y < 0 || y >= height as i64 { None } else { Some(y as usize * width + column) };
+        // indexed by column, so turned back into rows when it's added on
+        let columns = blur(&rows, &down_columns, width, height);
+        pixels.iter().enumerate().map(|(pixel, colour)| {
+            let (x, y) = (pixel % width, pixel / width);
+            *colour + columns[x * height + y] * self.strength
+        }).collect()
+    }
+}
+
+impl Default for Bloom {
+    fn default() -> Bloom {
+        Bloom::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_bright_pixels_glow() {
+        let (width, height) = (41, 21);
+        let grey = Color::new(0.5, 0.5, 0.5);
+        let mut pixels = vec![grey; width * height];
+        let bloom = Bloom::new().with_radius(0.05);
+        // nothing is over the threshold, so nothing changes
+        assert!(bloom.apply(&pixels, width as u32, height as u32).iter().all(|colour| colour.equal_to(&grey)));
+
+        // a lamp in the middle
+        let centre = 10 * width + 20;
+        pixels[centre] = Color::new(50.0, 50.0, 50.0);
+        let bloomed = bloom.apply(&pixels, width as u32, height as u32);
+        let glow = |x: usize, y: usize| bloomed[y * width + x].x() - 0.5;
+        assert!(glow(21, 10) > 0.0 && glow(21, 10) > glow(23, 10) && glow(23, 10) > glow(26, 10));
+        // the same all the way round
+        assert!((glow(23, 10) - glow(17, 10)).abs() < 1e-9 && (glow(23, 10) - glow(20, 13)).abs() < 1e-9);
+        // far away there's none, and the light added up is the strength's share
+        // of what was over the threshold
+        assert!(glow(0, 0) < 1e-6);
+        let added: f64 = (0..width * height).filter(|pixel| *pixel != centre).map(|pixel| bloomed[pixel].x() - 0.5).sum::<f64>()
+            + bloomed[centre].x() - 50.0;
+        assert!((added - 0.2 * 49.0).abs() < 0.01, "{}", added);
+    }
+}
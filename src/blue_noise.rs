@@ -0,0 +1,162 @@
+use crate::utilities::mix_seed;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::sync::OnceLock;
+
+// a square of numbers in [0, 1) where neighbours are as different as they can
+// be: no clumps of similar values, so it only has high frequencies (blue
+// noise). when every pixel takes the same samples, shifted by its value here,
+// the error left in the image at low sample counts is blue noise too. it looks
+// like fine grain instead of blotches and blurs away (or denoises) easily.
+// made with r. ulichney's void-and-cluster method (1993): points are added
+// one at a time where they're furthest from the others, and each value is
+// the order its point came in
+pub struct BlueNoiseTile {
+    size: usize,
+    values: Vec<f64>
+}
+
+// how far each point pushes the others away, in pixels
+const SIGMA: f64 = 1.5;
+
+impl BlueNoiseTile {
+    pub fn generate(size: usize, seed: u64) -> BlueNoiseTile {
+        if size < 2 {
+            panic!("A blue noise tile needs to be at least 2 pixels across, got {}", size);
+        }
+        let count = size * size;
+        // the gaussian around a point at 0, wrapping around the tile's edges
+        let kernel: Vec<f64> = (0..count).map(|p| {
+            let wrapped = |d: usize| d.min(size - d) as f64;
+            let (dx, dy) = (wrapped(p % size), wrapped(p / size));
+            (-(dx * dx + dy * dy) / (2.0 * SIGMA * SIGMA)).exp()
+        }).collect();
+        let mut tile = Tile{size, kernel, points: vec![false; count], energy: vec![0.0; count]};
+
+        // start from a tenth of the pixels at random, spread out evenly by
+        // moving the most crowded point to the emptiest spot until it settles
+        let mut rng = StdRng::seed_from_u64(seed);
+        while tile.count() < count / 10 {
+            let p = rng.gen_range(0..count);
+            if !tile.points[p] {
+                tile.toggle(p);
+            }
+        }
+        loop {
+            let cluster = tile.tightest_cluster();
+            tile.toggle(cluster);
+            let void = tile.largest_void();
+            if void == cluster {
+                tile.toggle(cluster);
+                break
+            }
+            tile.toggle(void);
+        }
+
+        // the starting points are ranked by taking them away most crowded
+        // first, the rest by filling in the emptiest spot
+        let mut ranks = vec![0; count];
+        let prototype = tile.points.clone();
+        let prototype_energy = tile.energy.clone();
+        let mut remaining = tile.count();
+        while remaining > 0 {
+            let cluster = tile.tightest_cluster();
+            tile.toggle(cluster);
+            remaining -= 1;
+            ranks[cluster] = remaining;
+        }
+        tile.points = prototype;
+        tile.energy = prototype_energy;
+        for rank in tile.count()..count {
+            let void = tile.largest_void();
+            tile.toggle(void);
+            ranks[void] = rank;
+        }
+
+        BlueNoiseTile {
+            size,
+            values: ranks.into_iter().map(|rank| (rank as f64 + 0.5) / count as f64).collect()
+        }
+    }
+
+    // the value at x, y, repeating the tile
+    pub fn value(&self, x: u32, y: u32) -> f64 {
+        self.values[(y as usize % self.size) * self.size + x as usize % self.size]
+    }
+
+    // the shift for dimension of the sample at pixel. each dimension reads
+    // the tile from a different place, so they aren't the same as each other
+    pub fn offset(&self, pixel: (u32, u32), dimension: u32) -> f64 {
+        let start = mix_seed(&[dimension as u64]);
+        let (x, y) = (start % self.size as u64, (start >> 32) % self.size as u64);
+        self.value(pixel.0.wrapping_add(x as u32), pixel.1.wrapping_add(y as u32))
+    }
+}
+
+// the tile while it's being made. energy is how crowded each pixel is, the
+// sum of every point's gaussian
+struct Tile {
+    size: usize,
+    kernel: Vec<f64>,
+    points: Vec<bool>,
+    energy: Vec<f64>
+}
+
+impl Tile {
+    fn count(&self) -> usize {
+        self.points.iter().filter(|&&point| point).count()
+    }
+
+    fn toggle(&mut self, p: usize) {
+        self.points[p] = !self.points[p];
+        let sign = if self.points[p] { 1.0 } else { -1.0 };
+        let (px, py) = (p % self.size, p / self.size);
+        for (q, energy) in self.energy.iter_mut().enumerate() {
+            let dx = (q % self.size + self.size - px) % self.size;
+            let dy = (q / self.size + self.size - py) % self.size;
+            *energy += sign * self.kernel[dy * self.size + dx];
+        }
+    }
+
+    // the most crowded point
+    fn tightest_cluster(&self) -> usize {
+        (0..self.points.len()).filter(|&p| self.points[p])
+            .max_by(|&a, &b| self.energy[a].partial_cmp(&self.energy[b]).unwrap()).unwrap()
+    }
+
+    // the emptiest pixel without a point
+    fn largest_void(&self) -> usize {
+        (0..self.points.len()).filter(|&p| !self.points[p])
+            .min_by(|&a, &b| self.energy[a].partial_cmp(&self.energy[b]).unwrap()).unwrap()
+    }
+}
+
+// the tile renders use, made the first time it's needed (it takes a moment)
+pub fn tile() -> &'static BlueNoiseTile {
+    static TILE: OnceLock<BlueNoiseTile> = OnceLock::new();
+    TILE.get_or_init(|| BlueNoiseTile::generate(64, 0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_neighbours_differ() {
+        let size = 16;
+        let tile = BlueNoiseTile::generate(size, 3);
+        // every value once
+        let mut ranks: Vec<usize> = tile.values.iter().map(|value| (value * (size * size) as f64) as usize).collect();
+        ranks.sort_unstable();
+        assert!(ranks.into_iter().eq(0..size * size));
+        // neighbouring values differ by about 1/3 on average in white noise
+        let mut difference = 0.0;
+        for y in 0..size as u32 {
+            for x in 0..size as u32 {
+                difference += (tile.value(x, y) - tile.value(x + 1, y)).abs() + (tile.value(x, y) - tile.value(x, y + 1)).abs();
+            }
+        }
+        let mean = difference / (2 * size * size) as f64;
+        assert!(mean > 0.4, "{}", mean);
+    }
+}
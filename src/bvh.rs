@@ -0,0 +1,226 @@
+use crate::Ray;
+use crate::aabb::{AABB, SlabRay};
+use crate::export::ExportMesh;
+use crate::bvh_stats::*;
+use crate::ray_stats::count_primitive_test;
+use crate::material::Material;
+use crate::hittable::*;
+use std::cmp::Ordering;
+use std::sync::Arc;
+
+// lists at least this big have their two halves built on separate threads.
+// below it, the overhead of handing work to another thread isn't worth it
+const PARALLEL_THRESHOLD: usize = 1024;
+
+// Bounding Volume Hierarchy.
+// construct a hierarchy of aabb boxes. this improves performance of
+// the 'hit' method (if a ray hits an object) by constructing a tree of
+// boxes, where the parent hit means one of the children were hit. finding
+// out if a box was hit is a fast computation, and traversing the tree is a
+// logarithmic operation(s) as opposed to checking a list of objects repeatedly
+// for every ray encountered
+#[allow(clippy::upper_case_acronyms)]
+pub enum BVH {
+    // left/right are Hittable's because it could refer to either:
+    // - another BVH node
+    // - an object (leaf node)
+    Leaf(Box<dyn Hittable>),
+    Branch {
+        left: Box<BVH>,
+        right: Box<BVH>,
+        bounding_box: AABB
+    }
+}
+
+impl BVH {
+    // ideally the children have smaller boxes, and each subtree is 
+    // equally distributed. implement a simple strategy:
+    // 1. pick the axis the objects spread furthest along
+    // 2. sort
+    // 3. take half of the sorted for the left and right subtrees
+    // nothing's left to chance, so the same objects always make the same
+    // tree, whichever threads build its halves
+
+    // not using HittableList for the list type because:
+    // 1 - don't need to use its methods since the elements implement them too
+    // 2 - list.objects makes the caller take ownership, then retrieving an
+    //     an element in objects causes a double borrow
+    pub fn construct(mut list: Vec<Box<dyn Hittable>>, t0: f64, t1: f64) -> Self {
+        let span = list.len();
+        if span == 0 {
+            panic!("Cannot have 0 objects in list during BVH construction");
+        }
+        if span == 1 {
+            return BVH::Leaf(list.pop().unwrap())
+        }
+
+        let axis = list.iter().map(|object| object.bounding_box(t0, t1).expect("No bounding box in BVH node"))
+            .reduce(AABB::surrounding_box).unwrap().longest_axis();
+        list.sort_by(|a, b| {
+            let box1 = a.bounding_box(t0, t1);
+            let box2 = b.bounding_box(t0, t1);
+            match(box1, box2) {
+                (Some(q), Some(u)) => {
+                    let (left_val, right_val) = match axis {
+                        0 => (q.minimum.x(), u.minimum.x()),
+                        1 => (q.minimum.y(), u.minimum.y()),
+                        _ => (q.minimum.z(), u.minimum.z())
+                    };
+                    if left_val < right_val {
+                        Ordering::Less
+                    } else if left_val == right_val {
+                        Ordering::Equal
+                    } else {
+                        Ordering::Greater
+                    }
+                },
+                (Some(_q), None) => panic!("No bounding box in BVH node"),
+                (None, Some(_q)) => panic!("No bounding box in BVH node"),
+                (None, None) => panic!("No bounding box in BVH node"),
+            }
+        });
+
+        let right_list: Vec<Box<dyn Hittable>> = list.drain(span / 2..).collect();
+        let (left, right) = if span >= PARALLEL_THRESHOLD {
+            rayon::join(|| BVH::construct(list, t0, t1), || BVH::construct(right_list, t0, t1))
+        } else {
+            (BVH::construct(list, t0, t1), BVH::construct(right_list, t0, t1))
+        };
+        let (left, right) = (Box::new(left), Box::new(right));
+
+        let left_box = left.bounding_box(t0, t1);
+        let right_box = right.bounding_box(t0, t1);
+        let result_box = match(left_box, right_box) {
+            (Some(q), Some(u)) => AABB::surrounding_box(q, u),
+            (Some(_q), None) => panic!("No bounding box in BVH node"),
+            (None, Some(_q)) => panic!("No bounding box in BVH node"),
+            (None, None) => panic!("No bounding box in BVH node"),
+        };
+
+        BVH::Branch {
+            left,
+            right,
+            bounding_box: result_box
+        }
+    }
+}
+
+impl BVH {
+    pub fn stats(&self, t0: f64, t1: f64) -> BvhStats {
+        let root = self.bounding_box(t0, t1).expect("No bounding box in BVH node");
+        let mut collector = StatsCollector::new(&root);
+        self.collect_stats(&mut collector, 0, t0, t1);
+        collector.finish()
+    }
+
+    fn collect_stats(&self, collector: &mut StatsCollector, depth: usize, t0: f64, t1: f64) {
+        match self {
+            BVH::Leaf(_) => collector.leaf(depth, 1),
+            BVH::Branch {left, right, bounding_box} => {
+                let left_box = left.bounding_box(t0, t1).expect("No bounding box in BVH node");
+                let right_box = right.bounding_box(t0, t1).expect("No bounding box in BVH node");
+                collector.branch(depth, bounding_box, &left_box, &right_box);
+                left.collect_stats(collector, depth + 1, t0, t1);
+                right.collect_stats(collector, depth + 1, t0, t1);
+            }
+        }
+    }
+
+    // hit, with the ray set up for box tests once for the whole way down
+    fn hit_slabs(&self, ray: &Ray, slab_ray: &SlabRay, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        count_node_visit();
+        match self {
+            BVH::Leaf(t) => {
+                count_primitive_test();
+                t.hit(ray, t_min, t_max)
+            },
+            BVH::Branch {left, right, bounding_box} => {
+                // only recursively check if the current box is even hit
+                bounding_box.hit_slabs(slab_ray, t_min, t_max)?;
+
+                let left_hit = left.hit_slabs(ray, slab_ray, t_min, t_max);
+                let mut end = t_max;
+                // don't unnecessarily search more area than needed
+                if let Some(hit) = &left_hit {
+                    end = hit.t;
+                }
+
+                let right_hit = right.hit_slabs(ray, slab_ray, t_min, end);
+
+                // return the closer object hit
+                match (left_hit, right_hit) {
+                    (None, None) => None,
+                    (None, Some(y)) => Some(y),
+                    (Some(x), None) => Some(x),
+                    (Some(x), Some(y)) => {
+                        if x.t < y.t {
+                            Some(x)
+                        } else {
+                            Some(y)
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Hittable for BVH {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        self.hit_slabs(ray, &SlabRay::new(ray), t_min, t_max)
+    }
+
+    // might need to fix this, surroung on left and right?
+    fn bounding_box(&self, t0: f64, t1: f64) -> Option<AABB> {
+        match self {
+            BVH::Leaf(t) => {
+                t.bounding_box(t0, t1)
+            },
+            BVH::Branch {left: _, right: _, bounding_box} => {
+                Some(*bounding_box)
+            }
+        }
+    }
+
+    fn tessellate(&self) -> Vec<ExportMesh> {
+        match self {
+            BVH::Leaf(t) => t.tessellate(),
+            BVH::Branch {left, right, bounding_box: _} => {
+                let mut meshes = left.tessellate();
+                meshes.extend(right.tessellate());
+                meshes
+            }
+        }
+    }
+
+    fn materials_mut(&mut self) -> Vec<&mut Arc<Material>> {
+        match self {
+            BVH::Leaf(object) => object.materials_mut(),
+            BVH::Branch {left, right, ..} => {
+                let mut materials = left.materials_mut();
+                materials.extend(right.materials_mut());
+                materials
+            }
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sphere::Sphere;
+    use crate::texture::SolidTexture;
+    use crate::utilities::mix_seed;
+    use crate::vec3::Vec3;
+
+    #[test]
+    fn test_same_objects_make_the_same_tree() {
+        // enough of them for the halves to be built on other threads
+        let spheres = || (0..2 * PARALLEL_THRESHOLD as u64).map(|i| {
+            let coordinate = |axis: u64| (mix_seed(&[i, axis]) % 1000) as f64 / 10.0;
+            let matte = Material::Lambertian{albedo: Box::new(SolidTexture::uniform(0.5)), normal_map: None};
+            Box::new(Sphere::new(Vec3::new(coordinate(0), coordinate(1), coordinate(2)), 0.5, matte)) as Box<dyn Hittable>
+        }).collect::<Vec<Box<dyn Hittable>>>();
+        let tree = || BVH::construct(spheres(), 0.0, 1.0).stats(0.0, 1.0).to_json();
+        assert_eq!(tree(), tree());
+    }
+}
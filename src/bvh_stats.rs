@@ -0,0 +1,123 @@
+use crate::aabb::AABB;
+use std::cell::Cell;
+
+thread_local! {
+    // nodes the accelerators have looked at on this thread, for the heat map
+    // (see DebugView). a thread local costs next to nothing to count into
+    static NODE_VISITS: Cell<u64> = const { Cell::new(0) };
+}
+
+pub fn count_node_visit() {
+    NODE_VISITS.with(|visits| visits.set(visits.get() + 1));
+}
+
+// the nodes visited on this thread since the last call
+pub fn take_node_visits() -> u64 {
+    NODE_VISITS.with(|visits| visits.replace(0))
+}
+
+// numbers describing how good a built tree is, for tuning accelerators.
+// "good" here means a ray has to open as few boxes as possible: boxes should be
+// small relative to their parents and siblings shouldn't overlap much (a ray
+// through the overlap has to search both)
+#[derive(Debug)]
+pub struct BvhStats {
+    pub node_count: usize,
+    pub leaf_count: usize,
+    // the root is at depth 0
+    pub max_depth: usize,
+    pub min_leaf_primitives: usize,
+    pub max_leaf_primitives: usize,
+    pub total_leaf_primitives: usize,
+    // summed over the branch nodes, the root included
+    pub total_surface_area: f64,
+    pub root_surface_area: f64,
+    // summed area of the overlap between every pair of siblings
+    pub overlap_surface_area: f64,
+    // the average of overlap / parent area over all branch nodes, 0 is best
+    pub mean_overlap_ratio: f64
+}
+
+impl BvhStats {
+    // the expected number of boxes a random ray hitting the root opens. lower is
+    // better, and it's comparable between trees built over the same primitives
+    pub fn expected_node_visits(&self) -> f64 {
+        if self.root_surface_area > 0.0 { self.total_surface_area / self.root_surface_area } else { 0.0 }
+    }
+
+    pub fn average_leaf_primitives(&self) -> f64 {
+        self.total_leaf_primitives as f64 / self.leaf_count.max(1) as f64
+    }
+
+    pub fn report(&self) -> String {
+        format!("nodes: {} ({} leaves)\nmax depth: {}\nprimitives per leaf: {} to {} ({:.2} on average)\nexpected node visits: {:.2}\nmean overlap ratio: {:.3}\n",
+            self.node_count, self.leaf_count, self.max_depth, self.min_leaf_primitives, self.max_leaf_primitives,
+            self.average_leaf_primitives(), self.expected_node_visits(), self.mean_overlap_ratio)
+    }
+
+    pub fn to_json(&self) -> String {
+        format!("{{\"node_count\":{},\"leaf_count\":{},\"max_depth\":{},\"min_leaf_primitives\":{},\"max_leaf_primitives\":{},\"average_leaf_primitives\":{},\"total_surface_area\":{},\"root_surface_area\":{},\"expected_node_visits\":{},\"overlap_surface_area\":{},\"mean_overlap_ratio\":{}}}",
+            self.node_count, self.leaf_count, self.max_depth, self.min_leaf_primitives, self.max_leaf_primitives,
+            self.average_leaf_primitives(), self.total_surface_area, self.root_surface_area, self.expected_node_visits(),
+            self.overlap_surface_area, self.mean_overlap_ratio)
+    }
+}
+
+// trees feed their nodes to this while walking themselves, so every tree
+// reports the same numbers the same way
+pub struct StatsCollector {
+    stats: BvhStats,
+    overlap_ratio_sum: f64,
+    branch_count: usize
+}
+
+impl StatsCollector {
+    pub fn new(root: &AABB) -> StatsCollector {
+        StatsCollector {
+            stats: BvhStats {
+                node_count: 0,
+                leaf_count: 0,
+                max_depth: 0,
+                min_leaf_primitives: usize::MAX,
+                max_leaf_primitives: 0,
+                total_leaf_primitives: 0,
+                total_surface_area: 0.0,
+                root_surface_area: root.surface_area(),
+                overlap_surface_area: 0.0,
+                mean_overlap_ratio: 0.0
+            },
+            overlap_ratio_sum: 0.0,
+            branch_count: 0
+        }
+    }
+
+    pub fn leaf(&mut self, depth: usize, primitives: usize) {
+        self.stats.node_count += 1;
+        self.stats.leaf_count += 1;
+        self.stats.max_depth = self.stats.max_depth.max(depth);
+        self.stats.min_leaf_primitives = self.stats.min_leaf_primitives.min(primitives);
+        self.stats.max_leaf_primitives = self.stats.max_leaf_primitives.max(primitives);
+        self.stats.total_leaf_primitives += primitives;
+    }
+
+    pub fn branch(&mut self, depth: usize, bounding_box: &AABB, left: &AABB, right: &AABB) {
+        self.stats.node_count += 1;
+        self.stats.max_depth = self.stats.max_depth.max(depth);
+        let area = bounding_box.surface_area();
+        self.stats.total_surface_area += area;
+        let overlap = AABB::intersection(*left, *right).map_or(0.0, |overlap| overlap.surface_area());
+        self.stats.overlap_surface_area += overlap;
+        if area > 0.0 {
+            self.overlap_ratio_sum += overlap / area;
+        }
+        self.branch_count += 1;
+    }
+
+    pub fn finish(mut self) -> BvhStats {
+        if self.stats.leaf_count == 0 {
+            self.stats.min_leaf_primitives = 0;
+        }
+        self.stats.mean_overlap_ratio = self.overlap_ratio_sum / self.branch_count.max(1) as f64;
+        self.stats
+    }
+}
@@ -1,6 +1,49 @@
 use crate::vec3::Vec3;
 use crate::Ray;
+use crate::ray::RayDifferential;
 use crate::utilities::*;
+use crate::transform::Transform;
+use crate::metadata::json_vec3;
+use crate::lens::{LensSystem, RealisticLens};
+
+// the shape of the opening light passes through. this is what gives out of focus
+// highlights (bokeh) their shape, e.g. 6 blades produce hexagonal highlights
+#[derive(Copy, Clone)]
+pub enum ApertureShape {
+    Circular,
+    // blades is the number of sides (at least 3), rotation is in degrees
+    Polygonal{blades: u32, rotation: f64}
+}
+
+// where the camera is and which way it faces, what the rays start from
+#[derive(Copy, Clone)]
+struct View {
+    origin: Vec3,
+    lower_left_corner: Vec3,
+    horizontal: Vec3,
+    vertical: Vec3,
+    plane_outward: Vec3,
+    plane_horizontal: Vec3,
+    plane_vertical: Vec3
+}
+
+impl View {
+    // t of the way from self to other. blending the vectors rather than
+    // lookfrom and lookat is close enough over a shutter's worth of movement,
+    // and what's in between is always a whole camera
+    fn interpolate(&self, other: &View, t: f64) -> View {
+        let blend = |a: Vec3, b: Vec3| a + (b - a) * t;
+        View {
+            origin: blend(self.origin, other.origin),
+            lower_left_corner: blend(self.lower_left_corner, other.lower_left_corner),
+            horizontal: blend(self.horizontal, other.horizontal),
+            vertical: blend(self.vertical, other.vertical),
+            plane_outward: blend(self.plane_outward, other.plane_outward),
+            plane_horizontal: blend(self.plane_horizontal, other.plane_horizontal),
+            plane_vertical: blend(self.plane_vertical, other.plane_vertical)
+        }
+    }
+}
 
 pub struct Camera {
     origin: Vec3,
@@ -8,11 +51,18 @@ pub struct Camera {
     horizontal: Vec3,
     vertical: Vec3,
     lens_radius: f64,
+    aperture_shape: ApertureShape,
     plane_outward: Vec3, // w
     plane_horizontal: Vec3, //u
     plane_vertical: Vec3, // v
     min_time: f64,
-    max_time: f64
+    max_time: f64,
+    focus_dist: f64,
+    // replaces the thin lens when set, see lens.rs
+    lens: Option<RealisticLens>,
+    // where the camera is when the shutter closes, if it moves while it's
+    // open. rays are taken from wherever it is at their time
+    closing: Option<View>
 }
 
 impl Camera {
@@ -20,20 +70,35 @@ impl Camera {
     // lookat is where the camera is pointed at.
     // vertical_up tells us where 'up' is to determine the camera tilt
     // focus_dist is the distance from the lens (camera) to the focus plane (target); not the same as focal length
+    #[allow(clippy::too_many_arguments)]
     pub fn new(lookfrom: Vec3, lookat: Vec3, vertical_up: Vec3, vertical_fov: f64, aspect_ratio: f64, 
         aperture: f64, focus_dist: f64, min_time: f64, max_time: f64) -> Camera { 
-        let theta = degrees_to_radians(vertical_fov);
-        let h = f64::tan(theta / 2.0);
-        let viewport_height = 2.0 * h;
-        let viewport_width = aspect_ratio * viewport_height;
-
         // w is a vector opposite to the direction the camera is looking in
         let plane_outward = (lookfrom - lookat).unit_vector(); // w
         // u and v are perpendicular vectors on the plane the camera is on
         let plane_horizontal = vertical_up.cross_product(&plane_outward).unit_vector(); // u
         let plane_vertical = plane_outward.cross_product(&plane_horizontal); // v
 
-        let origin = lookfrom;
+        Camera::from_basis(lookfrom, plane_horizontal, plane_vertical, plane_outward * -1.0, vertical_fov, aspect_ratio,
+            aperture, focus_dist, min_time, max_time)
+    }
+
+    // "free mode": the camera's orientation is given directly instead of being
+    // worked out from a target, so cameras from other tools can be matched
+    // exactly (including any roll). right, up and forward should be perpendicular,
+    // they're only normalized here
+    #[allow(clippy::too_many_arguments)]
+    pub fn from_basis(origin: Vec3, right: Vec3, up: Vec3, forward: Vec3, vertical_fov: f64, aspect_ratio: f64,
+        aperture: f64, focus_dist: f64, min_time: f64, max_time: f64) -> Camera {
+        let theta = degrees_to_radians(vertical_fov);
+        let h = f64::tan(theta / 2.0);
+        let viewport_height = 2.0 * h;
+        let viewport_width = aspect_ratio * viewport_height;
+
+        let plane_outward = forward.unit_vector() * -1.0; // w
+        let plane_horizontal = right.unit_vector(); // u
+        let plane_vertical = up.unit_vector(); // v
+
         let horizontal:Vec3 = plane_horizontal * viewport_width * focus_dist;
         let vertical:Vec3 = plane_vertical * viewport_height * focus_dist;
         let lens_radius = aperture / 2.0;
@@ -47,18 +112,257 @@ impl Camera {
             plane_vertical,
             lower_left_corner: origin - horizontal / 2.0 - vertical / 2.0 - plane_outward * focus_dist,
             lens_radius,
+            aperture_shape: ApertureShape::Circular,
             min_time,
-            max_time
+            max_time,
+            focus_dist,
+            lens: None,
+            closing: None
+        }
+    }
+
+    // from a camera to world transform, e.g. a camera's matrix_world in blender.
+    // like blender (and opengl) the camera looks down its local -z axis with +y up
+    #[allow(clippy::too_many_arguments)]
+    pub fn from_transform(camera_to_world: &Transform, vertical_fov: f64, aspect_ratio: f64,
+        aperture: f64, focus_dist: f64, min_time: f64, max_time: f64) -> Camera {
+        Camera::from_basis(
+            camera_to_world.point(&Vec3::new(0.0, 0.0, 0.0)),
+            camera_to_world.vector(&Vec3::new(1.0, 0.0, 0.0)),
+            camera_to_world.vector(&Vec3::new(0.0, 1.0, 0.0)),
+            camera_to_world.vector(&Vec3::new(0.0, 0.0, -1.0)),
+            vertical_fov, aspect_ratio, aperture, focus_dist, min_time, max_time
+        )
+    }
+
+    // from a 4x4 view matrix (world to camera, rows first), the inverse of the above.
+    // panics if the matrix isn't affine or can't be inverted
+    #[allow(clippy::too_many_arguments)]
+    pub fn from_view_matrix(view: [[f64; 4]; 4], vertical_fov: f64, aspect_ratio: f64,
+        aperture: f64, focus_dist: f64, min_time: f64, max_time: f64) -> Camera {
+        if view[3] != [0.0, 0.0, 0.0, 1.0] {
+            panic!("A view matrix's last row must be (0, 0, 0, 1), got {:?}", view[3]);
+        }
+        let world_to_camera = Transform::from_matrix([view[0], view[1], view[2]]);
+        Camera::from_transform(&world_to_camera.inverse(), vertical_fov, aspect_ratio, aperture, focus_dist, min_time, max_time)
+    }
+
+    pub fn with_aperture_shape(mut self, aperture_shape: ApertureShape) -> Camera {
+        if let ApertureShape::Polygonal{blades, rotation: _} = aperture_shape {
+            if blades < 3 {
+                panic!("An aperture needs at least 3 blades, got {}", blades);
+            }
+        }
+        self.aperture_shape = aperture_shape;
+        self
+    }
+
+    // traces rays through a real lens instead of the thin lens. the field of
+    // view then comes from the lens and film size (film_diagonal, in mm) rather
+    // than vertical_fov, and the aperture from the lens. scale converts the lens'
+    // millimetres to scene units (e.g. 0.001 for a scene in metres)
+    // focuses at the same distance as the thin lens did
+    pub fn with_realistic_lens(mut self, system: LensSystem, film_diagonal: f64, scale: f64) -> Camera {
+        let aspect_ratio = self.horizontal.length() / self.vertical.length();
+        self.lens = Some(RealisticLens::new(system, film_diagonal, aspect_ratio, self.focus_dist, scale));
+        self
+    }
+
+    // the same camera with its shutter open from open to close (in seconds),
+    // e.g. for one frame of an animation
+    pub fn with_shutter(mut self, open: f64, close: f64) -> Camera {
+        self.min_time = open;
+        self.max_time = close;
+        self
+    }
+
+    // the same camera moving while the shutter is open, from where it is to
+    // lookfrom, looking at lookat (y up) with vertical_fov when it closes.
+    // pans and zooms come out motion blurred like anything else moving
+    pub fn with_motion(mut self, lookfrom: Vec3, lookat: Vec3, vertical_fov: f64) -> Camera {
+        self.closing = Some(self.moved(lookfrom, lookat, vertical_fov).view());
+        self
+    }
+
+    fn view(&self) -> View {
+        View {
+            origin: self.origin,
+            lower_left_corner: self.lower_left_corner,
+            horizontal: self.horizontal,
+            vertical: self.vertical,
+            plane_outward: self.plane_outward,
+            plane_horizontal: self.plane_horizontal,
+            plane_vertical: self.plane_vertical
         }
     }
 
-    pub fn get_ray(&self, s: f64, t: f64) -> Ray {
-        let ray_dir = Vec3::random_in_unit_disk() * self.lens_radius;
-        let offset = self.plane_horizontal * ray_dir.x() + self.plane_vertical * ray_dir.y();
-        Ray {
-            origin: self.origin + offset,
-            direction: self.lower_left_corner + self.horizontal * s + self.vertical * t - self.origin - offset,
-            time: random_float_in_range(self.min_time, self.max_time)
+    // the view at time, in the shutter interval
+    fn view_at(&self, time: f64) -> View {
+        match &self.closing {
+            Some(closing) if self.max_time > self.min_time => {
+                self.view().interpolate(closing, ((time - self.min_time) / (self.max_time - self.min_time)).clamp(0.0, 1.0))
+            },
+            _ => self.view()
         }
     }
-}
\ No newline at end of file
+
+    // where the camera is, the middle of its focus plane (what it's looking at)
+    // and its vertical field of view in degrees, e.g. to move it from there
+    pub fn placement(&self) -> (Vec3, Vec3, f64) {
+        let lookat = self.origin - self.plane_outward * self.focus_dist;
+        let vertical_fov = 2.0 * (self.vertical.length() / (2.0 * self.focus_dist)).atan();
+        (self.origin, lookat, vertical_fov.to_degrees())
+    }
+
+    // the same camera (aperture, shutter and lens) moved to lookfrom, pointed
+    // at and focused on lookat, with y up. a realistic lens keeps its focus
+    pub fn moved(&self, lookfrom: Vec3, lookat: Vec3, vertical_fov: f64) -> Camera {
+        let aspect_ratio = self.horizontal.length() / self.vertical.length();
+        let focus_dist = (lookat - lookfrom).length();
+        let mut camera = Camera::new(lookfrom, lookat, Vec3::new(0.0, 1.0, 0.0), vertical_fov, aspect_ratio,
+            self.lens_radius * 2.0, focus_dist, self.min_time, self.max_time);
+        camera.aperture_shape = self.aperture_shape;
+        camera.lens = self.lens.clone();
+        camera
+    }
+
+    // everything that decides which rays the camera shoots, for render metadata
+    pub fn to_json(&self) -> String {
+        let aperture = match self.aperture_shape {
+            ApertureShape::Circular => "\"circular\"".to_string(),
+            ApertureShape::Polygonal{blades, rotation} => format!("{{\"blades\":{},\"rotation\":{}}}", blades, rotation)
+        };
+        let view_json = |view: &View| format!("\"origin\":{},\"lower_left_corner\":{},\"horizontal\":{},\"vertical\":{}",
+            json_vec3(&view.origin), json_vec3(&view.lower_left_corner), json_vec3(&view.horizontal), json_vec3(&view.vertical));
+        let closing = self.closing.as_ref().map_or(String::new(), |closing| format!(",\"closing\":{{{}}}", view_json(closing)));
+        format!("{{{},\"lens_radius\":{},\"aperture_shape\":{},\"time\":[{},{}]{}}}",
+            view_json(&self.view()), self.lens_radius, aperture, self.min_time, self.max_time, closing)
+    }
+
+    // a random point on the lens, used for defocus blur
+    fn sample_aperture(&self) -> Vec3 {
+        match self.aperture_shape {
+            ApertureShape::Circular => Vec3::random_in_unit_disk(),
+            ApertureShape::Polygonal{blades, rotation} => {
+                Vec3::random_in_unit_polygon(blades, degrees_to_radians(rotation))
+            }
+        }
+    }
+
+    // the ray through (s, t) on the image, or none where a realistic lens
+    // lets no light at all through to that part of the film (outside its
+    // image circle). that sample sees black, see CameraSample::blocked
+    pub fn get_ray(&self, s: f64, t: f64) -> Option<Ray> {
+        if let Some(lens) = &self.lens {
+            let time = random_float_in_range(self.min_time, self.max_time);
+            let view = self.view_at(time);
+            // lens space to world space, the lens looks down -z like the camera does down -w
+            let to_world = |v: Vec3| view.plane_horizontal * v.x() + view.plane_vertical * v.y() + view.plane_outward * v.z();
+            return lens.get_ray(s, t, time).map(|ray| Ray::new(view.origin + to_world(ray.origin), to_world(ray.direction), Some(time)))
+        }
+        let ray_dir = self.sample_aperture() * self.lens_radius;
+        let time = random_float_in_range(self.min_time, self.max_time);
+        let view = self.view_at(time);
+        let offset = view.plane_horizontal * ray_dir.x() + view.plane_vertical * ray_dir.y();
+        Some(Ray {
+            origin: view.origin + offset,
+            direction: view.lower_left_corner + view.horizontal * s + view.vertical * t - view.origin - offset,
+            time,
+            debug: None,
+            differential: None
+        })
+    }
+
+    // get_ray, along with the rays ds and dt further across and up the film
+    // (one pixel over) for texture filtering, see RayDifferential. they go
+    // through the same point on the lens. realistic lenses don't get them
+    pub fn get_ray_with_differentials(&self, s: f64, t: f64, ds: f64, dt: f64) -> Option<Ray> {
+        let ray = self.get_ray(s, t)?;
+        if self.lens.is_some() {
+            return Some(ray)
+        }
+        let view = self.view_at(ray.time);
+        let differential = RayDifferential {
+            x_origin: ray.origin,
+            x_direction: ray.direction + view.horizontal * ds,
+            y_origin: ray.origin,
+            y_direction: ray.direction + view.vertical * dt
+        };
+        Some(ray.with_differential(Some(differential)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_free_mode_matches_look_at() {
+        let lookfrom = Vec3::new(3.0, 2.0, 5.0);
+        let lookat = Vec3::new(0.0, 0.0, -1.0);
+        let camera = Camera::new(lookfrom, lookat, Vec3::new(0.0, 1.0, 0.0), 30.0, 1.5, 0.0, 4.0, 0.0, 1.0);
+
+        // the same camera as a transform: rotate the basis into place, then move it
+        let (u, v, w) = (camera.plane_horizontal, camera.plane_vertical, camera.plane_outward);
+        let camera_to_world = Transform::from_matrix([
+            [u.x(), v.x(), w.x(), lookfrom.x()],
+            [u.y(), v.y(), w.y(), lookfrom.y()],
+            [u.z(), v.z(), w.z(), lookfrom.z()]
+        ]);
+        let free = Camera::from_transform(&camera_to_world, 30.0, 1.5, 0.0, 4.0, 0.0, 1.0);
+        let inverse = camera_to_world.inverse().matrix();
+        let viewed = Camera::from_view_matrix([inverse[0], inverse[1], inverse[2], [0.0, 0.0, 0.0, 1.0]], 30.0, 1.5, 0.0, 4.0, 0.0, 1.0);
+
+        for (s, t) in [(0.0, 0.0), (0.5, 0.5), (1.0, 0.25)] {
+            let expected = camera.get_ray(s, t).unwrap();
+            for ray in [free.get_ray(s, t).unwrap(), viewed.get_ray(s, t).unwrap()] {
+                assert!((ray.origin - expected.origin).near_zero());
+                assert!((ray.direction - expected.direction).length() < 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn test_moved_keeps_the_lens() {
+        let camera = Camera::new(Vec3::new(3.0, 2.0, 5.0), Vec3::new(0.0, 0.0, -1.0), Vec3::new(0.0, 1.0, 0.0), 30.0, 1.5, 0.0, 4.0, 0.0, 1.0);
+        let (lookfrom, lookat, vertical_fov) = camera.placement();
+        assert!((vertical_fov - 30.0).abs() < 1e-9);
+        assert!((lookfrom - Vec3::new(3.0, 2.0, 5.0)).near_zero() && ((lookat - lookfrom).length() - 4.0).abs() < 1e-9);
+
+        // put back where it was, it's the same camera
+        let same = camera.moved(lookfrom, lookat, vertical_fov);
+        for (s, t) in [(0.0, 0.0), (0.5, 0.5), (1.0, 0.25)] {
+            assert!((same.get_ray(s, t).unwrap().direction - camera.get_ray(s, t).unwrap().direction).length() < 1e-9);
+        }
+        let (_, wider_lookat, wider) = camera.moved(Vec3::new(0.0, 0.0, 10.0), Vec3::new(0.0, 0.0, 0.0), 60.0).placement();
+        assert!((wider - 60.0).abs() < 1e-9 && wider_lookat.near_zero());
+    }
+
+    #[test]
+    fn test_moving_camera_shoots_from_where_it_is_at_the_ray_time() {
+        let up = Vec3::new(0.0, 1.0, 0.0);
+        let camera = Camera::new(Vec3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, 0.0), up, 30.0, 1.5, 0.0, 5.0, 2.0, 2.5)
+            .with_motion(Vec3::new(1.0, 0.0, 5.0), Vec3::new(1.0, 0.0, 0.0), 30.0);
+        for _ in 0..100 {
+            let ray = camera.get_ray(0.5, 0.5).unwrap();
+            assert!(ray.time >= 2.0 && ray.time <= 2.5);
+            // panning right, the middle of the image looks straight ahead from
+            // further along the longer the shutter's been open
+            let along = (ray.time - 2.0) / 0.5;
+            assert!((ray.origin - Vec3::new(along, 0.0, 5.0)).length() < 1e-9);
+            assert!((ray.direction.unit_vector() - Vec3::new(0.0, 0.0, -1.0)).length() < 1e-9);
+        }
+        // where it's going is also in the metadata
+        assert!(camera.to_json().contains("\"closing\":{\"origin\":[1,0,5]"));
+    }
+
+    #[test]
+    fn test_blocked_film_has_no_ray() {
+        let camera = Camera::new(Vec3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0), 30.0, 1.5, 0.0, 5.0, 0.0, 1.0)
+            .with_realistic_lens(LensSystem::double_gauss(), 43.27, 0.001);
+        assert!(camera.get_ray(0.5, 0.5).is_some());
+        // far outside the lens's image circle, rather than a pinhole ray there
+        assert!(camera.get_ray(20.0, 20.0).is_none());
+        assert!(camera.get_ray_with_differentials(20.0, 20.0, 0.01, 0.01).is_none());
+    }
+}
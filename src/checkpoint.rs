@@ -0,0 +1,209 @@
+use crate::restart::RenderState;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Error, ErrorKind, Read, Result, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+// snapshots of a render in progress, so a long render survives a crash (or can
+// be branched off with different settings). a checkpoint is the command line
+// the render was started with plus its film (every pixel's samples so far, see
+// restart.rs), which is all `rays resume <checkpoint>` needs to carry on.
+// a checkpointed render is always seeded (see main.rs), and a pixel's sample
+// count is the number of its next sample, so the resumed render takes the same
+// random numbers the render would have had it carried on, and matches it exactly
+//
+// file format (little endian): "RCKP", u32 version, u32 argument count, each
+// argument as a u32 length and utf-8 bytes, then a render state
+
+const MAGIC: &[u8; 4] = b"RCKP";
+const VERSION: u32 = 1;
+
+fn invalid_data(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("Bad checkpoint: {}", message))
+}
+
+fn read_u32(reader: &mut impl Read) -> Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+pub fn write_checkpoint(path: &Path, args: &[String], state: &RenderState) -> Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    file.write_all(MAGIC)?;
+    file.write_all(&VERSION.to_le_bytes())?;
+    file.write_all(&(args.len() as u32).to_le_bytes())?;
+    for arg in args.iter() {
+        file.write_all(&(arg.len() as u32).to_le_bytes())?;
+        file.write_all(arg.as_bytes())?;
+    }
+    state.write(&mut file)?;
+    file.flush()
+}
+
+// the arguments the render was started with and its state
+pub fn read_checkpoint(path: &Path) -> Result<(Vec<String>, RenderState)> {
+    let mut file = BufReader::new(File::open(path)?);
+    let mut magic = [0; 4];
+    file.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid_data("missing 'RCKP' header"))
+    }
+    let version = read_u32(&mut file)?;
+    if version != VERSION {
+        return Err(invalid_data(&format!("unsupported version {}", version)))
+    }
+    let count = read_u32(&mut file)?;
+    let mut args = Vec::new();
+    for _ in 0..count {
+        let mut bytes = vec![0; read_u32(&mut file)? as usize];
+        file.read_exact(&mut bytes)?;
+        args.push(String::from_utf8(bytes).map_err(|_| invalid_data("argument isn't utf-8"))?);
+    }
+    Ok((args, RenderState::read(&mut file)?))
+}
+
+// "90" or "90s", "5m", "2h"
+pub fn parse_duration(text: &str) -> std::result::Result<Duration, String> {
+    let (number, unit) = match text.find(|c: char| c.is_ascii_alphabetic()) {
+        Some(index) => text.split_at(index),
+        None => (text, "s")
+    };
+    let number: f64 = number.parse().map_err(|_| format!("'{}' isn't a duration", text))?;
+    let seconds = match unit {
+        "s" => number,
+        "m" => number * 60.0,
+        "h" => number * 3600.0,
+        _ => return Err(format!("Unknown unit '{}' in '{}', expected s, m or h", unit, text))
+    };
+    if seconds <= 0.0 {
+        return Err(format!("'{}' has to be longer than 0", text))
+    }
+    Ok(Duration::from_secs_f64(seconds))
+}
+
+// writes numbered checkpoints (name-0001.ckpt, name-0002.ckpt...) every
+// interval, keeping only the newest few. numbering carries on after the
+// checkpoints already on disk under the name (e.g. those of a render being
+// resumed), which count towards the few kept
+pub struct CheckpointSchedule {
+    name: String,
+    interval: Duration,
+    keep: usize,
+    last: Instant,
+    written: VecDeque<PathBuf>,
+    number: u32
+}
+
+impl CheckpointSchedule {
+    pub fn new(name: &str, interval: Duration, keep: usize) -> CheckpointSchedule {
+        let existing = CheckpointSchedule::existing(name);
+        CheckpointSchedule {
+            name: name.to_string(),
+            interval,
+            keep: keep.max(1),
+            last: Instant::now(),
+            number: existing.last().map_or(0, |(number, _)| *number),
+            written: existing.into_iter().map(|(_, path)| path).collect()
+        }
+    }
+
+    // the checkpoints written under name so far, oldest first
+    fn existing(name: &str) -> Vec<(u32, PathBuf)> {
+        let base = Path::new(name);
+        let directory = match base.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new(".")
+        };
+        let prefix = match base.file_name().and_then(|file_name| file_name.to_str()) {
+            Some(file_name) => format!("{}-", file_name),
+            None => return Vec::new()
+        };
+        let entries = match std::fs::read_dir(directory) {
+            Ok(entries) => entries,
+            Err(_) => return Vec::new()
+        };
+        let mut existing: Vec<(u32, PathBuf)> = entries.filter_map(|entry| {
+            let file_name = entry.ok()?.file_name();
+            let digits = file_name.to_str()?.strip_prefix(&prefix)?.strip_suffix(".ckpt")?;
+            if digits.len() < 4 || !digits.bytes().all(|byte| byte.is_ascii_digit()) {
+                return None
+            }
+            let number: u32 = digits.parse().ok()?;
+            Some((number, PathBuf::from(format!("{}-{:04}.ckpt", name, number))))
+        }).collect();
+        existing.sort();
+        existing
+    }
+
+    pub fn is_due(&self) -> bool {
+        self.last.elapsed() >= self.interval
+    }
+
+    // the path of the checkpoint written
+    pub fn write(&mut self, args: &[String], state: &RenderState) -> Result<PathBuf> {
+        self.number += 1;
+        let path = PathBuf::from(format!("{}-{:04}.ckpt", self.name, self.number));
+        write_checkpoint(&path, args, state)?;
+        self.written.push_back(path.clone());
+        // only removed once the newer one is safely written
+        while self.written.len() > self.keep {
+            if let Some(old) = self.written.pop_front() {
+                std::fs::remove_file(old)?;
+            }
+        }
+        self.last = Instant::now();
+        Ok(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vec3::Color;
+
+    #[test]
+    fn test_checkpoints_round_trip_and_rotate() {
+        assert_eq!(parse_duration("5m"), Ok(Duration::from_secs(300)));
+        assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+        assert!(parse_duration("5d").is_err() && parse_duration("0s").is_err());
+
+        let mut state = RenderState::new(2, 2);
+        state.pixel_mut(1, 0).add(Color::new(0.5, 0.25, 1.0));
+        let args = vec!["rays".to_string(), "--spp".to_string(), "64".to_string()];
+        let name = std::env::temp_dir().join("rays_test_checkpoint");
+        let mut schedule = CheckpointSchedule::new(name.to_str().unwrap(), Duration::from_secs(60), 2);
+        let paths: Vec<PathBuf> = (0..3).map(|_| schedule.write(&args, &state).unwrap()).collect();
+        assert!(!paths[0].exists() && paths[1].exists());
+
+        let (read_args, read_state) = read_checkpoint(&paths[2]).unwrap();
+        assert_eq!(read_args, args);
+        assert_eq!(read_state.pixels[1].count, 1);
+        assert_eq!(read_state.pixels[1].sum.y(), 0.25);
+        for path in paths[1..].iter() {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_resumed_render_numbers_and_rotates_on() {
+        let state = RenderState::new(1, 1);
+        let args = vec!["rays".to_string()];
+        let name = std::env::temp_dir().join("rays_test_resumed_checkpoint");
+        let name = name.to_str().unwrap();
+        let mut schedule = CheckpointSchedule::new(name, Duration::from_secs(60), 3);
+        let first: Vec<PathBuf> = (0..3).map(|_| schedule.write(&args, &state).unwrap()).collect();
+
+        // resuming from the last of them, keeping 2 this time
+        let mut resumed = CheckpointSchedule::new(name, Duration::from_secs(60), 2);
+        let next = resumed.write(&args, &state).unwrap();
+        assert!(next.to_str().unwrap().ends_with("-0004.ckpt"), "{}", next.display());
+        assert!(!first[0].exists() && !first[1].exists() && first[2].exists() && next.exists());
+        let after = resumed.write(&args, &state).unwrap();
+        assert!(!first[2].exists() && next.exists() && after.exists());
+        for path in [next, after].iter() {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+}
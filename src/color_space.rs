@@ -0,0 +1,141 @@
+use crate::vec3::*;
+
+// colour spaces images come in and go out as, so renders fit into a colour
+// managed pipeline (like an ocio config's roles, but just the common few).
+// the renderer itself works in linear rec.709, every other space is converted
+// to it when loading and from it when writing
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ColorSpace {
+    // what 8 bit images almost always are: rec.709 primaries with the srgb curve
+    Srgb,
+    // the working space. also right for data like normal or roughness maps
+    LinearRec709,
+    // aces' space for rendering and compositing: wider (AP1) primaries, linear
+    AcesCg,
+    // rec.709 primaries with a plain power curve instead of srgb's, e.g. 2.2
+    // for displays calibrated to that or 2.0 for how renders used to look
+    Gamma(f64)
+}
+
+// rec.709 to AP1 and back, including the white point change from D65 to
+// aces' ~D60 (bradford adaptation)
+const REC709_TO_ACESCG: [[f64; 3]; 3] = [
+    [0.6130974024, 0.3395231462, 0.0473794514],
+    [0.0701937225, 0.9163538791, 0.0134523985],
+    [0.0206155929, 0.1095697729, 0.8698146342]
+];
+const ACESCG_TO_REC709: [[f64; 3]; 3] = [
+    [1.7050509927, -0.6217921207, -0.0832588720],
+    [-0.1302564175, 1.1408047366, -0.0105483191],
+    [-0.0240033568, -0.1289689761, 1.1529723329]
+];
+
+fn transform(matrix: &[[f64; 3]; 3], colour: Color) -> Color {
+    let row = |r: [f64; 3]| r[0] * colour.x() + r[1] * colour.y() + r[2] * colour.z();
+    Color::new(row(matrix[0]), row(matrix[1]), row(matrix[2]))
+}
+
+fn per_channel(colour: Color, f: impl Fn(f64) -> f64) -> Color {
+    Color::new(f(colour.x()), f(colour.y()), f(colour.z()))
+}
+
+// the srgb curve, encoded value to linear and back
+fn srgb_to_linear(value: f64) -> f64 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(value: f64) -> f64 {
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+impl ColorSpace {
+    pub fn parse(name: &str) -> Option<ColorSpace> {
+        match name {
+            "srgb" => Some(ColorSpace::Srgb),
+            "linear" | "linear-rec709" => Some(ColorSpace::LinearRec709),
+            "acescg" => Some(ColorSpace::AcesCg),
+            _ => None
+        }
+    }
+
+    // a colour in this space to the working space (e.g. a texture when loading)
+    pub fn decode(self, colour: Color) -> Color {
+        match self {
+            ColorSpace::Srgb => per_channel(colour, srgb_to_linear),
+            ColorSpace::LinearRec709 => colour,
+            ColorSpace::AcesCg => transform(&ACESCG_TO_REC709, colour),
+            ColorSpace::Gamma(gamma) => per_channel(colour, |c| c.max(0.0).powf(gamma))
+        }
+    }
+
+    // a colour in the working space to this space (e.g. a pixel when writing)
+    pub fn encode(self, colour: Color) -> Color {
+        match self {
+            // the curve is only defined for [0, 1]
+            ColorSpace::Srgb => per_channel(colour, |c| linear_to_srgb(c.clamp(0.0, 1.0))),
+            ColorSpace::LinearRec709 => colour,
+            ColorSpace::AcesCg => transform(&REC709_TO_ACESCG, colour),
+            ColorSpace::Gamma(gamma) => per_channel(colour, |c| c.clamp(0.0, 1.0).powf(1.0 / gamma))
+        }
+    }
+
+    // only the primaries of this space, for outputs that have to stay linear
+    // (float images, viewers like tev which apply their own curve)
+    pub fn encode_linear(self, colour: Color) -> Color {
+        match self {
+            ColorSpace::AcesCg => transform(&REC709_TO_ACESCG, colour),
+            _ => colour
+        }
+    }
+
+    // a colour in the working space as an 8 bit pixel of this space
+    pub fn encode_rgb8(self, colour: Color) -> [u8; 3] {
+        self.encode_rgb8_dithered(colour, 0.0)
+    }
+
+    // the same, with offset (in steps, see Dither) added before rounding
+    pub fn encode_rgb8_dithered(self, colour: Color, offset: f64) -> [u8; 3] {
+        let encoded = self.encode(colour);
+        let quantize = |value: f64| (255.0 * value.clamp(0.0, 1.0) + offset).round().clamp(0.0, 255.0) as u8;
+        [quantize(encoded.x()), quantize(encoded.y()), quantize(encoded.z())]
+    }
+
+    // the same at 16 bits, where steps are too small to band
+    pub fn encode_rgb16(self, colour: Color) -> [u16; 3] {
+        let encoded = self.encode(colour);
+        let quantize = |value: f64| (65535.0 * value.clamp(0.0, 1.0)).round() as u16;
+        [quantize(encoded.x()), quantize(encoded.y()), quantize(encoded.z())]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_conversions_round_trip() {
+        // mid grey in srgb is about 21% linear
+        assert!((ColorSpace::Srgb.decode(Color::new(0.5, 0.5, 0.5)).x() - 0.2140).abs() < 1e-4);
+        // white stays white in every space
+        let white = Color::new(1.0, 1.0, 1.0);
+        let colour = Color::new(0.8, 0.3, 0.1);
+        for space in [ColorSpace::Srgb, ColorSpace::LinearRec709, ColorSpace::AcesCg, ColorSpace::Gamma(2.2)].iter() {
+            let converted = space.encode(white);
+            assert!((converted - white).length() < 1e-6, "{:?}", space);
+            let back = space.decode(space.encode(colour));
+            assert!((back - colour).length() < 1e-6, "{:?}", space);
+        }
+        assert_eq!(ColorSpace::parse("acescg"), Some(ColorSpace::AcesCg));
+        // a square root leaves the midtones darker than the srgb curve
+        assert!(ColorSpace::Gamma(2.0).encode(Color::new(0.2, 0.2, 0.2)).x() < ColorSpace::Srgb.encode(Color::new(0.2, 0.2, 0.2)).x());
+    }
+}
@@ -0,0 +1,201 @@
+use crate::vec3::*;
+use crate::Ray;
+use crate::hittable::*;
+use crate::material::*;
+use crate::sphere::Sphere;
+use crate::aabb::AABB;
+use crate::export::{ExportMaterial, ExportMesh, SPHERE_SEGMENTS};
+use crate::utilities::PI;
+use std::sync::Arc;
+
+// an upright cone standing on base with its tip height above it, e.g. a
+// traffic cone, a roof or a tree. wrap it in a Transform to tilt it
+pub struct Cone {
+    // the center of the bottom
+    base: Vec3,
+    radius: f64,
+    height: f64,
+    // whether the bottom is closed off
+    cap: bool,
+    material: Arc<Material>
+}
+
+impl Cone {
+    pub fn new(base: Vec3, radius: f64, height: f64, material: impl Into<Arc<Material>>) -> Cone {
+        Cone {
+            base,
+            radius,
+            height,
+            cap: true,
+            material: material.into()
+        }
+    }
+
+    pub fn with_cap(mut self, cap: bool) -> Cone {
+        self.cap = cap;
+        self
+    }
+
+    // the radius shrinks by this much for every unit up
+    fn slope(&self) -> f64 {
+        self.radius / self.height
+    }
+
+    // the side is x^2 + z^2 = (radius - slope * y)^2 between y = 0 and height,
+    // which becomes a quadratic in t
+    fn side_hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<(f64, Vec3)> {
+        let (origin, direction, k) = (ray.origin - self.base, ray.direction, self.slope());
+        let radius_at_origin = self.radius - k * origin.y();
+        let a = direction.x() * direction.x() + direction.z() * direction.z() - k * k * direction.y() * direction.y();
+        let half_b = origin.x() * direction.x() + origin.z() * direction.z() + radius_at_origin * k * direction.y();
+        let c = origin.x() * origin.x() + origin.z() * origin.z() - radius_at_origin * radius_at_origin;
+
+        let roots = if a.abs() < 1e-12 {
+            // parallel to the side, which it crosses once (if at all)
+            if half_b == 0.0 {
+                return None
+            }
+            [-c / (2.0 * half_b), f64::INFINITY]
+        } else {
+            let discriminant = half_b * half_b - a * c;
+            if discriminant < 0.0 {
+                return None
+            }
+            let root = discriminant.sqrt();
+            let (first, second) = ((-half_b - root) / a, (-half_b + root) / a);
+            [first.min(second), first.max(second)]
+        };
+        // the quadratic also has the mirrored cone above the tip, which the
+        // height check throws out
+        roots.iter().find_map(|&t| {
+            let y = origin.y() + t * direction.y();
+            if t > t_min && t < t_max && (0.0..=self.height).contains(&y) {
+                Some((t, origin + direction * t))
+            } else {
+                None
+            }
+        })
+    }
+}
+
+impl Hittable for Cone {
+    // the side's uvs wrap around like a sphere's (see Sphere::get_sphere_uv)
+    // with v going up to the tip. the cap's are the square around it seen from below
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let mut closest = t_max;
+        let mut hit = None;
+        if let Some((t, local)) = self.side_hit(ray, t_min, closest) {
+            closest = t;
+            let around = Vec3::new(local.x(), 0.0, local.z());
+            // leans up by the slope, straight up at the tip
+            let (outward_normal, u, tangent) = if around.near_zero() {
+                (Vec3::new(0.0, 1.0, 0.0), 0.5, Vec3::new(1.0, 0.0, 0.0))
+            } else {
+                let around = around.unit_vector();
+                let (u, _) = Sphere::get_sphere_uv(around);
+                ((around + Vec3::new(0.0, self.slope(), 0.0)).unit_vector(), u, Sphere::get_sphere_tangent(around))
+            };
+            hit = Some((t, outward_normal, u, local.y() / self.height, tangent));
+        }
+        if self.cap {
+            let t = (self.base.y() - ray.origin.y()) / ray.direction.y();
+            let local = ray.at(t) - self.base;
+            // also catches rays parallel to the cap, where t isn't a number
+            if t > t_min && t < closest && local.x() * local.x() + local.z() * local.z() <= self.radius * self.radius {
+                let (u, v) = ((local.x() / self.radius + 1.0) / 2.0, (1.0 + local.z() / self.radius) / 2.0);
+                hit = Some((t, Vec3::new(0.0, -1.0, 0.0), u, v, Vec3::new(1.0, 0.0, 0.0)));
+            }
+        }
+
+        let (t, outward_normal, u, v, tangent) = hit?;
+        let mut record = HitRecord::new(ray.at(t), outward_normal, t, u, v, false, &self.material);
+        record.set_face_normal(ray, &outward_normal);
+        record.tangent = Some(tangent);
+        Some(record)
+    }
+
+    fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<AABB> {
+        let radius = Vec3::new(self.radius, 0.0, self.radius);
+        Some(AABB::new(self.base - radius, self.base + radius + Vec3::new(0.0, self.height, 0.0)))
+    }
+
+    fn tessellate(&self) -> Vec<ExportMesh> {
+        let mut positions = Vec::new();
+        let mut normals = Vec::new();
+        let mut uvs = Vec::new();
+        let mut triangles = Vec::new();
+        let tip = self.base + Vec3::new(0.0, self.height, 0.0);
+        let around = |angle: f64| Vec3::new(-angle.cos(), 0.0, angle.sin());
+
+        // a triangle per segment up to the tip, which is repeated so each can
+        // have the normal halfway across its segment
+        for segment in 0..SPHERE_SEGMENTS {
+            let (start, end) = (segment as f64 / SPHERE_SEGMENTS as f64, (segment + 1) as f64 / SPHERE_SEGMENTS as f64);
+            let first = positions.len();
+            for (position, u, v) in [
+                (self.base + around(start * 2.0 * PI) * self.radius, start, 0.0),
+                (self.base + around(end * 2.0 * PI) * self.radius, end, 0.0),
+                (tip, (start + end) / 2.0, 1.0)
+            ] {
+                positions.push(position);
+                normals.push((around(u * 2.0 * PI) + Vec3::new(0.0, self.slope(), 0.0)).unit_vector());
+                uvs.push((u, v));
+            }
+            // counter clockwise seen from outside
+            triangles.push([first, first + 1, first + 2]);
+        }
+
+        if self.cap {
+            let center = positions.len();
+            positions.push(self.base);
+            normals.push(Vec3::new(0.0, -1.0, 0.0));
+            uvs.push((0.5, 0.5));
+            for segment in 0..SPHERE_SEGMENTS {
+                let offset = around(segment as f64 / SPHERE_SEGMENTS as f64 * 2.0 * PI);
+                positions.push(self.base + offset * self.radius);
+                normals.push(Vec3::new(0.0, -1.0, 0.0));
+                uvs.push(((offset.x() + 1.0) / 2.0, (1.0 + offset.z()) / 2.0));
+                triangles.push([center, center + 1 + (segment + 1) % SPHERE_SEGMENTS, center + 1 + segment]);
+            }
+        }
+
+        vec![ExportMesh {
+            positions,
+            normals,
+            uvs,
+            triangles,
+            material: ExportMaterial::from_material(&self.material)
+        }]
+    }
+
+    fn materials_mut(&mut self) -> Vec<&mut Arc<Material>> {
+        vec![&mut self.material]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cone_hit() {
+        let material = Material::Dielectric{index_of_refraction: 1.5, absorption: Color::new(0.0, 0.0, 0.0)};
+        let cone = Cone::new(Vec3::new(0.0, 0.0, 0.0), 1.0, 2.0, material);
+
+        // halfway up the radius is halved, and the normal leans up by the slope
+        let record = cone.hit(&Ray::new(Vec3::new(5.0, 1.0, 0.0), Vec3::new(-1.0, 0.0, 0.0), None), 0.001, f64::INFINITY).unwrap();
+        assert!((record.point - Vec3::new(0.5, 1.0, 0.0)).near_zero(), "{:?}", record.point);
+        assert!((record.normal - Vec3::new(1.0, 0.5, 0.0).unit_vector()).near_zero(), "{:?}", record.normal);
+        assert!((record.v - 0.5).abs() < 1e-9);
+
+        // the mirrored cone above the tip isn't there
+        assert!(cone.hit(&Ray::new(Vec3::new(5.0, 3.0, 0.0), Vec3::new(-1.0, 0.0, 0.0), None), 0.001, f64::INFINITY).is_none());
+        // from below it hits the cap, unless it's open and then the inside of the tip
+        let up = Ray::new(Vec3::new(0.2, -1.0, 0.0), Vec3::new(0.0, 1.0, 0.0), None);
+        assert!((cone.hit(&up, 0.001, f64::INFINITY).unwrap().normal - Vec3::new(0.0, -1.0, 0.0)).near_zero());
+        let open = Cone::new(Vec3::new(0.0, 0.0, 0.0), 1.0, 2.0, Material::Dielectric{index_of_refraction: 1.5, absorption: Color::new(0.0, 0.0, 0.0)})
+            .with_cap(false);
+        let record = open.hit(&up, 0.001, f64::INFINITY).unwrap();
+        assert!(!record.front_face && (record.point.y() - 1.6).abs() < 1e-9, "{:?}", record.point);
+    }
+}
@@ -0,0 +1,67 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+// counts kept per thread, for progress.rs and ray_stats.rs. every ray adds to
+// some of them, so a counter shared by all the threads would have them all
+// waiting on the same cache line. instead each thread has its own, which only
+// it adds to: plain loads and stores with nothing shared to wait on. they're
+// atomics so the totals can be read from another thread
+
+#[derive(Copy, Clone, Debug)]
+pub enum Counter {
+    // rays traced, by camera_sample, ray_colour and the wavefront
+    Rays,
+    // camera rays traced, one per sample
+    Samples,
+    // the rest are only counted with --stats, see ray_stats.rs
+    PrimaryRays,
+    BounceRays,
+    BoxTests,
+    PrimitiveTests
+}
+
+const COUNTERS: usize = 6;
+
+type Counts = [AtomicU64; COUNTERS];
+
+// every thread's counts, added to the first time the thread counts something
+static THREADS: Mutex<Vec<Arc<Counts>>> = Mutex::new(Vec::new());
+
+thread_local! {
+    static COUNTS: Arc<Counts> = {
+        let counts: Arc<Counts> = Arc::default();
+        THREADS.lock().unwrap().push(counts.clone());
+        counts
+    };
+}
+
+pub fn count(counter: Counter) {
+    COUNTS.with(|counts| {
+        let count = &counts[counter as usize];
+        count.store(count.load(Ordering::Relaxed) + 1, Ordering::Relaxed);
+    });
+}
+
+// counted so far, on every thread
+pub fn total(counter: Counter) -> u64 {
+    THREADS.lock().unwrap().iter().map(|counts| counts[counter as usize].load(Ordering::Relaxed)).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counts_add_up_across_threads() {
+        let before = (total(Counter::Rays), total(Counter::Samples));
+        count(Counter::Samples);
+        count(Counter::Rays);
+        std::thread::spawn(|| {
+            count(Counter::Rays);
+            count(Counter::Rays);
+        }).join().unwrap();
+        // other tests render in parallel, so only check this test's counts are there
+        let after = (total(Counter::Rays), total(Counter::Samples));
+        assert!(after.0 - before.0 >= 3 && after.1 - before.1 >= 1, "{:?} {:?}", before, after);
+    }
+}
@@ -0,0 +1,179 @@
+use crate::Ray;
+use crate::hittable::*;
+use crate::material::Material;
+use crate::aabb::AABB;
+use crate::utilities::INFINITY;
+use std::sync::Arc;
+
+// constructive solid geometry: two closed objects combined into one solid, e.g.
+// a lens from the intersection of two spheres or a pipe from the difference of
+// two cylinders. along a ray each object is a run of intervals where the ray is
+// inside it, and the combination is inside wherever its operation says so.
+// a ray hits the combination where it goes from outside to inside (or back),
+// which is always on one of the two surfaces, with that surface's material.
+// the objects need to be closed (an open Cylinder has no inside), though a
+// Plane works as the half of space behind it
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum CsgOperation {
+    // inside either
+    Union,
+    // inside both
+    Intersection,
+    // inside a but not b, so b carves a hole out of a
+    Difference
+}
+
+impl CsgOperation {
+    fn inside(&self, a: bool, b: bool) -> bool {
+        match self {
+            CsgOperation::Union => a || b,
+            CsgOperation::Intersection => a && b,
+            CsgOperation::Difference => a && !b
+        }
+    }
+}
+
+// gives up on objects with more surfaces than this along one ray
+const MAX_CROSSINGS: usize = 64;
+
+pub struct Csg {
+    a: Box<dyn Hittable>,
+    b: Box<dyn Hittable>,
+    operation: CsgOperation
+}
+
+impl Csg {
+    pub fn new(a: impl Hittable + 'static, b: impl Hittable + 'static, operation: CsgOperation) -> Csg {
+        Csg {
+            a: Box::new(a),
+            b: Box::new(b),
+            operation
+        }
+    }
+
+    pub fn union(a: impl Hittable + 'static, b: impl Hittable + 'static) -> Csg {
+        Csg::new(a, b, CsgOperation::Union)
+    }
+
+    pub fn intersection(a: impl Hittable + 'static, b: impl Hittable + 'static) -> Csg {
+        Csg::new(a, b, CsgOperation::Intersection)
+    }
+
+    pub fn difference(a: impl Hittable + 'static, b: impl Hittable + 'static) -> Csg {
+        Csg::new(a, b, CsgOperation::Difference)
+    }
+
+    // every surface the ray crosses from t_min on, nearest first, and whether
+    // it started out inside (its first crossing is on the way out)
+    fn crossings<'a>(object: &'a dyn Hittable, ray: &Ray, t_min: f64, t_max: f64) -> (bool, Vec<HitRecord<'a>>) {
+        let mut crossings: Vec<HitRecord> = Vec::new();
+        let mut from = t_min;
+        while crossings.len() < MAX_CROSSINGS {
+            match object.hit(ray, from, t_max) {
+                Some(record) => {
+                    // just past the hit, so the same surface isn't found again
+                    from = record.t + 1e-9 * record.t.abs().max(1.0);
+                    crossings.push(record);
+                },
+                None => break
+            }
+        }
+        (crossings.first().is_some_and(|first| !first.front_face), crossings)
+    }
+}
+
+impl Hittable for Csg {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        // crossings past t_max still tell whether the ray starts inside
+        let (mut inside_a, a) = Csg::crossings(self.a.as_ref(), ray, t_min, INFINITY);
+        let (mut inside_b, b) = Csg::crossings(self.b.as_ref(), ray, t_min, INFINITY);
+        let mut inside = self.operation.inside(inside_a, inside_b);
+
+        // walk both lists in order of t, until the combination changes
+        let (mut a, mut b) = (a.into_iter().peekable(), b.into_iter().peekable());
+        loop {
+            let from_a = match (a.peek(), b.peek()) {
+                (Some(next_a), Some(next_b)) => next_a.t <= next_b.t,
+                (Some(_), None) => true,
+                (None, Some(_)) => false,
+                (None, None) => return None
+            };
+            let mut record = if from_a {
+                let record = a.next()?;
+                inside_a = record.front_face;
+                record
+            } else {
+                let record = b.next()?;
+                inside_b = record.front_face;
+                record
+            };
+            if record.t >= t_max {
+                return None
+            }
+            let now_inside = self.operation.inside(inside_a, inside_b);
+            if now_inside != inside {
+                // the normal already faces the ray, only which side it came
+                // from is the combination's rather than the object's
+                record.front_face = now_inside;
+                return Some(record)
+            }
+            inside = now_inside;
+        }
+    }
+
+    fn bounding_box(&self, t0: f64, t1: f64) -> Option<AABB> {
+        let (a, b) = (self.a.bounding_box(t0, t1), self.b.bounding_box(t0, t1));
+        match self.operation {
+            CsgOperation::Union => Some(AABB::surrounding_box(a?, b?)),
+            // anything outside either box is outside both. with no overlap
+            // there's nothing to hit, a's box is as good as any
+            CsgOperation::Intersection => match (a, b) {
+                (Some(a), Some(b)) => AABB::intersection(a, b).or(Some(a)),
+                (a, b) => a.or(b)
+            },
+            CsgOperation::Difference => a
+        }
+    }
+
+    fn materials_mut(&mut self) -> Vec<&mut Arc<Material>> {
+        let mut materials = self.a.materials_mut();
+        materials.extend(self.b.materials_mut());
+        materials
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vec3::*;
+    use crate::sphere::Sphere;
+
+    fn sphere(x: f64, radius: f64) -> Sphere {
+        Sphere::new(Vec3::new(x, 0.0, 0.0), radius, Material::Dielectric{index_of_refraction: 1.5, absorption: Color::new(0.0, 0.0, 0.0)})
+    }
+
+    #[test]
+    fn test_csg_operations() {
+        // two unit spheres overlapping between x = -0.5 and 0.5, seen along x
+        let ray = Ray::new(Vec3::new(-5.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0), None);
+        let first_x = |csg: &Csg| csg.hit(&ray, 0.001, f64::INFINITY).map(|record| (record.point.x(), record.front_face));
+        assert_eq!(first_x(&Csg::union(sphere(-0.5, 1.0), sphere(0.5, 1.0))), Some((-1.5, true)));
+        assert_eq!(first_x(&Csg::intersection(sphere(-0.5, 1.0), sphere(0.5, 1.0))), Some((-0.5, true)));
+        // the hole's far wall, facing back towards the ray
+        let hollow = Csg::difference(sphere(-0.5, 1.0), sphere(0.5, 1.0));
+        assert_eq!(first_x(&hollow), Some((-1.5, true)));
+        let exit = hollow.hit(&ray, 4.0, f64::INFINITY).unwrap();
+        assert!((exit.point.x() + 0.5).abs() < 1e-9 && !exit.front_face);
+        assert!((exit.normal - Vec3::new(-1.0, 0.0, 0.0)).near_zero());
+
+        // starting inside the union, the first surface is the way out
+        let inside = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0), None);
+        let union = Csg::union(sphere(-0.5, 1.0), sphere(0.5, 1.0));
+        let record = union.hit(&inside, 0.001, f64::INFINITY).unwrap();
+        assert!((record.point.x() - 1.5).abs() < 1e-9 && !record.front_face);
+
+        // a lens' box is just the overlap
+        let lens = Csg::intersection(sphere(-0.5, 1.0), sphere(0.5, 1.0)).bounding_box(0.0, 1.0).unwrap();
+        assert!((lens.minimum.x() + 0.5).abs() < 1e-9 && (lens.maximum.x() - 0.5).abs() < 1e-9);
+    }
+}
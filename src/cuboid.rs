@@ -0,0 +1,54 @@
+use crate::vec3::*;
+use crate::Ray;
+use crate::hittable::*;
+use crate::hittable_list::HittableList;
+use crate::material::*;
+use crate::aabb::AABB;
+use crate::export::ExportMesh;
+use crate::rect::AxisAlignedRect;
+use std::sync::Arc;
+
+// an axis-aligned box made of 6 rectangles sharing one material. rotated or
+// moved boxes are an Instance of one of these
+pub struct Cuboid {
+    minimum: Vec3,
+    maximum: Vec3,
+    sides: HittableList
+}
+
+impl Cuboid {
+    pub fn new(minimum: Vec3, maximum: Vec3, material: impl Into<Arc<Material>>) -> Cuboid {
+        let material = material.into();
+        let mut sides = HittableList::new();
+        for axis in 0..3 {
+            let (a, b) = ((axis + 1) % 3, (axis + 2) % 3);
+            let (range_a, range_b) = ((minimum[a], maximum[a]), (minimum[b], maximum[b]));
+            // every side faces out
+            sides.add(AxisAlignedRect::new(axis, minimum[axis], range_a, range_b, material.clone()).flip_normal());
+            sides.add(AxisAlignedRect::new(axis, maximum[axis], range_a, range_b, material.clone()));
+        }
+        Cuboid {
+            minimum,
+            maximum,
+            sides
+        }
+    }
+}
+
+impl Hittable for Cuboid {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        self.sides.hit(ray, t_min, t_max)
+    }
+
+    fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<AABB> {
+        Some(AABB::new(self.minimum, self.maximum))
+    }
+
+    fn tessellate(&self) -> Vec<ExportMesh> {
+        self.sides.tessellate()
+    }
+
+    fn materials_mut(&mut self) -> Vec<&mut Arc<Material>> {
+        self.sides.materials_mut()
+    }
+}
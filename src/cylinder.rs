@@ -0,0 +1,205 @@
+use crate::vec3::*;
+use crate::Ray;
+use crate::hittable::*;
+use crate::material::*;
+use crate::sphere::Sphere;
+use crate::aabb::AABB;
+use crate::export::{ExportMaterial, ExportMesh, SPHERE_SEGMENTS};
+use crate::utilities::PI;
+use std::sync::Arc;
+
+// an upright cylinder standing on base, e.g. a can, a pillar or (without its
+// caps) a tube. wrap it in a Transform to tilt it
+pub struct Cylinder {
+    // the center of the bottom
+    base: Vec3,
+    radius: f64,
+    height: f64,
+    // whether the bottom and top are closed off
+    caps: (bool, bool),
+    material: Arc<Material>
+}
+
+impl Cylinder {
+    // closed at both ends
+    pub fn new(base: Vec3, radius: f64, height: f64, material: impl Into<Arc<Material>>) -> Cylinder {
+        Cylinder {
+            base,
+            radius,
+            height,
+            caps: (true, true),
+            material: material.into()
+        }
+    }
+
+    pub fn with_caps(mut self, bottom: bool, top: bool) -> Cylinder {
+        self.caps = (bottom, top);
+        self
+    }
+
+    fn side_hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<(f64, Vec3)> {
+        let origin = ray.origin - self.base;
+        let a = ray.direction.x() * ray.direction.x() + ray.direction.z() * ray.direction.z();
+        if a == 0.0 {
+            // straight up or down, along the side
+            return None
+        }
+        let half_b = origin.x() * ray.direction.x() + origin.z() * ray.direction.z();
+        let c = origin.x() * origin.x() + origin.z() * origin.z() - self.radius * self.radius;
+        let discriminant = half_b * half_b - a * c;
+        if discriminant < 0.0 {
+            return None
+        }
+        // the far side can still be hit when the near one is above or below
+        // the cylinder (looking into an open tube)
+        let root = discriminant.sqrt();
+        [(-half_b - root) / a, (-half_b + root) / a].iter().find_map(|&t| {
+            let y = origin.y() + t * ray.direction.y();
+            if t > t_min && t < t_max && (0.0..=self.height).contains(&y) {
+                Some((t, origin + ray.direction * t))
+            } else {
+                None
+            }
+        })
+    }
+
+    fn cap_hit(&self, ray: &Ray, y: f64, t_min: f64, t_max: f64) -> Option<(f64, Vec3)> {
+        let origin = ray.origin - self.base;
+        let t = (y - origin.y()) / ray.direction.y();
+        // also catches rays parallel to the cap, where t isn't a number
+        if !(t > t_min && t < t_max) {
+            return None
+        }
+        let local = origin + ray.direction * t;
+        if local.x() * local.x() + local.z() * local.z() > self.radius * self.radius {
+            return None
+        }
+        Some((t, local))
+    }
+}
+
+impl Hittable for Cylinder {
+    // the side's uvs wrap around like a sphere's (see Sphere::get_sphere_uv)
+    // with v going up. a cap's are the square around it seen from outside
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let mut closest = t_max;
+        let mut hit = None;
+        if let Some((t, local)) = self.side_hit(ray, t_min, closest) {
+            closest = t;
+            let outward_normal = Vec3::new(local.x(), 0.0, local.z()) / self.radius;
+            let (u, _) = Sphere::get_sphere_uv(outward_normal);
+            hit = Some((t, outward_normal, u, local.y() / self.height, Sphere::get_sphere_tangent(outward_normal)));
+        }
+        for (closed, y, facing) in [(self.caps.0, 0.0, -1.0), (self.caps.1, self.height, 1.0)] {
+            if !closed {
+                continue;
+            }
+            if let Some((t, local)) = self.cap_hit(ray, y, t_min, closest) {
+                closest = t;
+                let (u, v) = ((local.x() / self.radius + 1.0) / 2.0, (1.0 - facing * local.z() / self.radius) / 2.0);
+                hit = Some((t, Vec3::new(0.0, facing, 0.0), u, v, Vec3::new(1.0, 0.0, 0.0)));
+            }
+        }
+
+        let (t, outward_normal, u, v, tangent) = hit?;
+        let mut record = HitRecord::new(ray.at(t), outward_normal, t, u, v, false, &self.material);
+        record.set_face_normal(ray, &outward_normal);
+        record.tangent = Some(tangent);
+        Some(record)
+    }
+
+    fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<AABB> {
+        let radius = Vec3::new(self.radius, 0.0, self.radius);
+        Some(AABB::new(self.base - radius, self.base + radius + Vec3::new(0.0, self.height, 0.0)))
+    }
+
+    fn tessellate(&self) -> Vec<ExportMesh> {
+        let mut positions = Vec::new();
+        let mut normals = Vec::new();
+        let mut uvs = Vec::new();
+        let mut triangles = Vec::new();
+        let around = |segment: usize| {
+            let phi = segment as f64 / SPHERE_SEGMENTS as f64 * 2.0 * PI;
+            // inverse of get_sphere_uv at the equator
+            Vec3::new(-phi.cos(), 0.0, phi.sin())
+        };
+
+        // the side, with the seam duplicated (u = 0 and u = 1) so the uvs don't wrap
+        for segment in 0..=SPHERE_SEGMENTS {
+            let normal = around(segment);
+            let u = segment as f64 / SPHERE_SEGMENTS as f64;
+            for v in [0.0, 1.0] {
+                positions.push(self.base + normal * self.radius + Vec3::new(0.0, v * self.height, 0.0));
+                normals.push(normal);
+                uvs.push((u, v));
+            }
+            if segment < SPHERE_SEGMENTS {
+                let bottom = 2 * segment;
+                // counter clockwise seen from outside
+                triangles.push([bottom, bottom + 2, bottom + 1]);
+                triangles.push([bottom + 2, bottom + 3, bottom + 1]);
+            }
+        }
+
+        // each cap is a fan around its center, facing out
+        for (closed, y, facing) in [(self.caps.0, 0.0, -1.0), (self.caps.1, self.height, 1.0)] {
+            if !closed {
+                continue;
+            }
+            let center = positions.len();
+            let normal = Vec3::new(0.0, facing, 0.0);
+            positions.push(self.base + Vec3::new(0.0, y, 0.0));
+            normals.push(normal);
+            uvs.push((0.5, 0.5));
+            for segment in 0..SPHERE_SEGMENTS {
+                let offset = around(segment);
+                positions.push(self.base + offset * self.radius + Vec3::new(0.0, y, 0.0));
+                normals.push(normal);
+                uvs.push(((offset.x() + 1.0) / 2.0, (1.0 - facing * offset.z()) / 2.0));
+                let (current, next) = (center + 1 + segment, center + 1 + (segment + 1) % SPHERE_SEGMENTS);
+                triangles.push(if facing > 0.0 { [center, current, next] } else { [center, next, current] });
+            }
+        }
+
+        vec![ExportMesh {
+            positions,
+            normals,
+            uvs,
+            triangles,
+            material: ExportMaterial::from_material(&self.material)
+        }]
+    }
+
+    fn materials_mut(&mut self) -> Vec<&mut Arc<Material>> {
+        vec![&mut self.material]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cylinder_side_and_caps() {
+        let material = Material::Dielectric{index_of_refraction: 1.5, absorption: Color::new(0.0, 0.0, 0.0)};
+        let can = Cylinder::new(Vec3::new(0.0, 1.0, 0.0), 0.5, 2.0, material);
+
+        // from the side, a quarter of the way up
+        let record = can.hit(&Ray::new(Vec3::new(5.0, 1.5, 0.0), Vec3::new(-1.0, 0.0, 0.0), None), 0.001, f64::INFINITY).unwrap();
+        assert!((record.point - Vec3::new(0.5, 1.5, 0.0)).near_zero());
+        assert!((record.normal - Vec3::new(1.0, 0.0, 0.0)).near_zero());
+        assert!((record.v - 0.25).abs() < 1e-9 && (record.u - 0.5).abs() < 1e-9, "{} {}", record.u, record.v);
+
+        // from above it hits the top cap, or the inside of the far wall once it's open
+        let down = Ray::new(Vec3::new(0.2, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.2), None);
+        let record = can.hit(&down, 0.001, f64::INFINITY).unwrap();
+        assert!((record.normal - Vec3::new(0.0, 1.0, 0.0)).near_zero() && (record.point.y() - 3.0).abs() < 1e-9);
+        let tube = Cylinder::new(Vec3::new(0.0, 1.0, 0.0), 0.5, 2.0, Material::Dielectric{index_of_refraction: 1.5, absorption: Color::new(0.0, 0.0, 0.0)})
+            .with_caps(false, false);
+        let record = tube.hit(&down, 0.001, f64::INFINITY).unwrap();
+        assert!(!record.front_face && record.point.y() < 3.0 && record.point.y() > 1.0);
+
+        let bounds = can.bounding_box(0.0, 1.0).unwrap();
+        assert!((bounds.minimum - Vec3::new(-0.5, 1.0, -0.5)).near_zero() && (bounds.maximum - Vec3::new(0.5, 3.0, 0.5)).near_zero());
+    }
+}
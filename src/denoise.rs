@@ -0,0 +1,146 @@
+use crate::vec3::*;
+use crate::adaptive::perceived_brightness;
+use crate::aovs::AovFilm;
+use crate::restart::RenderState;
+use rayon::prelude::*;
+
+// a joint bilateral filter: each pixel becomes a weighted average of its
+// neighbours, where only neighbours that look like the same surface count.
+// that's judged by the aovs (albedo and normal), which are nearly noise free
+// even at a few samples per pixel, and by the colour, allowing for how noisy
+// each pixel's colour is. the colour is divided by the albedo first and
+// multiplied back after, so textures stay sharp and only the lighting is blurred.
+// not as good as a trained denoiser like open image denoise, but nothing to
+// install, and a low sample render comes out looking like a much longer one
+pub struct Denoiser {
+    // how far (in pixels) neighbours are taken from
+    pub radius: u32,
+    // how quickly neighbours count for less with distance, in pixels
+    pub spatial_sigma: f64,
+    // how different an albedo or a normal can be before it's another surface
+    pub albedo_sigma: f64,
+    pub normal_sigma: f64,
+    // how different the brightness can be, in standard errors of the two pixels
+    pub colour_sigma: f64
+}
+
+// albedos darker than this are taken as this, so black surfaces don't divide by 0
+const DARKEST_ALBEDO: f64 = 0.01;
+
+impl Denoiser {
+    pub fn new() -> Denoiser {
+        Denoiser {
+            radius: 5,
+            spatial_sigma: 3.0,
+            albedo_sigma: 0.1,
+            normal_sigma: 0.2,
+            colour_sigma: 3.0
+        }
+    }
+
+    pub fn with_radius(mut self, radius: u32) -> Denoiser {
+        self.radius = radius;
+        self.spatial_sigma = (radius as f64 * 0.6).max(0.5);
+        self
+    }
+
+    // every pixel of the render, denoised, rows from the top
+    pub fn denoise(&self, state: &RenderState, aovs: &AovFilm) -> Vec<Color> {
+        let (width, height) = (state.width as i64, state.height as i64);
+        let albedo = |pixel: usize| {
+            let albedo = aovs.albedo(pixel);
+            Color::new(albedo.x().max(DARKEST_ALBEDO), albedo.y().max(DARKEST_ALBEDO), albedo.z().max(DARKEST_ALBEDO))
+        };
+        let mean = |pixel: usize| state.pixels[pixel].colour();
+        // the variance of the pixel's mean brightness, none when it can't be
+        // told from 1 sample
+        let variance = |pixel: usize| {
+            let estimate = &state.pixels[pixel];
+            if estimate.count < 2 { None } else { Some(estimate.m2 / ((estimate.count - 1) * estimate.count) as f64) }
+        };
+        let radius = self.radius as i64;
+        (0..state.pixels.len()).into_par_iter().map(|pixel| {
+            let (x, y) = (pixel as i64 % width, pixel as i64 / width);
+            let (centre_albedo, centre_normal) = (albedo(pixel), aovs.normal(pixel));
+            let centre_brightness = perceived_brightness(&mean(pixel));
+            let centre_variance = variance(pixel);
+            let mut total = Color::new(0.0, 0.0, 0.0);
+            let mut total_weight = 0.0;
+            for ny in (y - radius).max(0)..(y + radius + 1).min(height) {
+                for nx in (x - radius).max(0)..(x + radius + 1).min(width) {
+                    let neighbour = (ny * width + nx) as usize;
+                    let distance_squared = ((nx - x) * (nx - x) + (ny - y) * (ny - y)) as f64;
+                    let neighbour_albedo = albedo(neighbour);
+                    let albedo_difference = (neighbour_albedo - centre_albedo).length_squared();
+                    let normal_difference = (aovs.normal(neighbour) - centre_normal).length_squared();
+                    let colour_term = match (centre_variance, variance(neighbour)) {
+                        (Some(a), Some(b)) => {
+                            let difference = perceived_brightness(&mean(neighbour)) - centre_brightness;
+                            difference * difference / (self.colour_sigma * self.colour_sigma * (a + b) + 1e-8)
+                        },
+                        _ => 0.0
+                    };
+                    let weight = (-distance_squared / (2.0 * self.spatial_sigma * self.spatial_sigma)
+                        - albedo_difference / (2.0 * self.albedo_sigma * self.albedo_sigma)
+                        - normal_difference / (2.0 * self.normal_sigma * self.normal_sigma)
+                        - colour_term / 2.0).exp();
+                    // the light arriving, without the surface's colour
+                    let irradiance = mean(neighbour) * Color::new(1.0 / neighbour_albedo.x(), 1.0 / neighbour_albedo.y(), 1.0 / neighbour_albedo.z());
+                    total = total + irradiance * weight;
+                    total_weight += weight;
+                }
+            }
+            // the pixel itself always counts fully, so the total isn't 0
+            total / total_weight * centre_albedo
+        }).collect()
+    }
+}
+
+impl Default for Denoiser {
+    fn default() -> Denoiser {
+        Denoiser::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aovs::{FirstHit, MaterialIds};
+    use crate::hittable_list::HittableList;
+    use crate::CameraSample;
+
+    #[test]
+    fn test_smooths_noise_but_keeps_edges() {
+        // a 16x8 image, the left half a dark surface and the right a bright
+        // one, both lit the same and noisy
+        let (width, height) = (16, 8);
+        let mut state = RenderState::new(width, height);
+        let mut aovs = AovFilm::new(width, height, MaterialIds::new(&mut HittableList::new()));
+        for pixel in 0..(width * height) as usize {
+            let albedo = if pixel as u32 % width < width / 2 { 0.2 } else { 0.8 };
+            for sample in 0..4 {
+                let noise = if (pixel * 7 + sample * 3) % 5 < 2 { 1.5 } else { 0.6 };
+                let colour = Color::new(albedo, albedo, albedo) * noise;
+                let first_hit = FirstHit{albedo: Color::new(albedo, albedo, albedo), normal: Vec3::new(0.0, 0.0, 1.0), depth: 1.0, material: 0};
+                state.pixels[pixel].add(colour);
+                aovs.add(pixel, &CameraSample{colour, foreground: colour, alpha: 1.0, layer: None, first_hit: Some(first_hit)});
+            }
+        }
+        let denoised = Denoiser::new().denoise(&state, &aovs);
+        let spread = |values: &mut dyn Iterator<Item = f64>| {
+            let values: Vec<f64> = values.collect();
+            let mean = values.iter().sum::<f64>() / values.len() as f64;
+            (mean, values.iter().map(|value| (value - mean).abs()).fold(0.0, f64::max))
+        };
+        let half = |image: &[Color], right: bool| spread(&mut (0..(width * height) as usize)
+            .filter(move |pixel| (*pixel as u32 % width >= width / 2) == right).map(move |pixel| image[pixel].x()));
+        let noisy: Vec<Color> = state.pixels.iter().map(|estimate| estimate.sum / estimate.count as f64).collect();
+        for right in [false, true].iter() {
+            let (noisy_mean, noisy_spread) = half(&noisy, *right);
+            let (mean, spread) = half(&denoised, *right);
+            assert!(spread < noisy_spread * 0.5, "{} {}", spread, noisy_spread);
+            // the halves didn't bleed into each other
+            assert!((mean - noisy_mean).abs() < 0.05 * noisy_mean, "{} {}", mean, noisy_mean);
+        }
+    }
+}
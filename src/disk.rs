@@ -0,0 +1,125 @@
+use crate::vec3::*;
+use crate::Ray;
+use crate::hittable::*;
+use crate::material::*;
+use crate::aabb::AABB;
+use crate::export::{ExportMaterial, ExportMesh, SPHERE_SEGMENTS};
+use crate::utilities::PI;
+use std::sync::Arc;
+
+// a flat circle facing any direction, e.g. a round area light (with a
+// DiffuseLight material), a table top or a patch of ground that ends
+pub struct Disk {
+    center: Vec3,
+    // unit length, the side the front faces
+    normal: Vec3,
+    radius: f64,
+    // the directions u and v grow in across the disk
+    tangent: Vec3,
+    bitangent: Vec3,
+    material: Arc<Material>
+}
+
+impl Disk {
+    pub fn new(center: Vec3, normal: Vec3, radius: f64, material: impl Into<Arc<Material>>) -> Disk {
+        if normal.near_zero() {
+            panic!("A disk needs a normal to face along");
+        }
+        let normal = normal.unit_vector();
+        let helper = if normal.x().abs() > 0.9 { Vec3::new(0.0, 1.0, 0.0) } else { Vec3::new(1.0, 0.0, 0.0) };
+        let bitangent = normal.cross_product(&helper).unit_vector();
+        Disk {
+            center,
+            normal,
+            radius,
+            tangent: bitangent.cross_product(&normal),
+            bitangent,
+            material: material.into()
+        }
+    }
+
+    // the point at (u, v) on the square around the disk
+    fn point(&self, u: f64, v: f64) -> Vec3 {
+        self.center + self.tangent * ((2.0 * u - 1.0) * self.radius) + self.bitangent * ((2.0 * v - 1.0) * self.radius)
+    }
+}
+
+impl Hittable for Disk {
+    // the uvs are the square around the disk, so a square image fits it exactly
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let t = (self.center - ray.origin).dot_product(&self.normal) / ray.direction.dot_product(&self.normal);
+        // also catches rays parallel to the disk, where t isn't a number
+        if !(t > t_min && t < t_max) {
+            return None
+        }
+        let point = ray.at(t);
+        let offset = point - self.center;
+        if offset.length_squared() > self.radius * self.radius {
+            return None
+        }
+
+        let u = (offset.dot_product(&self.tangent) / self.radius + 1.0) / 2.0;
+        let v = (offset.dot_product(&self.bitangent) / self.radius + 1.0) / 2.0;
+        let mut record = HitRecord::new(point, self.normal, t, u, v, false, &self.material);
+        record.set_face_normal(ray, &self.normal);
+        record.tangent = Some(self.tangent);
+        Some(record)
+    }
+
+    fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<AABB> {
+        // how far the rim reaches along each axis, padded since a box can't
+        // have 0 thickness
+        let padding = 0.0001;
+        let reach = |n: f64| self.radius * (1.0 - n * n).max(0.0).sqrt() + padding;
+        let extent = Vec3::new(reach(self.normal.x()), reach(self.normal.y()), reach(self.normal.z()));
+        Some(AABB::new(self.center - extent, self.center + extent))
+    }
+
+    fn tessellate(&self) -> Vec<ExportMesh> {
+        // a fan around the center, counter clockwise seen from the front
+        let mut positions = vec![self.center];
+        let mut uvs = vec![(0.5, 0.5)];
+        let mut triangles = Vec::new();
+        for segment in 0..SPHERE_SEGMENTS {
+            let angle = segment as f64 / SPHERE_SEGMENTS as f64 * 2.0 * PI;
+            let (u, v) = ((angle.cos() + 1.0) / 2.0, (angle.sin() + 1.0) / 2.0);
+            positions.push(self.point(u, v));
+            uvs.push((u, v));
+            triangles.push([0, 1 + segment, 1 + (segment + 1) % SPHERE_SEGMENTS]);
+        }
+
+        vec![ExportMesh {
+            normals: vec![self.normal; positions.len()],
+            positions,
+            uvs,
+            triangles,
+            material: ExportMaterial::from_material(&self.material)
+        }]
+    }
+
+    fn materials_mut(&mut self) -> Vec<&mut Arc<Material>> {
+        vec![&mut self.material]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disk_hit() {
+        let light = Material::DiffuseLight{emit: Box::new(crate::texture::SolidTexture::new(Color::new(4.0, 4.0, 4.0)))};
+        let disk = Disk::new(Vec3::new(0.0, 3.0, 0.0), Vec3::new(0.0, -2.0, 0.0), 1.0, light);
+
+        // from below, through the center and just inside the rim
+        let record = disk.hit(&Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0), None), 0.001, f64::INFINITY).unwrap();
+        assert!((record.point - Vec3::new(0.0, 3.0, 0.0)).near_zero() && record.front_face);
+        assert!((record.u - 0.5).abs() < 1e-9 && (record.v - 0.5).abs() < 1e-9);
+        assert!(disk.hit(&Ray::new(Vec3::new(0.7, 0.0, 0.7), Vec3::new(0.0, 1.0, 0.0), None), 0.001, f64::INFINITY).is_some());
+        // the corner of the square around it misses
+        assert!(disk.hit(&Ray::new(Vec3::new(0.8, 0.0, 0.8), Vec3::new(0.0, 1.0, 0.0), None), 0.001, f64::INFINITY).is_none());
+
+        let bounds = disk.bounding_box(0.0, 1.0).unwrap();
+        assert!((bounds.maximum.x() - 1.0001).abs() < 1e-9 && (bounds.maximum.y() - 3.0001).abs() < 1e-9);
+    }
+}
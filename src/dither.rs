@@ -0,0 +1,66 @@
+use crate::blue_noise;
+
+// rounding to 8 bits turns a smooth gradient (e.g. the sky) into bands of flat
+// colour one step apart, which the eye picks out easily. dithering adds a
+// different offset of less than a step to each pixel before rounding, so the
+// edges between bands break up into a fine pattern that averages out to the
+// right value and doesn't show
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Dither {
+    // an 8x8 bayer matrix: a regular cross-hatch, the least noisy
+    Ordered,
+    // the blue noise tile renders sample with (see blue_noise.rs): no visible
+    // pattern, and grain too fine to notice
+    BlueNoise
+}
+
+// the order pixels of an 8x8 block are switched on in, so every fraction of a
+// step is spread as evenly as it can be
+const BAYER: [[u8; 8]; 8] = [
+    [0, 32, 8, 40, 2, 34, 10, 42],
+    [48, 16, 56, 24, 50, 18, 58, 26],
+    [12, 44, 4, 36, 14, 46, 6, 38],
+    [60, 28, 52, 20, 62, 30, 54, 22],
+    [3, 35, 11, 43, 1, 33, 9, 41],
+    [51, 19, 59, 27, 49, 17, 57, 25],
+    [15, 47, 7, 39, 13, 45, 5, 37],
+    [63, 31, 55, 23, 61, 29, 53, 21]
+];
+
+impl Dither {
+    pub fn parse(name: &str) -> Option<Dither> {
+        match name {
+            "ordered" | "bayer" => Some(Dither::Ordered),
+            "blue-noise" => Some(Dither::BlueNoise),
+            _ => None
+        }
+    }
+
+    // the offset for pixel (x, y), in (-0.5, 0.5) of a step
+    pub fn offset(self, x: u32, y: u32) -> f64 {
+        let value = match self {
+            Dither::Ordered => (BAYER[y as usize % 8][x as usize % 8] as f64 + 0.5) / 64.0,
+            Dither::BlueNoise => blue_noise::tile().value(x, y)
+        };
+        value - 0.5
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_offsets_average_out() {
+        for dither in [Dither::Ordered, Dither::BlueNoise].iter() {
+            let offsets: Vec<f64> = (0..64).flat_map(|y| (0..64).map(move |x| dither.offset(x, y))).collect();
+            let mean = offsets.iter().sum::<f64>() / offsets.len() as f64;
+            assert!(mean.abs() < 1e-9, "{:?} {}", dither, mean);
+            assert!(offsets.iter().all(|offset| offset.abs() < 0.5));
+        }
+        // a value a quarter of the way between two steps rounds up in a quarter of the pixels
+        let up = (0..8).flat_map(|y| (0..8).map(move |x| (0.25 + Dither::Ordered.offset(x, y)).round())).sum::<f64>();
+        assert_eq!(up, 16.0);
+    }
+}
@@ -0,0 +1,220 @@
+use crate::vec3::*;
+use crate::sphere::Sphere;
+use crate::utilities::clamp;
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+use crate::mapped::MappedFile;
+
+// environment maps store the light arriving from every direction. there are two
+// common layouts:
+// - latitude/longitude (equirectangular): one image, u goes around the horizon
+//   and v from the bottom to the top. easy to author but finding a direction's
+//   pixel needs trigonometry, and the poles are heavily oversampled
+// - cube map: six square images on the faces of a cube around the viewer.
+//   lookups are just a division, and pixels cover similar solid angles
+// the lat/long layout uses the same convention as Sphere::get_sphere_uv so an
+// environment and a textured sphere line up
+
+// the order of the faces in a cube map (the usual +x, -x, +y, -y, +z, -z)
+pub const CUBE_FACE_NAMES: [&str; 6] = ["px", "nx", "py", "ny", "pz", "nz"];
+
+fn read_image(path: &Path) -> Result<(usize, usize, Vec<Color>)> {
+    let image = image::load_from_memory(&MappedFile::open(path)?)
+        .map_err(|e| Error::new(ErrorKind::InvalidData, format!("{}: {}", path.display(), e)))?
+        .into_rgb8();
+    let (width, height) = image.dimensions();
+    let pixels = image.pixels().map(|p| {
+        Color::new(p[0] as f64 / 255.0, p[1] as f64 / 255.0, p[2] as f64 / 255.0)
+    }).collect();
+    Ok((width as usize, height as usize, pixels))
+}
+
+fn write_image(path: &Path, width: usize, height: usize, pixels: &[Color]) -> Result<()> {
+    let mut image = image::RgbImage::new(width as u32, height as u32);
+    for (i, pixel) in image.pixels_mut().enumerate() {
+        let c = pixels[i];
+        let quantize = |value: f64| (255.0 * clamp(value, 0.0, 1.0)).round() as u8;
+        *pixel = image::Rgb([quantize(c.x()), quantize(c.y()), quantize(c.z())]);
+    }
+    image.save(path).map_err(|e| Error::other(format!("{}: {}", path.display(), e)))
+}
+
+// blends the 4 pixel centers around (x, y), given in pixels. x either wraps
+// around (lat/long maps) or is clamped like y
+fn bilinear(pixels: &[Color], width: usize, height: usize, x: f64, y: f64, wrap_x: bool) -> Color {
+    let x = x - 0.5;
+    let y = y - 0.5;
+    let (s, t) = (x - x.floor(), y - y.floor());
+    let (i, j) = (x.floor() as i64, y.floor() as i64);
+    let pixel = |i: i64, j: i64| {
+        let i = if wrap_x { i.rem_euclid(width as i64) } else { i.clamp(0, width as i64 - 1) } as usize;
+        let j = j.clamp(0, height as i64 - 1) as usize;
+        pixels[j * width + i]
+    };
+    let top = pixel(i, j).lerp(&pixel(i + 1, j), s);
+    let bottom = pixel(i, j + 1).lerp(&pixel(i + 1, j + 1), s);
+    top.lerp(&bottom, t)
+}
+
+pub struct LatLongMap {
+    width: usize,
+    height: usize,
+    // row by row, starting at the top left
+    pixels: Vec<Color>
+}
+
+impl LatLongMap {
+    pub fn new(width: usize, height: usize, pixels: Vec<Color>) -> LatLongMap {
+        if width == 0 || height == 0 || pixels.len() != width * height {
+            panic!("Image needs {}x{} pixels, got {}", width, height, pixels.len());
+        }
+        LatLongMap {
+            width,
+            height,
+            pixels
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<LatLongMap> {
+        let (width, height, pixels) = read_image(path)?;
+        Ok(LatLongMap::new(width, height, pixels))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        write_image(path, self.width, self.height, &self.pixels)
+    }
+
+    // the light coming from the given direction (doesn't need to be a unit vector)
+    pub fn sample(&self, direction: &Vec3) -> Color {
+        let (u, v) = Sphere::get_sphere_uv(direction.unit_vector());
+        bilinear(&self.pixels, self.width, self.height, u * self.width as f64, (1.0 - v) * self.height as f64, true)
+    }
+
+    // resamples into a cube map with faces of size x size pixels
+    pub fn to_cube_map(&self, size: usize) -> CubeMap {
+        let faces: Vec<Vec<Color>> = (0..6).map(|face| {
+            let mut pixels = Vec::with_capacity(size * size);
+            for j in 0..size {
+                for i in 0..size {
+                    let s = (i as f64 + 0.5) / size as f64;
+                    let t = (j as f64 + 0.5) / size as f64;
+                    pixels.push(self.sample(&CubeMap::face_direction(face, s, t)));
+                }
+            }
+            pixels
+        }).collect();
+        CubeMap::new(size, faces)
+    }
+}
+
+pub struct CubeMap {
+    size: usize,
+    // in the order of CUBE_FACE_NAMES, each row by row from the top left
+    faces: Vec<Vec<Color>>
+}
+
+impl CubeMap {
+    pub fn new(size: usize, faces: Vec<Vec<Color>>) -> CubeMap {
+        if size == 0 || faces.len() != 6 || faces.iter().any(|face| face.len() != size * size) {
+            panic!("Cube map needs 6 faces of {}x{} pixels", size, size);
+        }
+        CubeMap {
+            size,
+            faces
+        }
+    }
+
+    // loads <prefix>_px.png, <prefix>_nx.png etc.
+    pub fn load(directory: &Path, prefix: &str) -> Result<CubeMap> {
+        let mut faces = Vec::new();
+        let mut size = 0;
+        for name in CUBE_FACE_NAMES.iter() {
+            let (width, height, pixels) = read_image(&directory.join(format!("{}_{}.png", prefix, name)))?;
+            if width != height || (size != 0 && width != size) {
+                return Err(Error::new(ErrorKind::InvalidData, "cube map faces must be squares of the same size"))
+            }
+            size = width;
+            faces.push(pixels);
+        }
+        Ok(CubeMap::new(size, faces))
+    }
+
+    // writes <prefix>_px.png, <prefix>_nx.png etc.
+    pub fn save(&self, directory: &Path, prefix: &str) -> Result<()> {
+        for (face, name) in CUBE_FACE_NAMES.iter().enumerate() {
+            write_image(&directory.join(format!("{}_{}.png", prefix, name)), self.size, self.size, &self.faces[face])?;
+        }
+        Ok(())
+    }
+
+    // the direction through the point (s, t) of a face, where (0, 0) is the
+    // top left corner of the face's image and (1, 1) the bottom right
+    pub fn face_direction(face: usize, s: f64, t: f64) -> Vec3 {
+        let a = 2.0 * s - 1.0;
+        let b = 2.0 * t - 1.0;
+        match face {
+            0 => Vec3::new(1.0, -b, -a),
+            1 => Vec3::new(-1.0, -b, a),
+            2 => Vec3::new(a, 1.0, b),
+            3 => Vec3::new(a, -1.0, -b),
+            4 => Vec3::new(a, -b, 1.0),
+            _ => Vec3::new(-a, -b, -1.0)
+        }
+    }
+
+    // the inverse of face_direction: which face a direction points at and where on it
+    pub fn direction_to_face(direction: &Vec3) -> (usize, f64, f64) {
+        let (x, y, z) = (direction.x(), direction.y(), direction.z());
+        let (ax, ay, az) = (x.abs(), y.abs(), z.abs());
+        // the axis with the biggest component picks the face
+        let (face, sc, tc, major) = if ax >= ay && ax >= az {
+            if x > 0.0 { (0, -z, -y, ax) } else { (1, z, -y, ax) }
+        } else if ay >= az {
+            if y > 0.0 { (2, x, z, ay) } else { (3, x, -z, ay) }
+        } else if z > 0.0 {
+            (4, x, -y, az)
+        } else {
+            (5, -x, -y, az)
+        };
+        (face, 0.5 * (sc / major + 1.0), 0.5 * (tc / major + 1.0))
+    }
+
+    pub fn sample(&self, direction: &Vec3) -> Color {
+        let (face, s, t) = CubeMap::direction_to_face(direction);
+        bilinear(&self.faces[face], self.size, self.size, s * self.size as f64, t * self.size as f64, false)
+    }
+
+    // resamples into a lat/long map of the given size
+    pub fn to_lat_long(&self, width: usize, height: usize) -> LatLongMap {
+        let mut pixels = Vec::with_capacity(width * height);
+        for j in 0..height {
+            for i in 0..width {
+                let u = (i as f64 + 0.5) / width as f64;
+                let v = 1.0 - (j as f64 + 0.5) / height as f64;
+                pixels.push(self.sample(&lat_long_direction(u, v)));
+            }
+        }
+        LatLongMap::new(width, height, pixels)
+    }
+}
+
+// the inverse of Sphere::get_sphere_uv, the unit direction for a (u, v)
+pub fn lat_long_direction(u: f64, v: f64) -> Vec3 {
+    let theta = v * std::f64::consts::PI;
+    let phi = u * 2.0 * std::f64::consts::PI;
+    Vec3::new(-phi.cos() * theta.sin(), -theta.cos(), phi.sin() * theta.sin())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_face_direction_round_trip() {
+        for face in 0..6 {
+            let (found, s, t) = CubeMap::direction_to_face(&CubeMap::face_direction(face, 0.25, 0.75));
+            assert_eq!(found, face);
+            assert!((s - 0.25).abs() < 1e-9 && (t - 0.75).abs() < 1e-9);
+        }
+    }
+}
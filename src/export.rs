@@ -0,0 +1,400 @@
+use crate::Vec3;
+use crate::material::Material;
+use crate::utilities::PI;
+use std::fs::File;
+use std::io::{Result, Write};
+use std::path::Path;
+
+// exporting scenes as meshes so they can be opened in other tools (e.g. blender).
+// analytic primitives don't have vertices, so each one is tessellated into a
+// triangle mesh first. only a basic approximation of the material is kept since
+// textures are procedural and can't be expressed in OBJ/glTF
+
+// segments around the equator (and half as many from pole to pole) used when
+// tessellating a sphere
+pub const SPHERE_SEGMENTS: usize = 32;
+
+// a simplified, tool-agnostic description of a material
+#[derive(Copy, Clone)]
+pub struct ExportMaterial {
+    pub base_color: Vec3,
+    // 0 -> dielectric/diffuse, 1 -> metal
+    pub metallic: f64,
+    // 0 -> mirror-like, 1 -> fully rough
+    pub roughness: f64,
+    // only meaningful when transmission is 1 (glass)
+    pub index_of_refraction: f64,
+    pub transmission: f64,
+    // light given off, can be brighter than 1
+    pub emission: Vec3
+}
+
+impl ExportMaterial {
+    pub fn from_material(material: &Material) -> ExportMaterial {
+        match material {
+            // textures are evaluated at a single point, the best a flat colour can do
+            Material::Lambertian{albedo, ..} => ExportMaterial {
+                base_color: albedo.value(0.5, 0.5, &Vec3::new(0.0, 0.0, 0.0)),
+                metallic: 0.0,
+                roughness: 1.0,
+                index_of_refraction: 1.5,
+                transmission: 0.0,
+                emission: Vec3::new(0.0, 0.0, 0.0)
+            },
+            Material::Metal{albedo, fuzz, ..} => ExportMaterial {
+                base_color: albedo.value(0.5, 0.5, &Vec3::new(0.0, 0.0, 0.0)),
+                metallic: 1.0,
+                roughness: fuzz.value(0.5, 0.5, &Vec3::new(0.0, 0.0, 0.0)).x().min(1.0),
+                index_of_refraction: 1.5,
+                transmission: 0.0,
+                emission: Vec3::new(0.0, 0.0, 0.0)
+            },
+            // the tint after a unit of glass
+            Material::Dielectric{index_of_refraction, absorption} => ExportMaterial {
+                base_color: Vec3::new((-absorption.x()).exp(), (-absorption.y()).exp(), (-absorption.z()).exp()),
+                metallic: 0.0,
+                roughness: 0.0,
+                index_of_refraction: *index_of_refraction,
+                transmission: 1.0,
+                emission: Vec3::new(0.0, 0.0, 0.0)
+            },
+            // a measured metal's colour is how it reflects head on
+            Material::Principled{base_color, metallic, roughness, index_of_refraction, conductor, ..} => ExportMaterial {
+                base_color: conductor.map_or(Vec3::new(1.0, 1.0, 1.0), |conductor| conductor.fresnel(1.0))
+                    * base_color.value(0.5, 0.5, &Vec3::new(0.0, 0.0, 0.0)),
+                metallic: *metallic,
+                roughness: *roughness,
+                index_of_refraction: *index_of_refraction,
+                transmission: 0.0,
+                emission: Vec3::new(0.0, 0.0, 0.0)
+            },
+            // a flat colour can only be an average of the two
+            Material::Mix{a, b, factor} => {
+                let t = factor.value(0.5, 0.5, &Vec3::new(0.0, 0.0, 0.0)).x().clamp(0.0, 1.0);
+                let (a, b) = (ExportMaterial::from_material(a), ExportMaterial::from_material(b));
+                let lerp = |x: f64, y: f64| x * (1.0 - t) + y * t;
+                ExportMaterial {
+                    base_color: a.base_color.lerp(&b.base_color, t),
+                    metallic: lerp(a.metallic, b.metallic),
+                    roughness: lerp(a.roughness, b.roughness),
+                    index_of_refraction: lerp(a.index_of_refraction, b.index_of_refraction),
+                    transmission: lerp(a.transmission, b.transmission),
+                    emission: a.emission.lerp(&b.emission, t)
+                }
+            },
+            // exported as the ground it stands in for
+            Material::ShadowCatcher{albedo} => ExportMaterial {
+                base_color: albedo.value(0.5, 0.5, &Vec3::new(0.0, 0.0, 0.0)),
+                metallic: 0.0,
+                roughness: 1.0,
+                index_of_refraction: 1.5,
+                transmission: 0.0,
+                emission: Vec3::new(0.0, 0.0, 0.0)
+            },
+            // only found inside volumes, which aren't exported
+            Material::Isotropic{albedo} => ExportMaterial {
+                base_color: albedo.value(0.5, 0.5, &Vec3::new(0.0, 0.0, 0.0)),
+                metallic: 0.0,
+                roughness: 1.0,
+                index_of_refraction: 1.5,
+                transmission: 0.0,
+                emission: Vec3::new(0.0, 0.0, 0.0)
+            },
+            Material::DiffuseLight{emit} => ExportMaterial {
+                base_color: Vec3::new(0.0, 0.0, 0.0),
+                metallic: 0.0,
+                roughness: 1.0,
+                index_of_refraction: 1.5,
+                transmission: 0.0,
+                emission: emit.value(0.5, 0.5, &Vec3::new(0.0, 0.0, 0.0))
+            }
+        }
+    }
+}
+
+// an indexed triangle mesh. positions, normals and uvs are per vertex
+pub struct ExportMesh {
+    pub positions: Vec<Vec3>,
+    pub normals: Vec<Vec3>,
+    pub uvs: Vec<(f64, f64)>,
+    pub triangles: Vec<[usize; 3]>,
+    pub material: ExportMaterial
+}
+
+impl ExportMesh {
+    // builds a uv-sphere. the vertex uvs follow the same convention as Sphere::get_sphere_uv
+    pub fn uv_sphere(center: Vec3, radius: f64, material: ExportMaterial) -> ExportMesh {
+        let rings = SPHERE_SEGMENTS / 2;
+        let mut positions = Vec::new();
+        let mut normals = Vec::new();
+        let mut uvs = Vec::new();
+        let mut triangles = Vec::new();
+
+        // the seam column is duplicated (u = 0 and u = 1) so the uvs don't wrap
+        for ring in 0..=rings {
+            let v = ring as f64 / rings as f64;
+            let theta = v * PI;
+            for segment in 0..=SPHERE_SEGMENTS {
+                let u = segment as f64 / SPHERE_SEGMENTS as f64;
+                let phi = u * 2.0 * PI;
+                // inverse of get_sphere_uv
+                let normal = Vec3::new(-phi.cos() * theta.sin(), -theta.cos(), phi.sin() * theta.sin());
+                positions.push(center + normal * radius);
+                normals.push(normal);
+                uvs.push((u, v));
+            }
+        }
+
+        let columns = SPHERE_SEGMENTS + 1;
+        for ring in 0..rings {
+            for segment in 0..SPHERE_SEGMENTS {
+                let current = ring * columns + segment;
+                let above = current + columns;
+                // counter-clockwise seen from outside, as obj and gltf expect
+                triangles.push([current, current + 1, above]);
+                triangles.push([current + 1, above + 1, above]);
+            }
+        }
+
+        ExportMesh {
+            positions,
+            normals,
+            uvs,
+            triangles,
+            material
+        }
+    }
+}
+
+// wavefront OBJ, with the materials written to a sibling .mtl file
+pub fn write_obj(path: &Path, meshes: &[ExportMesh]) -> Result<()> {
+    let mtl_path = path.with_extension("mtl");
+    let mtl_name = mtl_path.file_name().unwrap().to_string_lossy().into_owned();
+    let mut obj = File::create(path)?;
+    let mut mtl = File::create(&mtl_path)?;
+
+    writeln!(obj, "mtllib {}", mtl_name)?;
+    // obj indices are global across the file and start at 1
+    let mut offset = 1;
+    for (i, mesh) in meshes.iter().enumerate() {
+        let material = &mesh.material;
+        writeln!(mtl, "newmtl material_{}", i)?;
+        writeln!(mtl, "Kd {} {} {}", material.base_color.x(), material.base_color.y(), material.base_color.z())?;
+        if material.metallic > 0.0 {
+            writeln!(mtl, "Ks {} {} {}", material.base_color.x(), material.base_color.y(), material.base_color.z())?;
+        } else {
+            writeln!(mtl, "Ks 0 0 0")?;
+        }
+        // map roughness onto a phong exponent (rough -> small, shiny -> large)
+        writeln!(mtl, "Ns {}", (1.0 - material.roughness) * (1.0 - material.roughness) * 1000.0)?;
+        writeln!(mtl, "Ni {}", material.index_of_refraction)?;
+        writeln!(mtl, "d {}", 1.0 - material.transmission)?;
+        writeln!(mtl, "Ke {} {} {}", material.emission.x(), material.emission.y(), material.emission.z())?;
+        writeln!(mtl, "illum {}\n", if material.transmission > 0.0 { 7 } else if material.metallic > 0.0 { 3 } else { 2 })?;
+
+        writeln!(obj, "o object_{}", i)?;
+        writeln!(obj, "usemtl material_{}", i)?;
+        for p in mesh.positions.iter() {
+            writeln!(obj, "v {} {} {}", p.x(), p.y(), p.z())?;
+        }
+        for (u, v) in mesh.uvs.iter() {
+            writeln!(obj, "vt {} {}", u, v)?;
+        }
+        for n in mesh.normals.iter() {
+            writeln!(obj, "vn {} {} {}", n.x(), n.y(), n.z())?;
+        }
+        for triangle in mesh.triangles.iter() {
+            let [a, b, c] = [triangle[0] + offset, triangle[1] + offset, triangle[2] + offset];
+            writeln!(obj, "f {0}/{0}/{0} {1}/{1}/{1} {2}/{2}/{2}", a, b, c)?;
+        }
+        offset += mesh.positions.len();
+    }
+    Ok(())
+}
+
+// glTF 2.0 with the binary buffer embedded as a base64 data uri, so the scene
+// is a single self contained file
+pub fn write_gltf(path: &Path, meshes: &[ExportMesh]) -> Result<()> {
+    let mut buffer: Vec<u8> = Vec::new();
+    let mut buffer_views = Vec::new();
+    let mut accessors = Vec::new();
+    let mut gltf_meshes = Vec::new();
+    let mut materials = Vec::new();
+    let mut nodes = Vec::new();
+    // only what a material uses, so viewers without them don't refuse the file
+    let mut extensions_used: Vec<&str> = Vec::new();
+
+    for (i, mesh) in meshes.iter().enumerate() {
+        let first_accessor = accessors.len();
+        let vertex_count = mesh.positions.len();
+
+        // positions need their bounds recorded in the accessor
+        let mut min = [f32::INFINITY; 3];
+        let mut max = [f32::NEG_INFINITY; 3];
+        let start = buffer.len();
+        for p in mesh.positions.iter() {
+            for (axis, value) in [p.x() as f32, p.y() as f32, p.z() as f32].iter().enumerate() {
+                min[axis] = min[axis].min(*value);
+                max[axis] = max[axis].max(*value);
+                buffer.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        buffer_views.push(format!("{{\"buffer\":0,\"byteOffset\":{},\"byteLength\":{},\"target\":34962}}", start, buffer.len() - start));
+        accessors.push(format!("{{\"bufferView\":{},\"componentType\":5126,\"count\":{},\"type\":\"VEC3\",\"min\":[{},{},{}],\"max\":[{},{},{}]}}",
+            buffer_views.len() - 1, vertex_count, min[0], min[1], min[2], max[0], max[1], max[2]));
+
+        let start = buffer.len();
+        for n in mesh.normals.iter() {
+            for value in [n.x() as f32, n.y() as f32, n.z() as f32].iter() {
+                buffer.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        buffer_views.push(format!("{{\"buffer\":0,\"byteOffset\":{},\"byteLength\":{},\"target\":34962}}", start, buffer.len() - start));
+        accessors.push(format!("{{\"bufferView\":{},\"componentType\":5126,\"count\":{},\"type\":\"VEC3\"}}", buffer_views.len() - 1, vertex_count));
+
+        // gltf puts the uv origin at the top left, hence the flipped v
+        let start = buffer.len();
+        for (u, v) in mesh.uvs.iter() {
+            buffer.extend_from_slice(&(*u as f32).to_le_bytes());
+            buffer.extend_from_slice(&(1.0 - *v as f32).to_le_bytes());
+        }
+        buffer_views.push(format!("{{\"buffer\":0,\"byteOffset\":{},\"byteLength\":{},\"target\":34962}}", start, buffer.len() - start));
+        accessors.push(format!("{{\"bufferView\":{},\"componentType\":5126,\"count\":{},\"type\":\"VEC2\"}}", buffer_views.len() - 1, vertex_count));
+
+        let start = buffer.len();
+        for triangle in mesh.triangles.iter() {
+            for index in triangle.iter() {
+                buffer.extend_from_slice(&(*index as u32).to_le_bytes());
+            }
+        }
+        buffer_views.push(format!("{{\"buffer\":0,\"byteOffset\":{},\"byteLength\":{},\"target\":34963}}", start, buffer.len() - start));
+        accessors.push(format!("{{\"bufferView\":{},\"componentType\":5125,\"count\":{},\"type\":\"SCALAR\"}}", buffer_views.len() - 1, mesh.triangles.len() * 3));
+
+        let material = &mesh.material;
+        let mut material_json = format!("{{\"name\":\"material_{}\",\"pbrMetallicRoughness\":{{\"baseColorFactor\":[{},{},{},1.0],\"metallicFactor\":{},\"roughnessFactor\":{}}}",
+            i, material.base_color.x(), material.base_color.y(), material.base_color.z(), material.metallic, material.roughness);
+        // emissive factors stop at 1, anything brighter goes in the strength
+        let strength = material.emission.max_component();
+        if strength > 0.0 {
+            let factor = material.emission / strength.max(1.0);
+            material_json.push_str(&format!(",\"emissiveFactor\":[{},{},{}]", factor.x(), factor.y(), factor.z()));
+        }
+        if material.transmission > 0.0 {
+            material_json.push_str(&format!(",\"extensions\":{{\"KHR_materials_transmission\":{{\"transmissionFactor\":{}}},\"KHR_materials_ior\":{{\"ior\":{}}}}}",
+                material.transmission, material.index_of_refraction));
+            extensions_used.extend(["KHR_materials_transmission", "KHR_materials_ior"]);
+        } else if strength > 1.0 {
+            material_json.push_str(&format!(",\"extensions\":{{\"KHR_materials_emissive_strength\":{{\"emissiveStrength\":{}}}}}", strength));
+            extensions_used.push("KHR_materials_emissive_strength");
+        }
+        material_json.push('}');
+        materials.push(material_json);
+
+        gltf_meshes.push(format!("{{\"name\":\"object_{0}\",\"primitives\":[{{\"attributes\":{{\"POSITION\":{1},\"NORMAL\":{2},\"TEXCOORD_0\":{3}}},\"indices\":{4},\"material\":{0}}}]}}",
+            i, first_accessor, first_accessor + 1, first_accessor + 2, first_accessor + 3));
+        nodes.push(format!("{{\"name\":\"object_{0}\",\"mesh\":{0}}}", i));
+    }
+
+    let node_indices: Vec<String> = (0..nodes.len()).map(|i| i.to_string()).collect();
+    let mut file = File::create(path)?;
+    write!(file, "{{\"asset\":{{\"version\":\"2.0\",\"generator\":\"rays\"}},")?;
+    extensions_used.sort_unstable();
+    extensions_used.dedup();
+    if !extensions_used.is_empty() {
+        let names: Vec<String> = extensions_used.iter().map(|name| format!("\"{}\"", name)).collect();
+        write!(file, "\"extensionsUsed\":[{}],", names.join(","))?;
+    }
+    write!(file, "\"scene\":0,\"scenes\":[{{\"nodes\":[{}]}}],", node_indices.join(","))?;
+    write!(file, "\"nodes\":[{}],", nodes.join(","))?;
+    write!(file, "\"meshes\":[{}],", gltf_meshes.join(","))?;
+    write!(file, "\"materials\":[{}],", materials.join(","))?;
+    write!(file, "\"accessors\":[{}],", accessors.join(","))?;
+    write!(file, "\"bufferViews\":[{}],", buffer_views.join(","))?;
+    writeln!(file, "\"buffers\":[{{\"byteLength\":{},\"uri\":\"data:application/octet-stream;base64,{}\"}}]}}", buffer.len(), base64_encode(&buffer))?;
+    Ok(())
+}
+
+// picks the format from the file extension (.gltf, anything else is OBJ)
+pub fn write_scene(path: &Path, meshes: &[ExportMesh]) -> Result<()> {
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some("gltf") => write_gltf(path, meshes),
+        _ => write_obj(path, meshes)
+    }
+}
+
+fn base64_encode(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut result = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let b0 = chunk[0] as u32;
+        let b1 = *chunk.get(1).unwrap_or(&0) as u32;
+        let b2 = *chunk.get(2).unwrap_or(&0) as u32;
+        let triple = (b0 << 16) | (b1 << 8) | b2;
+        result.push(ALPHABET[(triple >> 18) as usize & 63] as char);
+        result.push(ALPHABET[(triple >> 12) as usize & 63] as char);
+        result.push(if chunk.len() > 1 { ALPHABET[(triple >> 6) as usize & 63] as char } else { '=' });
+        result.push(if chunk.len() > 2 { ALPHABET[triple as usize & 63] as char } else { '=' });
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_base64_padding() {
+        assert_eq!(base64_encode(b"Man"), "TWFu");
+        assert_eq!(base64_encode(b"Ma"), "TWE=");
+        assert_eq!(base64_encode(b"M"), "TQ==");
+    }
+
+    fn matte(transmission: f64) -> ExportMaterial {
+        ExportMaterial{base_color: Vec3::new(0.5, 0.5, 0.5), metallic: 0.0, roughness: 1.0, index_of_refraction: 1.5, transmission, emission: Vec3::new(0.0, 0.0, 0.0)}
+    }
+
+    #[test]
+    fn test_exported_sphere_faces_outwards() {
+        let center = Vec3::new(1.0, 2.0, 3.0);
+        let path = std::env::temp_dir().join(format!("rays-export-{}.obj", std::process::id()));
+        write_obj(&path, &[ExportMesh::uv_sphere(center, 2.0, matte(0.0))]).unwrap();
+        let text = std::fs::read_to_string(&path).unwrap();
+        let _ = std::fs::remove_file(path.with_extension("mtl"));
+        let _ = std::fs::remove_file(&path);
+
+        let numbers = |line: &str| line.split_whitespace().skip(1).map(|n| n.split('/').next().unwrap().parse::<f64>().unwrap()).collect::<Vec<f64>>();
+        let positions: Vec<Vec3> = text.lines().filter(|line| line.starts_with("v ")).map(|line| {
+            let n = numbers(line);
+            Vec3::new(n[0], n[1], n[2])
+        }).collect();
+        let mut faces = 0;
+        for line in text.lines().filter(|line| line.starts_with("f ")) {
+            let corners: Vec<Vec3> = numbers(line).iter().map(|index| positions[*index as usize - 1]).collect();
+            let normal = (corners[1] - corners[0]).cross_product(&(corners[2] - corners[0]));
+            // the triangles at the poles have two corners in the same place
+            if normal.length() < 1e-9 {
+                continue
+            }
+            let middle = (corners[0] + corners[1] + corners[2]) / 3.0;
+            assert!(normal.dot_product(&(middle - center)) > 0.0, "{} faces inwards", line);
+            faces += 1;
+        }
+        // two per quad, less one at each pole
+        assert_eq!(faces, 2 * SPHERE_SEGMENTS * (SPHERE_SEGMENTS / 2) - 2 * SPHERE_SEGMENTS);
+    }
+
+    #[test]
+    fn test_gltf_lists_only_the_extensions_used() {
+        let path = std::env::temp_dir().join(format!("rays-export-{}.gltf", std::process::id()));
+        let exported = |meshes: &[ExportMesh]| {
+            write_gltf(&path, meshes).unwrap();
+            let text = std::fs::read_to_string(&path).unwrap();
+            let _ = std::fs::remove_file(&path);
+            text
+        };
+        let sphere = |material| ExportMesh::uv_sphere(Vec3::new(0.0, 0.0, 0.0), 1.0, material);
+        assert!(!exported(&[sphere(matte(0.0))]).contains("extensionsUsed"));
+        let glass = exported(&[sphere(matte(1.0)), sphere(matte(1.0))]);
+        assert!(glass.contains("\"extensionsUsed\":[\"KHR_materials_ior\",\"KHR_materials_transmission\"]"), "{}", &glass[..200]);
+    }
+}
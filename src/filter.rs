@@ -0,0 +1,169 @@
+use std::sync::OnceLock;
+
+// how a pixel's samples are weighted by where in (and around) the pixel they
+// land. averaging the samples inside the pixel is a box filter, which lets
+// through detail finer than a pixel as jaggies and moire. wider filters with
+// smooth falloffs (and mitchell's, whose negative lobes sharpen) reconstruct
+// the image better at the same samples per pixel.
+// rather than splatting every sample into each pixel it's near, samples are
+// taken around the pixel as densely as the filter weights that spot (filter
+// importance sampling, like pbrt-v4). so pixels are still independent of each
+// other, which adaptive sampling, checkpoints and streaming rows rely on.
+// where the filter goes negative samples are weighted -1 (a little scaled),
+// and pixels divide by the sum of their samples' weights rather than the
+// count (see PixelEstimate), so those don't show up as noise in flat areas
+
+#[derive(Copy, Clone, Default, PartialEq, Debug)]
+pub enum PixelFilter {
+    // within the pixel, all weighted the same, how renders have always been
+    #[default]
+    Box,
+    // falling off linearly to a pixel away
+    Tent,
+    // a gaussian with a standard deviation of half a pixel, cut off at 1.5
+    Gaussian,
+    // mitchell and netravali's cubic (b = c = 1/3), out to 2 pixels: sharper
+    // than the gaussian, with faint ringing at hard edges
+    Mitchell
+}
+
+// the tabulated filters' pieces across their width
+const TABLE_SIZE: usize = 256;
+
+// a filter along one axis as a piecewise constant distribution, for the ones
+// that can't be sampled in closed form
+struct FilterTable {
+    radius: f64,
+    // the chance of each piece, accumulated, from 0 to 1
+    cdf: Vec<f64>,
+    // the integral of the filter, which its samples are normalised by
+    integral: f64
+}
+
+impl FilterTable {
+    fn new(filter: PixelFilter) -> FilterTable {
+        let radius = filter.radius();
+        let width = 2.0 * radius / TABLE_SIZE as f64;
+        // each piece is as likely as the biggest the filter gets on it, so
+        // no part of it where the filter isn't 0 is too unlikely
+        let mut cdf = vec![0.0];
+        for piece in 0..TABLE_SIZE {
+            let x = -radius + piece as f64 * width;
+            let biggest = [x, x + width / 2.0, x + width].iter().map(|x| filter.evaluate(*x).abs()).fold(0.0, f64::max);
+            cdf.push(cdf[piece] + biggest);
+        }
+        let total = cdf[TABLE_SIZE];
+        for value in cdf.iter_mut() {
+            *value /= total;
+        }
+        let steps = 16 * TABLE_SIZE;
+        let integral = (0..steps).map(|step| filter.evaluate(-radius + (step as f64 + 0.5) * 2.0 * radius / steps as f64)).sum::<f64>()
+            * 2.0 * radius / steps as f64;
+        FilterTable {radius, cdf, integral}
+    }
+
+    fn sample(&self, filter: PixelFilter, u: f64) -> (f64, f64) {
+        let piece = (self.cdf.partition_point(|value| *value <= u) - 1).min(TABLE_SIZE - 1);
+        let (low, high) = (self.cdf[piece], self.cdf[piece + 1]);
+        let width = 2.0 * self.radius / TABLE_SIZE as f64;
+        let x = -self.radius + (piece as f64 + (u - low) / (high - low)) * width;
+        let pdf = (high - low) / width;
+        (x, filter.evaluate(x) / (pdf * self.integral))
+    }
+}
+
+impl PixelFilter {
+    pub fn parse(name: &str) -> Option<PixelFilter> {
+        match name {
+            "box" => Some(PixelFilter::Box),
+            "tent" | "triangle" => Some(PixelFilter::Tent),
+            "gaussian" => Some(PixelFilter::Gaussian),
+            "mitchell" => Some(PixelFilter::Mitchell),
+            _ => None
+        }
+    }
+
+    // how far from the pixel's centre samples count, in pixels
+    pub fn radius(self) -> f64 {
+        match self {
+            PixelFilter::Box => 0.5,
+            PixelFilter::Tent => 1.0,
+            PixelFilter::Gaussian => 1.5,
+            PixelFilter::Mitchell => 2.0
+        }
+    }
+
+    // the filter along one axis, x pixels from the centre. 2d filters are the
+    // product of this across and down
+    pub fn evaluate(self, x: f64) -> f64 {
+        let x = x.abs();
+        if x >= self.radius() {
+            return 0.0
+        }
+        match self {
+            PixelFilter::Box => 1.0,
+            PixelFilter::Tent => 1.0 - x,
+            PixelFilter::Gaussian => {
+                let gaussian = |x: f64| (-2.0 * x * x).exp();
+                gaussian(x) - gaussian(self.radius())
+            },
+            PixelFilter::Mitchell => {
+                let (b, c) = (1.0 / 3.0, 1.0 / 3.0);
+                if x < 1.0 {
+                    ((12.0 - 9.0 * b - 6.0 * c) * x * x * x + (-18.0 + 12.0 * b + 6.0 * c) * x * x + (6.0 - 2.0 * b)) / 6.0
+                } else {
+                    ((-b - 6.0 * c) * x * x * x + (6.0 * b + 30.0 * c) * x * x + (-12.0 * b - 48.0 * c) * x + (8.0 * b + 24.0 * c)) / 6.0
+                }
+            }
+        }
+    }
+
+    // where along one axis a sample goes for u in [0, 1), in pixels from the
+    // pixel's low edge (so 0.5 is its centre), and what it's weighted by. the
+    // weights average 1
+    pub fn sample(self, u: f64) -> (f64, f64) {
+        static TABLES: OnceLock<[FilterTable; 2]> = OnceLock::new();
+        match self {
+            PixelFilter::Box => (u, 1.0),
+            // the inverse of the tent's cdf
+            PixelFilter::Tent => {
+                let offset = if u < 0.5 { (2.0 * u).sqrt() - 1.0 } else { 1.0 - (2.0 - 2.0 * u).sqrt() };
+                (0.5 + offset, 1.0)
+            },
+            PixelFilter::Gaussian | PixelFilter::Mitchell => {
+                let tables = TABLES.get_or_init(|| [FilterTable::new(PixelFilter::Gaussian), FilterTable::new(PixelFilter::Mitchell)]);
+                let table = &tables[(self == PixelFilter::Mitchell) as usize];
+                let (offset, weight) = table.sample(self, u);
+                (0.5 + offset, weight)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_samples_reconstruct_the_filter() {
+        for filter in [PixelFilter::Box, PixelFilter::Tent, PixelFilter::Gaussian, PixelFilter::Mitchell].iter() {
+            let count = 100000;
+            let samples: Vec<(f64, f64)> = (0..count).map(|i| filter.sample((i as f64 + 0.5) / count as f64)).collect();
+            // the weights average 1 and every sample is within reach
+            let mean_weight = samples.iter().map(|(_, weight)| weight).sum::<f64>() / count as f64;
+            assert!((mean_weight - 1.0).abs() < 1e-3, "{:?} {}", filter, mean_weight);
+            assert!(samples.iter().all(|(offset, _)| (offset - 0.5).abs() <= filter.radius()));
+            // and, weighted, spread out as the filter is: what's in the middle
+            // pixel, in proportion to the filter's integral over it
+            let steps = 10000;
+            let integral = |from: f64, to: f64| (0..steps).map(|step| filter.evaluate(from + (step as f64 + 0.5) * (to - from) / steps as f64)).sum::<f64>() * (to - from) / steps as f64;
+            let share = integral(-0.5, 0.5) / integral(-filter.radius(), filter.radius());
+            let inside = samples.iter().filter(|(offset, _)| (0.0..1.0).contains(offset)).map(|(_, weight)| weight).sum::<f64>() / count as f64;
+            assert!((inside - share).abs() < 1e-3, "{:?} {} {}", filter, inside, share);
+        }
+        // mitchell's lobes are negative
+        assert!(PixelFilter::Mitchell.evaluate(1.5) < 0.0);
+        assert!(PixelFilter::Mitchell.sample(0.001).1 < 0.0);
+        assert!(PixelFilter::parse("lanczos").is_none());
+    }
+}
@@ -0,0 +1,428 @@
+use crate::Ray;
+use crate::Vec3;
+use crate::aabb::{AABB, SlabRay};
+use crate::material::Material;
+use crate::hittable::*;
+use crate::export::ExportMesh;
+use crate::bvh_stats::*;
+use crate::ray_stats::count_primitive_test;
+use std::cmp::Ordering;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+// the same hierarchy as bvh::BVH, but the nodes are stored next to each other
+// in a single Vec and refer to their children by index. walking the tree then
+// touches contiguous memory instead of chasing boxes around the heap, and hit()
+// uses an explicit stack so large scenes can't overflow the call stack
+
+// how deep a tree hit() can walk without allocating. trees built by halving
+// the list never get near it, but lbvh trees split wherever the morton codes
+// do, so clustered codes (and runs of identical ones) can go deeper, and the
+// rest of the stack spills onto the heap
+const TYPICAL_DEPTH: usize = 64;
+
+// hit()'s stack of nodes still to visit
+struct TraversalStack {
+    inline: [usize; TYPICAL_DEPTH],
+    size: usize,
+    // only used (and allocated) once inline is full
+    spilled: Vec<usize>
+}
+
+impl TraversalStack {
+    fn new(root: usize) -> TraversalStack {
+        TraversalStack {
+            inline: [root; TYPICAL_DEPTH],
+            size: 1,
+            spilled: Vec::new()
+        }
+    }
+
+    fn push(&mut self, node: usize) {
+        if self.size < TYPICAL_DEPTH {
+            self.inline[self.size] = node;
+            self.size += 1;
+        } else {
+            self.spilled.push(node);
+        }
+    }
+
+    fn pop(&mut self) -> Option<usize> {
+        if let Some(node) = self.spilled.pop() {
+            return Some(node)
+        }
+        if self.size == 0 {
+            return None
+        }
+        self.size -= 1;
+        Some(self.inline[self.size])
+    }
+}
+
+// spreads the lowest 21 bits of value out so there are two 0 bits between each
+fn expand_bits(value: u64) -> u64 {
+    let mut x = value & 0x1fffff;
+    x = (x | x << 32) & 0x1f00000000ffff;
+    x = (x | x << 16) & 0x1f0000ff0000ff;
+    x = (x | x << 8) & 0x100f00f00f00f00f;
+    x = (x | x << 4) & 0x10c30c30c30c30c3;
+    x = (x | x << 2) & 0x1249249249249249;
+    x
+}
+
+// interleaves the bits of x, y and z (each in [0, 1]) into a 63 bit morton code,
+// so points close together in space tend to have close codes
+fn morton_code(x: f64, y: f64, z: f64) -> u64 {
+    let scale = ((1u64 << 21) - 1) as f64;
+    let quantize = |value: f64| (value.clamp(0.0, 1.0) * scale) as u64;
+    (expand_bits(quantize(x)) << 2) | (expand_bits(quantize(y)) << 1) | expand_bits(quantize(z))
+}
+
+enum FlatNode {
+    // index of the primitive, as given to FlatTree::build
+    Leaf{primitive: usize, bounding_box: AABB},
+    // indices into the nodes list
+    Branch{left: usize, right: usize, bounding_box: AABB}
+}
+
+impl FlatNode {
+    fn bounding_box(&self) -> AABB {
+        match self {
+            FlatNode::Leaf{primitive: _, bounding_box} => *bounding_box,
+            FlatNode::Branch{left: _, right: _, bounding_box} => *bounding_box
+        }
+    }
+}
+
+// the tree on its own, over primitives identified by index. this lets things
+// that aren't a list of hittables (e.g. the triangles of a mesh) reuse it
+pub struct FlatTree {
+    nodes: Vec<FlatNode>
+}
+
+impl FlatTree {
+    // boxes[i] is the bounding box of primitive i
+    pub fn build(boxes: &[AABB]) -> FlatTree {
+        if boxes.is_empty() {
+            panic!("Cannot have 0 objects in list during BVH construction");
+        }
+
+        let mut indices: Vec<usize> = (0..boxes.len()).collect();
+        let mut nodes = Vec::with_capacity(2 * boxes.len() - 1);
+        FlatTree::build_partial(&mut nodes, boxes, &mut indices);
+        FlatTree {
+            nodes
+        }
+    }
+
+    // appends the subtree for the given primitives and returns the index of its root.
+    // uses the same strategy as BVH: sort along the longest axis and split in half
+    fn build_partial(nodes: &mut Vec<FlatNode>, boxes: &[AABB], indices: &mut [usize]) -> usize {
+        if indices.len() == 1 {
+            nodes.push(FlatNode::Leaf{primitive: indices[0], bounding_box: boxes[indices[0]]});
+            return nodes.len() - 1
+        }
+
+        let axis = indices.iter().map(|index| boxes[*index]).reduce(AABB::surrounding_box).unwrap().longest_axis();
+        indices.sort_by(|a, b| {
+            let (left_val, right_val) = match axis {
+                0 => (boxes[*a].minimum.x(), boxes[*b].minimum.x()),
+                1 => (boxes[*a].minimum.y(), boxes[*b].minimum.y()),
+                _ => (boxes[*a].minimum.z(), boxes[*b].minimum.z())
+            };
+            left_val.partial_cmp(&right_val).unwrap_or(Ordering::Equal)
+        });
+
+        // reserve the parent's slot first so it comes before its children
+        let index = nodes.len();
+        nodes.push(FlatNode::Leaf{primitive: 0, bounding_box: boxes[indices[0]]});
+        let mid = indices.len() / 2;
+        let (left_indices, right_indices) = indices.split_at_mut(mid);
+        let left = FlatTree::build_partial(nodes, boxes, left_indices);
+        let right = FlatTree::build_partial(nodes, boxes, right_indices);
+        let bounding_box = AABB::surrounding_box(nodes[left].bounding_box(), nodes[right].bounding_box());
+        nodes[index] = FlatNode::Branch{left, right, bounding_box};
+        index
+    }
+
+    // a linear BVH (LBVH): the primitives are sorted along a space filling
+    // curve (the morton/z-order curve of their box centers) and the tree is
+    // split wherever the curve's position changes the most significant bit.
+    // building is close to linear time which matters for huge scenes, at the
+    // cost of a lower quality tree than the sort-and-halve strategy.
+    // reference: https://developer.nvidia.com/blog/thinking-parallel-part-iii-tree-construction-gpu/
+    pub fn build_lbvh(boxes: &[AABB]) -> FlatTree {
+        if boxes.is_empty() {
+            panic!("Cannot have 0 objects in list during BVH construction");
+        }
+
+        // box centers relative to the box around all of them
+        let centers: Vec<Vec3> = boxes.iter().map(|b| (b.minimum + b.maximum) * 0.5).collect();
+        let mut minimum = centers[0];
+        let mut maximum = centers[0];
+        for center in centers.iter() {
+            minimum = minimum.min_components(center);
+            maximum = maximum.max_components(center);
+        }
+        let extent = maximum - minimum;
+        let normalize = |value: f64, min: f64, size: f64| if size > 0.0 { (value - min) / size } else { 0.0 };
+
+        let mut keyed: Vec<(u64, usize)> = centers.iter().enumerate().map(|(i, center)| {
+            let code = morton_code(
+                normalize(center.x(), minimum.x(), extent.x()),
+                normalize(center.y(), minimum.y(), extent.y()),
+                normalize(center.z(), minimum.z(), extent.z())
+            );
+            (code, i)
+        }).collect();
+        keyed.sort_unstable();
+
+        let codes: Vec<u64> = keyed.iter().map(|(code, _)| *code).collect();
+        let indices: Vec<usize> = keyed.iter().map(|(_, index)| *index).collect();
+        let mut nodes = Vec::with_capacity(2 * boxes.len() - 1);
+        FlatTree::build_lbvh_partial(&mut nodes, boxes, &codes, &indices, 0, indices.len() - 1);
+        FlatTree {
+            nodes
+        }
+    }
+
+    // appends the subtree for the sorted primitives first..=last and returns its root
+    fn build_lbvh_partial(nodes: &mut Vec<FlatNode>, boxes: &[AABB], codes: &[u64], indices: &[usize], first: usize, last: usize) -> usize {
+        if first == last {
+            nodes.push(FlatNode::Leaf{primitive: indices[first], bounding_box: boxes[indices[first]]});
+            return nodes.len() - 1
+        }
+
+        let split = FlatTree::find_split(codes, first, last);
+        let index = nodes.len();
+        nodes.push(FlatNode::Leaf{primitive: 0, bounding_box: boxes[indices[first]]});
+        let left = FlatTree::build_lbvh_partial(nodes, boxes, codes, indices, first, split);
+        let right = FlatTree::build_lbvh_partial(nodes, boxes, codes, indices, split + 1, last);
+        let bounding_box = AABB::surrounding_box(nodes[left].bounding_box(), nodes[right].bounding_box());
+        nodes[index] = FlatNode::Branch{left, right, bounding_box};
+        index
+    }
+
+    // the last position (in first..last) that shares more leading bits with the
+    // first code than the last code does, found with a binary search
+    fn find_split(codes: &[u64], first: usize, last: usize) -> usize {
+        let first_code = codes[first];
+        let last_code = codes[last];
+        // identical codes can't be told apart, split them in half
+        if first_code == last_code {
+            return (first + last) / 2
+        }
+
+        let common_prefix = (first_code ^ last_code).leading_zeros();
+        let mut split = first;
+        let mut step = last - first;
+        loop {
+            step = step.div_ceil(2);
+            let candidate = split + step;
+            if candidate < last && (first_code ^ codes[candidate]).leading_zeros() > common_prefix {
+                split = candidate;
+            }
+            if step <= 1 {
+                break
+            }
+        }
+        split
+    }
+
+    pub fn bounding_box(&self) -> AABB {
+        self.nodes[0].bounding_box()
+    }
+
+    pub fn stats(&self) -> BvhStats {
+        let mut collector = StatsCollector::new(&self.bounding_box());
+        let mut stack = vec![(0, 0)];
+        while let Some((index, depth)) = stack.pop() {
+            match &self.nodes[index] {
+                FlatNode::Leaf{primitive: _, bounding_box: _} => collector.leaf(depth, 1),
+                FlatNode::Branch{left, right, bounding_box} => {
+                    collector.branch(depth, bounding_box, &self.nodes[*left].bounding_box(), &self.nodes[*right].bounding_box());
+                    stack.push((*left, depth + 1));
+                    stack.push((*right, depth + 1));
+                }
+            }
+        }
+        collector.finish()
+    }
+
+    // writes the stats and every node (boxes as [min, max]) for inspecting the tree elsewhere
+    pub fn dump_json(&self, path: &Path) -> std::io::Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+        write!(file, "{{\"stats\":{},\"nodes\":[", self.stats().to_json())?;
+        for (i, node) in self.nodes.iter().enumerate() {
+            let b = node.bounding_box();
+            let bounds = format!("[[{},{},{}],[{},{},{}]]", b.minimum.x(), b.minimum.y(), b.minimum.z(), b.maximum.x(), b.maximum.y(), b.maximum.z());
+            if i > 0 {
+                write!(file, ",")?;
+            }
+            match node {
+                FlatNode::Leaf{primitive, bounding_box: _} => write!(file, "{{\"box\":{},\"primitive\":{}}}", bounds, primitive)?,
+                FlatNode::Branch{left, right, bounding_box: _} => write!(file, "{{\"box\":{},\"left\":{},\"right\":{}}}", bounds, left, right)?
+            }
+        }
+        writeln!(file, "]}}")?;
+        file.flush()
+    }
+
+    // finds the closest hit. hit_primitive(index, t_min, t_max) intersects a single primitive
+    pub fn hit<'a, F>(&self, ray: &Ray, t_min: f64, t_max: f64, hit_primitive: F) -> Option<HitRecord<'a>>
+    where F: Fn(usize, f64, f64) -> Option<HitRecord<'a>> {
+        let mut stack = TraversalStack::new(0);
+        let mut closest_so_far = t_max;
+        let mut result: Option<HitRecord> = None;
+        let slab_ray = SlabRay::new(ray);
+
+        while let Some(node) = stack.pop() {
+            count_node_visit();
+            match &self.nodes[node] {
+                FlatNode::Leaf{primitive, bounding_box: _} => {
+                    count_primitive_test();
+                    // don't unnecessarily search more area than needed
+                    if let Some(hit) = hit_primitive(*primitive, t_min, closest_so_far) {
+                        closest_so_far = hit.t;
+                        result = Some(hit);
+                    }
+                },
+                FlatNode::Branch{left, right, bounding_box} => {
+                    // only check the children if the current box is even hit
+                    if bounding_box.hit_slabs(&slab_ray, t_min, closest_so_far).is_some() {
+                        stack.push(*right);
+                        stack.push(*left);
+                    }
+                }
+            }
+        }
+
+        result
+    }
+}
+
+#[allow(clippy::upper_case_acronyms)]
+pub struct FlatBVH {
+    tree: FlatTree,
+    objects: Vec<Box<dyn Hittable>>
+}
+
+impl FlatBVH {
+    pub fn construct(list: Vec<Box<dyn Hittable>>, t0: f64, t1: f64) -> FlatBVH {
+        let boxes: Vec<AABB> = list.iter().map(|object| {
+            object.bounding_box(t0, t1).expect("No bounding box in BVH node")
+        }).collect();
+
+        FlatBVH {
+            tree: FlatTree::build(&boxes),
+            objects: list
+        }
+    }
+
+    // faster to build than construct() but slower to render, see FlatTree::build_lbvh
+    pub fn construct_lbvh(list: Vec<Box<dyn Hittable>>, t0: f64, t1: f64) -> FlatBVH {
+        let boxes: Vec<AABB> = list.iter().map(|object| {
+            object.bounding_box(t0, t1).expect("No bounding box in BVH node")
+        }).collect();
+
+        FlatBVH {
+            tree: FlatTree::build_lbvh(&boxes),
+            objects: list
+        }
+    }
+}
+
+impl FlatBVH {
+    pub fn stats(&self) -> BvhStats {
+        self.tree.stats()
+    }
+
+    pub fn dump_json(&self, path: &Path) -> std::io::Result<()> {
+        self.tree.dump_json(path)
+    }
+}
+
+impl Hittable for FlatBVH {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        self.tree.hit(ray, t_min, t_max, |object, t_min, t_max| self.objects[object].hit(ray, t_min, t_max))
+    }
+
+    fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<AABB> {
+        Some(self.tree.bounding_box())
+    }
+
+    fn tessellate(&self) -> Vec<ExportMesh> {
+        self.objects.iter().flat_map(|object| object.tessellate()).collect()
+    }
+
+    fn materials_mut(&mut self) -> Vec<&mut Arc<Material>> {
+        self.objects.iter_mut().flat_map(|object| object.materials_mut()).collect()
+    }
+
+    fn tree_stats(&self) -> Vec<BvhStats> {
+        vec![self.stats()]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_morton_code_interleaving() {
+        // the highest bit of each axis ends up next to each other, x first
+        let top = 1u64 << 62;
+        assert_eq!(morton_code(1.0, 0.0, 0.0) & (top | top >> 1 | top >> 2), top);
+        assert_eq!(morton_code(0.0, 1.0, 0.0) & (top | top >> 1 | top >> 2), top >> 1);
+        assert_eq!(morton_code(0.0, 0.0, 1.0) & (top | top >> 1 | top >> 2), top >> 2);
+    }
+
+    #[test]
+    fn test_lbvh_contains_every_primitive() {
+        let boxes: Vec<AABB> = (0..100).map(|i| {
+            let corner = Vec3::new((i % 7) as f64, (i % 5) as f64, (i / 10) as f64);
+            AABB::new(corner, corner + Vec3::new(0.5, 0.5, 0.5))
+        }).collect();
+        let tree = FlatTree::build_lbvh(&boxes);
+        let mut seen: Vec<usize> = tree.nodes.iter().filter_map(|node| match node {
+            FlatNode::Leaf{primitive, bounding_box: _} => Some(*primitive),
+            _ => None
+        }).collect();
+        seen.sort_unstable();
+        assert_eq!(seen, (0..100).collect::<Vec<usize>>());
+
+        let stats = tree.stats();
+        assert_eq!(stats.leaf_count, 100);
+        assert_eq!(stats.node_count, 199);
+        assert!(stats.max_depth >= 7 && stats.expected_node_visits() >= 1.0);
+    }
+
+    #[test]
+    fn test_lbvh_deeper_than_the_inline_stack() {
+        // morton codes with 0, 1, 2 ... 63 of their lowest bits set, so every
+        // split only peels off one of them, then a pile of boxes all in the same
+        // place, which can only be split in half, at the bottom of that
+        let scale = ((1u64 << 21) - 1) as f64;
+        let axis = |bits: usize| {
+            let quantized = ((1u64 << bits) - 1) as f64;
+            // nudged off the quantization step so rounding can't take it below
+            if quantized == 0.0 || quantized == scale { quantized / scale } else { (quantized + 0.25) / scale }
+        };
+        let mut centers: Vec<Vec3> = (0..=63).map(|set: usize| Vec3::new(axis(set / 3), axis((set + 1) / 3), axis(set.div_ceil(3)))).collect();
+        centers.extend(std::iter::repeat_n(Vec3::new(0.0, 0.0, 0.0), 1000));
+        let boxes: Vec<AABB> = centers.iter().map(|center| AABB::new(*center - Vec3::new(1.0, 1.0, 1.0), *center + Vec3::new(1.0, 1.0, 1.0))).collect();
+        let tree = FlatTree::build_lbvh(&boxes);
+        assert!(tree.stats().max_depth > TYPICAL_DEPTH, "{}", tree.stats().max_depth);
+
+        // every box overlaps the ray, so traversal goes all the way down everywhere
+        let tested = std::cell::Cell::new(0);
+        let ray = Ray::new(Vec3::new(-5.0, 0.5, 0.5), Vec3::new(1.0, 0.0, 0.0), None);
+        assert!(tree.hit(&ray, 0.0, f64::INFINITY, |_, _, _| {
+            tested.set(tested.get() + 1);
+            None
+        }).is_none());
+        assert_eq!(tested.get(), boxes.len());
+    }
+}
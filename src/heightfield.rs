@@ -0,0 +1,131 @@
+use crate::vec3::*;
+use crate::Ray;
+use crate::hittable::*;
+use crate::material::*;
+use crate::mesh::{Face, Mesh};
+use crate::mapped::MappedFile;
+use crate::aabb::AABB;
+use crate::export::ExportMesh;
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+use std::sync::Arc;
+
+// terrain: a grid of heights (from a grayscale image or a function) turned into
+// a smooth shaded triangle mesh, which keeps the triangles in its own BVH.
+// the grid covers size.x by size.z starting at corner, with heights from 0 to
+// 1 scaled up to size.y. the uvs cover the whole grid with the image's top row
+// at v = 1, so a colour map of the same area drapes over it the right way around
+pub struct Heightfield {
+    mesh: Mesh
+}
+
+impl Heightfield {
+    // heights in rows from the top (far, -z) edge down, columns from -x to +x
+    pub fn new(heights: &[f64], columns: usize, rows: usize, corner: Vec3, size: Vec3, material: impl Into<Arc<Material>>) -> Heightfield {
+        if columns < 2 || rows < 2 || heights.len() != columns * rows {
+            panic!("A heightfield needs at least 2x2 heights, got {} for {}x{}", heights.len(), columns, rows);
+        }
+        let spacing = (size.x() / (columns - 1) as f64, size.z() / (rows - 1) as f64);
+        let height = |i: usize, j: usize| heights[j * columns + i] * size.y();
+
+        let mut positions = Vec::with_capacity(columns * rows);
+        let mut normals = Vec::with_capacity(columns * rows);
+        let mut uvs = Vec::with_capacity(columns * rows);
+        for j in 0..rows {
+            for i in 0..columns {
+                positions.push(corner + Vec3::new(i as f64 * spacing.0, height(i, j), j as f64 * spacing.1));
+                // the slope from the neighbours either side (one side at the edges)
+                let (left, right) = (i.saturating_sub(1), (i + 1).min(columns - 1));
+                let (back, front) = (j.saturating_sub(1), (j + 1).min(rows - 1));
+                let slope_x = (height(right, j) - height(left, j)) / ((right - left) as f64 * spacing.0);
+                let slope_z = (height(i, front) - height(i, back)) / ((front - back) as f64 * spacing.1);
+                normals.push(Vec3::new(-slope_x, 1.0, -slope_z).unit_vector());
+                uvs.push((i as f64 / (columns - 1) as f64, 1.0 - j as f64 / (rows - 1) as f64));
+            }
+        }
+
+        // two triangles per cell, counter clockwise seen from above
+        let mut faces = Vec::with_capacity(2 * (columns - 1) * (rows - 1));
+        for j in 0..rows - 1 {
+            for i in 0..columns - 1 {
+                let corner = j * columns + i;
+                for positions in [[corner, corner + columns, corner + 1], [corner + 1, corner + columns, corner + columns + 1]] {
+                    faces.push(Face {
+                        positions,
+                        normals: Some(positions),
+                        uvs: Some(positions),
+                        material: 0
+                    });
+                }
+            }
+        }
+
+        Heightfield {
+            mesh: Mesh::new(positions, normals, uvs, faces, vec![material.into()])
+        }
+    }
+
+    // heights from a function of where on the grid they are, 0 to 1 across
+    // and down, e.g. noise
+    pub fn from_function(height: impl Fn(f64, f64) -> f64, columns: usize, rows: usize, corner: Vec3, size: Vec3,
+        material: impl Into<Arc<Material>>) -> Heightfield {
+        let heights: Vec<f64> = (0..columns * rows).map(|index| {
+            let (i, j) = (index % columns, index / columns);
+            height(i as f64 / (columns - 1).max(1) as f64, j as f64 / (rows - 1).max(1) as f64)
+        }).collect();
+        Heightfield::new(&heights, columns, rows, corner, size, material)
+    }
+
+    // a grayscale image, one height per pixel from black (0) to white (1).
+    // 16 bit images keep smooth slopes from stepping
+    pub fn load(path: &Path, corner: Vec3, size: Vec3, material: impl Into<Arc<Material>>) -> Result<Heightfield> {
+        let image = image::load_from_memory(&MappedFile::open(path)?)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, format!("{}: {}", path.display(), e)))?
+            .into_luma16();
+        let (columns, rows) = (image.width() as usize, image.height() as usize);
+        if columns < 2 || rows < 2 {
+            return Err(Error::new(ErrorKind::InvalidData, format!("{}: a heightfield needs at least 2x2 pixels", path.display())))
+        }
+        let heights: Vec<f64> = image.pixels().map(|p| p[0] as f64 / 65535.0).collect();
+        Ok(Heightfield::new(&heights, columns, rows, corner, size, material))
+    }
+}
+
+impl Hittable for Heightfield {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        self.mesh.hit(ray, t_min, t_max)
+    }
+
+    fn bounding_box(&self, t0: f64, t1: f64) -> Option<AABB> {
+        self.mesh.bounding_box(t0, t1)
+    }
+
+    fn tessellate(&self) -> Vec<ExportMesh> {
+        self.mesh.tessellate()
+    }
+
+    fn materials_mut(&mut self) -> Vec<&mut Arc<Material>> {
+        self.mesh.materials_mut()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ramp_heightfield() {
+        // rises from 0 at -x to 2 at +x
+        let grass = Material::Lambertian{albedo: Box::new(crate::texture::SolidTexture::uniform(0.5)), normal_map: None};
+        let ramp = Heightfield::from_function(|x, _| x, 5, 3, Vec3::new(0.0, 0.0, 0.0), Vec3::new(4.0, 2.0, 4.0), grass);
+
+        let record = ramp.hit(&Ray::new(Vec3::new(3.0, 10.0, 1.0), Vec3::new(0.0, -1.0, 0.0), None), 0.001, f64::INFINITY).unwrap();
+        assert!((record.point.y() - 1.5).abs() < 1e-9 && record.front_face, "{:?}", record.point);
+        // leaning back against the slope
+        assert!((record.normal - Vec3::new(-0.5, 1.0, 0.0).unit_vector()).near_zero(), "{:?}", record.normal);
+        assert!((record.u - 0.75).abs() < 1e-9 && (record.v - 0.75).abs() < 1e-9, "{} {}", record.u, record.v);
+
+        let bounds = ramp.bounding_box(0.0, 1.0).unwrap();
+        assert!((bounds.maximum.y() - 2.0).abs() < 1e-3 && (bounds.maximum.x() - 4.0).abs() < 1e-3);
+    }
+}
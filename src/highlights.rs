@@ -0,0 +1,95 @@
+use crate::vec3::*;
+
+// bringing bright values into the [0, 1] an 8 bit image can hold. clamping each
+// channel on its own shifts hues: a bright orange (10, 5, 1) clips to white
+// (1, 1, 1), and the fringe around it to yellow. clamping by luminance scales the
+// whole colour instead, so it keeps (most of) its hue. highlight compression
+// rolls values off smoothly towards 1 instead of cutting them at 1, so bright
+// areas keep some gradation rather than turning into flat blobs
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum ClampMode {
+    // each channel is clipped at 1 on its own (the default, how it's always been)
+    PerChannel,
+    // the colour is scaled down until its luminance is 1, anything still over
+    // 1 (very saturated colours) is clipped after
+    Luminance
+}
+
+impl ClampMode {
+    pub fn parse(name: &str) -> Option<ClampMode> {
+        match name {
+            "channel" => Some(ClampMode::PerChannel),
+            "luminance" => Some(ClampMode::Luminance),
+            _ => None
+        }
+    }
+}
+
+pub struct HighlightSettings {
+    pub clamp: ClampMode,
+    // where compression starts, in (0, 1). values below it are left alone,
+    // above it they approach 1 without reaching it
+    pub knee: Option<f64>
+}
+
+impl HighlightSettings {
+    pub fn new(clamp: ClampMode) -> HighlightSettings {
+        HighlightSettings {
+            clamp,
+            knee: None
+        }
+    }
+
+    pub fn with_compression(mut self, knee: f64) -> HighlightSettings {
+        if knee <= 0.0 || knee >= 1.0 {
+            panic!("Highlight compression has to start between 0 and 1, got {}", knee);
+        }
+        self.knee = Some(knee);
+        self
+    }
+
+    // an exponential shoulder: continuous with a slope of 1 at the knee
+    fn compress(&self, value: f64) -> f64 {
+        match self.knee {
+            Some(knee) if value > knee => {
+                let range = 1.0 - knee;
+                knee + range * (1.0 - (-(value - knee) / range).exp())
+            },
+            _ => value
+        }
+    }
+
+    // a linear colour brought into [0, 1], ready for gamma and quantizing
+    pub fn apply(&self, colour: Color) -> Color {
+        let colour = match self.clamp {
+            ClampMode::PerChannel => Color::new(self.compress(colour.x()), self.compress(colour.y()), self.compress(colour.z())),
+            ClampMode::Luminance => {
+                let y = colour.luminance();
+                let target = self.compress(y).min(1.0);
+                if y > target { colour * (target / y) } else { colour }
+            }
+        };
+        Color::new(colour.x().clamp(0.0, 1.0), colour.y().clamp(0.0, 1.0), colour.z().clamp(0.0, 1.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_luminance_clamp_keeps_hue() {
+        let orange = Color::new(10.0, 5.0, 1.0);
+        let per_channel = HighlightSettings::new(ClampMode::PerChannel).apply(orange);
+        assert!(per_channel.equal_to(&Color::new(1.0, 1.0, 1.0)));
+        let by_luminance = HighlightSettings::new(ClampMode::Luminance).apply(orange);
+        assert!(by_luminance.x() > by_luminance.y() && by_luminance.y() > by_luminance.z());
+
+        // compression is seamless at the knee and stays under 1
+        let compressed = HighlightSettings::new(ClampMode::PerChannel).with_compression(0.8);
+        assert!((compressed.compress(0.8 + 1e-6) - (0.8 + 1e-6)).abs() < 1e-9);
+        assert!(compressed.compress(4.0) < 1.0 && compressed.compress(4.0) > compressed.compress(2.0));
+        assert_eq!(compressed.compress(0.5), 0.5);
+    }
+}
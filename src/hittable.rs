@@ -1,7 +1,11 @@
 use crate::Vec3;
+use crate::vec3::Color;
 use crate::Ray;
 use crate::material::Material;
 use crate::aabb::AABB;
+use crate::export::ExportMesh;
+use crate::bvh_stats::BvhStats;
+use std::sync::Arc;
 
 pub struct HitRecord<'a> {
     // where ray hits a Hittable
@@ -17,11 +21,24 @@ pub struct HitRecord<'a> {
     // true if ray hits the outside surface
     pub front_face: bool,
     // the material of the object
-    pub material: &'a Material
+    pub material: &'a Material,
+    // colour interpolated from the object's vertices, for objects that have them
+    pub vertex_color: Option<Color>,
+    // the direction u increases in along the surface, for objects that have
+    // texture coordinates to follow (used to orient normal maps)
+    pub tangent: Option<Vec3>,
+    // the render layer of the object, 0 unless it's wrapped in an OnLayer
+    pub layer: u32,
+    // how far the texture coordinates move from one pixel to the next here, 0
+    // when unknown. see RayDifferential
+    pub uv_footprint: f64,
+    // when the ray that hit it was traced, for textures that change over an
+    // animation (see NoiseTexture)
+    pub time: f64
 }
 
 impl<'a> HitRecord<'a> {
-    pub fn new(point: Vec3, normal: Vec3, t: f64, u: f64, v: f64, front_face: bool, material: &Material) -> HitRecord {
+    pub fn new(point: Vec3, normal: Vec3, t: f64, u: f64, v: f64, front_face: bool, material: &Material) -> HitRecord<'_> {
         HitRecord{
             point,
             normal,
@@ -29,10 +46,31 @@ impl<'a> HitRecord<'a> {
             u,
             v,
             front_face,
-            material
+            material,
+            vertex_color: None,
+            tangent: None,
+            layer: 0,
+            uv_footprint: 0.0,
+            time: 0.0
         }
     }
 
+    // an orthonormal (tangent, bitangent, normal) frame around the normal. the
+    // bitangent roughly follows increasing v. without a tangent the frame is
+    // still valid but its rotation around the normal is arbitrary
+    pub fn tangent_frame(&self) -> (Vec3, Vec3, Vec3) {
+        let normal = self.normal;
+        // remove the part of the tangent along the normal (gram-schmidt)
+        let tangent = self.tangent.map(|t| t - normal * t.dot_product(&normal))
+            .filter(|t| !t.near_zero())
+            .unwrap_or_else(|| {
+                let helper = if normal.x().abs() > 0.9 { Vec3::new(0.0, 1.0, 0.0) } else { Vec3::new(1.0, 0.0, 0.0) };
+                helper.cross_product(&normal)
+            })
+            .unit_vector();
+        (tangent, normal.cross_product(&tangent), normal)
+    }
+
     // updates whether a ray hits an object from the front or back.
     // note: the normal, by design, will always point away from the ray
     pub fn set_face_normal(&mut self, ray: &Ray, outward_normal: &Vec3) {
@@ -46,13 +84,31 @@ impl<'a> HitRecord<'a> {
     }
 }
 
-pub trait Hittable {
+// Send + Sync so scenes can be built (and rendered) from multiple threads
+pub trait Hittable: Send + Sync {
     // returns if a given ray hits an object between a ray, updates the HitRecord.
     // note we're returning a record instead of updating references in place (pain)
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord>;
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>>;
     // compute the aabb (box) that contains a hittable object
     // note we're returning a record instead of updating references in place (pain)
     fn bounding_box(&self, t0: f64, t1: f64) -> Option<AABB>;
+    // triangle mesh approximations of the object, used for exporting scenes.
+    // objects that can't be tessellated are skipped
+    fn tessellate(&self) -> Vec<ExportMesh> {
+        Vec::new()
+    }
+    // the materials of the object's surfaces, so they can be swapped after the
+    // scene is built (e.g. from the preview's material panel). instances of
+    // geometry that's shared with other instances can't hand theirs out
+    fn materials_mut(&mut self) -> Vec<&mut Arc<Material>> {
+        Vec::new()
+    }
+
+    // how good the acceleration structures in the object are, see
+    // --tree-stats. trees give their own, not those of trees inside them
+    fn tree_stats(&self) -> Vec<BvhStats> {
+        Vec::new()
+    }
 
     // for BVH, can clone the Hittable if we dont wanna pass around references
     // fn clone(&self) -> Box<dyn Hittable>;
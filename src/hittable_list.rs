@@ -1,6 +1,10 @@
+use crate::material::Material;
 use crate::hittable::*;
 use crate::ray::Ray;
 use crate::aabb::AABB;
+use crate::export::ExportMesh;
+use crate::bvh_stats::BvhStats;
+use std::sync::Arc;
 
 pub struct HittableList {
     // "box" (put x trait into a fixed size container) Hittable because traits
@@ -18,13 +22,24 @@ impl HittableList {
         self.objects.push(Box::new(obj_to_add))
     }
 
+    // for objects that are already boxed, e.g. built by an AcceleratorKind
+    pub fn add_boxed(&mut self, obj_to_add: Box<dyn Hittable>) {
+        self.objects.push(obj_to_add)
+    }
+
     pub fn clear(&mut self) {
         self.objects.clear()
     }
 }
 
+impl Default for HittableList {
+    fn default() -> HittableList {
+        HittableList::new()
+    }
+}
+
 impl Hittable for HittableList {
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
         let mut closest_so_far = t_max;
         let mut result: Option<HitRecord> = None;
 
@@ -41,7 +56,7 @@ impl Hittable for HittableList {
     }
 
     fn bounding_box(&self, t0: f64, t1: f64) -> Option<AABB> {
-        if self.objects.len() == 0 {
+        if self.objects.is_empty() {
             return None
         }
 
@@ -73,4 +88,16 @@ impl Hittable for HittableList {
 
         result_box
     }
+
+    fn tessellate(&self) -> Vec<ExportMesh> {
+        self.objects.iter().flat_map(|object| object.tessellate()).collect()
+    }
+
+    fn materials_mut(&mut self) -> Vec<&mut Arc<Material>> {
+        self.objects.iter_mut().flat_map(|object| object.materials_mut()).collect()
+    }
+
+    fn tree_stats(&self) -> Vec<BvhStats> {
+        self.objects.iter().flat_map(|object| object.tree_stats()).collect()
+    }
 }
\ No newline at end of file
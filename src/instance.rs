@@ -0,0 +1,121 @@
+use crate::Ray;
+use crate::aabb::AABB;
+use crate::hittable::*;
+use crate::export::ExportMesh;
+use crate::material::Material;
+use crate::flat_bvh::FlatTree;
+use crate::transform::Transform;
+use std::sync::Arc;
+
+// a placement of shared geometry in the scene. the geometry (e.g. a mesh with
+// its own BVH) is only stored once, and every instance refers to it with its
+// own transform, so a mesh can appear thousands of times without copying its
+// triangles. rays are moved into the geometry's space instead of the other way around
+pub struct Instance {
+    object: Arc<dyn Hittable>,
+    transform: Transform,
+    // the geometry's box in world space, cached since it never changes
+    bounding_box: Option<AABB>
+}
+
+impl Instance {
+    pub fn new(object: Arc<dyn Hittable>, transform: Transform) -> Instance {
+        let bounding_box = object.bounding_box(0.0, 1.0).map(|b| transform.bounding_box(&b));
+        Instance {
+            object,
+            transform,
+            bounding_box
+        }
+    }
+}
+
+impl Hittable for Instance {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        // the direction isn't normalized so t is the same in both spaces
+        let local_ray = Ray::new(
+            self.transform.inverse_point(&ray.origin),
+            self.transform.inverse_vector(&ray.direction),
+            Some(ray.time)
+        );
+        let mut record = self.object.hit(&local_ray, t_min, t_max)?;
+
+        // the local normal points against the local ray, transform the outward one instead
+        let local_outward = if record.front_face { record.normal } else { record.normal * -1.0 };
+        let outward_normal = self.transform.normal(&local_outward).unit_vector();
+        record.point = self.transform.point(&record.point);
+        record.set_face_normal(ray, &outward_normal);
+        record.tangent = record.tangent.map(|tangent| self.transform.vector(&tangent));
+        Some(record)
+    }
+
+    fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<AABB> {
+        self.bounding_box
+    }
+
+    fn tessellate(&self) -> Vec<ExportMesh> {
+        let mut meshes = self.object.tessellate();
+        for mesh in meshes.iter_mut() {
+            for position in mesh.positions.iter_mut() {
+                *position = self.transform.point(position);
+            }
+            for normal in mesh.normals.iter_mut() {
+                *normal = self.transform.normal(normal).unit_vector();
+            }
+        }
+        meshes
+    }
+
+    // only geometry this is the sole placement of, changing shared geometry's
+    // materials would change every other instance of it too
+    fn materials_mut(&mut self) -> Vec<&mut Arc<Material>> {
+        Arc::get_mut(&mut self.object).map_or_else(Vec::new, |object| object.materials_mut())
+    }
+}
+
+// the top level of a two level hierarchy: a BVH over instances, where each
+// instance's geometry has its own (bottom level) BVH. only the instances'
+// boxes are in this tree so building it doesn't depend on how much geometry
+// is being instanced
+pub struct InstanceBVH {
+    tree: FlatTree,
+    instances: Vec<Instance>
+}
+
+impl InstanceBVH {
+    pub fn construct(instances: Vec<Instance>) -> InstanceBVH {
+        let boxes: Vec<AABB> = instances.iter().map(|instance| {
+            instance.bounding_box.expect("Instanced geometry needs a bounding box")
+        }).collect();
+
+        InstanceBVH {
+            tree: FlatTree::build(&boxes),
+            instances
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.instances.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.instances.is_empty()
+    }
+}
+
+impl Hittable for InstanceBVH {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        self.tree.hit(ray, t_min, t_max, |instance, t_min, t_max| self.instances[instance].hit(ray, t_min, t_max))
+    }
+
+    fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<AABB> {
+        Some(self.tree.bounding_box())
+    }
+
+    fn tessellate(&self) -> Vec<ExportMesh> {
+        self.instances.iter().flat_map(|instance| instance.tessellate()).collect()
+    }
+
+    fn materials_mut(&mut self) -> Vec<&mut Arc<Material>> {
+        self.instances.iter_mut().flat_map(|instance| instance.materials_mut()).collect()
+    }
+}
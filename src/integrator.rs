@@ -0,0 +1,573 @@
+use crate::vec3::*;
+use crate::hittable::{HitRecord, Hittable};
+use crate::ray::{Ray, RayDebug};
+use crate::material::MaterialScattering;
+use crate::light::is_visible;
+use crate::utilities::{random_float, INFINITY};
+use crate::adaptive::{perceived_brightness, PixelEstimate};
+use crate::bvh_stats::take_node_visits;
+use crate::{ray_colour, sky_colour, CameraSample, ImageConfig, Scene};
+
+// the algorithm that works out the light coming back along a ray. ray_colour
+// does the tracing (and debug logging) and hands each hit to the integrator,
+// so new ones only need to say what a hit or a miss looks like
+pub trait Integrator: Send + Sync {
+    // the light leaving record's surface back along ray, with depth bounces
+    // left. throughput is how much of that light makes it back to the camera
+    // (the product of the attenuations so far), 1 for a camera ray
+    fn shade(&self, ray: &Ray, record: &HitRecord, scene: &Scene, image: &ImageConfig, depth: u64, throughput: Color) -> Color;
+
+    // the light arriving along a ray that hit nothing
+    fn background(&self, ray: &Ray, scene: &Scene) -> Color {
+        sky_colour(&ray.direction, scene)
+    }
+}
+
+// follows light back from the camera, one bounce at a time, letting each
+// material pick where it goes next
+#[derive(Copy, Clone, Debug)]
+pub struct PathTracer {
+    // every hit also checks each light directly with a shadow ray. without it
+    // light only arrives from bounces that happen to hit something bright, so
+    // it's much noisier and lights that aren't objects (see Light) are never
+    // found at all. it's there to check the direct lighting against
+    pub next_event_estimation: bool
+}
+
+impl PathTracer {
+    pub fn new() -> PathTracer {
+        PathTracer {
+            next_event_estimation: true
+        }
+    }
+
+    pub fn with_next_event_estimation(mut self, next_event_estimation: bool) -> PathTracer {
+        self.next_event_estimation = next_event_estimation;
+        self
+    }
+}
+
+impl Default for PathTracer {
+    fn default() -> PathTracer {
+        PathTracer::new()
+    }
+}
+
+impl Integrator for PathTracer {
+    fn shade(&self, ray: &Ray, record: &HitRecord, scene: &Scene, image: &ImageConfig, depth: u64, throughput: Color) -> Color {
+        let settings = &image.integrator;
+        // light the surface gives off, plus light reaching it straight from the lights
+        let mut direct = record.material.emitted(record);
+        if self.next_event_estimation {
+            for light in scene.lights.iter() {
+                if let Some(sample) = light.sample(&record.point) {
+                    let reflected = record.material.evaluate(ray, record, &sample.direction);
+                    if !reflected.near_zero() && is_visible(&scene.world, record, &sample, ray.time, settings) {
+                        direct = direct + reflected * sample.radiance;
+                    }
+                }
+            }
+        }
+
+        if let Some(scattering) = record.material.scatter(ray, record) {
+            let mut attenuation = scattering.attenuation();
+            let bounce = image.max_depth - depth;
+            // roulette on what the whole path carries, not just this bounce: a
+            // path that's already been dimmed by several dark bounces adds
+            // little whatever it finds next
+            if let Some(survival) = settings.survival_probability(bounce, (throughput * attenuation).max_component()) {
+                if random_float() > survival {
+                    return direct
+                }
+                attenuation = attenuation / survival;
+            }
+            let mut scattered = scattering.scattered().with_debug(ray.debug.map(RayDebug::next_bounce));
+            scattered.origin = settings.offset_origin(record, &scattered.direction, 0.0);
+            // differentials only follow mirror bounces, anything rougher spreads
+            // the footprint far more than a pixel
+            let mirror = Vec3::reflect(&ray.direction.unit_vector(), &record.normal);
+            if (scattered.direction.unit_vector() - mirror).length_squared() < 1e-12 {
+                scattered.differential = ray.differential.and_then(|differential| differential.reflect(record));
+            }
+            return direct + attenuation * ray_colour(&scattered, scene, image, depth - 1, throughput * attenuation);
+        }
+
+        direct
+    }
+}
+
+// renders how enclosed each surface is instead of its lighting: 1 out in the
+// open, darker in creases and under things. it ignores materials and lights,
+// so it's a quick look at the geometry (and whether the accelerator finds it)
+#[derive(Copy, Clone, Debug)]
+pub struct AmbientOcclusion {
+    // rays per camera hit
+    pub rays: u32,
+    // only things closer than this occlude, so e.g. walls across a room don't
+    pub max_distance: f64
+}
+
+impl AmbientOcclusion {
+    pub fn new(rays: u32) -> AmbientOcclusion {
+        AmbientOcclusion {
+            rays: rays.max(1),
+            max_distance: INFINITY
+        }
+    }
+
+    pub fn with_max_distance(mut self, max_distance: f64) -> AmbientOcclusion {
+        self.max_distance = max_distance;
+        self
+    }
+
+    // the fraction of rays from record's point that get away. they're picked
+    // the way diffuse light arrives (cosine weighted), so it's how lit a
+    // white surface would be under a uniform white sky
+    pub fn visibility(&self, world: &dyn Hittable, record: &HitRecord, time: f64, settings: &IntegratorSettings) -> f64 {
+        let mut unoccluded = 0;
+        for _ in 0..self.rays {
+            let direction = record.normal + Vec3::random_unit_vector();
+            if direction.near_zero() {
+                unoccluded += 1;
+                continue
+            }
+            let ray = Ray::new(settings.offset_origin(record, &direction, 0.0), direction.unit_vector(), Some(time));
+            if world.hit(&ray, settings.continuation_epsilon, self.max_distance).is_none() {
+                unoccluded += 1;
+            }
+        }
+        unoccluded as f64 / self.rays as f64
+    }
+}
+
+impl Integrator for AmbientOcclusion {
+    fn shade(&self, ray: &Ray, record: &HitRecord, scene: &Scene, image: &ImageConfig, _depth: u64, _throughput: Color) -> Color {
+        let visibility = self.visibility(&scene.world, record, ray.time, &image.integrator);
+        Color::new(visibility, visibility, visibility)
+    }
+
+    // the sky counts as open, so it's white
+    fn background(&self, _ray: &Ray, _scene: &Scene) -> Color {
+        Color::new(1.0, 1.0, 1.0)
+    }
+}
+
+// shows something about the first hit instead of its shading, for finding
+// what's wrong with geometry, texture coordinates or an accelerator
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum DebugView {
+    // the shading normal (facing the ray), from -1..1 to 0..1 per channel
+    Normals,
+    // how far away the hit is: white right at the camera, fading to black at far
+    Depth{far: f64},
+    // the texture coordinates as red (u) and green (v), wrapped to 0..1
+    Uv,
+    // blue where the ray hit the outside of a surface, red the inside
+    // (flipped normals, or a camera inside something), shaded by how
+    // squarely it faces the camera so the shapes stay readable
+    Faces,
+    // how many nodes of the acceleration structure the camera ray looked at
+    // before finding its hit (or nothing), from blue at none through green to red
+    // at max_visits, logarithmically. a plain list of objects counts nothing
+    Heatmap{max_visits: u64}
+}
+
+impl DebugView {
+    // the heat map's colour for this many visits
+    fn heat(&self, visits: u64) -> Color {
+        let max_visits = match self {
+            DebugView::Heatmap{max_visits} => *max_visits,
+            _ => return Color::new(0.0, 0.0, 0.0)
+        };
+        let t = ((visits as f64).ln_1p() / (max_visits.max(1) as f64).ln_1p()).clamp(0.0, 1.0);
+        // blue to green to red
+        if t < 0.5 {
+            Color::new(0.0, 2.0 * t, 1.0 - 2.0 * t)
+        } else {
+            Color::new(2.0 * t - 1.0, 2.0 - 2.0 * t, 0.0)
+        }
+    }
+}
+
+impl Integrator for DebugView {
+    fn shade(&self, ray: &Ray, record: &HitRecord, _scene: &Scene, _image: &ImageConfig, _depth: u64, _throughput: Color) -> Color {
+        match self {
+            DebugView::Normals => (record.normal + Vec3::new(1.0, 1.0, 1.0)) * 0.5,
+            DebugView::Depth{far} => {
+                let closeness = (1.0 - record.t * ray.direction.length() / far).clamp(0.0, 1.0);
+                Color::new(closeness, closeness, closeness)
+            },
+            DebugView::Uv => Color::new(record.u.rem_euclid(1.0), record.v.rem_euclid(1.0), 0.0),
+            DebugView::Faces => {
+                let facing = 0.2 + 0.8 * record.normal.dot_product(&ray.direction.unit_vector()).abs();
+                let colour = if record.front_face { Color::new(0.2, 0.4, 1.0) } else { Color::new(1.0, 0.2, 0.2) };
+                colour * facing
+            },
+            // everything this thread visited since the last camera ray was
+            // coloured, i.e. this one's search
+            DebugView::Heatmap{..} => self.heat(take_node_visits())
+        }
+    }
+
+    fn background(&self, _ray: &Ray, _scene: &Scene) -> Color {
+        match self {
+            // rays that find nothing still search
+            DebugView::Heatmap{..} => self.heat(take_node_visits()),
+            _ => Color::new(0.0, 0.0, 0.0)
+        }
+    }
+}
+
+// which integrator renders the image, `--integrator name` picks one
+#[derive(Copy, Clone, Debug)]
+pub enum IntegratorKind {
+    PathTracer(PathTracer),
+    AmbientOcclusion(AmbientOcclusion),
+    Debug(DebugView)
+}
+
+impl IntegratorKind {
+    pub fn parse(name: &str) -> Option<IntegratorKind> {
+        match name {
+            "path" => Some(IntegratorKind::PathTracer(PathTracer::new())),
+            "naive" => Some(IntegratorKind::PathTracer(PathTracer::new().with_next_event_estimation(false))),
+            "ao" => Some(IntegratorKind::AmbientOcclusion(AmbientOcclusion::new(16))),
+            "normals" => Some(IntegratorKind::Debug(DebugView::Normals)),
+            "depth" => Some(IntegratorKind::Debug(DebugView::Depth{far: 20.0})),
+            "uv" => Some(IntegratorKind::Debug(DebugView::Uv)),
+            "faces" => Some(IntegratorKind::Debug(DebugView::Faces)),
+            "heatmap" => Some(IntegratorKind::Debug(DebugView::Heatmap{max_visits: 256})),
+            _ => None
+        }
+    }
+
+    pub fn integrator(&self) -> &dyn Integrator {
+        match self {
+            IntegratorKind::PathTracer(path_tracer) => path_tracer,
+            IntegratorKind::AmbientOcclusion(ambient_occlusion) => ambient_occlusion,
+            IntegratorKind::Debug(view) => view
+        }
+    }
+}
+
+// outlier_rejection leaves pixels with fewer samples alone
+pub const OUTLIER_MIN_SAMPLES: u64 = 8;
+
+// how rays are traced, as opposed to what they hit. the defaults suit scenes
+// measured in metres-ish units (objects from ~0.1 to ~1000 across)
+pub struct IntegratorSettings {
+    // how far along a bounced (continuation) ray the first hit can be. too small
+    // and a surface can hit itself (shadow acne), too big and rays skip through
+    // thin objects
+    pub continuation_epsilon: f64,
+    // how far shadow rays start off the surface and stop short of the light.
+    // shadow rays only need to clear the surface they start on, and any bigger
+    // offset makes shadows detach from what casts them (peter-panning) or light
+    // leak under thin objects, so this is smaller than continuation_epsilon
+    pub shadow_epsilon: f64,
+    // rays leaving a surface (bounces, shadow and sky rays) also start this far
+    // off it along its normal per unit of the hit point's size (its largest
+    // coordinate). rounding errors in hit points grow with their coordinates,
+    // so the fixed epsilons alone aren't enough on e.g. a radius 1000 ground
+    // sphere or kilometres of terrain
+    pub relative_offset: f64,
+    // camera rays carry the rays through the neighbouring pixels (see
+    // RayDifferential), so image textures can be filtered to the pixel's
+    // footprint. it costs two extra rays per camera hit, so it's off by default
+    pub ray_differentials: bool,
+    // after this many bounces paths are randomly ended (russian roulette), with
+    // the survivors brightened to make up for it. shadow rays are never ended,
+    // they're already a single cheap test and losing them only adds noise
+    pub russian_roulette_depth: u64,
+    // paths always survive with at least this probability
+    pub min_survival: f64,
+    // fireflies are pixels made far too bright by one rare sample, e.g. a
+    // diffuse bounce that finds a small light through a mirror. both of these
+    // get rid of them by throwing away some of the light, so the image comes
+    // out darker than it should (biased) and they're off by default.
+    // samples with a luminance over this are dimmed to it, keeping their hue
+    pub firefly_clamp: Option<f64>,
+    // samples this many standard deviations brighter than the rest of their
+    // pixel's are thrown away, left out of both its colour and the statistics
+    // the next samples are judged by. only once a pixel has
+    // OUTLIER_MIN_SAMPLES to know what's normal for it
+    pub outlier_rejection: Option<f64>,
+    // what the rays are traced with
+    pub kind: IntegratorKind
+}
+
+impl Default for IntegratorSettings {
+    fn default() -> IntegratorSettings {
+        IntegratorSettings {
+            continuation_epsilon: 0.001,
+            shadow_epsilon: 0.0001,
+            relative_offset: 1e-7,
+            ray_differentials: false,
+            russian_roulette_depth: 5,
+            min_survival: 0.05,
+            firefly_clamp: None,
+            outlier_rejection: None,
+            kind: IntegratorKind::PathTracer(PathTracer::new())
+        }
+    }
+}
+
+impl IntegratorSettings {
+    // the epsilons have to grow and shrink with the scene: a scene in
+    // millimetres needs them 1000x smaller than one in metres
+    pub fn for_scene_scale(scale: f64) -> IntegratorSettings {
+        let defaults = IntegratorSettings::default();
+        IntegratorSettings {
+            continuation_epsilon: defaults.continuation_epsilon * scale,
+            shadow_epsilon: defaults.shadow_epsilon * scale,
+            ..defaults
+        }
+    }
+
+    pub fn with_relative_offset(mut self, relative_offset: f64) -> IntegratorSettings {
+        self.relative_offset = relative_offset;
+        self
+    }
+
+    pub fn with_ray_differentials(mut self, ray_differentials: bool) -> IntegratorSettings {
+        self.ray_differentials = ray_differentials;
+        self
+    }
+
+    pub fn with_kind(mut self, kind: IntegratorKind) -> IntegratorSettings {
+        self.kind = kind;
+        self
+    }
+
+    pub fn with_firefly_clamp(mut self, max_luminance: f64) -> IntegratorSettings {
+        self.firefly_clamp = Some(max_luminance);
+        self
+    }
+
+    pub fn with_outlier_rejection(mut self, deviations: f64) -> IntegratorSettings {
+        self.outlier_rejection = Some(deviations);
+        self
+    }
+
+    // sample as it should be added to estimate, after firefly_clamp, or none
+    // if outlier_rejection throws it away (see PixelEstimate::reject)
+    pub fn filter_sample(&self, mut sample: CameraSample, estimate: &PixelEstimate) -> Option<CameraSample> {
+        if let Some(max_luminance) = self.firefly_clamp {
+            let luminance = sample.colour.luminance();
+            if luminance > max_luminance {
+                let scale = max_luminance / luminance;
+                sample.colour = sample.colour * scale;
+                sample.foreground = sample.foreground * scale;
+            }
+        }
+        if let Some(deviations) = self.outlier_rejection {
+            if estimate.measured() >= OUTLIER_MIN_SAMPLES {
+                let deviation = (estimate.m2 / (estimate.measured() - 1) as f64).sqrt();
+                if perceived_brightness(&sample.colour) > estimate.mean + deviations * deviation {
+                    return None
+                }
+            }
+        }
+        Some(sample)
+    }
+
+    // where a ray leaving the hit in direction starts: pushed off the surface
+    // to the side the ray leaves on (so refracted rays go in, reflected ones
+    // out) by an amount that scales with the point's coordinates
+    pub fn offset_origin(&self, record: &HitRecord, direction: &Vec3, epsilon: f64) -> Vec3 {
+        let side = if record.normal.dot_product(direction) >= 0.0 { 1.0 } else { -1.0 };
+        let offset = epsilon + self.relative_offset * record.point.abs().max_component();
+        record.point + record.normal * (side * offset)
+    }
+
+    // the chance a path carrying the given throughput continues, or None if
+    // it's too early for russian roulette
+    pub fn survival_probability(&self, bounce: u64, throughput: f64) -> Option<f64> {
+        if bounce < self.russian_roulette_depth {
+            return None
+        }
+        Some(throughput.clamp(self.min_survival, 1.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Material;
+    use crate::texture::SolidTexture;
+    use crate::utilities::seed_rng;
+
+    #[test]
+    fn test_offset_grows_with_coordinates() {
+        let material = Material::Lambertian{albedo: Box::new(SolidTexture::uniform(0.5)), normal_map: None};
+        let settings = IntegratorSettings::default().with_relative_offset(1e-6);
+        let up = Vec3::new(0.0, 1.0, 0.0);
+        let offset = |point: Vec3, direction: Vec3| {
+            let record = HitRecord::new(point, up, 1.0, 0.0, 0.0, true, &material);
+            settings.offset_origin(&record, &direction, 0.0) - point
+        };
+        // leaving through the back goes the other way
+        assert!((offset(Vec3::new(1.0, 0.0, 0.0), up).y() - 1e-6).abs() < 1e-15);
+        assert!((offset(Vec3::new(1.0, 0.0, 0.0), up * -1.0).y() + 1e-6).abs() < 1e-15);
+        assert!((offset(Vec3::new(0.0, -2000.0, 500.0), up).y() - 2e-3).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_ambient_occlusion() {
+        use crate::sphere::Sphere;
+        let material = || Material::Lambertian{albedo: Box::new(SolidTexture::uniform(0.5)), normal_map: None};
+        let settings = IntegratorSettings::default();
+        // a point on the floor right under a big ball, and one far from it
+        let ball = Sphere::new(Vec3::new(0.0, 1.01, 0.0), 1.0, material());
+        let floor = material();
+        let at = |x: f64| HitRecord::new(Vec3::new(x, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0), 1.0, 0.0, 0.0, true, &floor);
+        let ao = AmbientOcclusion::new(256);
+        // seeded, since far away the ball still covers a sliver of the sky
+        // that the odd ray finds
+        seed_rng(3);
+        assert!(ao.visibility(&ball, &at(0.0), 0.0, &settings) < 0.3);
+        assert!(ao.visibility(&ball, &at(50.0), 0.0, &settings) > 0.98);
+        // the ball's too far away to count, whichever way the rays go
+        assert_eq!(ao.with_max_distance(0.005).visibility(&ball, &at(0.0), 0.0, &settings), 1.0);
+    }
+
+    #[test]
+    fn test_integrators_light_a_floor() {
+        use crate::light::Light;
+        use crate::hittable_list::HittableList;
+        let floor = Material::Lambertian{albedo: Box::new(SolidTexture::uniform(0.5)), normal_map: None};
+        let mut scene = Scene::from(HittableList::new());
+        scene.lights.push(Light::Point{position: Vec3::new(0.0, 1.0, 0.0), intensity: Color::new(1.0, 1.0, 1.0)});
+        let image = crate::ImageConfig::new(1.0, 10, 1, 1);
+        let ray = Ray::new(Vec3::new(0.0, 1.0, 1.0), Vec3::new(0.0, -1.0, -1.0), None);
+        let record = HitRecord::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0), 1.0, 0.0, 0.0, true, &floor);
+
+        // with no bounces left only the direct light counts, which only next
+        // event estimation finds
+        let shade = |name: &str| IntegratorKind::parse(name).unwrap().integrator().shade(&ray, &record, &scene, &image, 1, Color::new(1.0, 1.0, 1.0));
+        assert!((shade("path").y() - 0.5 / std::f64::consts::PI).abs() < 1e-9, "{:?}", shade("path"));
+        assert_eq!(shade("naive").y(), 0.0);
+        assert!(shade("ao").equal_to(&Color::new(1.0, 1.0, 1.0)));
+        assert!(shade("normals").equal_to(&Color::new(0.5, 1.0, 0.5)));
+        // the ray's direction is sqrt(2) long, the floor 1 away along it
+        assert!((shade("depth").x() - (1.0 - 2f64.sqrt() / 20.0)).abs() < 1e-9);
+        assert!(shade("uv").equal_to(&Color::new(0.0, 0.0, 0.0)));
+        assert!(shade("faces").z() > shade("faces").x());
+        take_node_visits();
+        assert!(shade("heatmap").equal_to(&Color::new(0.0, 0.0, 1.0)));
+        assert!(IntegratorKind::parse("whitted").is_none());
+    }
+
+    #[test]
+    fn test_debug_views() {
+        let material = Material::Lambertian{albedo: Box::new(SolidTexture::uniform(0.5)), normal_map: None};
+        let scene = Scene::from(crate::hittable_list::HittableList::new());
+        let image = crate::ImageConfig::new(1.0, 10, 1, 1);
+        let shade = |view: DebugView, ray: &Ray, record: &HitRecord| view.shade(ray, record, &scene, &image, 1, Color::new(1.0, 1.0, 1.0));
+        let down = Ray::new(Vec3::new(0.0, 4.0, 0.0), Vec3::new(0.0, -2.0, 0.0), None);
+        // the floor is 4 away, half a ray direction per unit of t
+        let floor = HitRecord::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0), 2.0, 1.25, -0.25, true, &material);
+        assert!(shade(DebugView::Depth{far: 8.0}, &down, &floor).equal_to(&Color::new(0.5, 0.5, 0.5)));
+        assert!(shade(DebugView::Depth{far: 2.0}, &down, &floor).equal_to(&Color::new(0.0, 0.0, 0.0)));
+        // the coordinates wrap, so tiled ones still show
+        assert!(shade(DebugView::Uv, &down, &floor).equal_to(&Color::new(0.25, 0.75, 0.0)));
+        // front faces blue, back faces red, dimmer the more glancing
+        assert!(shade(DebugView::Faces, &down, &floor).equal_to(&Color::new(0.2, 0.4, 1.0)));
+        let glancing = Ray::new(Vec3::new(-1.0, 1.0, 0.0), Vec3::new(1.0, -1.0, 0.0), None);
+        let back = HitRecord::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0), 1.0, 0.0, 0.0, false, &material);
+        let facing = 0.2 + 0.8 * 0.5f64.sqrt();
+        assert!((shade(DebugView::Faces, &glancing, &back) - Color::new(1.0, 0.2, 0.2) * facing).length() < 1e-12);
+    }
+
+    #[test]
+    fn test_heatmap_follows_the_search() {
+        use crate::flat_bvh::FlatBVH;
+        use crate::sphere::Sphere;
+        // a row of balls along z, so a ray down the row searches more of the
+        // tree than one across it
+        let balls: Vec<Box<dyn Hittable>> = (0..64).map(|i| {
+            let material = Material::Lambertian{albedo: Box::new(SolidTexture::uniform(0.5)), normal_map: None};
+            Box::new(Sphere::new(Vec3::new(0.0, 0.0, -2.0 * i as f64), 0.9, material)) as Box<dyn Hittable>
+        }).collect();
+        let mut scene = Scene::from(crate::hittable_list::HittableList::new());
+        scene.world.add(FlatBVH::construct(balls, 0.0, 1.0));
+        let view = DebugView::Heatmap{max_visits: 63};
+        let image = crate::ImageConfig::new(1.0, 10, 1, 1)
+            .with_integrator(IntegratorSettings::default().with_kind(IntegratorKind::Debug(view)));
+
+        // the ramp: blue for nothing, green half way (on a log scale), red at the most
+        assert!(view.heat(0).equal_to(&Color::new(0.0, 0.0, 1.0)));
+        assert!((view.heat(7) - Color::new(0.0, 1.0, 0.0)).length() < 1e-12);
+        assert!(view.heat(63).equal_to(&Color::new(1.0, 0.0, 0.0)));
+        assert!(view.heat(1000).equal_to(&Color::new(1.0, 0.0, 0.0)));
+
+        let colour = |ray: &Ray| {
+            take_node_visits();
+            let colour = crate::camera_sample(ray, &scene, &image).colour;
+            // the same search again, counted by hand
+            scene.world.hit(ray, image.integrator.continuation_epsilon, INFINITY);
+            assert!(colour.equal_to(&view.heat(take_node_visits())));
+            colour
+        };
+        // grazing the whole row inside the balls' boxes finds nothing, after
+        // opening every box. across it, between two balls, only a few are
+        let along = colour(&Ray::new(Vec3::new(0.65, 0.65, 10.0), Vec3::new(0.0, 0.0, -1.0), None));
+        let across = colour(&Ray::new(Vec3::new(-10.0, 0.0, -63.0), Vec3::new(1.0, 0.0, 0.0), None));
+        assert!(along.x() > across.x() && along.z() < across.z(), "{:?} {:?}", along, across);
+    }
+
+    #[test]
+    fn test_survival_probability() {
+        let settings = IntegratorSettings::default();
+        assert_eq!(settings.survival_probability(settings.russian_roulette_depth - 1, 0.01), None);
+        assert_eq!(settings.survival_probability(settings.russian_roulette_depth, 0.5), Some(0.5));
+        assert_eq!(settings.survival_probability(settings.russian_roulette_depth, 2.0), Some(1.0));
+        // three 0.2 bounces leave a path little worth following, though the
+        // next bounce alone is bright
+        let throughput = Color::new(0.2, 0.2, 0.2) * Color::new(0.2, 0.2, 0.2) * Color::new(0.2, 0.2, 0.2);
+        let survival = settings.survival_probability(settings.russian_roulette_depth, (throughput * Color::new(0.9, 0.9, 0.9)).max_component());
+        assert_eq!(survival, Some(settings.min_survival));
+    }
+
+    #[test]
+    fn test_epsilons_follow_scene_scale() {
+        let millimetres = IntegratorSettings::for_scene_scale(0.001);
+        let defaults = IntegratorSettings::default();
+        assert!((millimetres.continuation_epsilon - defaults.continuation_epsilon / 1000.0).abs() < 1e-15);
+        assert!((millimetres.shadow_epsilon - defaults.shadow_epsilon / 1000.0).abs() < 1e-15);
+        let entry = crate::find_scene("zoomed-in").unwrap();
+        let (image, _, _) = entry.build(&crate::SceneOptions{scale: Some(0.001), ..Default::default()});
+        assert_eq!(image.integrator.continuation_epsilon, millimetres.continuation_epsilon);
+        assert_eq!(image.integrator.shadow_epsilon, millimetres.shadow_epsilon);
+    }
+
+    #[test]
+    fn test_firefly_filters() {
+        let grey = |value: f64| CameraSample{colour: Color::new(value, value, value), foreground: Color::new(value, value, value), alpha: 1.0, layer: None, first_hit: None};
+        let mut estimate = PixelEstimate::new();
+        // off by default
+        let firefly = IntegratorSettings::default().filter_sample(grey(100.0), &estimate).unwrap();
+        assert_eq!(firefly.colour.x(), 100.0);
+
+        let clamped = IntegratorSettings::default().with_firefly_clamp(10.0).filter_sample(grey(100.0), &estimate).unwrap();
+        assert!((clamped.colour.luminance() - 10.0).abs() < 1e-9 && (clamped.foreground.y() - 10.0).abs() < 1e-9);
+        assert_eq!(IntegratorSettings::default().with_firefly_clamp(10.0).filter_sample(grey(0.5), &estimate).unwrap().colour.x(), 0.5);
+
+        let rejecting = IntegratorSettings::default().with_outlier_rejection(3.0);
+        for i in 0..OUTLIER_MIN_SAMPLES {
+            // too few samples to tell yet
+            assert_eq!(rejecting.filter_sample(grey(100.0), &estimate).unwrap().colour.x(), 100.0);
+            estimate.add(Color::new(1.0, 1.0, 1.0) * (0.4 + 0.025 * i as f64));
+        }
+        assert!(rejecting.filter_sample(grey(100.0), &estimate).is_none());
+        assert_eq!(rejecting.filter_sample(grey(0.6), &estimate).unwrap().colour.x(), 0.6);
+
+        // a rejected sample moves the sample count on, but leaves the colour
+        // and the statistics the next samples are judged by as they were
+        let (colour, mean, m2, deviation) = (estimate.colour(), estimate.mean, estimate.m2, estimate.relative_deviation());
+        estimate.reject();
+        assert_eq!((estimate.count, estimate.measured()), (OUTLIER_MIN_SAMPLES + 1, OUTLIER_MIN_SAMPLES));
+        assert!(estimate.colour().equal_to(&colour));
+        assert_eq!((estimate.mean, estimate.m2, estimate.relative_deviation()), (mean, m2, deviation));
+    }
+}
@@ -0,0 +1,306 @@
+use crate::Ray;
+use crate::vec3::Vec3;
+use crate::aabb::AABB;
+use crate::export::ExportMesh;
+use crate::bvh_stats::*;
+use crate::ray_stats::count_primitive_test;
+use crate::material::Material;
+use crate::hittable::*;
+use std::sync::Arc;
+
+// the cost estimates used by the surface area heuristic (sah). only their ratio
+// matters: intersecting a primitive is assumed to be 80x the work of stepping
+// through a node
+const TRAVERSAL_COST: f64 = 1.0;
+const INTERSECTION_COST: f64 = 80.0;
+// splits that cut off empty space are worth more, rays can skip that side for free
+const EMPTY_BONUS: f64 = 0.5;
+// stop splitting after this many splits in a row that didn't look worth it
+const MAX_BAD_REFINES: u32 = 3;
+// also bounds the size of the traversal stack
+const MAX_DEPTH: usize = 64;
+
+enum KdNode {
+    Leaf {
+        primitives: Vec<usize>
+    },
+    // everything below `split` along `axis` is in the `below` subtree, and
+    // everything above in `above`. primitives crossing the plane are in both
+    Branch {
+        axis: usize,
+        split: f64,
+        below: usize,
+        above: usize
+    }
+}
+
+// where a primitive's box starts or ends along an axis
+struct Edge {
+    position: f64,
+    starts: bool,
+    primitive: usize
+}
+
+fn component(vector: &Vec3, axis: usize) -> f64 {
+    match axis {
+        0 => vector.x(),
+        1 => vector.y(),
+        _ => vector.z()
+    }
+}
+
+// cuts a box in two along the plane at split on the given axis
+fn split_box(node_box: &AABB, axis: usize, split: f64) -> (AABB, AABB) {
+    let set = |vector: &Vec3, value: f64| match axis {
+        0 => Vec3::new(value, vector.y(), vector.z()),
+        1 => Vec3::new(vector.x(), value, vector.z()),
+        _ => Vec3::new(vector.x(), vector.y(), value)
+    };
+    (AABB::new(node_box.minimum, set(&node_box.maximum, split)), AABB::new(set(&node_box.minimum, split), node_box.maximum))
+}
+
+// k-dimensional tree.
+// unlike a bvh, which splits the objects into groups, a kd-tree splits space:
+// each node cuts its box in two with an axis-aligned plane. the children never
+// overlap, so a ray can visit them strictly front to back and stop at the first
+// node that contains a hit. the downside is that objects crossing a plane end up
+// in both children. works best for scenes made of axis-aligned geometry
+// (buildings, voxels, boxes) where good planes are easy to find
+pub struct KdTree {
+    nodes: Vec<KdNode>,
+    objects: Vec<Box<dyn Hittable>>,
+    bounding_box: AABB
+}
+
+impl KdTree {
+    pub fn construct(list: Vec<Box<dyn Hittable>>, t0: f64, t1: f64) -> KdTree {
+        if list.is_empty() {
+            panic!("Cannot have 0 objects in list during kd-tree construction");
+        }
+        let boxes: Vec<AABB> = list.iter().map(|object| {
+            object.bounding_box(t0, t1).expect("No bounding box in kd-tree node")
+        }).collect();
+        let bounding_box = boxes.iter().skip(1).fold(boxes[0], |result, next| AABB::surrounding_box(result, *next));
+
+        // the usual rule of thumb for how deep a kd-tree is worth going
+        let max_depth = ((8.0 + 1.3 * (boxes.len() as f64).log2()).round() as usize).min(MAX_DEPTH);
+        let mut nodes = Vec::new();
+        KdTree::build(&mut nodes, &boxes, bounding_box, (0..boxes.len()).collect(), max_depth, 0);
+
+        KdTree {
+            nodes,
+            objects: list,
+            bounding_box
+        }
+    }
+
+    // appends the subtree for the given primitives and returns its root
+    fn build(nodes: &mut Vec<KdNode>, boxes: &[AABB], node_box: AABB, primitives: Vec<usize>, depth: usize, mut bad_refines: u32) -> usize {
+        let index = nodes.len();
+        if primitives.len() <= 1 || depth == 0 {
+            nodes.push(KdNode::Leaf{primitives});
+            return index
+        }
+
+        // try every box edge on every axis as a splitting plane and keep the cheapest
+        let total_area = node_box.surface_area();
+        let extent = node_box.maximum - node_box.minimum;
+        let mut best: Option<(f64, usize, usize)> = None;
+        let mut best_edges = Vec::new();
+        for axis in 0..3 {
+            let mut edges: Vec<Edge> = primitives.iter().flat_map(|&primitive| [
+                Edge{position: component(&boxes[primitive].minimum, axis), starts: true, primitive},
+                Edge{position: component(&boxes[primitive].maximum, axis), starts: false, primitive}
+            ]).collect();
+            // at the same position starts come first, so flat primitives on a plane aren't lost
+            edges.sort_by(|a, b| a.position.total_cmp(&b.position).then(b.starts.cmp(&a.starts)));
+
+            let (other_1, other_2) = (component(&extent, (axis + 1) % 3), component(&extent, (axis + 2) % 3));
+            let (low, high) = (component(&node_box.minimum, axis), component(&node_box.maximum, axis));
+            let mut below = 0;
+            let mut above = primitives.len();
+            for (offset, edge) in edges.iter().enumerate() {
+                if !edge.starts {
+                    above -= 1;
+                }
+                if edge.position > low && edge.position < high {
+                    let below_area = 2.0 * (other_1 * other_2 + (edge.position - low) * (other_1 + other_2));
+                    let above_area = 2.0 * (other_1 * other_2 + (high - edge.position) * (other_1 + other_2));
+                    let bonus = if below == 0 || above == 0 { EMPTY_BONUS } else { 0.0 };
+                    let cost = TRAVERSAL_COST + INTERSECTION_COST * (1.0 - bonus)
+                        * (below_area * below as f64 + above_area * above as f64) / total_area;
+                    if best.is_none_or(|(best_cost, _, _)| cost < best_cost) {
+                        best = Some((cost, axis, offset));
+                    }
+                }
+                if edge.starts {
+                    below += 1;
+                }
+            }
+            if best.is_some_and(|(_, best_axis, _)| best_axis == axis) {
+                best_edges = edges;
+            }
+        }
+
+        let leaf_cost = INTERSECTION_COST * primitives.len() as f64;
+        let (cost, axis, offset) = match best {
+            Some(split) => split,
+            None => {
+                nodes.push(KdNode::Leaf{primitives});
+                return index
+            }
+        };
+        if cost > leaf_cost {
+            bad_refines += 1;
+        }
+        if (cost > 4.0 * leaf_cost && primitives.len() < 16) || bad_refines == MAX_BAD_REFINES {
+            nodes.push(KdNode::Leaf{primitives});
+            return index
+        }
+
+        let split = best_edges[offset].position;
+        let below_primitives: Vec<usize> = best_edges[..offset].iter().filter(|edge| edge.starts).map(|edge| edge.primitive).collect();
+        let above_primitives: Vec<usize> = best_edges[offset + 1..].iter().filter(|edge| !edge.starts).map(|edge| edge.primitive).collect();
+        let (below_box, above_box) = split_box(&node_box, axis, split);
+
+        // placeholder until the children's positions are known
+        nodes.push(KdNode::Leaf{primitives: Vec::new()});
+        let below = KdTree::build(nodes, boxes, below_box, below_primitives, depth - 1, bad_refines);
+        let above = KdTree::build(nodes, boxes, above_box, above_primitives, depth - 1, bad_refines);
+        nodes[index] = KdNode::Branch{axis, split, below, above};
+        index
+    }
+}
+
+impl KdTree {
+    // the children of a node never overlap, but primitives crossing a plane are
+    // counted in every leaf they're in
+    pub fn stats(&self) -> BvhStats {
+        let mut collector = StatsCollector::new(&self.bounding_box);
+        let mut stack = vec![(0, 0, self.bounding_box)];
+        while let Some((index, depth, node_box)) = stack.pop() {
+            match &self.nodes[index] {
+                KdNode::Leaf{primitives} => collector.leaf(depth, primitives.len()),
+                KdNode::Branch{axis, split, below, above} => {
+                    let (below_box, above_box) = split_box(&node_box, *axis, *split);
+                    collector.branch(depth, &node_box, &below_box, &above_box);
+                    stack.push((*below, depth + 1, below_box));
+                    stack.push((*above, depth + 1, above_box));
+                }
+            }
+        }
+        collector.finish()
+    }
+}
+
+impl Hittable for KdTree {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let (mut node_min, mut node_max) = self.bounding_box.hit_range(ray, t_min, t_max)?;
+        // nodes still to visit, with the part of the ray inside each
+        let mut stack = [(0usize, 0.0, 0.0); MAX_DEPTH];
+        let mut stack_size = 0;
+        let mut node = 0;
+        let mut closest_so_far = t_max;
+        let mut result: Option<HitRecord> = None;
+
+        // nodes are visited front to back, so once a hit is closer than the
+        // start of the next node nothing behind it can be closer
+        while closest_so_far >= node_min {
+            count_node_visit();
+            match &self.nodes[node] {
+                KdNode::Branch{axis, split, below, above} => {
+                    let origin = component(&ray.origin, *axis);
+                    let direction = component(&ray.direction, *axis);
+                    let t_split = (split - origin) / direction;
+                    let below_first = origin < *split || (origin == *split && direction <= 0.0);
+                    let (first, second) = if below_first { (*below, *above) } else { (*above, *below) };
+
+                    // NaN when the ray runs along the plane
+                    if t_split.is_nan() || t_split > node_max || t_split <= 0.0 {
+                        node = first;
+                    } else if t_split < node_min {
+                        node = second;
+                    } else {
+                        stack[stack_size] = (second, t_split, node_max);
+                        stack_size += 1;
+                        node = first;
+                        node_max = t_split;
+                    }
+                },
+                KdNode::Leaf{primitives} => {
+                    for &primitive in primitives.iter() {
+                        count_primitive_test();
+                        if let Some(hit) = self.objects[primitive].hit(ray, t_min, closest_so_far) {
+                            closest_so_far = hit.t;
+                            result = Some(hit);
+                        }
+                    }
+                    if stack_size == 0 {
+                        break
+                    }
+                    stack_size -= 1;
+                    (node, node_min, node_max) = stack[stack_size];
+                }
+            }
+        }
+
+        result
+    }
+
+    fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<AABB> {
+        Some(self.bounding_box)
+    }
+
+    fn tessellate(&self) -> Vec<ExportMesh> {
+        self.objects.iter().flat_map(|object| object.tessellate()).collect()
+    }
+
+    fn materials_mut(&mut self) -> Vec<&mut Arc<Material>> {
+        self.objects.iter_mut().flat_map(|object| object.materials_mut()).collect()
+    }
+
+    fn tree_stats(&self) -> Vec<BvhStats> {
+        vec![self.stats()]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittable_list::HittableList;
+    use crate::material::Material;
+    use crate::vec3::Color;
+    use crate::sphere::Sphere;
+    use crate::utilities::random_float_in_range;
+
+    #[test]
+    fn test_same_hits_as_list() {
+        let mut list = HittableList::new();
+        let mut objects: Vec<Box<dyn Hittable>> = Vec::new();
+        for i in 0..200 {
+            let center = Vec3::new((i % 10) as f64, ((i / 10) % 5) as f64, (i / 50) as f64);
+            let radius = random_float_in_range(0.1, 0.8);
+            list.add(Sphere::new(center, radius, Material::Dielectric{index_of_refraction: 1.5, absorption: Color::new(0.0, 0.0, 0.0)}));
+            objects.push(Box::new(Sphere::new(center, radius, Material::Dielectric{index_of_refraction: 1.5, absorption: Color::new(0.0, 0.0, 0.0)})));
+        }
+        let tree = KdTree::construct(objects, 0.0, 1.0);
+
+        for _ in 0..500 {
+            let origin = Vec3::random_in_range(-5.0, 15.0);
+            let ray = Ray::new(origin, Vec3::random_in_range(-1.0, 1.0), None);
+            let expected = list.hit(&ray, 0.001, f64::INFINITY).map(|hit| hit.t);
+            let found = tree.hit(&ray, 0.001, f64::INFINITY).map(|hit| hit.t);
+            match (expected, found) {
+                (Some(a), Some(b)) => assert!((a - b).abs() < 1e-9),
+                (None, None) => (),
+                _ => panic!("kd-tree and list disagree: {:?} vs {:?}", expected, found)
+            }
+        }
+
+        // a node's children only share the plane between them
+        let stats = tree.tree_stats();
+        assert_eq!(stats.len(), 1);
+        assert_eq!(stats[0].overlap_surface_area, 0.0);
+        assert!(stats[0].leaf_count > 1);
+    }
+}
@@ -0,0 +1,172 @@
+use crate::vec3::*;
+use crate::Ray;
+use crate::CameraSample;
+use crate::hittable::*;
+use crate::material::Material;
+use crate::aabb::AABB;
+use crate::export::ExportMesh;
+use std::fs::File;
+use std::io::{BufWriter, Result, Write};
+use std::sync::Arc;
+
+// rendering a scene in layers, so e.g. the foreground can be graded separately
+// from the background without rendering twice. every object is on a layer (0
+// unless it's wrapped in an OnLayer) and each camera sample counts towards the
+// layer of whatever it hits first. in every other layer that sample is a
+// holdout: black with no coverage. so the layers are premultiplied images that
+// add up exactly to the full render (the sky is its own background layer),
+// including everything the objects reflect of each other.
+
+pub struct OnLayer {
+    object: Box<dyn Hittable>,
+    layer: u32
+}
+
+impl OnLayer {
+    pub fn new(object: impl Hittable + 'static, layer: u32) -> OnLayer {
+        OnLayer {
+            object: Box::new(object),
+            layer
+        }
+    }
+}
+
+impl Hittable for OnLayer {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let mut record = self.object.hit(ray, t_min, t_max)?;
+        record.layer = self.layer;
+        Some(record)
+    }
+
+    fn bounding_box(&self, t0: f64, t1: f64) -> Option<AABB> {
+        self.object.bounding_box(t0, t1)
+    }
+
+    fn tessellate(&self) -> Vec<ExportMesh> {
+        self.object.tessellate()
+    }
+
+    fn materials_mut(&mut self) -> Vec<&mut Arc<Material>> {
+        self.object.materials_mut()
+    }
+}
+
+// per pixel sums of every layer's samples. layers are added as samples on
+// them come in, so it doesn't need to know the scene's layers up front
+pub struct LayerFilm {
+    width: u32,
+    height: u32,
+    // samples taken per pixel, the same for every layer
+    counts: Vec<u64>,
+    background: Vec<Color>,
+    // colour and coverage sums per layer, then per pixel
+    layers: Vec<Vec<(Color, f64)>>
+}
+
+impl LayerFilm {
+    pub fn new(width: u32, height: u32) -> LayerFilm {
+        let pixels = (width * height) as usize;
+        LayerFilm {
+            width,
+            height,
+            counts: vec![0; pixels],
+            background: vec![Color::new(0.0, 0.0, 0.0); pixels],
+            layers: Vec::new()
+        }
+    }
+
+    // pixel counts rows from the top
+    pub fn add(&mut self, pixel: usize, sample: &CameraSample) {
+        self.counts[pixel] += 1;
+        match sample.layer {
+            None => self.background[pixel] = self.background[pixel] + sample.colour,
+            Some(layer) => {
+                let layer = layer as usize;
+                while self.layers.len() <= layer {
+                    self.layers.push(vec![(Color::new(0.0, 0.0, 0.0), 0.0); self.counts.len()]);
+                }
+                let (colour, alpha) = self.layers[layer][pixel];
+                self.layers[layer][pixel] = (colour + sample.colour, alpha + 1.0);
+            }
+        }
+    }
+
+    // the average of a layer in one pixel, premultiplied, with its coverage.
+    // none is the background
+    pub fn pixel(&self, layer: Option<u32>, pixel: usize) -> (Color, f64) {
+        let count = self.counts[pixel].max(1) as f64;
+        let (sum, covered) = match layer {
+            None => (self.background[pixel], self.counts[pixel] as f64 - self.coverage(pixel)),
+            Some(layer) => self.layers.get(layer as usize).map_or((Color::new(0.0, 0.0, 0.0), 0.0), |pixels| pixels[pixel])
+        };
+        (sum / count, covered / count)
+    }
+
+    // how many of the pixel's samples hit an object
+    fn coverage(&self, pixel: usize) -> f64 {
+        self.layers.iter().map(|pixels| pixels[pixel].1).sum()
+    }
+
+    // writes prefix-background.pfm and prefix-layerN.pfm for every layer that
+    // was hit, each with a greyscale prefix-...-alpha.pfm of its coverage.
+    // the colours are linear and premultiplied, as compositing wants them
+    pub fn write(&self, prefix: &str) -> Result<()> {
+        let mut names = vec![(None, format!("{}-background", prefix))];
+        for layer in 0..self.layers.len() as u32 {
+            names.push((Some(layer), format!("{}-layer{}", prefix, layer)));
+        }
+        for (layer, name) in names {
+            let pixels: Vec<(Color, f64)> = (0..self.counts.len()).map(|pixel| self.pixel(layer, pixel)).collect();
+            write_pfm(&format!("{}.pfm", name), self.width, self.height, pixels.iter().map(|(colour, _)| *colour))?;
+            write_pfm(&format!("{}-alpha.pfm", name), self.width, self.height, pixels.iter().map(|(_, alpha)| Color::new(*alpha, *alpha, *alpha)))?;
+        }
+        Ok(())
+    }
+}
+
+// a float image, with pixels in rows from the top. pfm stores rows from the
+// bottom up
+pub fn write_pfm(path: &str, width: u32, height: u32, pixels: impl Iterator<Item = Color>) -> Result<()> {
+    let pixels: Vec<Color> = pixels.collect();
+    let mut file = BufWriter::new(File::create(path)?);
+    write!(file, "PF\n{} {}\n-1.0\n", width, height)?;
+    for row in pixels.chunks(width as usize).rev() {
+        for colour in row {
+            for value in [colour.x(), colour.y(), colour.z()].iter() {
+                file.write_all(&(*value as f32).to_le_bytes())?;
+            }
+        }
+    }
+    file.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sphere::Sphere;
+    use crate::texture::SolidTexture;
+
+    fn sample(colour: f64, layer: Option<u32>) -> CameraSample {
+        let colour = Color::new(colour, colour, colour);
+        CameraSample{colour, foreground: colour, alpha: 1.0, layer, first_hit: None}
+    }
+
+    #[test]
+    fn test_layers_add_up_to_the_render() {
+        let material = Material::Lambertian{albedo: Box::new(SolidTexture::new(Color::new(0.5, 0.5, 0.5))), normal_map: None};
+        let sphere = OnLayer::new(Sphere::new(Vec3::new(0.0, 0.0, -2.0), 0.5, material), 2);
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0), None);
+        assert_eq!(sphere.hit(&ray, 0.001, f64::INFINITY).unwrap().layer, 2);
+
+        let mut film = LayerFilm::new(1, 1);
+        let samples = [sample(0.8, None), sample(0.4, Some(2)), sample(0.2, Some(0)), sample(0.6, Some(2))];
+        for s in samples.iter() {
+            film.add(0, s);
+        }
+        let layers = [None, Some(0), Some(1), Some(2)];
+        let colour = layers.iter().fold(0.0, |total, layer| total + film.pixel(*layer, 0).0.x());
+        let alpha = layers.iter().fold(0.0, |total, layer| total + film.pixel(*layer, 0).1);
+        assert!((colour - 0.5).abs() < 1e-12 && (alpha - 1.0).abs() < 1e-12);
+        assert_eq!(film.pixel(Some(2), 0).1, 0.5);
+    }
+}
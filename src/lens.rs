@@ -0,0 +1,322 @@
+use crate::vec3::*;
+use crate::Ray;
+
+// a real camera lens made of several spherical glass elements, as an
+// alternative to the camera's thin lens. rays from the film are traced through
+// every element, so focusing moves the film (changing the field of view, "focus
+// breathing") and the lens' own distortion and bokeh shapes come through.
+// reference: https://pbr-book.org/3ed-2018/Camera_Models/Realistic_Cameras
+//
+// lens space follows pbrt: the film sits at z = 0 and the scene is towards -z.
+// distances in lens descriptions are in millimetres
+
+// one surface of the lens, listed from the scene side to the film side
+#[derive(Copy, Clone, Debug)]
+pub struct LensElement {
+    // radius of the spherical surface, positive when it bulges towards the
+    // scene. 0 is a flat opening, i.e. the aperture stop
+    pub curvature_radius: f64,
+    // distance along the axis to the next surface (or the film, for the last)
+    pub thickness: f64,
+    // index of refraction of the glass after this surface. 0 (the aperture
+    // stop) and 1 are air
+    pub index_of_refraction: f64,
+    // diameter of the opening
+    pub aperture: f64
+}
+
+#[derive(Clone, Debug)]
+pub struct LensSystem {
+    elements: Vec<LensElement>
+}
+
+impl LensSystem {
+    pub fn new(elements: Vec<LensElement>) -> LensSystem {
+        if elements.is_empty() {
+            panic!("A lens needs at least 1 element");
+        }
+        LensSystem {
+            elements
+        }
+    }
+
+    // the usual lens description format (e.g. pbrt's .dat files): one element
+    // per line as "radius thickness index aperture", # starts a comment
+    pub fn parse(text: &str) -> Result<LensSystem, String> {
+        let mut elements = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue
+            }
+            let values: Vec<f64> = line.split_whitespace().map(|value| value.parse::<f64>())
+                .collect::<Result<_, _>>()
+                .map_err(|e| format!("line {}: {}", number + 1, e))?;
+            if values.len() != 4 {
+                return Err(format!("line {}: expected 4 values, got {}", number + 1, values.len()))
+            }
+            elements.push(LensElement{curvature_radius: values[0], thickness: values[1], index_of_refraction: values[2], aperture: values[3]});
+        }
+        if elements.is_empty() {
+            return Err("no lens elements".to_string())
+        }
+        Ok(LensSystem::new(elements))
+    }
+
+    // a classic 50mm f/2 double gauss design
+    pub fn double_gauss() -> LensSystem {
+        LensSystem::parse("
+            29.475   3.76   1.67   25.2
+            84.83    0.12   1      25.2
+            19.275   4.025  1.67   23
+            40.77    3.275  1.699  23
+            12.75    5.705  1      18
+            0        4.5    0      17.1
+            -14.495  1.18   1.603  17
+            40.77    6.065  1.658  20
+            -20.385  0.19   1      20
+            437.065  3.22   1.717  20
+            -39.73   5      1      20
+        ").unwrap()
+    }
+
+    // scales the opening of the aperture stop, e.g. 0.5 stops down by 2 f-stops
+    pub fn with_aperture_scale(mut self, scale: f64) -> LensSystem {
+        for element in self.elements.iter_mut().filter(|element| element.curvature_radius == 0.0) {
+            element.aperture *= scale;
+        }
+        self
+    }
+
+    fn rear_element(&self) -> &LensElement {
+        self.elements.last().unwrap()
+    }
+
+    // the z of each surface with the last element thickness film_distance
+    fn element_positions(&self, film_distance: f64) -> Vec<f64> {
+        let mut positions = vec![0.0; self.elements.len()];
+        let mut z = -film_distance;
+        for i in (0..self.elements.len()).rev() {
+            positions[i] = z;
+            if i > 0 {
+                z -= self.elements[i - 1].thickness;
+            }
+        }
+        positions
+    }
+
+    // where the ray crosses a surface, and the surface's normal there facing
+    // against the ray
+    fn intersect(element: &LensElement, z: f64, ray: &Ray) -> Option<(Vec3, Vec3)> {
+        if element.curvature_radius == 0.0 {
+            let t = (z - ray.origin.z()) / ray.direction.z();
+            if !t.is_finite() || t < 0.0 {
+                return None
+            }
+            return Some((ray.at(t), Vec3::new(0.0, 0.0, -ray.direction.z().signum())))
+        }
+
+        // a sphere centred on the axis
+        let radius = element.curvature_radius;
+        let center = Vec3::new(0.0, 0.0, z + radius);
+        let oc = ray.origin - center;
+        let a = ray.direction.length_squared();
+        let half_b = oc.dot_product(&ray.direction);
+        let c = oc.length_squared() - radius * radius;
+        let discriminant = half_b * half_b - a * c;
+        if discriminant < 0.0 {
+            return None
+        }
+        let (t0, t1) = ((-half_b - discriminant.sqrt()) / a, (-half_b + discriminant.sqrt()) / a);
+        // which of the two hits is the lens surface depends on which way the
+        // ray goes and which way the surface bulges
+        let closer = (ray.direction.z() > 0.0) ^ (radius < 0.0);
+        let t = if closer { t0.min(t1) } else { t0.max(t1) };
+        if t < 0.0 {
+            return None
+        }
+        let point = ray.at(t);
+        let normal = (point - center).unit_vector();
+        let normal = if normal.dot_product(&ray.direction) > 0.0 { normal * -1.0 } else { normal };
+        Some((point, normal))
+    }
+
+    // bends a unit direction going from index eta_i into eta_t. None on total internal reflection
+    fn refract(direction: &Vec3, normal: &Vec3, eta_i: f64, eta_t: f64) -> Option<Vec3> {
+        let ratio = eta_i / eta_t;
+        let cos_i = -direction.dot_product(normal);
+        let sin2_t = ratio * ratio * (1.0 - cos_i * cos_i).max(0.0);
+        if sin2_t >= 1.0 {
+            return None
+        }
+        let cos_t = (1.0 - sin2_t).sqrt();
+        Some(*direction * ratio + *normal * (ratio * cos_i - cos_t))
+    }
+
+    fn index_or_air(index: f64) -> f64 {
+        if index == 0.0 { 1.0 } else { index }
+    }
+
+    // traces a ray from the film out of the front of the lens. None if the
+    // lens housing or an aperture blocks it
+    pub fn trace_from_film(&self, ray: &Ray, film_distance: f64) -> Option<Ray> {
+        let positions = self.element_positions(film_distance);
+        let mut ray = Ray::new(ray.origin, ray.direction.unit_vector(), Some(ray.time));
+        for i in (0..self.elements.len()).rev() {
+            let element = &self.elements[i];
+            let (point, normal) = LensSystem::intersect(element, positions[i], &ray)?;
+            let radius = element.aperture / 2.0;
+            if point.x() * point.x() + point.y() * point.y() > radius * radius {
+                return None
+            }
+            let mut direction = ray.direction;
+            if element.curvature_radius != 0.0 {
+                // leaving this element's glass for the one in front of it
+                let eta_i = LensSystem::index_or_air(element.index_of_refraction);
+                let eta_t = if i > 0 { LensSystem::index_or_air(self.elements[i - 1].index_of_refraction) } else { 1.0 };
+                direction = LensSystem::refract(&direction, &normal, eta_i, eta_t)?;
+            }
+            ray = Ray::new(point, direction, Some(ray.time));
+        }
+        Some(ray)
+    }
+
+    // traces a ray from the scene into the front of the lens and out towards the film
+    fn trace_from_scene(&self, ray: &Ray, film_distance: f64) -> Option<Ray> {
+        let positions = self.element_positions(film_distance);
+        let mut ray = Ray::new(ray.origin, ray.direction.unit_vector(), Some(ray.time));
+        for (i, element) in self.elements.iter().enumerate() {
+            let (point, normal) = LensSystem::intersect(element, positions[i], &ray)?;
+            let radius = element.aperture / 2.0;
+            if point.x() * point.x() + point.y() * point.y() > radius * radius {
+                return None
+            }
+            let mut direction = ray.direction;
+            if element.curvature_radius != 0.0 {
+                let eta_i = if i > 0 { LensSystem::index_or_air(self.elements[i - 1].index_of_refraction) } else { 1.0 };
+                let eta_t = LensSystem::index_or_air(element.index_of_refraction);
+                direction = LensSystem::refract(&direction, &normal, eta_i, eta_t)?;
+            }
+            ray = Ray::new(point, direction, Some(ray.time));
+        }
+        Some(ray)
+    }
+
+    // the z of the principal plane and focal point for rays coming into the lens
+    // parallel to the axis, found by tracing one close to the axis
+    fn cardinal_points(incoming: &Ray, outgoing: &Ray) -> (f64, f64) {
+        let t_focus = -outgoing.origin.x() / outgoing.direction.x();
+        let focal_z = outgoing.at(t_focus).z();
+        let t_principal = (incoming.origin.x() - outgoing.origin.x()) / outgoing.direction.x();
+        let principal_z = outgoing.at(t_principal).z();
+        (principal_z, focal_z)
+    }
+
+    // how far the rear element has to be from the film to bring things
+    // focus_distance (in lens units) in front of the film into focus.
+    // uses the thick lens approximation, same as pbrt
+    pub fn focus(&self, focus_distance: f64) -> f64 {
+        let height = 0.001 * self.rear_element().aperture;
+        let film_distance = self.rear_element().thickness;
+        let front_z = self.element_positions(film_distance)[0];
+
+        // parallel rays from the scene side, then from the film side
+        let from_scene = Ray::new(Vec3::new(height, 0.0, front_z - 1.0), Vec3::new(0.0, 0.0, 1.0), Some(0.0));
+        let out_back = self.trace_from_scene(&from_scene, film_distance).expect("Lens can't be focused, a ray near the axis is blocked");
+        let (principal_back, focal_back) = LensSystem::cardinal_points(&from_scene, &out_back);
+        let from_film = Ray::new(Vec3::new(height, 0.0, 1.0), Vec3::new(0.0, 0.0, -1.0), Some(0.0));
+        let out_front = self.trace_from_film(&from_film, film_distance).expect("Lens can't be focused, a ray near the axis is blocked");
+        let (principal_front, _) = LensSystem::cardinal_points(&from_film, &out_front);
+
+        let focal_length = focal_back - principal_back;
+        let z = -focus_distance;
+        let root = (principal_front - z - principal_back) * (principal_front - z - 4.0 * focal_length - principal_back);
+        if root < 0.0 {
+            panic!("Lens can't focus as close as {}", focus_distance);
+        }
+        let delta = 0.5 * (principal_back - z + principal_front - root.sqrt());
+        film_distance + delta
+    }
+
+    // a random point on the rear element, which rays from the film aim at
+    pub fn sample_rear_element(&self, film_distance: f64) -> Vec3 {
+        let point = Vec3::random_in_unit_disk() * (self.rear_element().aperture / 2.0);
+        Vec3::new(point.x(), point.y(), -film_distance)
+    }
+}
+
+// a lens focused at a distance with a piece of film behind it
+#[derive(Clone, Debug)]
+pub struct RealisticLens {
+    pub system: LensSystem,
+    pub film_distance: f64,
+    pub film_width: f64,
+    pub film_height: f64,
+    // lens units (millimetres) to scene units
+    pub scale: f64
+}
+
+// rays that hit the lens housing are retried this many times before giving up
+const MAX_ATTEMPTS: usize = 64;
+
+impl RealisticLens {
+    // film_diagonal is in millimetres (e.g. ~43.3 for full frame 35mm),
+    // focus_distance is in scene units and scale converts millimetres to them
+    pub fn new(system: LensSystem, film_diagonal: f64, aspect_ratio: f64, focus_distance: f64, scale: f64) -> RealisticLens {
+        let film_height = film_diagonal / (1.0 + aspect_ratio * aspect_ratio).sqrt();
+        RealisticLens {
+            film_distance: system.focus(focus_distance / scale),
+            system,
+            film_width: film_height * aspect_ratio,
+            film_height,
+            scale
+        }
+    }
+
+    // a ray leaving the lens, in lens space scaled to scene units, for the
+    // image position (s, t) (each in [0, 1], t upwards). the image on the film
+    // is upside down so it's flipped back here. rays the lens blocks are retried
+    // so vignetting doesn't darken the corners
+    pub fn get_ray(&self, s: f64, t: f64, time: f64) -> Option<Ray> {
+        let film_point = Vec3::new((0.5 - s) * self.film_width, (0.5 - t) * self.film_height, 0.0);
+        for _ in 0..MAX_ATTEMPTS {
+            let target = self.system.sample_rear_element(self.film_distance);
+            let ray = Ray::new(film_point, target - film_point, Some(time));
+            if let Some(ray) = self.system.trace_from_film(&ray, self.film_distance) {
+                return Some(Ray::new(ray.origin * self.scale, ray.direction, Some(time)))
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_focused_rays_meet() {
+        let focus_distance = 2000.0;
+        let lens = LensSystem::double_gauss();
+        let film_distance = lens.focus(focus_distance);
+
+        // every ray from the middle of the film should pass close to the same
+        // point on the axis, focus_distance away from the film
+        let mut count = 0;
+        for _ in 0..200 {
+            let target = lens.sample_rear_element(film_distance);
+            let ray = Ray::new(Vec3::new(0.0, 0.0, 0.0), target, Some(0.0));
+            if let Some(out) = lens.trace_from_film(&ray, film_distance) {
+                let t = (-focus_distance - out.origin.z()) / out.direction.z();
+                let point = out.at(t);
+                assert!((point.x() * point.x() + point.y() * point.y()).sqrt() < 2.0, "missed focus by {:?}", point);
+                count += 1;
+            }
+        }
+        assert!(count > 50);
+
+        // focusing closer moves the lens away from the film
+        assert!(lens.focus(500.0) > film_distance);
+        assert!(LensSystem::parse("1 2 3").is_err());
+    }
+}
@@ -0,0 +1,941 @@
+pub mod vec3;
+pub mod ray;
+pub mod sphere;
+pub mod moving_sphere;
+pub mod hittable;
+pub mod hittable_list;
+pub mod utilities;
+pub mod camera;
+pub mod material;
+pub mod aabb;
+pub mod bvh;
+pub mod bvh_stats;
+pub mod ray_stats;
+pub mod counters;
+pub mod flat_bvh;
+pub mod texture;
+pub mod perlin;
+pub mod simplex;
+pub mod worley;
+pub mod export;
+pub mod texture_graph;
+pub mod triangle;
+pub mod mesh;
+pub mod obj;
+pub mod transform;
+pub mod instance;
+pub mod point_cloud;
+pub mod ply;
+pub mod environment;
+pub mod sky;
+pub mod kd_tree;
+pub mod adaptive;
+pub mod accelerator;
+pub mod mapped;
+pub mod palette;
+pub mod light;
+pub mod integrator;
+pub mod principled;
+pub mod metadata;
+pub mod voxel;
+pub mod vox;
+pub mod rect;
+pub mod cuboid;
+pub mod cylinder;
+pub mod cone;
+pub mod disk;
+pub mod plane;
+pub mod csg;
+pub mod sdf;
+pub mod heightfield;
+pub mod lens;
+pub mod restart;
+pub mod checkpoint;
+pub mod subsurface;
+pub mod color_space;
+pub mod sided;
+pub mod highlights;
+pub mod tonemap;
+pub mod bloom;
+pub mod dither;
+pub mod layers;
+pub mod aovs;
+pub mod denoise;
+pub mod wavefront;
+pub mod sampler;
+pub mod filter;
+pub mod blue_noise;
+pub mod snapshot;
+pub mod region;
+pub mod planet;
+pub mod animation;
+pub mod progress;
+pub mod ascii;
+pub mod web;
+pub mod scene_builder;
+
+use vec3::*;
+use sphere::Sphere;
+use ray::{Ray, RayDebug};
+use hittable::*;
+use hittable_list::HittableList;
+use utilities::*;
+use camera::Camera;
+use aovs::FirstHit;
+use planet::Planet;
+use animation::{AnimatedInstance, CameraPose, Easing, Keyframes, Pose};
+use sampler::SamplerKind;
+use filter::PixelFilter;
+use material::*;
+use accelerator::AcceleratorKind;
+use texture::*;
+use texture_graph::TextureGraph;
+use std::sync::Arc;
+use std::path::Path;
+use perlin::Perlin;
+use mesh::*;
+use transform::Transform;
+use instance::*;
+use adaptive::*;
+use palette::MaterialPalette;
+use light::*;
+use sky::PreethamSky;
+use integrator::IntegratorSettings;
+use voxel::VoxelGrid;
+use rect::AxisAlignedRect;
+use cuboid::Cuboid;
+use plane::Plane;
+use scene_builder::SceneBuilder;
+
+// we shade the spere based on its normal (gives us orientation of lighting)
+// e.g. if an object faces a light source it should be bright, dark if not
+// e.g. if _|_ * (| is object, * is sun, _ is ground) how should | be shaded
+pub fn ray_colour(ray: &Ray, scene: &Scene, image: &ImageConfig, depth: u64, throughput: Color) -> Vec3 {
+    if depth == 0 {
+        return Color::new(0.0, 0.0, 0.0);
+    }
+    let settings = &image.integrator;
+    let integrator = settings.kind.integrator();
+    progress::count_ray();
+    ray_stats::count_bounce_ray();
+
+    // see if ray intersects sphere so adjust color accordingly.
+    // use a small epsilon instead of 0 to correct for the 'shadow acne' problem:
+    // https://www.scratchapixel.com/lessons/3d-basic-rendering/introduction-to-shading/ligth-and-shadows
+    let record = scene.world.hit(ray, settings.continuation_epsilon, INFINITY)
+        .map(|record| with_footprint(record, ray, scene, settings));
+    let colour = match &record {
+        Some(record) => integrator.shade(ray, record, scene, image, depth, throughput),
+        None => integrator.background(ray, scene)
+    };
+    if let Some(debug) = &ray.debug {
+        log_bounce(debug, record.as_ref(), colour);
+    }
+    colour
+}
+
+// fills in how much of the surface the ray's pixel covers, for rays that
+// carry differentials, and when it was hit
+pub fn with_footprint<'a>(mut record: HitRecord<'a>, ray: &Ray, scene: &Scene, settings: &IntegratorSettings) -> HitRecord<'a> {
+    record.time = ray.time;
+    if let Some(differential) = &ray.differential {
+        record.uv_footprint = differential.uv_footprint(&record, &scene.world, ray.time, settings.continuation_epsilon);
+    }
+    record
+}
+
+// one line per bounce of a ray picked by `--debug-pixel`. they come out
+// deepest first, since a bounce's light is only known once the rest of the
+// path is. a conditional breakpoint here is the easiest way into one sample
+fn log_bounce(debug: &RayDebug, record: Option<&HitRecord>, colour: Color) {
+    let (x, y) = debug.pixel;
+    let what = match record {
+        Some(record) => format!("hit at t = {:.6}, point {:?}, normal {:?}{}", record.t, record.point, record.normal,
+            if record.front_face { "" } else { " (back face)" }),
+        None => String::from("missed, sky")
+    };
+    eprintln!("pixel ({}, {}) sample {} bounce {}: {} -> {:?}", x, y, debug.sample, debug.bounce, what, colour);
+}
+
+pub fn sky_colour(direction: &Vec3, scene: &Scene) -> Color {
+    if let Some(sky) = &scene.sky {
+        return sky.radiance(direction) * scene.sky_tint
+    }
+    let unit_direction = direction.unit_vector();
+    let t = 0.5 * (unit_direction.y() + 1.0);
+    // blue to white background gradient (t = 1 -> blue, t = 0 -> white)
+    Color::new(1.0, 1.0, 1.0).lerp(&Color::new(0.5, 0.7, 1.0), t) * scene.sky_tint
+}
+
+// a camera ray's colour, plus what compositing the render over a backplate
+// needs: the colour without the background (premultiplied) and the coverage
+pub struct CameraSample {
+    pub colour: Color,
+    pub foreground: Color,
+    pub alpha: f64,
+    // the render layer of what the ray hit first, none for the sky
+    pub layer: Option<u32>,
+    // what the ray hit first, for renders with aovs (see aovs.rs). none for
+    // the sky, or when there aren't any
+    pub first_hit: Option<FirstHit>
+}
+
+impl CameraSample {
+    // a sample whose part of the film a realistic lens doesn't let any light
+    // through to. it still counts, black, so the image darkens there as the
+    // lens's does, and opaque, as the lens barrel is in front of the scene
+    pub fn blocked() -> CameraSample {
+        let nothing = Color::new(0.0, 0.0, 0.0);
+        CameraSample{colour: nothing, foreground: nothing, alpha: 1.0, layer: None, first_hit: None}
+    }
+}
+
+pub fn camera_sample(ray: &Ray, scene: &Scene, image: &ImageConfig) -> CameraSample {
+    let nothing = Color::new(0.0, 0.0, 0.0);
+    let integrator = image.integrator.kind.integrator();
+    progress::count_ray();
+    progress::count_sample();
+    ray_stats::count_primary_ray();
+    let record = match scene.world.hit(ray, image.integrator.continuation_epsilon, INFINITY) {
+        Some(record) => with_footprint(record, ray, scene, &image.integrator),
+        None => {
+            let colour = integrator.background(ray, scene);
+            if let Some(debug) = &ray.debug {
+                log_bounce(debug, None, colour);
+            }
+            return CameraSample{colour, foreground: nothing, alpha: 0.0, layer: None, first_hit: None}
+        }
+    };
+    let colour = integrator.shade(ray, &record, scene, image, image.max_depth, Color::new(1.0, 1.0, 1.0));
+    if let Some(debug) = &ray.debug {
+        log_bounce(debug, Some(&record), colour);
+    }
+    let layer = Some(record.layer);
+    let first_hit = Some(&record).filter(|_| image.aovs).map(|record| FirstHit::new(ray, record));
+    match record.material {
+        // only the shadow is kept, as black
+        Material::ShadowCatcher{..} => CameraSample{colour, foreground: nothing, alpha: shadow_amount(&record, scene, ray.time, &image.integrator), layer, first_hit},
+        // premultiplied, so what's seen through the glass is left to the background
+        Material::Dielectric{..} if image.glass_alpha => {
+            let alpha = glass_coverage(ray, &record, scene, &image.integrator, image.max_depth);
+            CameraSample{colour, foreground: colour * alpha, alpha, layer, first_hit}
+        },
+        _ => CameraSample{colour, foreground: colour, alpha: 1.0, layer, first_hit}
+    }
+}
+
+// how much of what's behind glass it covers, for the alpha channel. the ray is
+// followed on through the glass, refracting or reflecting as the glass picks,
+// and if it comes out the far side into the sky the glass only covers what it
+// absorbed on the way. reflections, and anything else seen through it, are
+// the glass's own look, so cover fully. one path per sample, so like the
+// colour it's right on average. it runs after the sample's colour is worked
+// out, so it doesn't change that
+pub fn glass_coverage(ray: &Ray, record: &HitRecord, scene: &Scene, settings: &IntegratorSettings, depth: u64) -> f64 {
+    let scattering = match record.material.scatter(ray, record) {
+        Some(scattering) if depth > 0 => scattering,
+        _ => return 1.0
+    };
+    let mut scattered = scattering.scattered();
+    // the normal faces the incoming ray, so a reflection stays on its side
+    if scattered.direction.dot_product(&record.normal) > 0.0 {
+        return 1.0
+    }
+    scattered.origin = settings.offset_origin(record, &scattered.direction, 0.0);
+    // how much gets through the glass this far
+    let transmitted = scattering.attenuation().luminance().clamp(0.0, 1.0);
+    match scene.world.hit(&scattered, settings.continuation_epsilon, INFINITY) {
+        None => 1.0 - transmitted,
+        Some(next) => match next.material {
+            Material::Dielectric{..} => 1.0 - transmitted * (1.0 - glass_coverage(&scattered, &next, scene, settings, depth - 1)),
+            _ => 1.0
+        }
+    }
+}
+
+// how much of the light reaching a shadow catcher is blocked: 0 out in the
+// open, 1 in full shadow. the sky is checked from one direction per sample,
+// picked the way diffuse light arrives, so it averages out over the samples
+pub fn shadow_amount(record: &HitRecord, scene: &Scene, time: f64, settings: &IntegratorSettings) -> f64 {
+    let mut unblocked = 0.0;
+    let mut arriving = 0.0;
+    for light in scene.lights.iter() {
+        if let Some(sample) = light.sample(&record.point) {
+            let amount = sample.radiance.luminance() * record.normal.dot_product(&sample.direction).max(0.0);
+            unblocked += amount;
+            if amount > 0.0 && is_visible(&scene.world, record, &sample, time, settings) {
+                arriving += amount;
+            }
+        }
+    }
+    // cosine weighted, so the sky's irradiance is pi times the radiance
+    let direction = record.normal + Vec3::random_unit_vector();
+    if !direction.near_zero() {
+        let amount = PI * sky_colour(&direction, scene).luminance();
+        unblocked += amount;
+        let sky_ray = Ray::new(settings.offset_origin(record, &direction, 0.0), direction, Some(time));
+        if scene.world.hit(&sky_ray, settings.continuation_epsilon, INFINITY).is_none() {
+            arriving += amount;
+        }
+    }
+    if unblocked <= 0.0 {
+        return 0.0
+    }
+    1.0 - arriving / unblocked
+}
+
+fn random_scene(accelerator: AcceleratorKind, palette: &MaterialPalette) -> HittableList {
+    let mut world: HittableList = HittableList::new();
+    let ground_albedo = Vec3::new(0.5, 0.5, 0.5);
+    // ground
+    world.add(Plane::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0), Material::Lambertian{albedo: Box::new(SolidTexture::new(ground_albedo)), normal_map: None}));
+
+    // every glass sphere shares this one material
+    let glass = Arc::new(Material::Dielectric{index_of_refraction: palette.index_of_refraction, absorption: Color::new(0.0, 0.0, 0.0)});
+
+    // the small spheres sit on a grid, which a kd-tree can cut up neatly
+    let mut small_spheres: Vec<Box<dyn Hittable>> = Vec::new();
+    for a in -11..11 {
+        for b in -11..11 {
+            let center = Vec3::new(a as f64 + 0.9 * random_float(), 0.2, b as f64 + 0.9 * random_float());
+
+            if (center - Vec3::new(4.0, 0.2, 0.0)).length() > 0.9 {
+                let material = match palette.random_material() {
+                    Material::Dielectric{..} => glass.clone(),
+                    material => Arc::new(material)
+                };
+                small_spheres.push(Box::new(Sphere::new(center, 0.2, material)));
+            }
+        }
+    }
+    world.add_boxed(accelerator.build(small_spheres, 0.0, 1.0));
+
+    // front glass sphere
+    world.add(Sphere::new(Vec3::new(0.0, 1.0, 0.0), 1.0, glass));
+    let m_albedo = Color::new(0.4, 0.2, 0.1);
+    // front matte sphere
+    world.add(Sphere::new(Vec3::new(-4.0, 1.0, 0.0), 1.0, Material::Lambertian{albedo: Box::new(SolidTexture::new(m_albedo)), normal_map: None}));
+    let m2_albedo = Color::new(0.7, 0.6, 0.5);
+    let m2_fuzz = 0.0;
+    // front metal sphere
+    world.add(Sphere::new(Vec3::new(4.0, 1.0, 0.0), 1.0, Material::Metal{albedo: Box::new(SolidTexture::new(m2_albedo)), fuzz: Box::new(SolidTexture::uniform(m2_fuzz)), normal_map: None}));
+
+    world
+}
+
+fn basic_zoomed_in_scene(accelerator: AcceleratorKind) -> HittableList {
+    let mut world: HittableList = HittableList::new();
+
+    let material_center = Color::new(0.1, 0.2, 0.5);
+    let material_right = Color::new(0.7, 0.6, 0.5);
+
+    let white_green_checkered = CheckeredTexture::new_with_solid(Vec3::new(0.2, 0.3, 0.1), Vec3::new(0.9, 0.9, 0.9));
+    let ground = Sphere::new(Vec3::new(0.0, -100.5, -1.0), 100.0, Material::Lambertian{albedo: Box::new(white_green_checkered), normal_map: None});
+    // let middle = Sphere::new(Vec3::new(0.0, 0.0, -1.0), 0.5, Material::Lambertian{albedo: material_center, normal_map: None});
+    // moving middle sphere
+    let center_0 = Vec3::new(0.0, 0.0, -1.0);
+    // let middle = MovingSphere::new(center_0, 0.0, center_1, 1.0, 0.5, Material::Lambertian{albedo: Box::new(SolidTexture::new(material_center)), normal_map: None});
+    let middle = Sphere::new(center_0, 0.5, Material::Lambertian{albedo: Box::new(SolidTexture::new(material_center)), normal_map: None});
+    // the 2 spheres below work together to make a hollow glass 'bubble'
+    let left = Sphere::new(Vec3::new(-1.0, 0.0, -1.0), 0.5, Material::Dielectric{index_of_refraction: 1.5, absorption: Color::new(0.0, 0.0, 0.0)});
+    // note: negative radius doesn't change anything, however normal's point inward.
+    // note: doesn't work properly with AABB/BVH because of the radius
+    // let left_inner = Sphere::new(Vec3::new(-1.0, 0.0, -1.0), -0.4, Material::Dielectric{index_of_refraction: 1.5, absorption: Color::new(0.0, 0.0, 0.0)});
+    let right = Sphere::new(Vec3::new(1.0, 0.0, -1.0), 0.5, Material::Metal{albedo: Box::new(SolidTexture::new(material_right)), fuzz: Box::new(SolidTexture::uniform(0.0)), normal_map: None});
+
+    let y: Vec<Box<dyn Hittable>> = vec![
+        Box::new(ground),        // ground
+        Box::new(middle),        // middle, matte sphere
+        Box::new(left),          // left glass sphere
+        // Box::new(left_inner),    // left glass sphere (inner)
+        Box::new(right),         // right metal sphere
+    ];
+    world.add_boxed(accelerator.build(y, 0.0, 1.0));
+
+    // world.add(ground);        // ground
+    // world.add(middle);        // middle, matte sphere
+    // world.add(left);          // left glass sphere
+    // // world.add(left_inner);    // left glass sphere (inner)
+    // world.add(right);         // right metal sphere
+
+    world
+}
+
+// a ball bouncing and rolling past a spinning box over 2 seconds, with the
+// camera following along, to render as an animation (see --frames)
+fn bouncing_ball_scene() -> Scene {
+    let mut world: HittableList = HittableList::new();
+    let checkered = CheckeredTexture::new_with_solid(Vec3::new(0.2, 0.3, 0.1), Vec3::new(0.9, 0.9, 0.9));
+    world.add(Sphere::new(Vec3::new(0.0, -1000.0, 0.0), 1000.0, Material::Lambertian{albedo: Box::new(checkered), normal_map: None}));
+
+    // a bounce a second, 2 high, keyed often enough that it follows the
+    // parabola. it rolls as it goes, turning a radian per radius travelled
+    let radius = 0.5;
+    let bounce = |time: f64| {
+        let phase = time.fract();
+        let x = -3.0 + 3.0 * time;
+        Pose::new(Vec3::new(x, radius + 2.0 * 4.0 * phase * (1.0 - phase), 0.0)).with_rotation(Vec3::new(0.0, 0.0, -(x / radius).to_degrees()))
+    };
+    let ball_path = (1..=32).fold(Keyframes::new(0.0, bounce(0.0)), |path, key| {
+        let time = key as f64 / 16.0;
+        // the bounce itself is sudden, so it's keyed just before it as well
+        if key % 16 == 0 { path.with_key(time - 1e-3, bounce(time - 1e-3)) } else { path }.with_key(time, bounce(time))
+    });
+    let ball = Material::Lambertian{albedo: Box::new(CheckeredTexture::new_with_solid(Color::new(0.8, 0.1, 0.1), Color::new(0.9, 0.9, 0.9))), normal_map: None};
+    world.add(AnimatedInstance::new(Arc::new(Sphere::new(Vec3::new(0.0, 0.0, 0.0), radius, ball)), ball_path));
+
+    let metal = Material::Metal{albedo: Box::new(SolidTexture::new(Color::new(0.7, 0.6, 0.5))), fuzz: Box::new(SolidTexture::uniform(0.1)), normal_map: None};
+    let spin = Keyframes::new(0.0, Pose::new(Vec3::new(0.0, 0.75, -2.0)))
+        .with_key(2.0, Pose::new(Vec3::new(0.0, 0.75, -2.0)).with_rotation(Vec3::new(0.0, 360.0, 0.0)));
+    world.add(AnimatedInstance::new(Arc::new(Cuboid::new(Vec3::new(-0.75, -0.75, -0.75), Vec3::new(0.75, 0.75, 0.75), metal)), spin));
+
+    let mut scene = Scene::from(world);
+    scene.camera_path = Some(Keyframes::new(0.0, CameraPose{lookfrom: Vec3::new(-5.0, 2.5, 9.0), lookat: Vec3::new(-1.5, 0.75, 0.0), vertical_fov: 30.0})
+        .with_key(2.0, CameraPose{lookfrom: Vec3::new(5.0, 2.5, 9.0), lookat: Vec3::new(1.5, 0.75, 0.0), vertical_fov: 25.0})
+        .with_easing(Easing::Smooth));
+    scene
+}
+
+// a small cone-on-a-stick tree, with the trunk and leaves using different
+// materials. it's read from the repository, so the forest needs a checkout
+fn low_poly_tree() -> Mesh {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("models/low_poly_tree.obj");
+    let fallback = Material::Lambertian{albedo: Box::new(SolidTexture::new(Color::new(0.5, 0.5, 0.5))), normal_map: None};
+    obj::load_obj(&path, fallback).unwrap_or_else(|error| panic!("couldn't load {}: {}", path.display(), error))
+}
+
+// thousands of copies of a single tree mesh scattered over rolling hills. the
+// tree's triangles are only stored once, every copy is an instance of it
+fn forest_scene() -> HittableList {
+    let mut world: HittableList = HittableList::new();
+    let terrain_noise = Perlin::new(1);
+    let size = 100.0;
+    let height = |x: f64, z: f64| 3.0 * terrain_noise.noise(&Vec3::new(x * 0.04, 0.0, z * 0.04));
+
+    // the ground, as a grid of triangles following the noise
+    let resolution = 100;
+    let mut positions = Vec::new();
+    let mut faces = Vec::new();
+    for j in 0..=resolution {
+        for i in 0..=resolution {
+            let x = (i as f64 / resolution as f64 - 0.5) * size;
+            let z = (j as f64 / resolution as f64 - 0.5) * size;
+            positions.push(Vec3::new(x, height(x, z), z));
+        }
+    }
+    for j in 0..resolution {
+        for i in 0..resolution {
+            let corner = j * (resolution + 1) + i;
+            let above = corner + resolution + 1;
+            faces.push(Face{positions: [corner, above, above + 1], normals: None, uvs: None, material: 0});
+            faces.push(Face{positions: [corner, above + 1, corner + 1], normals: None, uvs: None, material: 0});
+        }
+    }
+    let grass = Material::Lambertian{albedo: Box::new(SolidTexture::new(Color::new(0.3, 0.45, 0.2))), normal_map: None};
+    world.add(Mesh::new(positions, Vec::new(), Vec::new(), faces, vec![grass]));
+
+    let tree: Arc<dyn Hittable> = Arc::new(low_poly_tree());
+    let mut instances = Vec::new();
+    for _ in 0..3000 {
+        let x = random_float_in_range(-0.45, 0.45) * size;
+        let z = random_float_in_range(-0.45, 0.45) * size;
+        let scale = random_float_in_range(0.6, 1.4);
+        let transform = Transform::scaling(Vec3::new(scale, scale * random_float_in_range(0.8, 1.2), scale))
+            .then(&Transform::rotation_y(random_float_in_range(0.0, 360.0)))
+            .then(&Transform::translation(Vec3::new(x, height(x, z), z)));
+        instances.push(Instance::new(tree.clone(), transform));
+    }
+    world.add(InstanceBVH::construct(instances));
+
+    world
+}
+
+// blocky hills made of voxels, with the valleys flooded
+fn voxel_terrain() -> Scene {
+    let mut world: HittableList = HittableList::new();
+    let solid = |colour: Color| Material::Lambertian{albedo: Box::new(SolidTexture::new(colour)), normal_map: None};
+    let materials = vec![
+        solid(Color::new(0.3, 0.55, 0.2)),      // 1: grass
+        solid(Color::new(0.45, 0.3, 0.18)),     // 2: dirt
+        solid(Color::new(0.45, 0.45, 0.47)),    // 3: stone
+        solid(Color::new(0.85, 0.8, 0.55)),     // 4: sand
+        Material::Metal{albedo: Box::new(SolidTexture::new(Color::new(0.25, 0.4, 0.6))), fuzz: Box::new(SolidTexture::uniform(0.05)), normal_map: None} // 5: water
+    ];
+    let (width, height, depth) = (128, 32, 128);
+    let water_level = 9;
+    let mut grid = VoxelGrid::new([width, height, depth], Vec3::new(-(width as f64) / 2.0, 0.0, -(depth as f64) / 2.0), 1.0, materials);
+
+    let noise = Perlin::new(2);
+    for z in 0..depth {
+        for x in 0..width {
+            let point = Vec3::new(x as f64 * 0.05, 0.0, z as f64 * 0.05);
+            let surface = ((noise.turbulence(&point, 4) * 24.0) as usize + 4).min(height - 1);
+            for y in 0..=surface.max(water_level) {
+                let block = if y > surface {
+                    5
+                } else if y + 4 < surface {
+                    3
+                } else if surface <= water_level + 1 {
+                    4
+                } else if y == surface {
+                    1
+                } else {
+                    2
+                };
+                grid.set([x, y, z], block);
+            }
+        }
+    }
+    world.add(grid);
+
+    // low sun off to the side for long shadows
+    let sky = PreethamSky::new(Vec3::new(-60.0, 80.0, 40.0), 3.0);
+    Scene {
+        world,
+        lights: vec![sky.sun()],
+        sky: Some(sky),
+        sky_tint: Color::new(1.0, 1.0, 1.0),
+        camera_path: None
+    }
+}
+
+// a grid of city blocks, each split into 4 lots with a building of random
+// height and material. every building is an instance of one of a few unit
+// boxes. at night the sky goes dark, some windows light up and street lamps
+// light the main roads
+fn city_scene(night: bool) -> Scene {
+    let mut world: HittableList = HittableList::new();
+    let blocks = 12;
+    let block_size = 10.0;
+    let street_width = 4.0;
+    let spacing = block_size + street_width;
+    let extent = blocks as f64 * spacing / 2.0;
+
+    let solid = |colour: Color| Material::Lambertian{albedo: Box::new(SolidTexture::new(colour)), normal_map: None};
+    let asphalt = solid(Color::new(0.12, 0.12, 0.13));
+    world.add(AxisAlignedRect::xz((-extent - 50.0, extent + 50.0), (-extent - 50.0, extent + 50.0), 0.0, asphalt));
+
+    let facades: Vec<Arc<dyn Hittable>> = vec![
+        solid(Color::new(0.7, 0.68, 0.64)),     // concrete
+        solid(Color::new(0.55, 0.3, 0.22)),     // brick
+        solid(Color::new(0.85, 0.82, 0.75)),    // sandstone
+        Material::Metal{albedo: Box::new(SolidTexture::new(Color::new(0.5, 0.6, 0.7))), fuzz: Box::new(SolidTexture::uniform(0.15)), normal_map: None} // glass and steel
+    ].into_iter().map(|material| Arc::new(Cuboid::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 1.0, 1.0), material)) as Arc<dyn Hittable>).collect();
+    // a window facing +z with its corner at the origin
+    let window: Arc<dyn Hittable> = Arc::new(AxisAlignedRect::xy((0.0, 1.0), (0.0, 1.0), 0.0, Material::DiffuseLight{emit: Box::new(SolidTexture::new(Color::new(4.0, 3.2, 1.8)))}));
+    let floor_height = 3.0;
+    let window_spacing = 2.5;
+
+    let mut buildings = Vec::new();
+    let mut windows = Vec::new();
+    let lot_size = block_size / 2.0;
+    for block_x in 0..blocks {
+        for block_z in 0..blocks {
+            let block_corner = (block_x as f64 * spacing - extent + street_width / 2.0, block_z as f64 * spacing - extent + street_width / 2.0);
+            // taller towards the middle of the city
+            let center_distance = (block_corner.0 * block_corner.0 + block_corner.1 * block_corner.1).sqrt() / extent;
+            for lot in 0..4 {
+                let inset = random_float_in_range(0.3, 1.0);
+                let x0 = block_corner.0 + (lot % 2) as f64 * lot_size + inset;
+                let z0 = block_corner.1 + (lot / 2) as f64 * lot_size + inset;
+                let (width, depth) = (lot_size - 2.0 * inset, lot_size - 2.0 * inset);
+                let height = 4.0 + random_float().powi(3) * 40.0 * (1.0 - center_distance).max(0.15);
+                let facade = facades[random_int_in_range(0, facades.len() as u32) as usize].clone();
+                let transform = Transform::scaling(Vec3::new(width, height, depth)).then(&Transform::translation(Vec3::new(x0, 0.0, z0)));
+                buildings.push(Instance::new(facade, transform));
+
+                if !night {
+                    continue
+                }
+                let (x1, z1) = (x0 + width, z0 + depth);
+                // the bottom left corner of each side seen from outside, which way
+                // is right along it, and the rotation turning +z to face out
+                let gap = 0.01;
+                let sides = [
+                    (Vec3::new(x0, 0.0, z1 + gap), 0.0, width),
+                    (Vec3::new(x1 + gap, 0.0, z1), 90.0, depth),
+                    (Vec3::new(x1, 0.0, z0 - gap), 180.0, width),
+                    (Vec3::new(x0 - gap, 0.0, z0), 270.0, depth)
+                ];
+                for (corner, rotation, length) in sides.iter() {
+                    let columns = (*length / window_spacing) as usize;
+                    let floors = (height / floor_height) as usize;
+                    let right = Transform::rotation_y(*rotation).vector(&Vec3::new(1.0, 0.0, 0.0));
+                    for floor in 0..floors {
+                        for column in 0..columns {
+                            if random_float() > 0.3 {
+                                continue
+                            }
+                            let position = *corner + right * (column as f64 * window_spacing + 0.6) + Vec3::new(0.0, floor as f64 * floor_height + 1.0, 0.0);
+                            let transform = Transform::scaling(Vec3::new(1.3, 1.4, 1.0))
+                                .then(&Transform::rotation_y(*rotation))
+                                .then(&Transform::translation(position));
+                            windows.push(Instance::new(window.clone(), transform));
+                        }
+                    }
+                }
+            }
+        }
+    }
+    world.add(InstanceBVH::construct(buildings));
+
+    let mut lights = Vec::new();
+    if night {
+        if !windows.is_empty() {
+            world.add(InstanceBVH::construct(windows));
+        }
+        // lamps along the two avenues through the middle
+        for i in 0..=blocks {
+            let along = i as f64 * spacing - extent;
+            for position in [Vec3::new(along, 6.0, 0.0), Vec3::new(0.0, 6.0, along)] {
+                lights.push(Light::Point{position, intensity: Color::new(60.0, 45.0, 25.0)});
+            }
+        }
+    }
+
+    Scene {
+        world,
+        lights,
+        sky: None,
+        sky_tint: if night { Color::new(0.01, 0.012, 0.03) } else { Color::new(1.0, 1.0, 1.0) },
+        camera_path: None
+    }
+}
+
+pub struct ImageConfig {
+    pub aspect_ratio: f32,
+    pub image_width: i32,
+    pub image_height: i32,
+    // the most samples a pixel gets
+    pub samples_per_pixel: u64,
+    pub max_depth: u64,
+    pub integrator: IntegratorSettings,
+    // when set, pixels stop sampling early once they look converged
+    pub adaptive: Option<AdaptiveSampling>,
+    // when set, every sample draws its random numbers from a generator seeded
+    // by this, its pixel and its number, so the image comes out the same each
+    // time (and however the work is split up, or if it's resumed)
+    pub seed: Option<u64>,
+    // where samples' random numbers come from, see sampler.rs
+    pub sampler: SamplerKind,
+    // how samples around each pixel are weighted, see filter.rs
+    pub filter: PixelFilter,
+    // every pixel takes the same samples from the sampler, shifted by a blue
+    // noise tile, so what noise is left is fine grained rather than blotchy
+    pub blue_noise: bool,
+    // camera samples keep what they hit first, for the aovs
+    pub aovs: bool,
+    // camera rays that hit glass check how much of the background shows
+    // through it, for the alpha channel (see glass_coverage)
+    pub glass_alpha: bool
+}
+
+impl ImageConfig {
+    pub fn new(aspect_ratio: f32, image_width: i32, samples_per_pixel: u64, max_depth: u64) -> ImageConfig {
+        ImageConfig {
+            aspect_ratio,
+            image_width,
+            image_height: (image_width as f32 / aspect_ratio) as i32,
+            samples_per_pixel,
+            max_depth,
+            integrator: IntegratorSettings::default(),
+            adaptive: None,
+            seed: None,
+            sampler: SamplerKind::default(),
+            filter: PixelFilter::default(),
+            blue_noise: false,
+            aovs: false,
+            glass_alpha: false
+        }
+    }
+
+    pub fn with_integrator(mut self, integrator: IntegratorSettings) -> ImageConfig {
+        self.integrator = integrator;
+        self
+    }
+
+    pub fn with_adaptive_sampling(mut self, min_samples: u64, threshold: f64) -> ImageConfig {
+        self.adaptive = Some(AdaptiveSampling::new(min_samples, threshold));
+        self
+    }
+}
+
+// everything a render needs besides the camera
+pub struct Scene {
+    pub world: HittableList,
+    pub lights: Vec<Light>,
+    // a physical sky instead of the blue gradient
+    pub sky: Option<PreethamSky>,
+    // multiplies the sky's colour, e.g. dark for night scenes
+    pub sky_tint: Color,
+    // where the camera goes over time, for animations (see --frames). without
+    // it the camera stays where the scene's builder put it
+    pub camera_path: Option<Keyframes<CameraPose>>
+}
+
+impl From<HittableList> for Scene {
+    // for scenes lit only by the sky
+    fn from(world: HittableList) -> Scene {
+        Scene {
+            world,
+            lights: Vec::new(),
+            sky: None,
+            sky_tint: Color::new(1.0, 1.0, 1.0),
+            camera_path: None
+        }
+    }
+}
+
+// settings that change how a scene is built rather than what's in it
+#[derive(Default)]
+pub struct SceneOptions {
+    // overrides the structure a scene would pick for itself
+    pub accelerator: Option<AcceleratorKind>,
+    // for scenes that make up their materials
+    pub palette: MaterialPalette,
+    // makes scenes built from random numbers (e.g. the random scene) the same each time
+    pub seed: Option<u64>,
+    // how big the scene's objects are next to the metres-ish ones the
+    // epsilons are set for, e.g. 0.001 for a model in millimetres, see
+    // IntegratorSettings::for_scene_scale
+    pub scale: Option<f64>
+}
+
+// a scene that can be rendered by name, see --scene and --list-scenes. adding
+// one is writing its builder and giving it an entry in SCENES
+pub struct SceneEntry {
+    pub name: &'static str,
+    pub description: &'static str,
+    builder: fn(&SceneOptions) -> (ImageConfig, Camera, Scene)
+}
+
+impl SceneEntry {
+    pub fn build(&self, options: &SceneOptions) -> (ImageConfig, Camera, Scene) {
+        if let Some(seed) = options.seed {
+            seed_rng(seed);
+        }
+        let (mut image, camera, scene) = (self.builder)(options);
+        if let Some(scale) = options.scale {
+            let scaled = IntegratorSettings::for_scene_scale(scale);
+            image.integrator.continuation_epsilon = scaled.continuation_epsilon;
+            image.integrator.shadow_epsilon = scaled.shadow_epsilon;
+        }
+        (image, camera, scene)
+    }
+}
+
+// every scene, in the order they were added (the numbers they used to go by).
+// the first is the one rendered without --scene
+pub const SCENES: &[SceneEntry] = &[
+    SceneEntry{name: "zoomed-in", description: "glass, matte and metal spheres on a checkered ground, close up", builder: build_zoomed_in},
+    SceneEntry{name: "checkered-spheres", description: "2 big checkered spheres, one on top of the other", builder: build_checkered_spheres},
+    SceneEntry{name: "perlin-noise", description: "a marble sphere on marble ground, lit from the side", builder: build_perlin_noise},
+    SceneEntry{name: "forest", description: "thousands of instanced trees over rolling hills", builder: build_forest},
+    SceneEntry{name: "voxel-terrain", description: "blocky voxel hills with flooded valleys", builder: build_voxel_terrain},
+    SceneEntry{name: "city", description: "a procedural city by day", builder: build_city},
+    SceneEntry{name: "city-night", description: "the procedural city at night, lit by windows and street lamps", builder: build_city_night},
+    SceneEntry{name: "planet", description: "a procedural earth with clouds", builder: build_planet},
+    SceneEntry{name: "bouncing-ball", description: "a ball bouncing past a spinning box, for --frames", builder: build_bouncing_ball},
+    SceneEntry{name: "random", description: "the book cover: hundreds of random spheres", builder: build_random},
+    SceneEntry{name: "metals", description: "gold, silver, copper and aluminium spheres, from measured data", builder: build_metals}
+];
+
+// the scene called name, or a number for the one at that position in SCENES
+pub fn find_scene(name: &str) -> Option<&'static SceneEntry> {
+    match name.parse::<usize>() {
+        Ok(number) => SCENES.get(number),
+        Err(_) => SCENES.iter().find(|entry| entry.name == name)
+    }
+}
+
+fn build_zoomed_in(options: &SceneOptions) -> (ImageConfig, Camera, Scene) {
+    SceneBuilder::new(ImageConfig::new(16.0 / 9.0, 400, 10, 50))
+        .add_object(basic_zoomed_in_scene(options.accelerator.unwrap_or(AcceleratorKind::FlatBvh)))
+        .set_camera(Vec3::new(5.0, 2.0, 4.0), Vec3::new(0.0, 0.0, -1.0), 20.0)
+        .set_aperture(0.1)
+        .build_with_bvh()
+}
+
+fn build_checkered_spheres(_options: &SceneOptions) -> (ImageConfig, Camera, Scene) {
+    let white = Color::new(0.2, 0.3, 0.1);
+    let green = Color::new(0.9, 0.9, 0.9);
+    let checkered = || Material::Lambertian{albedo: Box::new(CheckeredTexture::new_with_solid(white, green)), normal_map: None};
+    SceneBuilder::new(ImageConfig::new(16.0 / 9.0, 400, 10, 50))
+        .add_sphere(Vec3::new(0.0, 10.0, -1.0), 10.0, checkered())
+        .add_sphere(Vec3::new(0.0, -10.0, -1.0), 10.0, checkered())
+        .set_camera(Vec3::new(13.0, 2.0, 3.0), Vec3::new(0.0, 0.0, 0.0), 20.0)
+        .build_with_bvh()
+}
+
+fn build_perlin_noise(_options: &SceneOptions) -> (ImageConfig, Camera, Scene) {
+    let noise = Arc::new(Perlin::new(0));
+    let perlin = Box::new(NoiseTexture::new(noise.clone(), 4.0, NoisePattern::Marble, Fbm::default()));
+    // same look as NoiseTexture, built from texture graph nodes on the same noise
+    let perlin_sphere = Box::new(TextureGraph::marble(noise, 4.0));
+    SceneBuilder::new(ImageConfig::new(16.0 / 9.0, 400, 100, 50).with_adaptive_sampling(16, 0.02))
+        .add_sphere(Vec3::new(0.0, -1000.0, 0.0), 1000.0, Material::Lambertian{albedo: perlin, normal_map: None})
+        .add_sphere(Vec3::new(0.0, 2.0, 0.0), 2.0, Material::Lambertian{albedo: perlin_sphere, normal_map: None})
+        // a warm light off to the side, so the sphere casts a shadow
+        .add_light(Light::Point{position: Vec3::new(2.0, 9.0, -4.0), intensity: Color::new(150.0, 130.0, 100.0)})
+        .set_camera(Vec3::new(13.0, 2.0, 3.0), Vec3::new(0.0, 0.0, 0.0), 20.0)
+        .build_with_bvh()
+}
+
+fn build_forest(_options: &SceneOptions) -> (ImageConfig, Camera, Scene) {
+    SceneBuilder::new(ImageConfig::new(16.0 / 9.0, 400, 10, 50))
+        .add_object(forest_scene())
+        .set_camera(Vec3::new(0.0, 12.0, 55.0), Vec3::new(0.0, 0.0, 0.0), 40.0)
+        .build_with_bvh()
+}
+
+fn build_voxel_terrain(_options: &SceneOptions) -> (ImageConfig, Camera, Scene) {
+    SceneBuilder::from_scene(ImageConfig::new(16.0 / 9.0, 400, 10, 50), voxel_terrain())
+        .set_camera(Vec3::new(70.0, 45.0, 70.0), Vec3::new(0.0, 8.0, 0.0), 40.0)
+        .build_with_bvh()
+}
+
+fn build_city(_options: &SceneOptions) -> (ImageConfig, Camera, Scene) {
+    SceneBuilder::from_scene(ImageConfig::new(16.0 / 9.0, 400, 10, 50), city_scene(false))
+        .set_camera(Vec3::new(70.0, 45.0, 95.0), Vec3::new(0.0, 5.0, 0.0), 40.0)
+        .build_with_bvh()
+}
+
+fn build_city_night(_options: &SceneOptions) -> (ImageConfig, Camera, Scene) {
+    SceneBuilder::from_scene(ImageConfig::new(16.0 / 9.0, 400, 10, 50), city_scene(true))
+        .set_camera(Vec3::new(70.0, 45.0, 95.0), Vec3::new(0.0, 5.0, 0.0), 40.0)
+        .build_with_bvh()
+}
+
+// a procedural earth with a cloud layer just above the ground
+fn build_planet(_options: &SceneOptions) -> (ImageConfig, Camera, Scene) {
+    let planet = Arc::new(Planet::new(3));
+    SceneBuilder::new(ImageConfig::new(16.0 / 9.0, 400, 50, 50))
+        .add_sphere(Vec3::new(0.0, 0.0, 0.0), 2.0, planet.surface_material())
+        .add_sphere(Vec3::new(0.0, 0.0, 0.0), 2.04, planet.cloud_material())
+        .set_camera(Vec3::new(6.0, 3.0, 8.0), Vec3::new(0.0, 0.0, 0.0), 30.0)
+        .build_with_bvh()
+}
+
+// the camera starts where its path does
+fn build_bouncing_ball(_options: &SceneOptions) -> (ImageConfig, Camera, Scene) {
+    SceneBuilder::from_scene(ImageConfig::new(16.0 / 9.0, 400, 10, 50), bouncing_ball_scene()).build_with_bvh()
+}
+
+fn build_random(options: &SceneOptions) -> (ImageConfig, Camera, Scene) {
+    //                                           500 spp originally
+    SceneBuilder::new(ImageConfig::new(3.0 / 2.0, 1200, 10, 50))
+        .add_object(random_scene(options.accelerator.unwrap_or(AcceleratorKind::KdTree), &options.palette))
+        .set_camera(Vec3::new(13.0, 2.0, 3.0), Vec3::new(0.0, 0.0, 0.0), 20.0)
+        .set_aperture(0.1)
+        .set_focus_distance(10.0)
+        .build_with_bvh()
+}
+
+// the measured conductors side by side, each a little rougher than the last
+fn build_metals(_options: &SceneOptions) -> (ImageConfig, Camera, Scene) {
+    let checkered = CheckeredTexture::new_with_solid(Color::new(0.2, 0.3, 0.1), Color::new(0.9, 0.9, 0.9));
+    let mut builder = SceneBuilder::new(ImageConfig::new(16.0 / 9.0, 400, 10, 50))
+        .add_sphere(Vec3::new(0.0, -100.5, -1.0), 100.0, Material::Lambertian{albedo: Box::new(checkered), normal_map: None});
+    for (i, name) in ["gold", "silver", "copper", "aluminum"].iter().enumerate() {
+        let metal = Material::conductor(name, 0.05 + 0.1 * i as f64).unwrap();
+        builder = builder.add_sphere(Vec3::new(-1.65 + 1.1 * i as f64, 0.0, -1.0), 0.5, metal);
+    }
+    builder
+        .set_camera(Vec3::new(0.0, 1.0, 4.0), Vec3::new(0.0, 0.0, -1.0), 30.0)
+        .build_with_bvh()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scenes_are_found_by_name_or_number() {
+        assert_eq!(find_scene("forest").unwrap().name, "forest");
+        assert_eq!(find_scene("6").unwrap().name, "city-night");
+        assert!(find_scene("moon").is_none() && find_scene("11").is_none());
+        // names are unique, so none hides another
+        assert!(SCENES.iter().all(|entry| SCENES.iter().filter(|other| other.name == entry.name).count() == 1));
+    }
+
+    #[test]
+    fn test_checked_in_tree_loads() {
+        let tree = low_poly_tree();
+        assert_eq!(tree.face_count(), 36);
+        // the trunk is bark and the canopy is leaves
+        let colour_at = |height: f64| {
+            let ray = Ray::new(Vec3::new(0.0, height, 5.0), Vec3::new(0.0, 0.0, -1.0), None);
+            match tree.hit(&ray, 0.001, f64::INFINITY).unwrap().material {
+                Material::Lambertian{albedo, ..} => albedo.value(0.0, 0.0, &Vec3::new(0.0, 0.0, 0.0)),
+                _ => panic!("expected a lambertian material")
+            }
+        };
+        assert!(colour_at(0.3).equal_to(&Color::new(0.35, 0.2, 0.1)));
+        assert!(colour_at(2.0).equal_to(&Color::new(0.1, 0.4, 0.12)));
+    }
+
+    #[test]
+    fn test_shadow_catcher_keeps_only_the_shadow() {
+        // a ball over a shadow catcher floor, lit from straight above. the sky
+        // is black so only the light decides what's in shadow
+        let catcher = Material::ShadowCatcher{albedo: Box::new(SolidTexture::uniform(0.5))};
+        let mut world = HittableList::new();
+        world.add(Plane::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0), catcher));
+        world.add(Sphere::new(Vec3::new(0.0, 1.0, 0.0), 0.5, Material::Lambertian{albedo: Box::new(SolidTexture::uniform(0.5)), normal_map: None}));
+        world.add(Sphere::new(Vec3::new(0.0, 1.0, -3.0), 0.5, Material::Dielectric{index_of_refraction: 1.5, absorption: Color::new(0.0, 0.0, 0.0)}));
+        let mut scene = Scene::from(world);
+        scene.sky_tint = Color::new(0.0, 0.0, 0.0);
+        scene.lights.push(Light::Point{position: Vec3::new(0.0, 5.0, 0.0), intensity: Color::new(50.0, 50.0, 50.0)});
+        let mut image = ImageConfig::new(1.0, 10, 1, 10);
+        image.glass_alpha = true;
+        let sample_towards = |from: Vec3, to: Vec3| camera_sample(&Ray::new(from, to - from, Some(0.0)), &scene, &image);
+
+        // under the ball it's all shadow, out in the open there's none, and
+        // either way the floor itself isn't part of the foreground
+        let shadowed = sample_towards(Vec3::new(2.0, 2.0, 0.0), Vec3::new(0.0, 0.0, 0.0));
+        assert_eq!((shadowed.alpha, shadowed.foreground.near_zero()), (1.0, true));
+        let open = sample_towards(Vec3::new(4.0, 2.0, 0.0), Vec3::new(4.0, 0.0, 0.0));
+        assert_eq!((open.alpha, open.foreground.near_zero()), (0.0, true));
+        // what's solid covers fully, the sky not at all
+        let ball = sample_towards(Vec3::new(0.0, 1.0, 3.0), Vec3::new(0.0, 1.0, 0.0));
+        assert!(ball.alpha == 1.0 && (ball.foreground - ball.colour).near_zero());
+        let sky = sample_towards(Vec3::new(0.0, 1.0, 3.0), Vec3::new(0.0, 10.0, 3.0));
+        assert!(sky.alpha == 0.0 && sky.foreground.near_zero());
+        // glass is premultiplied by however much it covered, which for clear
+        // glass with only sky behind it is usually nothing
+        let glass: Vec<CameraSample> = (0..20).map(|_| sample_towards(Vec3::new(3.0, 1.0, -3.0), Vec3::new(0.0, 1.0, -3.0))).collect();
+        assert!(glass.iter().all(|glass| (0.0..=1.0).contains(&glass.alpha) && (glass.foreground - glass.colour * glass.alpha).near_zero()));
+        assert!(glass.iter().filter(|glass| glass.alpha < 0.5).count() > 10);
+    }
+
+    // every scene at thumbnail size, so a change can't quietly break one nobody
+    // renders while working on something else. slow (the random scene alone has
+    // hundreds of spheres), so only with `cargo test --release --features slow-tests`
+    #[cfg(feature = "slow-tests")]
+    #[test]
+    fn test_every_scene_renders() {
+        let (width, height, samples) = (64, 36, 2);
+        for entry in SCENES {
+            // seeded, so a failure shows up again on the next run
+            let (image, camera, scene) = entry.build(&SceneOptions{seed: Some(1), ..Default::default()});
+            let mut luminance = Vec::with_capacity(width * height);
+            for j in 0..height {
+                for i in 0..width {
+                    let mut colour = Color::new(0.0, 0.0, 0.0);
+                    for _ in 0..samples {
+                        let u = (i as f64 + random_float()) / (width - 1) as f64;
+                        let v = (j as f64 + random_float()) / (height - 1) as f64;
+                        colour = colour + camera.get_ray(u, v).map_or_else(CameraSample::blocked, |ray| camera_sample(&ray, &scene, &image)).colour;
+                    }
+                    assert!(colour.x().is_finite() && colour.y().is_finite() && colour.z().is_finite(),
+                        "scene {} has a bad pixel at ({}, {}): {:?}", entry.name, i, j, colour);
+                    luminance.push((colour / samples as f64).luminance());
+                }
+            }
+            // something is in view, not just a flat colour (or black)
+            let mean = luminance.iter().sum::<f64>() / luminance.len() as f64;
+            let variance = luminance.iter().map(|l| (l - mean) * (l - mean)).sum::<f64>() / luminance.len() as f64;
+            assert!(mean > 1e-3 && variance > 1e-4, "scene {} looks empty: mean {} variance {}", entry.name, mean, variance);
+        }
+    }
+}
@@ -0,0 +1,142 @@
+use crate::vec3::*;
+use crate::ray::Ray;
+use crate::hittable::*;
+use crate::integrator::IntegratorSettings;
+use crate::utilities::{degrees_to_radians, INFINITY};
+
+// lights that aren't objects in the scene: they can't be seen or bumped into,
+// they only light things up. all of their light comes from a single point (or
+// direction), so a bounced ray would never find them. instead every hit checks
+// each light directly with a shadow ray (next event estimation)
+pub enum Light {
+    // shines equally in every direction, dimming with the square of the distance
+    Point{position: Vec3, intensity: Color},
+    // a point light that only shines in a cone around direction. it's at full
+    // strength inside inner_angle, fades out smoothly to nothing at outer_angle
+    // (both in degrees from the direction to the cone's edge), and dims with
+    // distance^falloff_exponent (2 is physically correct, lower carries further)
+    Spot{position: Vec3, direction: Vec3, intensity: Color, inner_angle: f64, outer_angle: f64, falloff_exponent: f64},
+    // light from so far away (e.g. the sun) it arrives everywhere along the same
+    // direction (the way it travels, not towards the light) and as strong.
+    // irradiance is what falls on a surface facing it, it doesn't dim
+    Directional{direction: Vec3, irradiance: Color}
+}
+
+// 0 at edge_0, 1 at edge_1 and an s-curve in between (no sudden edges)
+fn smoothstep(edge_0: f64, edge_1: f64, x: f64) -> f64 {
+    if edge_1 <= edge_0 {
+        return if x >= edge_1 { 1.0 } else { 0.0 }
+    }
+    let t = ((x - edge_0) / (edge_1 - edge_0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+pub struct LightSample {
+    // from the lit point towards the light, unit length
+    pub direction: Vec3,
+    pub distance: f64,
+    // the light arriving at the point, if nothing is in the way
+    pub radiance: Color
+}
+
+impl Light {
+    pub fn sample(&self, point: &Vec3) -> Option<LightSample> {
+        match self {
+            Light::Point{position, intensity} => {
+                let to_light = *position - *point;
+                let distance = to_light.length();
+                if distance <= 0.0 {
+                    return None
+                }
+                Some(LightSample {
+                    direction: to_light / distance,
+                    distance,
+                    radiance: *intensity / (distance * distance)
+                })
+            },
+            Light::Spot{position, direction, intensity, inner_angle, outer_angle, falloff_exponent} => {
+                let to_light = *position - *point;
+                let distance = to_light.length();
+                if distance <= 0.0 {
+                    return None
+                }
+                let to_light = to_light / distance;
+                // compare cosines rather than angles, a bigger cosine is closer to the middle
+                let cosine = (to_light * -1.0).dot_product(&direction.unit_vector());
+                let cone = smoothstep(degrees_to_radians(*outer_angle).cos(), degrees_to_radians(*inner_angle).cos(), cosine);
+                if cone <= 0.0 {
+                    return None
+                }
+                Some(LightSample {
+                    direction: to_light,
+                    distance,
+                    radiance: *intensity * (cone / distance.powf(*falloff_exponent))
+                })
+            },
+            Light::Directional{direction, irradiance} => {
+                if direction.near_zero() {
+                    return None
+                }
+                // nothing past the point can block it, however far away
+                Some(LightSample {
+                    direction: direction.unit_vector() * -1.0,
+                    distance: INFINITY,
+                    radiance: *irradiance
+                })
+            }
+        }
+    }
+}
+
+// whether the light in sample reaches record's point. the shadow ray starts a
+// little off the surface (on the light's side, see offset_origin) and stops a
+// little short of the light by the shadow epsilon
+pub fn is_visible(world: &dyn Hittable, record: &HitRecord, sample: &LightSample, time: f64, settings: &IntegratorSettings) -> bool {
+    let epsilon = settings.shadow_epsilon;
+    let origin = settings.offset_origin(record, &sample.direction, epsilon);
+    let shadow_ray = Ray::new(origin, sample.direction, Some(time));
+    world.hit(&shadow_ray, 0.0, sample.distance - epsilon).is_none()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spot_cone_falloff() {
+        let spot = Light::Spot{
+            position: Vec3::new(0.0, 1.0, 0.0),
+            direction: Vec3::new(0.0, -1.0, 0.0),
+            intensity: Color::new(1.0, 1.0, 1.0),
+            inner_angle: 20.0,
+            outer_angle: 40.0,
+            falloff_exponent: 2.0
+        };
+        let brightness = |x: f64| spot.sample(&Vec3::new(x, 0.0, 0.0)).map_or(0.0, |sample| sample.radiance.x() * sample.distance * sample.distance);
+        // inside the inner cone (10 degrees), between the cones (30) and outside (50)
+        assert!((brightness(10f64.to_radians().tan()) - 1.0).abs() < 1e-9);
+        let between = brightness(30f64.to_radians().tan());
+        assert!(between > 0.0 && between < 1.0);
+        assert_eq!(brightness(50f64.to_radians().tan()), 0.0);
+    }
+
+    #[test]
+    fn test_directional_shadows() {
+        use crate::sphere::Sphere;
+        use crate::material::Material;
+        let sun = Light::Directional{direction: Vec3::new(0.0, -2.0, 0.0), irradiance: Color::new(1.0, 1.0, 1.0)};
+        let grey = || Material::Lambertian{albedo: Box::new(crate::texture::SolidTexture::uniform(0.5)), normal_map: None};
+        let ground = Sphere::new(Vec3::new(0.0, -1000.0, 0.0), 1000.0, grey());
+        let blocker = Sphere::new(Vec3::new(0.0, 500.0, 0.0), 1.0, grey());
+        let settings = IntegratorSettings::default();
+
+        // as bright anywhere, but blocked by something however high up
+        let down = |x: f64| ground.hit(&Ray::new(Vec3::new(x, 1.0, 0.0), Vec3::new(0.0, -1.0, 0.0), None), 0.001, INFINITY).unwrap();
+        for x in [0.0, 5.0] {
+            let record = down(x);
+            let sample = sun.sample(&record.point).unwrap();
+            assert!((sample.direction - Vec3::new(0.0, 1.0, 0.0)).near_zero() && sample.radiance.x() == 1.0);
+            assert_eq!(is_visible(&blocker, &record, &sample, 0.0, &settings), x != 0.0);
+        }
+    }
+}
@@ -1,252 +1,897 @@
-mod vec3;
-mod ray;
-mod sphere;
-mod moving_sphere;
-mod hittable;
-mod hittable_list;
-mod utilities;
-mod camera;
-mod material;
-mod aabb;
-mod bvh_v3;
-mod texture;
-mod perlin;
-
-use vec3::*;
-use sphere::Sphere;
-use moving_sphere::MovingSphere;
-use ray::Ray;
-use hittable::*;
-use hittable_list::HittableList;
-use utilities::*;
-use camera::Camera;
-use material::*;
-use bvh_v3::BVH;
-use texture::*;
-use perlin::Perlin;
-
-// we shade the spere based on its normal (gives us orientation of lighting)
-// e.g. if an object faces a light source it should be bright, dark if not
-// e.g. if _|_ * (| is object, * is sun, _ is ground) how should | be shaded
-fn ray_colour(ray: &Ray, world: &HittableList, depth: u64) -> Vec3 {
-    if depth <= 0 {
-        return Color::new(0.0, 0.0, 0.0);
-    }
-
-    // see if ray intersects sphere so adjust color accordingly.
-    // use 0.001 instead of 0 to correct for the 'shadow acne' problem:
-    // https://www.scratchapixel.com/lessons/3d-basic-rendering/introduction-to-shading/ligth-and-shadows
-    if let Some(record) = world.hit(ray, 0.001, INFINITY) {
-        if let Some(scattering) = record.material.scatter(ray, &record) {
-            return scattering.attenuation() * ray_colour(&scattering.scattered(), world, depth - 1);
-        }
-
-        return Color::new(0.0, 0.0, 0.0)
-    }
-
-    let unit_direction = ray.direction.unit_vector();
-    let t = 0.5 * (unit_direction.y() + 1.0);
-    // blue to white background gradient (t = 1 -> blue, t = 0 -> white)
-    Color::new(1.0, 1.0, 1.0) * (1.0 - t) + Color::new(0.5, 0.7, 1.0) * t
-}
+mod stream;
+mod tev;
+mod video;
+#[cfg(feature = "preview")]
+mod preview;
 
-fn random_scene() -> HittableList {
-    let mut world: HittableList = HittableList::new();
-    let ground_albedo = Vec3::new(0.5, 0.5, 0.5);
-    // ground
-    world.add(Sphere::new(Vec3::new(0.0, -1000.0, 0.0), 1000.0, Material::Lambertian{albedo: Box::new(SolidTexture::new(ground_albedo))}));
-    
-    for a in -11..11 {
-        for b in -11..11 {
-            let mat_choice = random_float();
-            let center = Vec3::new(a as f64 + 0.9 * random_float(), 0.2, b as f64 + 0.9 * random_float());
-
-            if (center - Vec3::new(4.0, 0.2, 0.0)).length() > 0.9 {
-                // diffuse
-                if mat_choice < 0.8 {
-                    let albedo = Color::random() * Vec3::random();
-                    world.add(Sphere::new(center, 0.2, Material::Lambertian{albedo: Box::new(SolidTexture::new(albedo))}));
-                // metal
-                } else if mat_choice < 0.95 {
-                    let albedo = Color::random_in_range(0.5, 1.0);
-                    let fuzz = random_float_in_range(0.0, 0.5);
-                    world.add(Sphere::new(center, 0.2, Material::Metal{albedo: albedo, fuzz: fuzz}));
-                // glass
-                } else {
-                    world.add(Sphere::new(center, 0.2, Material::Dielectric{index_of_refraction: 1.5}));
+use rays::*;
+use rays::vec3::*;
+use rays::ray::RayDebug;
+use rays::hittable::*;
+use rays::utilities::*;
+use rays::camera::{ApertureShape, Camera};
+use rays::lens::LensSystem;
+use rays::restart::RenderState;
+use rays::adaptive::{AdaptiveSampling, PixelEstimate};
+use rays::checkpoint::*;
+use rays::color_space::ColorSpace;
+use rays::highlights::{ClampMode, HighlightSettings};
+use rays::tonemap::{AutoExposure, Metering, ToneMapper, ToneMapping};
+use rays::bloom::Bloom;
+use rays::dither::Dither;
+use rays::layers::LayerFilm;
+use rays::aovs::{AovFilm, MaterialIds};
+use rays::denoise::Denoiser;
+use rays::animation::FrameTiming;
+use rays::progress::{Progress, ProgressStyle};
+use rays::ray_stats::RayStats;
+use rays::ascii::AsciiRamp;
+use rays::sampler::{SamplerKind, Sobol};
+use rays::filter::PixelFilter;
+use rays::snapshot::{SnapshotInterval, SnapshotSchedule};
+use rays::region::Region;
+use rays::accelerator::AcceleratorKind;
+use rays::palette::MaterialPalette;
+use rays::integrator::{AmbientOcclusion, DebugView, IntegratorKind};
+use rays::metadata::*;
+use video::VideoWriter;
+use stream::StreamWriter;
+use tev::TevClient;
+use std::path::Path;
+use std::io::{BufWriter, Write};
+use std::time::Instant;
+
+fn main() {
+    let mut args: Vec<String> = std::env::args().collect();
+    // `--list-scenes` prints the names --scene takes, and what each scene is
+    if args.iter().any(|arg| arg == "--list-scenes") {
+        let width = SCENES.iter().map(|entry| entry.name.len()).max().unwrap_or(0);
+        for entry in SCENES {
+            println!("{:width$}  {}", entry.name, entry.description, width = width);
+        }
+        return
+    }
+    // `rays resume render-0003.ckpt` carries on from a checkpoint with the
+    // settings it was started with. options after the path are added to (and
+    // win over) those, e.g. `--spp 256` to branch off a longer render
+    let mut resumed_state: Option<RenderState> = None;
+    if args.get(1).map(|arg| arg.as_str()) == Some("resume") {
+        let path = args.get(2).expect("resume needs a checkpoint file");
+        let (saved_args, state) = read_checkpoint(Path::new(path)).expect("Failed to read checkpoint");
+        let mut resumed_args = vec![args[0].clone()];
+        resumed_args.extend_from_slice(&args[3..]);
+        resumed_args.extend(saved_args.into_iter().skip(1));
+        args = resumed_args;
+        resumed_state = Some(state);
+    }
+    // `--frames 48` renders an animation: frames 0 to 47 one after another,
+    // each as if with `--frame n` (see render). #### in file paths (e.g.
+    // `--output frame-####.ppm`) is replaced by the frame number, padded to as
+    // many digits. with no --output, the frames follow one another on stdout.
+    // `--video ball.mp4` (or .webm) encodes the frames into a video with
+    // ffmpeg instead, at --fps, see video.rs
+    let frames: Option<u64> = args.iter().position(|arg| arg == "--frames")
+        .map(|position| args.get(position + 1).and_then(|arg| arg.parse().ok()).expect("--frames needs a number of frames"));
+    let mut video = args.iter().position(|arg| arg == "--video").map(|position| {
+        let path = Path::new(args.get(position + 1).expect("--video needs a file path"));
+        if frames.is_none() {
+            panic!("--video needs --frames");
+        }
+        let fps = args.iter().position(|arg| arg == "--fps")
+            .map_or(24.0, |position| args.get(position + 1).and_then(|arg| arg.parse().ok()).expect("--fps needs a number of frames per second"));
+        VideoWriter::new(path, fps)
+    });
+    match frames {
+        Some(frames) => {
+            if resumed_state.is_some() {
+                panic!("A checkpoint only has one frame, resume it without --frames");
+            }
+            if let Some(position) = args.iter().position(|arg| arg == "--output") {
+                if !args.get(position + 1).is_some_and(|path| path.contains('#')) {
+                    panic!("--output needs #### in its path for --frames, or every frame would overwrite the last");
+                }
+            }
+            for frame in 0..frames {
+                eprintln!("Frame {} of {}", frame + 1, frames);
+                let mut frame_args: Vec<String> = Vec::with_capacity(args.len() + 2);
+                let mut skip = false;
+                for arg in args.iter() {
+                    if std::mem::take(&mut skip) {
+                        continue
+                    }
+                    skip = arg == "--frames";
+                    if !skip {
+                        frame_args.push(animation::frame_path(arg, frame));
+                    }
                 }
+                frame_args.extend(["--frame".to_string(), frame.to_string()]);
+                render(frame_args, None, video.as_mut());
+            }
+            if let Some(video) = video.take() {
+                video.finish().expect("Failed to finish video");
             }
+        },
+        None => render(args, resumed_state, None)
+    }
+}
+
+// renders the image args ask for, on top of a saved render's state if there
+// is one, and adds it to the video if it's a frame of one
+fn render(mut args: Vec<String>, resumed_state: Option<RenderState>, video: Option<&mut VideoWriter>) {
+    // `--accelerator kd-tree` (or bvh, flat-bvh, lbvh) to compare them on the same scene
+    let accelerator = args.iter().position(|arg| arg == "--accelerator").map(|position| {
+        let name = args.get(position + 1).expect("--accelerator needs a name");
+        AcceleratorKind::parse(name).unwrap_or_else(|| panic!("Unknown accelerator {}", name))
+    });
+    // a checkpointed render always has a seed, which its checkpoints keep with
+    // the other arguments. each sample is seeded from it and the sample's
+    // number, which the checkpoint's film has (a pixel's count is its next
+    // sample's number), so a resumed render comes out as if it never stopped
+    if args.iter().any(|arg| arg == "--checkpoint-every") && !args.iter().any(|arg| arg == "--seed") {
+        args.extend(["--seed".to_string(), rand::random::<u64>().to_string()]);
+    }
+    // `--seed 42` makes the render repeatable: the same scene (for scenes that
+    // are generated) and the same samples, so the same image
+    let seed: Option<u64> = args.iter().position(|arg| arg == "--seed")
+        .map(|position| args.get(position + 1).and_then(|arg| arg.parse().ok()).expect("--seed needs a number"));
+    // `--scene-scale 0.001` scales the epsilons rays leave surfaces by with
+    // the scene, e.g. for a model in millimetres, so small parts don't get
+    // skipped over (or a big scene's surfaces don't shadow themselves)
+    let scale = args.iter().position(|arg| arg == "--scene-scale")
+        .map(|position| args.get(position + 1).and_then(|arg| arg.parse().ok()).expect("--scene-scale needs a number"));
+    let mut options = SceneOptions{accelerator, seed, scale, ..Default::default()};
+    // files the scene is built from, recorded in the render's metadata
+    let mut inputs = Vec::new();
+    // `--palette palette.txt` constrains generated materials, see MaterialPalette::parse
+    if let Some(position) = args.iter().position(|arg| arg == "--palette") {
+        let path = args.get(position + 1).expect("--palette needs a file path");
+        let text = std::fs::read_to_string(path).expect("Failed to read palette");
+        options.palette = MaterialPalette::parse(&text).unwrap_or_else(|e| panic!("Bad palette {}: {}", path, e));
+        inputs.push(SceneInput::read(path).expect("Failed to read palette"));
+    }
+    // `--scene forest` renders the scene called forest (or `--scene 3`, by its
+    // place in the list), see --list-scenes. without it it's the first
+    let scene_entry = args.iter().position(|arg| arg == "--scene").map_or(&SCENES[0], |position| {
+        let name = args.get(position + 1).expect("--scene needs a scene name");
+        find_scene(name).unwrap_or_else(|| panic!("Unknown scene {}, see --list-scenes", name))
+    });
+    let scene_start = Instant::now();
+    let (mut image, mut camera, mut scene): (ImageConfig, Camera, Scene) = scene_entry.build(&options);
+    image.seed = seed;
+    // `--frame 12` renders frame 12 of an animation (from 0): the shutter is
+    // open for that frame's time, `--fps` (24 without it) and `--shutter-angle`
+    // (180 degrees without it, 360 for the whole frame) deciding when that is,
+    // so what moves is blurred by how far it moves in that time. a scene with
+    // a camera path has the camera follow it while the shutter is open, so
+    // pans are blurred too
+    if let Some(position) = args.iter().position(|arg| arg == "--frame") {
+        let frame: u64 = args.get(position + 1).and_then(|arg| arg.parse().ok()).expect("--frame needs a frame number");
+        let fps = args.iter().position(|arg| arg == "--fps")
+            .map_or(24.0, |position| args.get(position + 1).and_then(|arg| arg.parse().ok()).expect("--fps needs a number of frames per second"));
+        let mut timing = FrameTiming::new(fps);
+        if let Some(position) = args.iter().position(|arg| arg == "--shutter-angle") {
+            timing = timing.with_shutter_angle(args.get(position + 1).and_then(|arg| arg.parse().ok()).expect("--shutter-angle needs a number of degrees"));
+        }
+        let (open, close) = timing.shutter(frame);
+        if let Some(path) = &scene.camera_path {
+            let (opening, closing) = (path.at(open), path.at(close));
+            camera = camera.moved(opening.lookfrom, opening.lookat, opening.vertical_fov)
+                .with_motion(closing.lookfrom, closing.lookat, closing.vertical_fov);
+        }
+        camera = camera.with_shutter(open, close);
+    }
+    // `--spp 64` overrides the scene's samples per pixel
+    if let Some(position) = args.iter().position(|arg| arg == "--spp") {
+        let spp = args.get(position + 1).and_then(|arg| arg.parse().ok()).expect("--spp needs a number of samples");
+        image.samples_per_pixel = spp;
+    }
+    // `--adaptive 0.02` stops sampling a pixel once its relative error is
+    // under 0.02 (`--adaptive off` always takes every sample), and
+    // `--adaptive-min 16` never stops before 16 samples (16 without it), see
+    // AdaptiveSampling
+    if let Some(position) = args.iter().position(|arg| arg == "--adaptive") {
+        let threshold = args.get(position + 1).expect("--adaptive needs a relative error or off");
+        image.adaptive = match threshold.as_str() {
+            "off" => None,
+            threshold => {
+                let threshold: f64 = threshold.parse().unwrap_or_else(|_| panic!("--adaptive needs a relative error or off, got {}", threshold));
+                let min_samples = image.adaptive.as_ref().map_or(16, |adaptive| adaptive.min_samples);
+                Some(AdaptiveSampling::new(min_samples, threshold))
+            }
+        };
+    }
+    if let Some(position) = args.iter().position(|arg| arg == "--adaptive-min") {
+        let min_samples: u64 = args.get(position + 1).and_then(|arg| arg.parse().ok()).expect("--adaptive-min needs a number of samples");
+        match &mut image.adaptive {
+            Some(adaptive) => adaptive.min_samples = min_samples.max(2),
+            None => panic!("--adaptive-min needs --adaptive or a scene with adaptive sampling")
+        }
+    }
+    // `--sampler sobol` (or halton, stratified, independent) spreads each
+    // pixel's samples out evenly instead of at random, see SamplerKind
+    if let Some(position) = args.iter().position(|arg| arg == "--sampler") {
+        let name = args.get(position + 1).expect("--sampler needs a name");
+        image.sampler = SamplerKind::parse(name, image.samples_per_pixel).unwrap_or_else(|| panic!("Unknown sampler {}", name));
+    }
+    // `--filter mitchell` (or gaussian, tent, box) weights samples around each
+    // pixel by that filter instead of averaging the ones inside it, for
+    // smoother edges, see filter.rs
+    if let Some(position) = args.iter().position(|arg| arg == "--filter") {
+        let name = args.get(position + 1).expect("--filter needs box, tent, gaussian or mitchell");
+        image.filter = PixelFilter::parse(name).unwrap_or_else(|| panic!("Unknown filter {}", name));
+    }
+    // `--blue-noise` dithers the sampler's samples with blue noise between
+    // pixels (see blue_noise.rs). independent samples have nothing to shift,
+    // so it brings in sobol unless another sampler was asked for
+    if args.iter().any(|arg| arg == "--blue-noise") {
+        image.blue_noise = true;
+        if let SamplerKind::Independent(_) = image.sampler {
+            image.sampler = SamplerKind::Sobol(Sobol);
+        }
+    }
+    // `--aperture-blades 6` makes the lens' opening a hexagon rather than a
+    // circle, and so the out of focus highlights (bokeh). `--aperture-rotation 30`
+    // turns the blades by 30 degrees
+    if let Some(position) = args.iter().position(|arg| arg == "--aperture-blades") {
+        let blades = args.get(position + 1).and_then(|arg| arg.parse().ok()).expect("--aperture-blades needs a number of blades");
+        let rotation = args.iter().position(|arg| arg == "--aperture-rotation")
+            .map_or(0.0, |position| args.get(position + 1).and_then(|arg| arg.parse().ok()).expect("--aperture-rotation needs a number of degrees"));
+        camera = camera.with_aperture_shape(ApertureShape::Polygonal{blades, rotation});
+    } else if args.iter().any(|arg| arg == "--aperture-rotation") {
+        panic!("--aperture-rotation needs --aperture-blades");
+    }
+    // `--lens lens.dat` (or double-gauss) shoots rays through a real lens on a
+    // full frame (36x24mm) film instead, taking the scene's units as metres
+    if let Some(position) = args.iter().position(|arg| arg == "--lens") {
+        let name = args.get(position + 1).expect("--lens needs a lens file or double-gauss");
+        let system = if name == "double-gauss" {
+            LensSystem::double_gauss()
+        } else {
+            let text = std::fs::read_to_string(name).expect("Failed to read lens");
+            LensSystem::parse(&text).unwrap_or_else(|e| panic!("Bad lens {}: {}", name, e))
+        };
+        camera = camera.with_realistic_lens(system, 43.27, 0.001);
+    }
+    // `--ray-differentials` filters image textures to each pixel's footprint
+    // (see RayDifferential), for scenes that don't already turn it on
+    if args.iter().any(|arg| arg == "--ray-differentials") {
+        image.integrator.ray_differentials = true;
+    }
+    // `--integrator naive` (or path, ao, normals, depth, uv, faces, heatmap)
+    // renders with another integrator, see IntegratorKind and DebugView.
+    // `--ao 16` is ambient occlusion with 16 rays per hit, and `--ao-distance 2`
+    // only counts things closer than 2 units. `--depth-far 20` makes the depth
+    // view black 20 units away
+    if let Some(position) = args.iter().position(|arg| arg == "--integrator") {
+        let name = args.get(position + 1).expect("--integrator needs a name");
+        image.integrator.kind = IntegratorKind::parse(name).unwrap_or_else(|| panic!("Unknown integrator {}", name));
+    }
+    if let Some(position) = args.iter().position(|arg| arg == "--ao") {
+        let rays = args.get(position + 1).and_then(|arg| arg.parse().ok()).expect("--ao needs a number of rays");
+        image.integrator.kind = IntegratorKind::AmbientOcclusion(AmbientOcclusion::new(rays));
+    }
+    if let Some(position) = args.iter().position(|arg| arg == "--ao-distance") {
+        let distance = args.get(position + 1).and_then(|arg| arg.parse().ok()).expect("--ao-distance needs a distance");
+        match &mut image.integrator.kind {
+            IntegratorKind::AmbientOcclusion(ambient_occlusion) => ambient_occlusion.max_distance = distance,
+            _ => panic!("--ao-distance needs --ao or --integrator ao")
+        }
+    }
+    if let Some(position) = args.iter().position(|arg| arg == "--depth-far") {
+        let far = args.get(position + 1).and_then(|arg| arg.parse().ok()).expect("--depth-far needs a distance");
+        match &mut image.integrator.kind {
+            IntegratorKind::Debug(DebugView::Depth{far: depth_far}) => *depth_far = far,
+            _ => panic!("--depth-far needs --integrator depth")
+        }
+    }
+    // `--firefly-clamp 10` dims any sample brighter than 10 to it, and
+    // `--outlier-rejection 4` throws away samples 4 standard deviations
+    // brighter than their pixel's others. both remove fireflies at the cost
+    // of a slightly darker, biased image, see IntegratorSettings
+    if let Some(position) = args.iter().position(|arg| arg == "--firefly-clamp") {
+        let max_luminance = args.get(position + 1).and_then(|arg| arg.parse().ok()).expect("--firefly-clamp needs a luminance");
+        image.integrator.firefly_clamp = Some(max_luminance);
+    }
+    if let Some(position) = args.iter().position(|arg| arg == "--outlier-rejection") {
+        let deviations = args.get(position + 1).and_then(|arg| arg.parse().ok()).expect("--outlier-rejection needs a number of standard deviations");
+        image.integrator.outlier_rejection = Some(deviations);
+    }
+    let scene_build_time = scene_start.elapsed();
+    // `--tree-stats` prints how good the scene's acceleration structures are
+    // (e.g. to compare `--accelerator kd-tree` with the bvhs), see BvhStats
+    if args.iter().any(|arg| arg == "--tree-stats") {
+        for stats in scene.world.tree_stats() {
+            eprint!("{}", stats.report());
         }
     }
 
-    // front glass sphere
-    world.add(Sphere::new(Vec3::new(0.0, 1.0, 0.0), 1.0, Material::Dielectric{index_of_refraction: 1.5}));
-    let m_albedo = Color::new(0.4, 0.2, 0.1);
-    // front matte sphere
-    world.add(Sphere::new(Vec3::new(-4.0, 1.0, 0.0), 1.0, Material::Lambertian{albedo: Box::new(SolidTexture::new(m_albedo))}));
-    let m2_albedo = Color::new(0.7, 0.6, 0.5);
-    let m2_fuzz = 0.0;
-    // front metal sphere
-    world.add(Sphere::new(Vec3::new(4.0, 1.0, 0.0), 1.0, Material::Metal{albedo: m2_albedo, fuzz: m2_fuzz}));
+    // `--preview` renders progressively in a window instead, with a panel for
+    // tweaking the scene's materials. see preview.rs
+    if args.iter().any(|arg| arg == "--preview") {
+        #[cfg(feature = "preview")]
+        {
+            preview::run(scene, camera, image);
+            return;
+        }
+        #[cfg(not(feature = "preview"))]
+        panic!("--preview needs rays built with --features preview");
+    }
 
-    world
-}
+    // `--export scene.obj` (or .gltf) writes the scene's geometry instead of rendering it
+    if let Some(position) = args.iter().position(|arg| arg == "--export") {
+        let path = args.get(position + 1).expect("--export needs a file path");
+        export::write_scene(Path::new(path), &scene.world.tessellate()).expect("Failed to export scene");
+        eprintln!("Exported scene to {}", path);
+        return;
+    }
 
-fn basic_zoomed_in_scene() -> HittableList {
-    let mut world: HittableList = HittableList::new();
-
-    let material_ground = Color::new(0.8, 0.8, 0.0);
-    let material_center = Color::new(0.1, 0.2, 0.5);
-    let material_right = Color::new(0.7, 0.6, 0.5);
-
-    let white_green_checkered = CheckeredTexture::new_with_solid(Vec3::new(0.2, 0.3, 0.1), Vec3::new(0.9, 0.9, 0.9));
-    let ground = Sphere::new(Vec3::new(0.0, -100.5, -1.0), 100.0, Material::Lambertian{albedo: Box::new(white_green_checkered)});
-    // let middle = Sphere::new(Vec3::new(0.0, 0.0, -1.0), 0.5, Material::Lambertian{albedo: material_center});
-    // moving middle sphere
-    let center_0 = Vec3::new(0.0, 0.0, -1.0);
-    let center_1 = center_0 + Vec3::new(0.0, random_float_in_range(0.0, 0.5), 0.0);
-    // let middle = MovingSphere::new(center_0, 0.0, center_1, 1.0, 0.5, Material::Lambertian{albedo: Box::new(SolidTexture::new(material_center))});
-    let middle = Sphere::new(center_0, 0.5, Material::Lambertian{albedo: Box::new(SolidTexture::new(material_center))});
-    // the 2 spheres below work together to make a hollow glass 'bubble'
-    let left = Sphere::new(Vec3::new(-1.0, 0.0, -1.0), 0.5, Material::Dielectric{index_of_refraction: 1.5});
-    // note: negative radius doesn't change anything, however normal's point inward.
-    // note: doesn't work properly with AABB/BVH because of the radius
-    // let left_inner = Sphere::new(Vec3::new(-1.0, 0.0, -1.0), -0.4, Material::Dielectric{index_of_refraction: 1.5});
-    let right = Sphere::new(Vec3::new(1.0, 0.0, -1.0), 0.5, Material::Metal{albedo: material_right, fuzz: 0.0});
-
-    let mut y: Vec<Box<dyn Hittable>> = Vec::new();
-    y.push(Box::new(ground));        // ground
-    y.push(Box::new(middle));        // middle, matte sphere
-    y.push(Box::new(left));          // left metal sphere
-    // y.push(Box::new(left_inner));    // left metal sphere (inner)
-    y.push(Box::new(right));         // right metal sphere
-    world.add(BVH::construct(y, 0.0, 1.0));
-
-    // world.add(ground);        // ground
-    // world.add(middle);        // middle, matte sphere
-    // world.add(left);          // left metal sphere
-    // // world.add(left_inner);    // left metal sphere (inner)
-    // world.add(right);         // right metal sphere
-
-    world
-}
+    // `--output render.ppm` writes the image to a file, with a json sidecar
+    // (render.json) describing how it was made, see metadata.rs.
+    // `--metadata path.json` writes the sidecar somewhere else, or for stdout renders
+    let output_path = args.iter().position(|arg| arg == "--output").map(|position| {
+        Path::new(args.get(position + 1).expect("--output needs a file path"))
+    });
+    let metadata_path = args.iter().position(|arg| arg == "--metadata")
+        .map(|position| Path::new(args.get(position + 1).expect("--metadata needs a file path")).to_path_buf())
+        .or_else(|| output_path.map(sidecar_path));
 
-fn perlin_noise() -> HittableList {
-    let mut world: HittableList = HittableList::new();
+    // `--region 100,50,300,200` only renders the pixels from (100, 50) up to
+    // (300, 200), counting from the top left, e.g. to look into one noisy
+    // spot. they come out as they would in the whole render and the rest of
+    // the image is black, or `--crop` writes only the region
+    let region = match args.iter().position(|arg| arg == "--region") {
+        Some(position) => {
+            let text = args.get(position + 1).expect("--region needs x0,y0,x1,y1");
+            Region::parse(text).unwrap_or_else(|| panic!("Bad region {}, expected x0,y0,x1,y1", text))
+                .within(image.image_width as u32, image.image_height as u32)
+                .unwrap_or_else(|| panic!("Region {} is outside the {}x{} image", text, image.image_width, image.image_height))
+        },
+        None => Region::whole(image.image_width as u32, image.image_height as u32)
+    };
+    let crop = args.iter().any(|arg| arg == "--crop");
+    let (output_width, output_height) = if crop { (region.width(), region.height()) } else { (image.image_width as u32, image.image_height as u32) };
 
-    let perlin = Box::new(NoiseTexture::new(4.0));
-    let perlin_sphere = Box::new(NoiseTexture::new(4.0));
-    let ground = Sphere::new(Vec3::new(0.0, -1000.0, 0.0), 1000.0, Material::Lambertian{albedo: perlin});
-    let sphere = Sphere::new(Vec3::new(0.0, 2.0, 0.0), 2.0, Material::Lambertian{albedo: perlin_sphere});
+    // `--stream` sends each finished scanline in the binary format from stream.rs
+    // instead of writing a ppm, so a viewer can show the render as it goes
+    let mut stream = None;
+    let mut ppm: Option<Box<dyn Write>> = None;
+    let output: Option<Box<dyn Write>> = match output_path {
+        Some(path) => Some(Box::new(BufWriter::new(std::fs::File::create(path).expect("Failed to create output file")))),
+        // frames going into a video aren't written out as well unless asked for
+        None if video.is_some() => None,
+        None => Some(Box::new(BufWriter::new(std::io::stdout().lock())))
+    };
+    if let Some(mut output) = output {
+        if args.iter().any(|arg| arg == "--stream") {
+            stream = Some(StreamWriter::new(output, output_width, output_height).expect("Failed to write stream header"));
+        } else {
+            writeln!(output, "P3\n{0} {1}\n255", output_width, output_height).expect("Failed to write image");
+            ppm = Some(output);
+        }
+    }
 
-    world.add(ground);
-    world.add(sphere);
-    // let mut y: Vec<Box<dyn Hittable>> = Vec::new();
-    // y.push(Box::new(ground));
-    // y.push(Box::new(sphere));
-    // world.add(BVH::construct(y, 0.0, 1.0));
+    // `--rgba render.png` also writes the image with an alpha channel, for
+    // compositing over a backplate: the background is transparent, so is clear
+    // glass in front of it, and shadow catchers only keep their shadows. the
+    // colour is premultiplied by alpha
+    let rgba_path = args.iter().position(|arg| arg == "--rgba")
+        .map(|position| Path::new(args.get(position + 1).expect("--rgba needs a file path")));
+    image.glass_alpha = rgba_path.is_some();
+    // `--alpha straight` writes the colour not multiplied by alpha instead,
+    // for programs that want it that way
+    let straight_alpha = match args.iter().position(|arg| arg == "--alpha") {
+        Some(position) => match args.get(position + 1).map(|arg| arg.as_str()) {
+            Some("straight") => true,
+            Some("premultiplied") => false,
+            _ => panic!("--alpha needs straight or premultiplied")
+        },
+        None => false
+    };
+    // `--png16 render.png` also writes the image with 16 bits per channel,
+    // for grading without banding
+    let png16_path = args.iter().position(|arg| arg == "--png16")
+        .map(|position| Path::new(args.get(position + 1).expect("--png16 needs a file path")));
 
-    world
-}
+    // `--output-space acescg` (or srgb, linear) converts the image to the space
+    // the rest of a colour managed pipeline expects. without it pixels get the
+    // srgb curve. tev and streams get the space's primaries but stay linear.
+    // `--gamma 2.2` uses a plain power curve instead (2.0 is the old square root)
+    let mut output_space = args.iter().position(|arg| arg == "--output-space").map(|position| {
+        let name = args.get(position + 1).expect("--output-space needs a colour space");
+        ColorSpace::parse(name).unwrap_or_else(|| panic!("Unknown colour space {}", name))
+    });
+    if let Some(position) = args.iter().position(|arg| arg == "--gamma") {
+        let gamma: f64 = args.get(position + 1).and_then(|arg| arg.parse().ok()).expect("--gamma needs a number");
+        if gamma <= 0.0 || output_space.is_some() {
+            panic!("--gamma needs a positive number, and no --output-space");
+        }
+        output_space = Some(ColorSpace::Gamma(gamma));
+    }
+    // `--clamp luminance` scales colours that are too bright as a whole instead
+    // of clipping each channel, which keeps their hue, and `--highlight-knee 0.8`
+    // rolls values from 0.8 up off smoothly instead of cutting them at 1.
+    // only the 8 bit image is affected, tev and streams get the values as they are
+    let mut highlights = HighlightSettings::new(ClampMode::PerChannel);
+    if let Some(position) = args.iter().position(|arg| arg == "--clamp") {
+        let name = args.get(position + 1).expect("--clamp needs channel or luminance");
+        highlights.clamp = ClampMode::parse(name).unwrap_or_else(|| panic!("Unknown clamp mode {}", name));
+    }
+    if let Some(position) = args.iter().position(|arg| arg == "--highlight-knee") {
+        let knee = args.get(position + 1).and_then(|arg| arg.parse().ok()).expect("--highlight-knee needs a number");
+        highlights = highlights.with_compression(knee);
+    }
+    // `--exposure 1.5` brightens the image by 1.5 stops (negative darkens) and
+    // `--tonemap aces` (or reinhard, clamp) rolls bright values off instead of
+    // clipping them, see tonemap.rs. like the highlights, only the 8 bit image
+    let mut tone_mapping = ToneMapping::default();
+    if let Some(position) = args.iter().position(|arg| arg == "--tonemap") {
+        let name = args.get(position + 1).expect("--tonemap needs clamp, reinhard or aces");
+        tone_mapping.mapper = ToneMapper::parse(name).unwrap_or_else(|| panic!("Unknown tone mapper {}", name));
+    }
+    if let Some(position) = args.iter().position(|arg| arg == "--exposure") {
+        let exposure = args.get(position + 1).and_then(|arg| arg.parse().ok()).expect("--exposure needs a number of stops");
+        tone_mapping = tone_mapping.with_exposure(exposure);
+    }
+    // `--auto-exposure average` picks the exposure from how bright the render
+    // came out, so its log average is middle grey (or `median`, or a percentile
+    // like `90`, `--exposure-key 0.5` for something other than middle grey).
+    // `--exposure` then adjusts from there. the ppm is written once the render
+    // is done, see tonemap.rs
+    let auto_exposure = args.iter().position(|arg| arg == "--auto-exposure").map(|position| {
+        let name = args.get(position + 1).expect("--auto-exposure needs average, median or a percentile");
+        let mut auto_exposure = AutoExposure::new(Metering::parse(name).unwrap_or_else(|| panic!("Unknown metering {}", name)));
+        if let Some(position) = args.iter().position(|arg| arg == "--exposure-key") {
+            auto_exposure = auto_exposure.with_key(args.get(position + 1).and_then(|arg| arg.parse().ok()).expect("--exposure-key needs a number"));
+        }
+        auto_exposure
+    });
+    // `--bloom` makes lights and bright highlights glow, see bloom.rs.
+    // `--bloom-threshold 2` only has what's brighter than 2 glow, `--bloom-strength 0.5`
+    // spreads more of it and `--bloom-radius 0.03` (of the image's width) further
+    // (any of them turns it on).
+    // the ppm is then written once the render is done, and like tone mapping
+    // it's only the 8 and 16 bit images, and not the rgba one
+    let bloom = args.iter().any(|arg| arg.starts_with("--bloom")).then(|| {
+        let number = |flag: &str| args.iter().position(|arg| arg == flag)
+            .map(|position| args.get(position + 1).and_then(|arg| arg.parse().ok()).unwrap_or_else(|| panic!("{} needs a number", flag)));
+        let mut bloom = Bloom::new();
+        if let Some(threshold) = number("--bloom-threshold") {
+            bloom = bloom.with_threshold(threshold);
+        }
+        if let Some(strength) = number("--bloom-strength") {
+            bloom = bloom.with_strength(strength);
+        }
+        if let Some(radius) = number("--bloom-radius") {
+            bloom = bloom.with_radius(radius);
+        }
+        bloom
+    });
+    // `--dither blue-noise` (or ordered) breaks up the banding 8 bits leaves
+    // in smooth gradients like the sky, see dither.rs
+    let dither = args.iter().position(|arg| arg == "--dither").map(|position| {
+        let name = args.get(position + 1).expect("--dither needs ordered or blue-noise");
+        Dither::parse(name).unwrap_or_else(|| panic!("Unknown dither {}", name))
+    });
+    // both need all of the render, so the ppm is only written once it's done
+    let finished_image_only = auto_exposure.is_some() || bloom.is_some();
+    let linear_output = |colour: Color| output_space.map_or(colour, |space| space.encode_linear(colour));
+    // what the 8 bit images hold at pixel x, y
+    let display_rgb8 = |colour: Color, x: u32, y: u32| {
+        let colour = highlights.apply(tone_mapping.apply(colour));
+        let offset = dither.map_or(0.0, |dither| dither.offset(x, y));
+        output_space.unwrap_or(ColorSpace::Srgb).encode_rgb8_dithered(colour, offset)
+    };
+    // and the 16 bit ones, which don't need dithering
+    let display_rgb16 = |colour: Color| output_space.unwrap_or(ColorSpace::Srgb).encode_rgb16(highlights.apply(tone_mapping.apply(colour)));
 
-fn checkered_spheres() -> HittableList {
-    let mut world: HittableList = HittableList::new();
-    let white = Color::new(0.2, 0.3, 0.1);
-    let green = Color::new(0.9, 0.9, 0.9);
-    let top_texture = Box::new(CheckeredTexture::new_with_solid(white, green));
-    let bottom_texture = Box::new(CheckeredTexture::new_with_solid(white, green));
-    let top_circle = Sphere::new(Vec3::new(0.0, -10.0, -1.0), 10.0, Material::Lambertian{albedo: bottom_texture});
-    let bottom_circle = Sphere::new(Vec3::new(0.0, 10.0, -1.0), 10.0, Material::Lambertian{albedo: top_texture});
-    world.add(bottom_circle);
-    world.add(top_circle);
-    world
-}
+    // `--ascii 80` also prints the finished render as 80 columns of text on
+    // stderr, and `--ascii-ramp blocks` uses unicode shading instead of ascii
+    let ascii_columns: Option<usize> = args.iter().position(|arg| arg == "--ascii").map(|position| {
+        args.get(position + 1).and_then(|arg| arg.parse().ok()).expect("--ascii needs a number of columns")
+    });
+    let ascii_ramp = args.iter().position(|arg| arg == "--ascii-ramp").map_or(AsciiRamp::Ascii, |position| {
+        let name = args.get(position + 1).expect("--ascii-ramp needs ascii or blocks");
+        AsciiRamp::parse(name).unwrap_or_else(|| panic!("Unknown ascii ramp {}", name))
+    });
 
-pub struct ImageConfig {
-    pub aspect_ratio: f32,
-    pub image_width: i32,
-    pub image_height: i32,
-    pub samples_per_pixel: u64,
-    pub max_depth: u64
-}
+    // `--tev [address]` also shows the render in tev as it goes, with the
+    // number of samples each pixel took as an extra layer
+    let tev_channels = ["R", "G", "B", "samples.Y"];
+    let mut tev = args.iter().position(|arg| arg == "--tev").and_then(|position| {
+        let address = args.get(position + 1).filter(|arg| !arg.starts_with("--")).map_or(tev::DEFAULT_ADDRESS, |arg| arg.as_str());
+        let client = TevClient::connect(address).and_then(|mut client| {
+            client.create_image("rays", image.image_width as u32, image.image_height as u32, &tev_channels)?;
+            Ok(client)
+        });
+        // the render is still worth finishing without the viewer
+        client.map_err(|e| eprintln!("Couldn't connect to tev at {}: {}", address, e)).ok()
+    });
+
+    // `--save-state render.rsta` keeps every pixel's samples after rendering and
+    // `--resume render.rsta` adds to them (e.g. with a higher --spp), spending
+    // the new samples where the old render was noisiest. see restart.rs
+    let save_state_path = args.iter().position(|arg| arg == "--save-state")
+        .map(|position| Path::new(args.get(position + 1).expect("--save-state needs a file path")));
+    // `--checkpoint-every 5m` saves the render so far every 5 minutes to
+    // checkpoint-0001.ckpt, checkpoint-0002.ckpt... (or `--checkpoint-name
+    // name`), keeping the newest `--keep 3`. see checkpoint.rs
+    let mut checkpoints = args.iter().position(|arg| arg == "--checkpoint-every").map(|position| {
+        let interval = args.get(position + 1).expect("--checkpoint-every needs a duration");
+        let interval = parse_duration(interval).unwrap_or_else(|e| panic!("{}", e));
+        let name = args.iter().position(|arg| arg == "--checkpoint-name")
+            .map_or("checkpoint", |position| args.get(position + 1).expect("--checkpoint-name needs a name"));
+        let keep = args.iter().position(|arg| arg == "--keep")
+            .map_or(3, |position| args.get(position + 1).and_then(|arg| arg.parse().ok()).expect("--keep needs a number"));
+        CheckpointSchedule::new(name, interval, keep)
+    });
 
-impl ImageConfig {
-    pub fn new(aspect_ratio: f32, image_width: i32, samples_per_pixel: u64, max_depth: u64) -> ImageConfig {
-        ImageConfig {
-            aspect_ratio,
-            image_width,
-            image_height: (image_width as f32 / aspect_ratio) as i32,
-            samples_per_pixel,
-            max_depth
+    let (mut state, budget) = match args.iter().position(|arg| arg == "--resume") {
+        Some(position) => {
+            let path = args.get(position + 1).expect("--resume needs a file path");
+            let state = RenderState::load(Path::new(path)).expect("Failed to load render state");
+            if state.width != image.image_width as u32 || state.height != image.image_height as u32 {
+                panic!("Can't resume a {}x{} render at {}x{}", state.width, state.height, image.image_width, image.image_height);
+            }
+            let budget = state.sample_budget(image.samples_per_pixel);
+            (state, Some(budget))
+        },
+        None => match resumed_state {
+            // pixels that already have their samples are skipped
+            Some(state) => {
+                if state.width != image.image_width as u32 || state.height != image.image_height as u32 {
+                    panic!("Checkpoint is {}x{}, the render {}x{}", state.width, state.height, image.image_width, image.image_height);
+                }
+                (state, None)
+            },
+            None => (RenderState::new(image.image_width as u32, image.image_height as u32), None)
         }
+    };
+
+    // `--layers render` also writes every render layer (see layers.rs) as its
+    // own premultiplied float image, render-layer0.pfm, render-layer1.pfm...
+    // plus render-background.pfm for the sky, which add up to the full render
+    let layers_prefix = args.iter().position(|arg| arg == "--layers")
+        .map(|position| args.get(position + 1).expect("--layers needs a file name prefix").clone());
+    let mut layers = layers_prefix.as_ref().map(|_| LayerFilm::new(image.image_width as u32, image.image_height as u32));
+
+    // `--aovs render` also writes what the camera rays hit first, for
+    // denoisers and compositing: render-albedo.pfm, render-normal.pfm,
+    // render-depth.pfm and render-material.pfm (see aovs.rs)
+    let aovs_prefix = args.iter().position(|arg| arg == "--aovs")
+        .map(|position| args.get(position + 1).expect("--aovs needs a file name prefix").clone());
+    // `--denoise denoised.ppm` also writes the image denoised with the help of
+    // the aovs (see denoise.rs), and `--denoise-radius 8` blurs further
+    let denoise_path = args.iter().position(|arg| arg == "--denoise")
+        .map(|position| Path::new(args.get(position + 1).expect("--denoise needs a file path")));
+    let mut denoiser = Denoiser::new();
+    if let Some(position) = args.iter().position(|arg| arg == "--denoise-radius") {
+        let radius = args.get(position + 1).and_then(|arg| arg.parse().ok()).expect("--denoise-radius needs a number of pixels");
+        denoiser = denoiser.with_radius(radius);
     }
-}
+    let mut aovs = (aovs_prefix.is_some() || denoise_path.is_some())
+        .then(|| AovFilm::new(image.image_width as u32, image.image_height as u32, MaterialIds::new(&mut scene.world)));
+    image.aovs = aovs.is_some();
 
-fn get_scene(number: usize) -> (ImageConfig, Camera, HittableList) {
-    match number {
-        // basic zoomed in scene
-        0 => {
-            let image = ImageConfig::new(16.0 / 9.0, 400, 10, 50);
-            let lookfrom = Vec3::new(5.0, 2.0, 4.0);
-            let lookat = Vec3::new(0.0, 0.0, -1.0);
-            let vup = Vec3::new(0.0, 1.0, 0.0);
-            let dist_to_focus = (lookfrom - lookat).length();
-            let aperture = 0.1;
-            let camera: Camera = Camera::new(lookfrom, lookat, vup, 20.0, image.aspect_ratio.into(), aperture, dist_to_focus, 0.0, 1.0);
-            (image, camera, basic_zoomed_in_scene())
-        },
-        // 2 big checkered spheres
-        1 => {
-            let image = ImageConfig::new(16.0 / 9.0, 400, 10, 50);
-            let lookfrom = Vec3::new(13.0, 2.0, 3.0);
-            let lookat = Vec3::new(0.0, 0.0, 0.0);
-            let vup = Vec3::new(0.0, 1.0, 0.0);
-            let dist_to_focus = 10.0;
-            let aperture = 0.0;
-            let camera: Camera = Camera::new(lookfrom, lookat, vup, 20.0, image.aspect_ratio.into(), aperture, dist_to_focus, 0.0, 1.0);
-            (image, camera, checkered_spheres())
+    // `--progress json` reports progress as json lines instead of text
+    let progress_style = args.iter().position(|arg| arg == "--progress").map_or(ProgressStyle::Text, |position| {
+        let name = args.get(position + 1).expect("--progress needs text or json");
+        ProgressStyle::parse(name).unwrap_or_else(|| panic!("Unknown progress style {}", name))
+    });
+    let mut progress = Progress::new(progress_style);
+
+    // `--stats` counts the rays and intersection tests the render takes and
+    // prints them at the end, see ray_stats.rs
+    let stats_start = args.iter().any(|arg| arg == "--stats").then(|| {
+        ray_stats::enable();
+        RayStats::total()
+    });
+
+    // `--debug-pixel 312,190` logs every bounce of that pixel's samples (or
+    // only sample 7 with `--debug-sample 7`), see log_bounce
+    let debug_pixel: Option<(u32, u32)> = args.iter().position(|arg| arg == "--debug-pixel").map(|position| {
+        let text = args.get(position + 1).expect("--debug-pixel needs x,y");
+        let coordinates: Vec<u32> = text.split(',').map(|part| part.trim().parse().unwrap_or_else(|_| panic!("Bad pixel {}, expected x,y", text))).collect();
+        match coordinates[..] {
+            [x, y] => (x, y),
+            _ => panic!("Bad pixel {}, expected x,y", text)
+        }
+    });
+    let debug_sample: Option<u64> = args.iter().position(|arg| arg == "--debug-sample")
+        .map(|position| args.get(position + 1).and_then(|arg| arg.parse().ok()).expect("--debug-sample needs a sample number"));
+
+    // `--wavefront` traces each row's samples together, a bounce at a time (see
+    // wavefront.rs). a pixel being debugged is traced the usual way
+    let wavefront = args.iter().any(|arg| arg == "--wavefront") && debug_pixel.is_none();
+
+    // `--progressive 8` renders the whole image 8 samples per pixel at a time
+    // instead of a pixel at a time, and `--snapshot-every 4` (passes, or a
+    // time like 30s) writes the render so far to `--snapshot path.ppm`
+    // (snapshot.ppm without it) in between. see snapshot.rs
+    let pass_targets = match args.iter().position(|arg| arg == "--progressive") {
+        Some(position) => {
+            let per_pass = args.get(position + 1).and_then(|arg| arg.parse().ok()).expect("--progressive needs a number of samples per pass");
+            if budget.is_some() {
+                panic!("--progressive can't add to a render with --resume");
+            }
+            snapshot::pass_targets(image.samples_per_pixel, per_pass)
         },
-        // perlin noise
-        2 => {
-            let image = ImageConfig::new(16.0 / 9.0, 400, 100, 50);
-            let lookfrom = Vec3::new(13.0, 2.0, 3.0);
-            let lookat = Vec3::new(0.0, 0.0, 0.0);
-            let vup = Vec3::new(0.0, 1.0, 0.0);
-            let dist_to_focus = 10.0;
-            let aperture = 0.0;
-            let camera: Camera = Camera::new(lookfrom, lookat, vup, 20.0, image.aspect_ratio.into(), aperture, dist_to_focus, 0.0, 1.0);
-            (image, camera, perlin_noise())
-        }
-        // random scene
-        _ => {
-            //                                           500 spp originally
-            let image = ImageConfig::new(3.0 / 2.0, 1200, 10, 50);
-            let lookfrom = Vec3::new(13.0, 2.0, 3.0);
-            let lookat = Vec3::new(0.0, 0.0, 0.0);
-            let vup = Vec3::new(0.0, 1.0, 0.0);
-            let dist_to_focus = 10.0;
-            let aperture = 0.1;
-            let camera: Camera = Camera::new(lookfrom, lookat, vup, 20.0, image.aspect_ratio.into(), aperture, dist_to_focus, 0.0, 1.0);
-            (image, camera, random_scene())
+        None => vec![image.samples_per_pixel]
+    };
+    let mut snapshots = args.iter().position(|arg| arg == "--snapshot-every").map(|position| {
+        let interval = args.get(position + 1).expect("--snapshot-every needs a number of passes or a duration");
+        let interval = SnapshotInterval::parse(interval).unwrap_or_else(|e| panic!("{}", e));
+        let path = args.iter().position(|arg| arg == "--snapshot")
+            .map_or("snapshot.ppm", |position| args.get(position + 1).expect("--snapshot needs a file path"));
+        SnapshotSchedule::new(Path::new(path), interval)
+    });
+
+    let render_start = Instant::now();
+    for (pass, &target) in pass_targets.iter().enumerate() {
+        let last_pass = pass + 1 == pass_targets.len();
+        for j in (0..image.image_height).rev() {
+            let mut tev_scanline: Vec<f32> = Vec::new();
+            let mut scanline = Vec::with_capacity(image.image_width as usize);
+            // the image's top row is j = image_height - 1
+            let row = (image.image_height - 1 - j) as u32;
+            // a ray through (or near, by the filter) pixel i of the row, for
+            // its index'th sample, and the weight the filter gives it
+            let camera_ray = |i: i32, index: u64| {
+                if let Some(seed) = image.seed {
+                    // the rest of the sample is traced on this thread too
+                    seed_rng(mix_seed(&[seed, row as u64, i as u64, index]));
+                }
+                let pixel = (i as u32, row);
+                if image.blue_noise {
+                    sampler::start_sample(image.sampler, mix_seed(&[image.seed.unwrap_or(0)]), index, Some(pixel));
+                } else {
+                    sampler::start_sample(image.sampler, mix_seed(&[image.seed.unwrap_or(0), row as u64, i as u64]), index, None);
+                }
+                let (x, x_weight) = image.filter.sample(random_float());
+                let (y, y_weight) = image.filter.sample(random_float());
+                let u = (i as f64 + x) / (image.image_width - 1) as f64;
+                let v = (j as f64 + y) / (image.image_height - 1) as f64;
+                let debug = Some(RayDebug{pixel, sample: index, bounce: 0})
+                    .filter(|debug| debug_pixel == Some(debug.pixel) && debug_sample.is_none_or(|sample| sample == index));
+                let ray = if image.integrator.ray_differentials {
+                    camera.get_ray_with_differentials(u, v, 1.0 / (image.image_width - 1) as f64, 1.0 / (image.image_height - 1) as f64)
+                } else {
+                    camera.get_ray(u, v)
+                };
+                (ray.map(|ray| ray.with_debug(debug)), x_weight * y_weight)
+            };
+            // a sample into its pixel and the films, unless outlier rejection
+            // throws it away
+            let mut add_sample = |estimate: &mut PixelEstimate, pixel: usize, sample: CameraSample, weight: f64| {
+                match image.integrator.filter_sample(sample, estimate) {
+                    Some(sample) => {
+                        estimate.add_filtered(sample.colour, sample.foreground, sample.alpha, weight);
+                        if let Some(film) = layers.as_mut() {
+                            film.add(pixel, &sample);
+                        }
+                        if let Some(film) = aovs.as_mut() {
+                            film.add(pixel, &sample);
+                        }
+                    },
+                    None => estimate.reject()
+                }
+            };
+            if wavefront {
+                // every sample the row still needs at once, except adaptive
+                // sampling has to look in between, so it gets one per pixel a round
+                let mut first_round = true;
+                loop {
+                    let mut rays = Vec::new();
+                    // and whether the sample has a ray, see Camera::get_ray
+                    let mut pixels = Vec::new();
+                    for i in 0..image.image_width {
+                        let pixel = (row * image.image_width as u32 + i as u32) as usize;
+                        let estimate = state.pixel_mut(i as u32, row);
+                        let wanted = match &budget {
+                            _ if !region.contains(i as u32, row) => 0,
+                            Some(budget) => if first_round { budget[pixel] } else { 0 },
+                            None => match &image.adaptive {
+                                Some(adaptive) => (estimate.count < target && !adaptive.is_converged(estimate)) as u64,
+                                None => target.saturating_sub(estimate.count)
+                            }
+                        };
+                        for index in estimate.count..estimate.count + wanted {
+                            let (ray, weight) = camera_ray(i, index);
+                            pixels.push((i, weight, ray.is_some()));
+                            rays.extend(ray);
+                        }
+                    }
+                    first_round = false;
+                    if pixels.is_empty() {
+                        break
+                    }
+                    let mut traced = wavefront::trace(&rays, &scene, &image).into_iter();
+                    for (i, weight, has_ray) in pixels {
+                        let sample = if has_ray { traced.next().unwrap() } else { CameraSample::blocked() };
+                        let pixel = (row * image.image_width as u32 + i as u32) as usize;
+                        let estimate = state.pixel_mut(i as u32, row);
+                        add_sample(estimate, pixel, sample, weight);
+                    }
+                }
+            }
+            for i in 0..image.image_width {
+                let sample = |index: u64| {
+                    let (ray, weight) = camera_ray(i, index);
+                    (ray.map_or_else(CameraSample::blocked, |ray| camera_sample(&ray, &scene, &image)), weight)
+                };
+                let pixel = (row * image.image_width as u32 + i as u32) as usize;
+                let estimate = state.pixel_mut(i as u32, row);
+                let inside = region.contains(i as u32, row);
+                match &budget {
+                    _ if wavefront || !inside => (),
+                    Some(budget) => {
+                        for _ in 0..budget[pixel] {
+                            let (sample, weight) = sample(estimate.count);
+                            add_sample(estimate, pixel, sample, weight);
+                        }
+                    },
+                    None => while estimate.count < target
+                        && !image.adaptive.as_ref().is_some_and(|adaptive| adaptive.is_converged(estimate)) {
+                        let (sample, weight) = sample(estimate.count);
+                        add_sample(estimate, pixel, sample, weight);
+                    }
+                }
+                if tev.is_some() {
+                    let colour = linear_output(estimate.colour());
+                    tev_scanline.extend_from_slice(&[colour.x() as f32, colour.y() as f32, colour.z() as f32, estimate.count as f32]);
+                }
+                // the image only goes out once it's finished
+                if !last_pass || (crop && !inside) {
+                    continue
+                }
+                // pixels outside the region have no samples, so are black
+                if let Some(output) = ppm.as_mut().filter(|_| !finished_image_only) {
+                    let [r, g, b] = display_rgb8(estimate.colour(), i as u32, row);
+                    writeln!(output, "{0} {1} {2}", r, g, b).expect("Failed to write image");
+                } else if ppm.is_none() {
+                    scanline.push(linear_output(estimate.colour()));
+                }
+            }
+            if let Some(client) = tev.as_mut() {
+                if let Err(e) = client.update_image("rays", &tev_channels, 0, row, image.image_width as u32, 1, &tev_scanline) {
+                    eprintln!("Lost the connection to tev: {}", e);
+                    tev = None;
+                }
+            }
+            if let Some(writer) = stream.as_mut().filter(|_| last_pass && !scanline.is_empty()) {
+                let y = if crop { row - region.y0 } else { row };
+                writer.write_tile(0, y, output_width, 1, &scanline).expect("Failed to stream scanline");
+            }
+            if let Some(schedule) = checkpoints.as_mut().filter(|schedule| schedule.is_due()) {
+                match schedule.write(&args, &state) {
+                    Ok(path) => eprintln!("Saved checkpoint {}", path.display()),
+                    // losing a checkpoint isn't worth losing the render
+                    Err(e) => eprintln!("Couldn't save checkpoint: {}", e)
+                }
+            }
+            progress.update((pass as f64 + (row + 1) as f64 / image.image_height as f64) / pass_targets.len() as f64);
+        }
+        if last_pass {
+            break
+        }
+        if let Some(schedule) = snapshots.as_mut() {
+            if schedule.pass_done() {
+                match schedule.write(&state, display_rgb8) {
+                    Ok(()) => eprintln!("Saved snapshot after {} samples per pixel", target),
+                    Err(e) => eprintln!("Couldn't save snapshot: {}", e)
+                }
+            }
         }
     }
-}
+    if let Some(writer) = stream {
+        writer.finish().expect("Failed to finish stream");
+    }
+    if let Some(start) = stats_start {
+        eprint!("{}", RayStats::total().since(&start).report());
+    }
+    // the finished image, exposed automatically and with the glow added on if
+    // they're wanted
+    let mut framebuffer: Vec<Color> = state.pixels.iter().map(|estimate| estimate.colour()).collect();
+    let auto_gain = auto_exposure.as_ref().map_or(1.0, |auto_exposure| {
+        let exposure = auto_exposure.exposure(&framebuffer);
+        eprintln!("Auto exposure: {:+.2} EV", exposure);
+        2f64.powf(exposure)
+    });
+    let post_process = |pixels: Vec<Color>| {
+        let pixels: Vec<Color> = pixels.into_iter().map(|colour| colour * auto_gain).collect();
+        match &bloom {
+            Some(bloom) => bloom.apply(&pixels, state.width, state.height),
+            None => pixels
+        }
+    };
+    framebuffer = post_process(framebuffer);
+    if let Some(mut output) = ppm {
+        if finished_image_only {
+            for (pixel, colour) in framebuffer.iter().enumerate() {
+                let (x, row) = (pixel as u32 % state.width, pixel as u32 / state.width);
+                if crop && !region.contains(x, row) {
+                    continue
+                }
+                let [r, g, b] = display_rgb8(*colour, x, row);
+                writeln!(output, "{0} {1} {2}", r, g, b).expect("Failed to write image");
+            }
+        }
+        output.flush().expect("Failed to write image");
+    }
+    if let Some(video) = video {
+        let pixels = framebuffer.iter().enumerate().filter_map(|(pixel, colour)| {
+            let (x, row) = (pixel as u32 % state.width, pixel as u32 / state.width);
+            Some(display_rgb8(*colour, x, row)).filter(|_| !crop || region.contains(x, row))
+        });
+        video.write_frame(output_width, output_height, pixels).expect("Failed to add the frame to the video");
+    }
 
-fn main() {
-    let (image, camera, world): (ImageConfig, Camera, HittableList) = get_scene(0);
-    println!("P3\n{0} {1}\n255", image.image_width, image.image_height);
-
-    for j in (0..image.image_height).rev() {
-        eprintln!("\rScanlines remaining: {}", j);
-        for i in 0..image.image_width {
-            let mut pixel_colour = Vec3::new(0.0, 0.0, 0.0);
-            for _s in 0..image.samples_per_pixel {
-                let u = (i as f64 + random_float()) / (image.image_width - 1) as f64;
-                let v = (j as f64 + random_float()) / (image.image_height - 1) as f64;
-                let ray = camera.get_ray(u, v);
-                pixel_colour = pixel_colour + ray_colour(&ray, &world, image.max_depth);
+    if let (Some(prefix), Some(film)) = (layers_prefix, layers) {
+        film.write(&prefix).expect("Failed to write render layers");
+    }
+    if let (Some(prefix), Some(film)) = (aovs_prefix, aovs.as_ref()) {
+        film.write(&prefix).expect("Failed to write aovs");
+    }
+    if let (Some(path), Some(film)) = (denoise_path, aovs.as_ref()) {
+        let denoised = post_process(denoiser.denoise(&state, film));
+        let mut output = BufWriter::new(std::fs::File::create(path).expect("Failed to create denoised image"));
+        writeln!(output, "P3\n{0} {1}\n255", output_width, output_height).expect("Failed to write denoised image");
+        for (pixel, colour) in denoised.iter().enumerate() {
+            let (x, row) = (pixel as u32 % state.width, pixel as u32 / state.width);
+            if crop && !region.contains(x, row) {
+                continue
             }
-            pixel_colour.write_colour(image.samples_per_pixel);
+            let [r, g, b] = display_rgb8(*colour, x, row);
+            writeln!(output, "{0} {1} {2}", r, g, b).expect("Failed to write denoised image");
+        }
+        output.flush().expect("Failed to write denoised image");
+    }
+
+    // the image's pixel at the top left of what's written, the region's with --crop
+    let (x0, y0) = if crop { (region.x0, region.y0) } else { (0, 0) };
+    if let Some(path) = rgba_path {
+        let mut rgba = image::RgbaImage::new(output_width, output_height);
+        for (x, y, pixel) in rgba.enumerate_pixels_mut() {
+            let (x, row) = (x0 + x, y0 + y);
+            let (foreground, alpha) = state.pixels[(row * state.width + x) as usize].rgba(straight_alpha);
+            let [r, g, b] = display_rgb8(foreground * auto_gain, x, row);
+            *pixel = image::Rgba([r, g, b, (255.0 * alpha).round() as u8]);
         }
+        rgba.save(path).expect("Failed to write rgba image");
+    }
+
+    if let Some(path) = png16_path {
+        let mut png = image::ImageBuffer::<image::Rgb<u16>, Vec<u16>>::new(output_width, output_height);
+        for (x, y, pixel) in png.enumerate_pixels_mut() {
+            *pixel = image::Rgb(display_rgb16(framebuffer[((y0 + y) * state.width + x0 + x) as usize]));
+        }
+        png.save(path).expect("Failed to write 16 bit png");
+    }
+
+    if let Some(columns) = ascii_columns {
+        let luminance: Vec<f64> = framebuffer.iter().enumerate().map(|(pixel, colour)| {
+            let [r, g, b] = display_rgb8(*colour, pixel as u32 % state.width, pixel as u32 / state.width);
+            Color::new(r as f64, g as f64, b as f64).luminance() / 255.0
+        }).collect();
+        eprint!("{}", ascii::ascii_art(&luminance, state.width as usize, state.height as usize, columns, ascii_ramp));
+    }
+
+    if let Some(path) = save_state_path {
+        state.save(path).expect("Failed to save render state");
+    }
+
+    if let Some(path) = metadata_path {
+        let metadata = RenderMetadata {
+            scene: scene_entry.name.to_string(),
+            inputs,
+            seed,
+            image_width: image.image_width,
+            image_height: image.image_height,
+            samples_per_pixel: image.samples_per_pixel,
+            max_depth: image.max_depth,
+            camera: camera.to_json(),
+            scene_build_time,
+            render_time: render_start.elapsed()
+        };
+        metadata.write(&path).expect("Failed to write render metadata");
     }
 }
@@ -0,0 +1,43 @@
+use memmap2::Mmap;
+use std::fs::File;
+use std::io::{Error, ErrorKind, Result};
+use std::ops::Deref;
+use std::path::Path;
+
+// a file mapped into memory instead of read into a buffer. the os only pages
+// in the parts that are actually touched, so a loader that skips over data it
+// doesn't need never pays for reading it, and big assets don't need a second
+// copy on the heap just to be parsed.
+// the file must not be changed by another program while it's mapped (that's
+// undefined behaviour), which is fine for scene assets that are only read
+pub struct MappedFile {
+    // empty files can't be mapped
+    map: Option<Mmap>
+}
+
+impl MappedFile {
+    pub fn open(path: &Path) -> Result<MappedFile> {
+        let file = File::open(path)?;
+        if file.metadata()?.len() == 0 {
+            return Ok(MappedFile{map: None})
+        }
+        // safety: see above, nothing else is expected to write to the file
+        let map = unsafe { Mmap::map(&file)? };
+        Ok(MappedFile {
+            map: Some(map)
+        })
+    }
+
+    // for text formats, without copying the file into a String
+    pub fn as_str(&self) -> Result<&str> {
+        std::str::from_utf8(self).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+    }
+}
+
+impl Deref for MappedFile {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.map.as_deref().unwrap_or(&[])
+    }
+}
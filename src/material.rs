@@ -3,16 +3,123 @@ use crate::texture::*;
 use crate::Color;
 use crate::Ray;
 use crate::HitRecord;
-use crate::utilities::random_float;
+use crate::utilities::{random_float, PI};
+use crate::principled::{self, Conductor, PrincipledParameters};
 
 pub enum Material {
     // diffuse (matte). albedo is the degree of reflection
-    Lambertian{albedo: Box<dyn Texture>},
+    // normal_map optionally adds surface detail, see perturbed_normal
+    Lambertian{albedo: Box<dyn Texture>, normal_map: Option<Box<dyn Texture>>},
     // Lambertian{albedo: Box<dyn Texture>},
-    // metal (shiny). albedo is the degree of reflection, fuzz is how much to blur
-    Metal{albedo: Vec3, fuzz: f64},
-    // glass. index of refraction adjusts how much to bend light
-    Dielectric{index_of_refraction: f64}
+    // metal (shiny). albedo is the degree of reflection, fuzz is how much to blur.
+    // fuzz is read from the texture's red channel, so roughness maps work as is
+    Metal{albedo: Box<dyn Texture>, fuzz: Box<dyn Texture>, normal_map: Option<Box<dyn Texture>>},
+    // glass. index of refraction adjusts how much to bend light.
+    // absorption is how much of each channel is soaked up per unit of distance
+    // travelled inside (beer-lambert), so thicker glass is more deeply tinted.
+    // 0 for clear glass, see Material::tinted_glass for picking it by colour
+    Dielectric{index_of_refraction: f64, absorption: Color},
+    // one material for most opaque looks, like blender's principled bsdf: a
+    // diffuse base under a ggx glossy coat, see principled.rs. metallic blends
+    // between plastic-like (0) and metal (1), roughness from mirror (0) to matte (1).
+    // conductor makes the metal a measured one, see Material::conductor
+    Principled{base_color: Box<dyn Texture>, metallic: f64, roughness: f64, specular: f64, index_of_refraction: f64, normal_map: Option<Box<dyn Texture>>,
+        conductor: Option<Conductor>},
+    // gives off light (from both sides, unless the object is wrapped in a
+    // OneSided) instead of reflecting it, e.g. a lamp or a lit window
+    DiffuseLight{emit: Box<dyn Texture>},
+    // one of two materials picked at random per hit, b with the chance given by
+    // factor's red channel. with a mask as the factor this gives patchy looks,
+    // e.g. rust on metal, without a material made specially for it
+    Mix{a: Box<Material>, b: Box<Material>, factor: Box<dyn Texture>},
+    // scatters light equally in every direction. not for surfaces but for
+    // points inside a volume, e.g. the medium of a Subsurface object
+    Isotropic{albedo: Box<dyn Texture>},
+    // stands in for the ground (or a wall) of a photographed backplate. camera
+    // rays see through it except for the shadows objects cast on it, which
+    // end up in the alpha channel (see camera_sample in lib.rs). everything
+    // else sees it as a diffuse surface of the backplate's colour, so objects
+    // still pick up its bounce light and show up in it
+    ShadowCatcher{albedo: Box<dyn Texture>}
+}
+
+impl Material {
+    // how much of b a Mix is at the hit
+    fn mix_factor(factor: &dyn Texture, record: &HitRecord) -> f64 {
+        factor.value_at(record).x().clamp(0.0, 1.0)
+    }
+
+    // glass that light comes out of as colour after travelling distance through it
+    pub fn tinted_glass(index_of_refraction: f64, colour: Color, distance: f64) -> Material {
+        // exp(-absorption * distance) = colour. a channel of 0 would need an
+        // infinite absorption, so it's kept just above. a colour reached after
+        // no distance at all says nothing about how fast light is soaked up
+        // (and would divide by 0), so that glass is left clear
+        let absorption = |c: f64| if distance > 0.0 { -c.clamp(1e-6, 1.0).ln() / distance } else { 0.0 };
+        Material::Dielectric{
+            index_of_refraction,
+            absorption: Color::new(absorption(colour.x()), absorption(colour.y()), absorption(colour.z()))
+        }
+    }
+
+    // a principled metal that looks like a real one, e.g. "gold", "silver",
+    // "copper" or "aluminum". None for a name there's no preset for
+    pub fn conductor(name: &str, roughness: f64) -> Option<Material> {
+        Some(Material::Principled{
+            base_color: Box::new(SolidTexture::uniform(1.0)),
+            metallic: 1.0,
+            roughness,
+            specular: 0.5,
+            index_of_refraction: 1.5,
+            normal_map: None,
+            conductor: Some(Conductor::parse(name)?)
+        })
+    }
+
+    // the surface's colour at the hit, without any lighting, as denoisers
+    // want it. glass is white, since what's behind it shows through, and
+    // lights are their colour, as bright as it gets
+    pub fn albedo(&self, record: &HitRecord) -> Color {
+        match self {
+            Self::Lambertian{albedo, ..} | Self::Metal{albedo, ..} | Self::Isotropic{albedo} | Self::ShadowCatcher{albedo} => albedo.value_at(record),
+            Self::Principled{base_color, ..} => base_color.value_at(record),
+            Self::Dielectric{..} => Color::new(1.0, 1.0, 1.0),
+            Self::DiffuseLight{emit} => {
+                let emit = emit.value_at(record);
+                emit / emit.max_component().max(1.0)
+            },
+            Self::Mix{a, b, factor} => a.albedo(record).lerp(&b.albedo(record), Material::mix_factor(factor.as_ref(), record))
+        }
+    }
+
+    fn lambertian_scatter(albedo: &dyn Texture, normal: &Vec3, inc_ray: &Ray, record: &HitRecord) -> Option<Scattering> {
+        let mut scatter_direction = *normal + Vec3::random_unit_vector();
+
+        // case where random vector could cancel out the normal
+        if scatter_direction.near_zero() {
+            scatter_direction = *normal;
+        }
+
+        let scattered = Ray::new(record.point, scatter_direction, Some(inc_ray.time));
+        // let attenuation = Color::new(albedo.x(), albedo.y(), albedo.z());
+        // let attenuation = Color::new(record.t, record.u, record.v); //
+        let attenuation = albedo.value_at(record);
+        Some(Scattering::new(attenuation, scattered))
+    }
+
+    fn principled_parameters(&self, record: &HitRecord) -> Option<PrincipledParameters> {
+        match self {
+            Self::Principled{base_color, metallic, roughness, specular, index_of_refraction, conductor, ..} => Some(PrincipledParameters {
+                base_color: base_color.value_at(record),
+                metallic: *metallic,
+                roughness: *roughness,
+                specular: *specular,
+                index_of_refraction: *index_of_refraction,
+                conductor: *conductor
+            }),
+            _ => None
+        }
+    }
 }
 
 pub struct Scattering {
@@ -33,7 +140,8 @@ impl Scattering {
     }
 
     pub fn scattered(&self) -> Ray {
-        Ray::new(self.scattered.origin, self.scattered.direction, Some(self.scattered.time))
+        Ray::new(self.scattered.origin, self.scattered.direction, Some(self.scattered.time)).with_debug(self.scattered.debug)
+            .with_differential(self.scattered.differential)
     }
 }
 
@@ -46,30 +154,42 @@ fn reflectance(cosine: f64, reflective_index: f64) -> f64 {
     r0 + (1.0 - r0) * (1.0 - cosine).powi(5)
 }
 
+// bends the normal by a tangent space normal map, so a flat surface shades like
+// a bumpy one. the map's red, green and blue in [0, 1] are the [-1, 1] offsets
+// along the tangent, bitangent and normal (a flat map is (0.5, 0.5, 1.0))
+fn perturbed_normal(normal_map: &Option<Box<dyn Texture>>, record: &HitRecord) -> Vec3 {
+    let map = match normal_map {
+        Some(map) => map,
+        None => return record.normal
+    };
+    let (tangent, bitangent, normal) = record.tangent_frame();
+    let value = map.value_at(record) * 2.0 - Vec3::new(1.0, 1.0, 1.0);
+    let perturbed = tangent * value.x() + bitangent * value.y() + normal * value.z();
+    // a broken map could point the normal into the surface
+    if perturbed.dot_product(&normal) <= 0.0 || perturbed.near_zero() {
+        return normal
+    }
+    perturbed.unit_vector()
+}
+
 impl MaterialScattering for Material {
     fn scatter(&self, inc_ray: &Ray, record: &HitRecord) -> Option<Scattering> {
         match self {
             // implement diffusion (matte material) via rays bouncing off into random directions.
-            Self::Lambertian{albedo} => {
-                let mut scatter_direction = record.normal + Vec3::random_unit_vector();
-
-                // case where random vector could cancel out the normal
-                if scatter_direction.near_zero() {
-                    scatter_direction = record.normal;
-                }
-
-                let scattered = Ray::new(record.point, scatter_direction, Some(inc_ray.time));
-                // let attenuation = Color::new(albedo.x(), albedo.y(), albedo.z());
-                // let attenuation = Color::new(record.t, record.u, record.v); //
-                let attenuation = albedo.value(record.u, record.v, &record.point);
-                return Some(Scattering::new(attenuation, scattered))
+            Self::Lambertian{albedo, normal_map} => {
+                let normal = perturbed_normal(normal_map, record);
+                Material::lambertian_scatter(albedo.as_ref(), &normal, inc_ray, record)
             },
+            Self::ShadowCatcher{albedo} => Material::lambertian_scatter(albedo.as_ref(), &record.normal, inc_ray, record),
             // with metal surfaces, rays are reflected off the surface of the object
-            Self::Metal{albedo, fuzz} => {
-                let reflected = Vec3::reflect(&inc_ray.direction.unit_vector(), &record.normal);
+            Self::Metal{albedo, fuzz, normal_map} => {
+                let normal = perturbed_normal(normal_map, record);
+                let reflected = Vec3::reflect(&inc_ray.direction.unit_vector(), &normal);
+                let fuzz = fuzz.value_at(record).x();
                 // without the fuzz and random vector it would look like glass
-                let scattered = Ray::new(record.point, reflected + Vec3::random_in_unit_sphere() * (*fuzz), Some(inc_ray.time));
-                let attenuation = Color::new(albedo.x(), albedo.y(), albedo.z());
+                let scattered = Ray::new(record.point, reflected + Vec3::random_in_unit_sphere() * fuzz, Some(inc_ray.time));
+                let attenuation = albedo.value_at(record);
+                // still checked against the real surface, the ray can't go into it
                 let dot = scattered.direction.dot_product(&record.normal);
                 if dot > 0.0 {
                     Some(Scattering::new(attenuation, scattered))
@@ -78,8 +198,15 @@ impl MaterialScattering for Material {
                 }
             },
             // glass material
-            Self::Dielectric{index_of_refraction} => {
-                let attenuation = Color::new(1.0, 1.0, 1.0);
+            Self::Dielectric{index_of_refraction, absorption} => {
+                // hitting the back of the surface means the ray travelled through
+                // the glass to get here. this assumes nothing else is inside
+                let attenuation = if record.front_face {
+                    Color::new(1.0, 1.0, 1.0)
+                } else {
+                    let distance = record.t * inc_ray.direction.length();
+                    Color::new((-absorption.x() * distance).exp(), (-absorption.y() * distance).exp(), (-absorption.z() * distance).exp())
+                };
                 let mut refraction_ratio = *index_of_refraction;
                 if record.front_face {
                     refraction_ratio = 1.0 / (*index_of_refraction);
@@ -88,21 +215,159 @@ impl MaterialScattering for Material {
                 let cos_theta = (unit_direction * -1.0).dot_product(&record.normal).min(1.0);
                 let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
                 let cannot_refract = (refraction_ratio * sin_theta) > 1.0;
-                let direction: Vec3;
 
                 // total internal reflection
-                if cannot_refract || reflectance(cos_theta, refraction_ratio) > random_float() {
-                    direction = Vec3::reflect(&unit_direction, &record.normal);
+                let direction = if cannot_refract || reflectance(cos_theta, refraction_ratio) > random_float() {
+                    Vec3::reflect(&unit_direction, &record.normal)
                 } else {
-                    direction = Vec3::refract(&unit_direction, &record.normal, refraction_ratio);
-                }
+                    Vec3::refract(&unit_direction, &record.normal, refraction_ratio)
+                };
 
                 Some(Scattering::new(attenuation, Ray::new(record.point, direction, Some(inc_ray.time))))
+            },
+            Self::Principled{normal_map, ..} => {
+                let parameters = self.principled_parameters(record)?;
+                let normal = perturbed_normal(normal_map, record);
+                let towards_viewer = (inc_ray.direction * -1.0).unit_vector();
+                let (direction, weight) = principled::sample(&parameters, &normal, &towards_viewer)?;
+                // a bumpy normal can still send the ray into the real surface
+                if direction.dot_product(&record.normal) <= 0.0 {
+                    return None
+                }
+                Some(Scattering::new(weight, Ray::new(record.point, direction, Some(inc_ray.time))))
+            },
+            Self::DiffuseLight{..} => None,
+            Self::Isotropic{albedo} => {
+                let scattered = Ray::new(record.point, Vec3::random_unit_vector(), Some(inc_ray.time));
+                Some(Scattering::new(albedo.value_at(record), scattered))
+            },
+            // picking one at random averages out to the blend of the two
+            Self::Mix{a, b, factor} => {
+                if random_float() < Material::mix_factor(factor.as_ref(), record) {
+                    b.scatter(inc_ray, record)
+                } else {
+                    a.scatter(inc_ray, record)
+                }
             }
         }
     }
+
+    fn evaluate(&self, inc_ray: &Ray, record: &HitRecord, direction: &Vec3) -> Color {
+        match self {
+            // light is spread evenly over the hemisphere, hence the 1 / pi
+            Self::Lambertian{albedo, normal_map} => {
+                let cosine = perturbed_normal(normal_map, record).dot_product(direction).max(0.0);
+                albedo.value_at(record) * (cosine / PI)
+            },
+            Self::ShadowCatcher{albedo} => albedo.value_at(record) * (record.normal.dot_product(direction).max(0.0) / PI),
+            // a mirror-like reflection only sends light one way, which a light
+            // at a single point is never exactly in
+            Self::Metal{..} | Self::Dielectric{..} | Self::DiffuseLight{..} => Color::new(0.0, 0.0, 0.0),
+            // rough enough to pick up light from any direction
+            Self::Principled{normal_map, ..} => match self.principled_parameters(record) {
+                Some(parameters) => {
+                    let normal = perturbed_normal(normal_map, record);
+                    let towards_viewer = (inc_ray.direction * -1.0).unit_vector();
+                    principled::evaluate(&parameters, &normal, &towards_viewer, direction)
+                },
+                None => Color::new(0.0, 0.0, 0.0)
+            },
+            Self::Mix{a, b, factor} => {
+                let t = Material::mix_factor(factor.as_ref(), record);
+                a.evaluate(inc_ray, record, direction).lerp(&b.evaluate(inc_ray, record, direction), t)
+            },
+            // spread evenly over the whole sphere, there's no cosine inside a volume
+            Self::Isotropic{albedo} => albedo.value_at(record) / (4.0 * PI)
+        }
+    }
+
+    fn emitted(&self, record: &HitRecord) -> Color {
+        match self {
+            Self::DiffuseLight{emit} => emit.value_at(record),
+            Self::Mix{a, b, factor} => {
+                let t = Material::mix_factor(factor.as_ref(), record);
+                a.emitted(record).lerp(&b.emitted(record), t)
+            },
+            _ => Color::new(0.0, 0.0, 0.0)
+        }
+    }
 }
 
 pub trait MaterialScattering {
     fn scatter(&self, inc_ray: &Ray, record: &HitRecord) -> Option<Scattering>;
-}
\ No newline at end of file
+    // how much of the light arriving from direction (unit length, pointing away
+    // from the surface) is reflected back along inc_ray, i.e. the brdf times
+    // the cosine. used for lights that scattered rays can't find by themselves
+    fn evaluate(&self, inc_ray: &Ray, record: &HitRecord, direction: &Vec3) -> Color;
+    // light given off by the surface itself
+    fn emitted(&self, record: &HitRecord) -> Color;
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normal_map_follows_tangent() {
+        let material = Material::Dielectric{index_of_refraction: 1.0, absorption: Color::new(0.0, 0.0, 0.0)};
+        let mut record = HitRecord::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0), 1.0, 0.5, 0.5, true, &material);
+        record.tangent = Some(Vec3::new(1.0, 0.0, 0.0));
+
+        // a flat map changes nothing
+        let flat: Option<Box<dyn Texture>> = Some(Box::new(SolidTexture::new(Color::new(0.5, 0.5, 1.0))));
+        assert!((perturbed_normal(&flat, &record) - record.normal).near_zero());
+        // tilting towards +u leans the normal along the tangent
+        let tilted: Option<Box<dyn Texture>> = Some(Box::new(SolidTexture::new(Color::new(1.0, 0.5, 1.0))));
+        let normal = perturbed_normal(&tilted, &record);
+        assert!(normal.x() > 0.5 && normal.y().abs() < 1e-9 && normal.z() > 0.5);
+    }
+
+    #[test]
+    fn test_thicker_glass_absorbs_more() {
+        let material = Material::tinted_glass(1.0, Color::new(0.5, 1.0, 0.25), 2.0);
+        // an index of 1 doesn't bend light, so the ray always goes straight out
+        let exits_after = |distance: f64| {
+            let ray = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 2.0), Some(0.0));
+            let record = HitRecord::new(Vec3::new(0.0, 0.0, distance), Vec3::new(0.0, 0.0, -1.0), distance / 2.0, 0.0, 0.0, false, &material);
+            material.scatter(&ray, &record).unwrap().attenuation()
+        };
+        assert!((exits_after(2.0) - Color::new(0.5, 1.0, 0.25)).near_zero());
+        assert!((exits_after(4.0) - Color::new(0.25, 1.0, 0.0625)).near_zero());
+
+        match Material::tinted_glass(1.5, Color::new(0.5, 1.0, 0.0), 0.0) {
+            Material::Dielectric{absorption, ..} => assert!(absorption.equal_to(&Color::new(0.0, 0.0, 0.0))),
+            _ => panic!("tinted glass isn't glass")
+        }
+    }
+
+    #[test]
+    fn test_mix_blends_materials() {
+        let lamp = Material::DiffuseLight{emit: Box::new(SolidTexture::new(Color::new(4.0, 4.0, 4.0)))};
+        let matte = Material::Lambertian{albedo: Box::new(SolidTexture::new(Color::new(0.5, 0.5, 0.5))), normal_map: None};
+        let mix = Material::Mix{a: Box::new(lamp), b: Box::new(matte), factor: Box::new(SolidTexture::uniform(0.25))};
+        let record = HitRecord::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0), 1.0, 0.5, 0.5, true, &mix);
+
+        assert!((mix.emitted(&record) - Color::new(3.0, 3.0, 3.0)).near_zero());
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 0.0, -1.0), Some(0.0));
+        let straight_up = mix.evaluate(&ray, &record, &Vec3::new(0.0, 0.0, 1.0));
+        assert!((straight_up.x() - 0.25 * 0.5 / PI).abs() < 1e-9);
+        // only the lambertian scatters, so about a quarter of rays do
+        let scattered = (0..4000).filter(|_| mix.scatter(&ray, &record).is_some()).count();
+        assert!(scattered > 800 && scattered < 1200, "{} scattered", scattered);
+    }
+
+    #[test]
+    fn test_highlight_follows_the_viewer() {
+        let glossy = Material::Principled{base_color: Box::new(SolidTexture::new(Color::new(0.2, 0.2, 0.2))), metallic: 0.0, roughness: 0.2,
+            specular: 0.5, index_of_refraction: 1.5, normal_map: None, conductor: None};
+        let record = HitRecord::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0), 1.0, 0.5, 0.5, true, &glossy);
+        let light = Vec3::new(1.0, 0.0, 1.0).unit_vector();
+        // looking from where the light mirrors to, and from straight above
+        let from = |eye: Vec3| glossy.evaluate(&Ray::new(eye, eye * -1.0, Some(0.0)), &record, &light).x();
+        let (mirrored, above) = (from(Vec3::new(-1.0, 0.0, 1.0)), from(Vec3::new(0.0, 0.0, 1.0)));
+        assert!(mirrored > 2.0 * above, "{} {}", mirrored, above);
+        // and the highlight moves when the light does
+        let other_light = Vec3::new(-1.0, 0.0, 1.0).unit_vector();
+        let lit_from_behind = glossy.evaluate(&Ray::new(Vec3::new(-1.0, 0.0, 1.0), Vec3::new(1.0, 0.0, -1.0), Some(0.0)), &record, &other_light).x();
+        assert!(lit_from_behind < mirrored, "{} {}", lit_from_behind, mirrored);
+    }
+}
@@ -0,0 +1,164 @@
+use crate::Vec3;
+use crate::vec3::Color;
+use crate::Ray;
+use crate::hittable::*;
+use crate::material::*;
+use crate::aabb::AABB;
+use crate::export::*;
+use std::sync::Arc;
+use crate::flat_bvh::FlatTree;
+use crate::triangle;
+
+// a triangle of a mesh. all indices refer to the mesh's lists
+#[derive(Copy, Clone)]
+pub struct Face {
+    pub positions: [usize; 3],
+    // per vertex normals, for smooth shading. without them the face is flat
+    pub normals: Option<[usize; 3]>,
+    // per vertex texture coordinates
+    pub uvs: Option<[usize; 3]>,
+    // which of the mesh's materials the face uses
+    pub material: usize
+}
+
+// a triangle mesh, e.g. loaded from an OBJ file. the vertex data is shared
+// between faces, and each face picks one of the mesh's materials so a single
+// model can be made of several materials. the triangles are kept in their
+// own BVH, so the mesh is a single object to the rest of the scene
+pub struct Mesh {
+    positions: Vec<Vec3>,
+    normals: Vec<Vec3>,
+    uvs: Vec<(f64, f64)>,
+    // one per position, or empty
+    colors: Vec<Color>,
+    faces: Vec<Face>,
+    materials: Vec<Arc<Material>>,
+    tree: FlatTree
+}
+
+impl Mesh {
+    pub fn new(positions: Vec<Vec3>, normals: Vec<Vec3>, uvs: Vec<(f64, f64)>, faces: Vec<Face>, materials: Vec<impl Into<Arc<Material>>>) -> Mesh {
+        let materials: Vec<Arc<Material>> = materials.into_iter().map(|material| material.into()).collect();
+        for (i, face) in faces.iter().enumerate() {
+            let in_range = face.positions.iter().all(|p| *p < positions.len()) &&
+                face.normals.is_none_or(|n| n.iter().all(|n| *n < normals.len())) &&
+                face.uvs.is_none_or(|t| t.iter().all(|t| *t < uvs.len())) &&
+                face.material < materials.len();
+            if !in_range {
+                panic!("Face {} of the mesh references missing vertex data or material", i);
+            }
+        }
+
+        let boxes: Vec<AABB> = faces.iter().map(|face| {
+            triangle::bounding_box(positions[face.positions[0]], positions[face.positions[1]], positions[face.positions[2]])
+        }).collect();
+
+        Mesh {
+            tree: FlatTree::build(&boxes),
+            positions,
+            normals,
+            uvs,
+            colors: Vec::new(),
+            faces,
+            materials
+        }
+    }
+
+    // colours the mesh per vertex (e.g. from a scan). use a VertexColorTexture
+    // in the material to show them
+    pub fn with_vertex_colors(mut self, colors: Vec<Color>) -> Mesh {
+        if colors.len() != self.positions.len() {
+            panic!("Mesh has {} vertices but {} vertex colours", self.positions.len(), colors.len());
+        }
+        self.colors = colors;
+        self
+    }
+
+    pub fn face_count(&self) -> usize {
+        self.faces.len()
+    }
+
+    fn hit_face(&self, index: usize, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let face = &self.faces[index];
+        let [p0, p1, p2] = face.positions.map(|p| self.positions[p]);
+        let (t, b1, b2) = triangle::intersect(ray, p0, p1, p2, t_min, t_max)?;
+        let b0 = 1.0 - b1 - b2;
+
+        let outward_normal = match face.normals {
+            // interpolate the vertex normals for smooth shading
+            Some([n0, n1, n2]) => (self.normals[n0] * b0 + self.normals[n1] * b1 + self.normals[n2] * b2).unit_vector(),
+            None => (p1 - p0).cross_product(&(p2 - p0)).unit_vector()
+        };
+        let (u, v, tangent) = match face.uvs {
+            Some([t0, t1, t2]) => {
+                let (uv0, uv1, uv2) = (self.uvs[t0], self.uvs[t1], self.uvs[t2]);
+                // solve for the direction along the triangle where only u changes
+                let (du1, dv1, du2, dv2) = (uv1.0 - uv0.0, uv1.1 - uv0.1, uv2.0 - uv0.0, uv2.1 - uv0.1);
+                let determinant = du1 * dv2 - du2 * dv1;
+                let tangent = if determinant.abs() > 1e-12 {
+                    Some(((p1 - p0) * dv2 - (p2 - p0) * dv1) / determinant)
+                } else {
+                    None
+                };
+                (uv0.0 * b0 + uv1.0 * b1 + uv2.0 * b2, uv0.1 * b0 + uv1.1 * b1 + uv2.1 * b2, tangent)
+            },
+            // the barycentric coordinates stand in for uvs
+            None => (b1, b2, Some(p1 - p0))
+        };
+
+        let mut record = HitRecord::new(ray.at(t), outward_normal, t, u, v, false, &self.materials[face.material]);
+        record.set_face_normal(ray, &outward_normal);
+        record.tangent = tangent;
+        if !self.colors.is_empty() {
+            let [c0, c1, c2] = face.positions.map(|p| self.colors[p]);
+            record.vertex_color = Some(c0 * b0 + c1 * b1 + c2 * b2);
+        }
+        Some(record)
+    }
+}
+
+impl Hittable for Mesh {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        self.tree.hit(ray, t_min, t_max, |face, t_min, t_max| self.hit_face(face, ray, t_min, t_max))
+    }
+
+    fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<AABB> {
+        Some(self.tree.bounding_box())
+    }
+
+    // one exported mesh per material, with the vertices of each face written
+    // out separately so per face normals/uvs survive
+    fn tessellate(&self) -> Vec<ExportMesh> {
+        let mut meshes = Vec::new();
+        for (material_index, material) in self.materials.iter().enumerate() {
+            let mut mesh = ExportMesh {
+                positions: Vec::new(),
+                normals: Vec::new(),
+                uvs: Vec::new(),
+                triangles: Vec::new(),
+                material: ExportMaterial::from_material(material)
+            };
+
+            for face in self.faces.iter().filter(|face| face.material == material_index) {
+                let [p0, p1, p2] = face.positions.map(|p| self.positions[p]);
+                let flat_normal = (p1 - p0).cross_product(&(p2 - p0)).unit_vector();
+                let first = mesh.positions.len();
+                for corner in 0..3 {
+                    mesh.positions.push(self.positions[face.positions[corner]]);
+                    mesh.normals.push(face.normals.map_or(flat_normal, |n| self.normals[n[corner]]));
+                    mesh.uvs.push(face.uvs.map_or((0.0, 0.0), |t| self.uvs[t[corner]]));
+                }
+                mesh.triangles.push([first, first + 1, first + 2]);
+            }
+
+            if !mesh.triangles.is_empty() {
+                meshes.push(mesh);
+            }
+        }
+        meshes
+    }
+
+    fn materials_mut(&mut self) -> Vec<&mut Arc<Material>> {
+        self.materials.iter_mut().collect()
+    }
+}
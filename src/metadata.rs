@@ -0,0 +1,122 @@
+use crate::Vec3;
+use std::fs::File;
+use std::io::{BufWriter, Result, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+// a json file written next to a render describing how it was made, so an image
+// found later can be traced back to its inputs and rendered again exactly
+
+// a file the scene was built from (e.g. a palette) and a hash of its contents
+pub struct SceneInput {
+    pub path: String,
+    pub hash: u64
+}
+
+impl SceneInput {
+    pub fn read(path: &str) -> Result<SceneInput> {
+        Ok(SceneInput {
+            path: path.to_string(),
+            hash: fnv1a(&std::fs::read(path)?)
+        })
+    }
+}
+
+pub struct RenderMetadata {
+    pub scene: String,
+    pub inputs: Vec<SceneInput>,
+    // None for renders without --seed, which can't be repeated exactly
+    pub seed: Option<u64>,
+    pub image_width: i32,
+    pub image_height: i32,
+    pub samples_per_pixel: u64,
+    pub max_depth: u64,
+    // already json, see Camera::to_json
+    pub camera: String,
+    pub scene_build_time: Duration,
+    pub render_time: Duration
+}
+
+impl RenderMetadata {
+    pub fn to_json(&self) -> String {
+        let inputs: Vec<String> = self.inputs.iter().map(|input| {
+            format!("{{\"path\":{},\"fnv1a\":\"{:016x}\"}}", json_string(&input.path), input.hash)
+        }).collect();
+        let seed = self.seed.map_or("null".to_string(), |seed| seed.to_string());
+        format!("{{\"software\":{{\"name\":\"{}\",\"version\":\"{}\"}},\"scene\":{},\"inputs\":[{}],\"seed\":{},\"resolution\":[{},{}],\"samples_per_pixel\":{},\"max_depth\":{},\"camera\":{},\"timings\":{{\"scene_build_seconds\":{},\"render_seconds\":{}}}}}",
+            env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"), json_string(&self.scene), inputs.join(","), seed,
+            self.image_width, self.image_height, self.samples_per_pixel, self.max_depth, self.camera,
+            self.scene_build_time.as_secs_f64(), self.render_time.as_secs_f64())
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+        writeln!(file, "{}", self.to_json())?;
+        file.flush()
+    }
+}
+
+// render.ppm -> render.json
+pub fn sidecar_path(output: &Path) -> PathBuf {
+    output.with_extension("json")
+}
+
+pub fn json_vec3(v: &Vec3) -> String {
+    format!("[{},{},{}]", v.x(), v.y(), v.z())
+}
+
+fn json_string(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c)
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+// 64 bit fnv-1a. std's hasher isn't guaranteed to stay the same between rust
+// versions, which would defeat comparing hashes from old renders
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sidecar_json() {
+        // known answers for fnv-1a
+        assert_eq!(fnv1a(b""), 0xcbf29ce484222325);
+        assert_eq!(fnv1a(b"a"), 0xaf63dc4c8601ec8c);
+
+        let metadata = RenderMetadata {
+            scene: "perlin-noise".to_string(),
+            inputs: vec![SceneInput{path: "my \"palette\".txt".to_string(), hash: 1}],
+            seed: None,
+            image_width: 4,
+            image_height: 3,
+            samples_per_pixel: 8,
+            max_depth: 5,
+            camera: "{}".to_string(),
+            scene_build_time: Duration::from_millis(250),
+            render_time: Duration::from_secs(2)
+        };
+        let json = metadata.to_json();
+        assert!(json.contains("\"inputs\":[{\"path\":\"my \\\"palette\\\".txt\",\"fnv1a\":\"0000000000000001\"}]"));
+        assert!(json.contains("\"scene\":\"perlin-noise\",\"inputs\""));
+        assert!(json.contains("\"seed\":null,\"resolution\":[4,3]"));
+        assert!(json.contains("\"scene_build_seconds\":0.25,\"render_seconds\":2}"));
+        assert_eq!(sidecar_path(Path::new("out/render.ppm")), PathBuf::from("out/render.json"));
+    }
+}
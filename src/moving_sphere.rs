@@ -5,6 +5,8 @@ use crate::Material;
 use crate::Hittable;
 use crate::HitRecord;
 use crate::aabb::AABB;
+use crate::export::*;
+use std::sync::Arc;
 
 // sphere linearly moves from center0 at time0 to center1 at time1
 pub struct MovingSphere {
@@ -13,22 +15,30 @@ pub struct MovingSphere {
     center_1: Vec3,
     time_1: f64,
     radius: f64,
-    material: Material
+    material: Arc<Material>
 }
 
 impl MovingSphere {
     pub fn new(center_0: Vec3, time_0: f64, center_1: Vec3, time_1: f64,
-         radius: f64, material: Material) -> MovingSphere {
+         radius: f64, material: impl Into<Arc<Material>>) -> MovingSphere {
         MovingSphere {
             center_0,
             time_0,
             center_1,
             time_1,
             radius,
-            material
+            material: material.into()
         }
     }
 
+    pub fn material(&self) -> &Arc<Material> {
+        &self.material
+    }
+
+    pub fn set_material(&mut self, material: impl Into<Arc<Material>>) {
+        self.material = material.into();
+    }
+
     pub fn center(&self, time: f64) -> Vec3 {
         self.center_0 + (self.center_1 - self.center_0) * ((time - self.time_0) / (self.time_1 - self.time_0))
     }
@@ -59,26 +69,21 @@ impl MovingSphere {
 }
 
 impl Hittable for MovingSphere {
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
         let origin_to_center = ray.origin - self.center(ray.time);
         // using an (optimized) quadratic formula (let b = 2h so the '2a' becomes an 'a')
         // to see if ray intersects sphere
         let a: f64 = ray.direction.length_squared();
         let half_b: f64 = origin_to_center.dot_product(&ray.direction);
         let c: f64 = origin_to_center.length_squared() - self.radius * self.radius;
-        let root = MovingSphere::get_first_root_in_range(a, half_b, c, t_min, t_max);
-        match root {
-            None => return None,
-            Some(_) => ()
-        }
-
-        let t = root.unwrap();
+        let t = MovingSphere::get_first_root_in_range(a, half_b, c, t_min, t_max)?;
         let point = ray.at(t);
         let outward_normal = (point - self.center(ray.time)) / self.radius;
         let (u, v): (f64, f64) = Sphere::get_sphere_uv(outward_normal);
         let mut record = HitRecord::new(point, outward_normal, t, u, v, false, &self.material);
         // adjust normal so that it's always pointing away from the ray
         record.set_face_normal(ray, &outward_normal);
+        record.tangent = Some(Sphere::get_sphere_tangent(outward_normal));
         Some(record)
     }
 
@@ -90,4 +95,13 @@ impl Hittable for MovingSphere {
         let second_box = AABB::new(t1_center - radius, t1_center + radius);
         Some(AABB::surrounding_box(first_box, second_box))
     }
+
+    // meshes are static, so export where the sphere is at the start of its motion
+    fn tessellate(&self) -> Vec<ExportMesh> {
+        vec![ExportMesh::uv_sphere(self.center_0, self.radius, ExportMaterial::from_material(&self.material))]
+    }
+
+    fn materials_mut(&mut self) -> Vec<&mut Arc<Material>> {
+        vec![&mut self.material]
+    }
 }
\ No newline at end of file
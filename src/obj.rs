@@ -0,0 +1,381 @@
+use crate::vec3::*;
+use crate::material::Material;
+use crate::principled::Conductor;
+use crate::texture::*;
+use crate::mesh::*;
+use crate::mapped::MappedFile;
+use crate::color_space::ColorSpace;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+
+// loading wavefront OBJ models (and their MTL material libraries) as a Mesh.
+// reference: http://paulbourke.net/dataformats/obj/
+// only the parts needed for rendering are supported: vertices, texture
+// coordinates, normals, polygonal faces (split into triangles) and materials
+
+fn invalid_data(line_number: usize, message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("line {}: {}", line_number + 1, message))
+}
+
+fn parse_floats(args: &[&str], count: usize, line_number: usize) -> Result<Vec<f64>> {
+    if args.len() < count {
+        return Err(invalid_data(line_number, &format!("expected {} numbers", count)))
+    }
+    args[..count].iter().map(|arg| {
+        arg.parse::<f64>().map_err(|_| invalid_data(line_number, &format!("'{}' is not a number", arg)))
+    }).collect()
+}
+
+// obj indices start at 1, negative indices count back from the latest element
+fn resolve_index(index: &str, count: usize, line_number: usize) -> Result<usize> {
+    let value = index.parse::<i64>().map_err(|_| invalid_data(line_number, &format!("'{}' is not an index", index)))?;
+    let resolved = if value < 0 { count as i64 + value } else { value - 1 };
+    if resolved < 0 || resolved >= count as i64 {
+        return Err(invalid_data(line_number, &format!("index {} is out of range", value)))
+    }
+    Ok(resolved as usize)
+}
+
+// the parameters of an MTL material that map onto the crate's materials.
+// reference: http://paulbourke.net/dataformats/mtl/
+pub struct MtlMaterial {
+    pub name: String,
+    // Kd
+    pub diffuse: Color,
+    // Ks
+    pub specular: Color,
+    // Ns, the phong exponent (0 - 1000)
+    pub shininess: f64,
+    // d (or 1 - Tr), 1 is fully opaque
+    pub dissolve: f64,
+    // Ni
+    pub index_of_refraction: f64,
+    // Tf, the colour light is filtered to going through. taken as the tint
+    // after a unit of distance inside see-through materials
+    pub transmission_filter: Color,
+    // illum
+    pub illumination: u32,
+    // map_Kd, already resolved relative to the MTL file
+    pub diffuse_map: Option<PathBuf>,
+    // map_Ks, the colour of metals
+    pub specular_map: Option<PathBuf>,
+    // map_Pr, a roughness map from the PBR extension to MTL. used as metal fuzz
+    pub roughness_map: Option<PathBuf>,
+    // map_bump/bump
+    pub bump_map: Option<PathBuf>,
+    // the bump map's -bm option
+    pub bump_multiplier: f64,
+    // not part of MTL: `conductor gold` makes the material that measured
+    // metal (see Conductor::parse for the names), roughened by Ns
+    pub conductor: Option<String>
+}
+
+impl MtlMaterial {
+    pub fn new(name: String) -> MtlMaterial {
+        MtlMaterial {
+            name,
+            diffuse: Color::new(0.8, 0.8, 0.8),
+            specular: Color::new(0.0, 0.0, 0.0),
+            shininess: 0.0,
+            dissolve: 1.0,
+            index_of_refraction: 1.5,
+            transmission_filter: Color::new(1.0, 1.0, 1.0),
+            illumination: 2,
+            diffuse_map: None,
+            specular_map: None,
+            roughness_map: None,
+            bump_map: None,
+            bump_multiplier: 1.0,
+            conductor: None
+        }
+    }
+
+    // picks the closest material:
+    // - see-through -> dielectric
+    // - a named conductor -> that metal
+    // - reflective (illum 3), or a stronger specular than diffuse colour -> metal
+    // - anything else -> lambertian
+    pub fn to_material(&self) -> Result<Material> {
+        if self.dissolve < 1.0 {
+            return Ok(Material::tinted_glass(self.index_of_refraction, self.transmission_filter, 1.0))
+        }
+
+        // a multiplier of 1 raises white 1% of the texture's width above black
+        let normal_map: Option<Box<dyn Texture>> = match &self.bump_map {
+            Some(path) => Some(Box::new(BumpMap::new(Box::new(ImageTexture::load(path)?), 0.01 * self.bump_multiplier))),
+            None => None
+        };
+
+        // convert the phong exponent to a roughness (0 for a mirror, 1 for very rough)
+        let roughness = (2.0 / (self.shininess + 2.0)).sqrt();
+        if let Some(name) = &self.conductor {
+            return Material::conductor(name, roughness).ok_or_else(|| Error::new(ErrorKind::InvalidData, format!("unknown conductor '{}'", name)))
+        }
+
+        let luminance = |c: Color| 0.2126 * c.x() + 0.7152 * c.y() + 0.0722 * c.z();
+        if self.illumination == 3 || luminance(self.specular) > luminance(self.diffuse) {
+            let albedo: Box<dyn Texture> = match &self.specular_map {
+                Some(path) => Box::new(ImageTexture::load_with_color_space(path, ColorSpace::Srgb)?),
+                None => Box::new(SolidTexture::new(self.specular))
+            };
+            let fuzz: Box<dyn Texture> = match &self.roughness_map {
+                Some(path) => Box::new(ImageTexture::load(path)?),
+                None => Box::new(SolidTexture::uniform(roughness))
+            };
+            return Ok(Material::Metal{albedo, fuzz, normal_map})
+        }
+
+        // colour maps are painted in srgb, the bump and roughness maps are data
+        let albedo: Box<dyn Texture> = match &self.diffuse_map {
+            Some(path) => Box::new(ImageTexture::load_with_color_space(path, ColorSpace::Srgb)?),
+            None => Box::new(SolidTexture::new(self.diffuse))
+        };
+        Ok(Material::Lambertian{albedo, normal_map})
+    }
+}
+
+// the materials of an MTL file, in the order they're defined. texture paths
+// are relative to directory
+pub fn parse_mtl(text: &str, directory: &Path) -> Result<Vec<MtlMaterial>> {
+    let mut materials: Vec<MtlMaterial> = Vec::new();
+
+    for (line_number, line) in text.lines().enumerate() {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        if tokens.is_empty() || tokens[0].starts_with('#') {
+            continue;
+        }
+        let args = &tokens[1..];
+        if tokens[0] == "newmtl" {
+            materials.push(MtlMaterial::new(args.join(" ")));
+            continue;
+        }
+
+        let material = match materials.last_mut() {
+            Some(material) => material,
+            None => return Err(invalid_data(line_number, "material parameters before 'newmtl'"))
+        };
+        match tokens[0] {
+            "Kd" => {
+                let values = parse_floats(args, 3, line_number)?;
+                material.diffuse = Color::new(values[0], values[1], values[2]);
+            },
+            "Ks" => {
+                let values = parse_floats(args, 3, line_number)?;
+                material.specular = Color::new(values[0], values[1], values[2]);
+            },
+            "Tf" => {
+                let values = parse_floats(args, 3, line_number)?;
+                material.transmission_filter = Color::new(values[0], values[1], values[2]);
+            },
+            "Ns" => material.shininess = parse_floats(args, 1, line_number)?[0],
+            "d" => material.dissolve = parse_floats(args, 1, line_number)?[0],
+            "Tr" => material.dissolve = 1.0 - parse_floats(args, 1, line_number)?[0],
+            "Ni" => material.index_of_refraction = parse_floats(args, 1, line_number)?[0],
+            "illum" => material.illumination = parse_floats(args, 1, line_number)?[0] as u32,
+            "conductor" => match args.first() {
+                Some(name) if Conductor::parse(name).is_some() => material.conductor = Some(name.to_string()),
+                _ => return Err(invalid_data(line_number, "expected a conductor, e.g. 'conductor gold'"))
+            },
+            // texture options (e.g. -bm 1.0) come before the file name, which is last
+            "map_Kd" => material.diffuse_map = args.last().map(|file| directory.join(file)),
+            "map_Ks" => material.specular_map = args.last().map(|file| directory.join(file)),
+            "map_Pr" => material.roughness_map = args.last().map(|file| directory.join(file)),
+            "map_bump" | "bump" => {
+                material.bump_map = args.last().map(|file| directory.join(file));
+                if let Some(position) = args.iter().position(|arg| *arg == "-bm") {
+                    material.bump_multiplier = parse_floats(&args[position + 1..], 1, line_number)?[0];
+                }
+            },
+            // anything else isn't supported, so is ignored
+            _ => ()
+        }
+    }
+
+    Ok(materials)
+}
+
+// parses OBJ text. mtllib statements are resolved relative to directory, faces
+// without a material (or with an unknown one) use default_material
+pub fn parse_obj(text: &str, directory: &Path, default_material: Material) -> Result<Mesh> {
+    let mut positions: Vec<Vec3> = Vec::new();
+    let mut normals: Vec<Vec3> = Vec::new();
+    let mut uvs: Vec<(f64, f64)> = Vec::new();
+    let mut colors: Vec<Color> = Vec::new();
+    let mut faces: Vec<Face> = Vec::new();
+    let mut materials = vec![default_material];
+    let mut material_names: Vec<String> = vec![String::new()];
+    let mut current_material = 0;
+
+    for (line_number, line) in text.lines().enumerate() {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        if tokens.is_empty() || tokens[0].starts_with('#') {
+            continue;
+        }
+        let args = &tokens[1..];
+        match tokens[0] {
+            "v" => {
+                let values = parse_floats(args, 3, line_number)?;
+                positions.push(Vec3::new(values[0], values[1], values[2]));
+                // a common extension puts the vertex colour after the position
+                if args.len() >= 6 {
+                    let values = parse_floats(&args[3..], 3, line_number)?;
+                    colors.push(Color::new(values[0], values[1], values[2]));
+                }
+            },
+            "vn" => {
+                let values = parse_floats(args, 3, line_number)?;
+                normals.push(Vec3::new(values[0], values[1], values[2]));
+            },
+            "vt" => {
+                let values = parse_floats(args, 2, line_number)?;
+                uvs.push((values[0], values[1]));
+            },
+            "f" => {
+                if args.len() < 3 {
+                    return Err(invalid_data(line_number, "a face needs at least 3 vertices"))
+                }
+
+                // each corner is v, v/vt, v//vn or v/vt/vn
+                let mut corners: Vec<(usize, Option<usize>, Option<usize>)> = Vec::new();
+                for corner in args.iter() {
+                    let mut parts = corner.split('/');
+                    let position = resolve_index(parts.next().unwrap(), positions.len(), line_number)?;
+                    let uv = match parts.next() {
+                        Some(index) if !index.is_empty() => Some(resolve_index(index, uvs.len(), line_number)?),
+                        _ => None
+                    };
+                    let normal = match parts.next() {
+                        Some(index) if !index.is_empty() => Some(resolve_index(index, normals.len(), line_number)?),
+                        _ => None
+                    };
+                    corners.push((position, uv, normal));
+                }
+
+                // split polygons into a fan of triangles around the first corner
+                for i in 1..corners.len() - 1 {
+                    let triangle = [corners[0], corners[i], corners[i + 1]];
+                    let uvs = match triangle.map(|corner| corner.1) {
+                        [Some(a), Some(b), Some(c)] => Some([a, b, c]),
+                        _ => None
+                    };
+                    let normals = match triangle.map(|corner| corner.2) {
+                        [Some(a), Some(b), Some(c)] => Some([a, b, c]),
+                        _ => None
+                    };
+                    faces.push(Face {
+                        positions: triangle.map(|corner| corner.0),
+                        normals,
+                        uvs,
+                        material: current_material
+                    });
+                }
+            },
+            "mtllib" => {
+                for library in args.iter() {
+                    let library_path = directory.join(library);
+                    let library_file = MappedFile::open(&library_path)?;
+                    let library_directory = library_path.parent().unwrap_or(directory);
+                    for material in parse_mtl(library_file.as_str()?, library_directory)? {
+                        materials.push(material.to_material()?);
+                        material_names.push(material.name);
+                    }
+                }
+            },
+            "usemtl" => {
+                let name = args.join(" ");
+                current_material = match material_names.iter().skip(1).position(|known| *known == name) {
+                    Some(index) => index + 1,
+                    None => {
+                        eprintln!("Unknown material '{}' on line {}, using the default", name, line_number + 1);
+                        0
+                    }
+                };
+            },
+            // groups, objects, smoothing groups etc. don't affect rendering
+            _ => ()
+        }
+    }
+
+    if faces.is_empty() {
+        return Err(Error::new(ErrorKind::InvalidData, "OBJ file has no faces"))
+    }
+
+    // only use the colours if every vertex has one
+    let has_colors = colors.len() == positions.len();
+    let mesh = Mesh::new(positions, normals, uvs, faces, materials);
+    if has_colors {
+        Ok(mesh.with_vertex_colors(colors))
+    } else {
+        Ok(mesh)
+    }
+}
+
+pub fn load_obj(path: &Path, default_material: Material) -> Result<Mesh> {
+    let file = MappedFile::open(path)?;
+    let directory = path.parent().unwrap_or_else(|| Path::new("."));
+    parse_obj(file.as_str()?, directory, default_material)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use crate::hittable::Hittable;
+    use crate::ray::Ray;
+
+    #[test]
+    fn test_per_face_materials() {
+        let directory = std::env::temp_dir().join("rays_obj_test");
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(directory.join("quad.mtl"), "newmtl red\nKd 1 0 0\nnewmtl blue\nKd 0 0 1\n").unwrap();
+        let obj = "mtllib quad.mtl\n\
+                   v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\n\
+                   usemtl red\nf 1 2 3\n\
+                   usemtl blue\nf -4 -2 -1\n";
+        let mesh = parse_obj(obj, &directory, Material::Dielectric{index_of_refraction: 1.5, absorption: Color::new(0.0, 0.0, 0.0)}).unwrap();
+        assert_eq!(mesh.face_count(), 2);
+
+        let colour_at = |x: f64, y: f64| {
+            let ray = Ray::new(Vec3::new(x, y, 1.0), Vec3::new(0.0, 0.0, -1.0), None);
+            let record = mesh.hit(&ray, 0.001, f64::INFINITY).unwrap();
+            match record.material {
+                Material::Lambertian{albedo, ..} => albedo.value(record.u, record.v, &record.point),
+                _ => panic!("expected a lambertian material")
+            }
+        };
+        assert!(colour_at(0.75, 0.25).equal_to(&Color::new(1.0, 0.0, 0.0)));
+        assert!(colour_at(0.25, 0.75).equal_to(&Color::new(0.0, 0.0, 1.0)));
+    }
+
+    #[test]
+    fn test_mtl_mapping() {
+        let text = "newmtl glass\nd 0.2\nNi 1.33\n\
+                    newmtl chrome\nKd 0.1 0.1 0.1\nKs 0.9 0.9 0.9\nNs 998\n\
+                    newmtl matte\nKd 0.5 0.5 0.5\nKs 0.1 0.1 0.1\n";
+        let materials = parse_mtl(text, Path::new(".")).unwrap();
+        assert_eq!(materials.len(), 3);
+        match materials[0].to_material().unwrap() {
+            Material::Dielectric{index_of_refraction, ..} => assert_eq!(index_of_refraction, 1.33),
+            _ => panic!("expected glass")
+        }
+        match materials[1].to_material().unwrap() {
+            Material::Metal{fuzz, ..} => assert!(fuzz.value(0.0, 0.0, &Vec3::new(0.0, 0.0, 0.0)).x() < 0.1),
+            _ => panic!("expected metal")
+        }
+        assert!(matches!(materials[2].to_material().unwrap(), Material::Lambertian{..}));
+    }
+
+    #[test]
+    fn test_mtl_conductor() {
+        let materials = parse_mtl("newmtl ring\nconductor gold\nNs 200\n", Path::new(".")).unwrap();
+        match materials[0].to_material().unwrap() {
+            Material::Principled{metallic, roughness, conductor, ..} => {
+                assert_eq!(metallic, 1.0);
+                assert!(roughness < 0.15);
+                assert!(conductor.is_some());
+            },
+            _ => panic!("expected a principled metal")
+        }
+        assert!(parse_mtl("newmtl ring\nconductor cheese\n", Path::new(".")).is_err());
+    }
+}
\ No newline at end of file
@@ -0,0 +1,130 @@
+use crate::vec3::*;
+use crate::material::Material;
+use crate::texture::SolidTexture;
+use crate::utilities::{random_float, random_float_in_range, random_int_in_range};
+
+// the rules procedural scenes (e.g. random_scene) follow when making up
+// materials, so a generated scene can match an art direction instead of
+// getting arbitrary colours. the default palette is fully random
+pub struct MaterialPalette {
+    // colours to pick albedos from. empty means any colour
+    pub albedos: Vec<Color>,
+    // how far each channel of a picked colour may randomly move, so many
+    // objects don't look copy-pasted
+    pub jitter: f64,
+    // the chance a material is metal or glass, the rest are diffuse
+    pub metal_probability: f64,
+    pub glass_probability: f64,
+    // range of metal fuzz (0 is a mirror)
+    pub fuzz_range: (f64, f64),
+    // range of metal albedos when there's no palette to pick from
+    pub metal_albedo_range: (f64, f64),
+    pub index_of_refraction: f64
+}
+
+impl Default for MaterialPalette {
+    fn default() -> MaterialPalette {
+        MaterialPalette {
+            albedos: Vec::new(),
+            jitter: 0.0,
+            metal_probability: 0.15,
+            glass_probability: 0.05,
+            fuzz_range: (0.0, 0.5),
+            metal_albedo_range: (0.5, 1.0),
+            index_of_refraction: 1.5
+        }
+    }
+}
+
+// like random_float_in_range, but an empty range is fine
+fn in_range(low: f64, high: f64) -> f64 {
+    if high > low { random_float_in_range(low, high) } else { low }
+}
+
+impl MaterialPalette {
+    // one setting per line, e.g.
+    //   albedo 0.8 0.3 0.2    (repeat for more colours)
+    //   jitter 0.05
+    //   metal 0.3
+    //   glass 0
+    //   fuzz 0 0.1
+    //   metal_albedo 0.6 0.9
+    //   ior 1.5
+    // anything not given keeps its default. # starts a comment
+    pub fn parse(text: &str) -> Result<MaterialPalette, String> {
+        let mut palette = MaterialPalette::default();
+
+        for (line_number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let mut tokens = line.split_whitespace();
+            let name = tokens.next().unwrap();
+            let args: Vec<&str> = tokens.collect();
+            let number = |i: usize| -> Result<f64, String> {
+                args.get(i).ok_or(format!("line {}: missing argument {}", line_number + 1, i + 1))?
+                    .parse::<f64>().map_err(|e| format!("line {}: {}", line_number + 1, e))
+            };
+
+            match name {
+                "albedo" => palette.albedos.push(Color::new(number(0)?, number(1)?, number(2)?)),
+                "jitter" => palette.jitter = number(0)?,
+                "metal" => palette.metal_probability = number(0)?,
+                "glass" => palette.glass_probability = number(0)?,
+                "fuzz" => palette.fuzz_range = (number(0)?, number(1)?),
+                "metal_albedo" => palette.metal_albedo_range = (number(0)?, number(1)?),
+                "ior" => palette.index_of_refraction = number(0)?,
+                other => return Err(format!("line {}: unknown setting '{}'", line_number + 1, other))
+            }
+        }
+
+        if palette.metal_probability + palette.glass_probability > 1.0 {
+            return Err(String::from("metal and glass probabilities add up to more than 1"))
+        }
+        Ok(palette)
+    }
+
+    fn pick_albedo(&self) -> Option<Color> {
+        if self.albedos.is_empty() {
+            return None
+        }
+        let albedo = self.albedos[random_int_in_range(0, self.albedos.len() as u32) as usize];
+        let jitter = || in_range(-self.jitter, self.jitter);
+        let clamp = |value: f64| value.clamp(0.0, 1.0);
+        Some(Color::new(clamp(albedo.x() + jitter()), clamp(albedo.y() + jitter()), clamp(albedo.z() + jitter())))
+    }
+
+    pub fn random_material(&self) -> Material {
+        let choice = random_float();
+        if choice < self.glass_probability {
+            return Material::Dielectric{index_of_refraction: self.index_of_refraction, absorption: Color::new(0.0, 0.0, 0.0)}
+        }
+        if choice < self.glass_probability + self.metal_probability {
+            let (low, high) = self.metal_albedo_range;
+            let albedo = self.pick_albedo().unwrap_or_else(|| Color::new(in_range(low, high), in_range(low, high), in_range(low, high)));
+            let fuzz = in_range(self.fuzz_range.0, self.fuzz_range.1);
+            return Material::Metal{albedo: Box::new(SolidTexture::new(albedo)), fuzz: Box::new(SolidTexture::uniform(fuzz)), normal_map: None}
+        }
+        // multiplying two random colours favours darker, more saturated ones
+        let albedo = self.pick_albedo().unwrap_or_else(|| Color::random() * Color::random());
+        Material::Lambertian{albedo: Box::new(SolidTexture::new(albedo)), normal_map: None}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_palette_only_uses_its_colours() {
+        let palette = MaterialPalette::parse("albedo 0.9 0.1 0.1 # red\nmetal 0\nglass 0\n").unwrap();
+        for _ in 0..20 {
+            match palette.random_material() {
+                Material::Lambertian{albedo, ..} => assert!(albedo.value(0.0, 0.0, &Vec3::new(0.0, 0.0, 0.0)).equal_to(&Color::new(0.9, 0.1, 0.1))),
+                _ => panic!("metal and glass are turned off")
+            }
+        }
+        assert!(MaterialPalette::parse("metal 0.8\nglass 0.5").is_err());
+    }
+}
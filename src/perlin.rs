@@ -1,5 +1,31 @@
 use crate::Vec3;
-use crate::utilities::*;
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+
+// the same seed always makes the same noise, so renders using it can be
+// repeated exactly. one Perlin can be shared (in an Arc) by any number of
+// textures instead of each building its own tables
+
+// a noise generator textures can be built on: perlin, or simplex (see simplex.rs)
+pub trait Noise: Send + Sync {
+    // about -1 to 1. generators without a time dimension ignore time
+    fn noise_at(&self, point: &Vec3, time: f64) -> f64;
+
+    // fractal brownian motion: octaves of noise, each lacunarity times the
+    // frequency and gain times the weight of the one before
+    fn fbm_at(&self, point: &Vec3, time: f64, octaves: i32, lacunarity: f64, gain: f64) -> f64 {
+        let mut accumulate = 0.0;
+        let mut previous_point = *point;
+        let mut weight = 1.0;
+
+        for _i in 0..octaves {
+            accumulate += weight * self.noise_at(&previous_point, time);
+            weight *= gain;
+            previous_point = previous_point * lacunarity;
+        }
+        accumulate
+    }
+}
 
 pub struct Perlin {
     rand_vec: Vec<Vec3>,
@@ -11,32 +37,28 @@ pub struct Perlin {
 const POINT_COUNT: u32 = 256;
 
 impl Perlin {
-    pub fn new() -> Perlin {
+    pub fn new(seed: u64) -> Perlin {
+        let mut rng = StdRng::seed_from_u64(seed);
         let mut rand_vec: Vec<Vec3> = Vec::new();
         for _i in 0..POINT_COUNT {
-            rand_vec.push(Vec3::random_unit_vector());
+            rand_vec.push(Perlin::random_unit_vector(&mut rng));
         }
 
         Perlin {
-            rand_vec: rand_vec,
-            x_perms: Perlin::generate_perm(),
-            y_perms: Perlin::generate_perm(),
-            z_perms: Perlin::generate_perm(),
+            rand_vec,
+            x_perms: Perlin::generate_perm(&mut rng),
+            y_perms: Perlin::generate_perm(&mut rng),
+            z_perms: Perlin::generate_perm(&mut rng),
         }
     }
 
     // a sum of multiple frequencies
     pub fn turbulence(&self, point: &Vec3, depth: i32) -> f64 {
-        let mut accumulate = 0.0;
-        let mut previous_point = *point;
-        let mut weight = 1.0;
+        self.fbm(point, depth, 2.0, 0.5).abs()
+    }
 
-        for _i in 0..depth {
-            accumulate += weight * self.noise(&previous_point);
-            weight *= 0.5;
-            previous_point = previous_point * 2.0;
-        }
-        accumulate.abs()
+    pub fn fbm(&self, point: &Vec3, octaves: i32, lacunarity: f64, gain: f64) -> f64 {
+        self.fbm_at(point, 0.0, octaves, lacunarity, gain)
     }
 
     pub fn noise(&self, point: &Vec3) -> f64 {
@@ -48,9 +70,9 @@ impl Perlin {
         // let index = self.x_perms[i as usize] ^ self.y_perms[j as usize] ^ self.z_perms[k as usize];        
         // self.rand_float[index]
 
-        let mut u = point.x() - point.x().floor();
-        let mut v = point.y() - point.y().floor();
-        let mut w = point.z() - point.z().floor();
+        let u = point.x() - point.x().floor();
+        let v = point.y() - point.y().floor();
+        let w = point.z() - point.z().floor();
         // apply Hermitian smoothing
         // u = u * u * (3.0 - 2.0 * u);
         // v = v * v * (3.0 - 2.0 * v);
@@ -64,8 +86,8 @@ impl Perlin {
             for dj in 0..2 {
                 for dk in 0..2 {
                     let a = self.x_perms[((i + di) & 255) as usize];
-                    let b = self.x_perms[((j + dj) & 255) as usize];
-                    let c = self.x_perms[((k + dk) & 255) as usize];
+                    let b = self.y_perms[((j + dj) & 255) as usize];
+                    let c = self.z_perms[((k + dk) & 255) as usize];
                     values[di as usize][dj as usize][dk as usize] = self.rand_vec[a ^ b ^ c]
                 }
             }
@@ -75,33 +97,91 @@ impl Perlin {
 
     fn trilinear_interpolation(values: &[[[Vec3; 2]; 2];2], u: f64, v: f64, w: f64) -> f64 {
         let mut accum = 0.0;
-        for i in 0..2 {
-            for j in 0..2 {
-                for k in 0..2 {
+        for (i, plane) in values.iter().enumerate() {
+            for (j, row) in plane.iter().enumerate() {
+                for (k, value) in row.iter().enumerate() {
                     let weight = Vec3::new(u - i as f64, v - j as f64, w - k as f64);
                     accum += (i as f64 * u + (1.0 - i as f64) * (1.0 - u)) *
                              (j as f64 * v + (1.0 - j as f64) * (1.0 - v)) *
                              (k as f64 * w + (1.0 - k as f64) * (1.0 - w)) *
-                             values[i][j][k].dot_product(&weight);
+                             value.dot_product(&weight);
                 }
             }
         }
         accum
     }
 
-    fn generate_perm() -> Vec<usize> {
+    // like Vec3::random_unit_vector, from the seeded generator
+    fn random_unit_vector(rng: &mut StdRng) -> Vec3 {
+        loop {
+            let p = Vec3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0));
+            let length = p.length();
+            if length > 1e-6 && length < 1.0 {
+                return p / length
+            }
+        }
+    }
+
+    fn generate_perm(rng: &mut StdRng) -> Vec<usize> {
         let mut result: Vec<usize> = Vec::new();
         for i in 0..POINT_COUNT {
             result.push(i as usize);
         }
-        Perlin::permute(&mut result, POINT_COUNT);
+        Perlin::permute(&mut result, POINT_COUNT, rng);
         result
     }
 
-    fn permute(perms: &mut Vec<usize>, n: u32) {
+    fn permute(perms: &mut [usize], n: u32, rng: &mut StdRng) {
         for i in (0..n as usize).rev() {
-            let target = random_int_in_range(0, (i + 1) as u32);
-            perms.swap(i, target as usize);
+            let target = rng.gen_range(0..i + 1);
+            perms.swap(i, target);
         }
     }
-}
\ No newline at end of file
+}
+
+impl Noise for Perlin {
+    fn noise_at(&self, point: &Vec3, _time: f64) -> f64 {
+        self.noise(point)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seeds_repeat() {
+        let point = Vec3::new(1.3, -0.4, 2.7);
+        assert_eq!(Perlin::new(5).noise(&point), Perlin::new(5).noise(&point));
+        assert_ne!(Perlin::new(5).noise(&point), Perlin::new(6).noise(&point));
+    }
+
+    #[test]
+    fn test_each_axis_hashes_with_its_own_permutation() {
+        // hand made tables, so what's checked is how the lattice is hashed
+        // rather than what a seed happens to generate. y and z used to be
+        // looked up in x's table, which made the noise along the diagonal repeat
+        let perlin = Perlin {
+            rand_vec: (0..POINT_COUNT).map(|i| Vec3::new((i as f64).sin(), (i as f64 * 2.0).cos(), 1.0).unit_vector()).collect(),
+            x_perms: (0..POINT_COUNT as usize).collect(),
+            y_perms: (0..POINT_COUNT as usize).map(|i| i * 7 % 256).collect(),
+            z_perms: (0..POINT_COUNT as usize).map(|i| i * 13 % 256).collect()
+        };
+        let point = Vec3::new(2.25, 5.5, 9.75);
+        let corners = |hash: &dyn Fn(usize, usize, usize) -> usize| {
+            let mut values = [[[Vec3::new(0.0, 0.0, 0.0); 2]; 2]; 2];
+            for (di, plane) in values.iter_mut().enumerate() {
+                for (dj, row) in plane.iter_mut().enumerate() {
+                    for (dk, value) in row.iter_mut().enumerate() {
+                        *value = perlin.rand_vec[hash(2 + di, 5 + dj, 9 + dk)];
+                    }
+                }
+            }
+            Perlin::trilinear_interpolation(&values, 0.25, 0.5, 0.75)
+        };
+        let expected = corners(&|i, j, k| perlin.x_perms[i] ^ perlin.y_perms[j] ^ perlin.z_perms[k]);
+        assert!((perlin.noise(&point) - expected).abs() < 1e-12);
+        let x_only = corners(&|i, j, k| perlin.x_perms[i] ^ perlin.x_perms[j] ^ perlin.x_perms[k]);
+        assert!((perlin.noise(&point) - x_only).abs() > 1e-3);
+    }
+}
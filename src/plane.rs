@@ -0,0 +1,108 @@
+use crate::vec3::*;
+use crate::Ray;
+use crate::hittable::*;
+use crate::material::*;
+use crate::aabb::AABB;
+use std::sync::Arc;
+
+// a flat surface going on forever, e.g. the ground or a backdrop. a huge
+// sphere does the same job, but its hits are far from its center so they lose
+// precision, which shows up as speckled shadows (acne) along the surface.
+// it has no bounding box, so accelerators leave it out of their trees and
+// test it on its own (see AcceleratorKind::build)
+pub struct Plane {
+    point: Vec3,
+    // unit length, the side the front faces
+    normal: Vec3,
+    // the directions u and v grow in across the plane
+    tangent: Vec3,
+    bitangent: Vec3,
+    // how far apart the texture repeats, in scene units
+    uv_size: f64,
+    material: Arc<Material>
+}
+
+impl Plane {
+    pub fn new(point: Vec3, normal: Vec3, material: impl Into<Arc<Material>>) -> Plane {
+        if normal.near_zero() {
+            panic!("A plane needs a normal to face along");
+        }
+        let normal = normal.unit_vector();
+        let helper = if normal.x().abs() > 0.9 { Vec3::new(0.0, 1.0, 0.0) } else { Vec3::new(1.0, 0.0, 0.0) };
+        let bitangent = normal.cross_product(&helper).unit_vector();
+        Plane {
+            point,
+            normal,
+            tangent: bitangent.cross_product(&normal),
+            bitangent,
+            uv_size: 1.0,
+            material: material.into()
+        }
+    }
+
+    // the texture covers size by size units, then repeats
+    pub fn with_uv_size(mut self, size: f64) -> Plane {
+        self.uv_size = size;
+        self
+    }
+}
+
+impl Hittable for Plane {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let t = (self.point - ray.origin).dot_product(&self.normal) / ray.direction.dot_product(&self.normal);
+        // also catches rays parallel to the plane, where t isn't a number
+        if !(t > t_min && t < t_max) {
+            return None
+        }
+        let point = ray.at(t);
+        let offset = point - self.point;
+        let u = (offset.dot_product(&self.tangent) / self.uv_size).rem_euclid(1.0);
+        let v = (offset.dot_product(&self.bitangent) / self.uv_size).rem_euclid(1.0);
+        let mut record = HitRecord::new(point, self.normal, t, u, v, false, &self.material);
+        record.set_face_normal(ray, &self.normal);
+        record.tangent = Some(self.tangent);
+        Some(record)
+    }
+
+    fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<AABB> {
+        None
+    }
+
+    // there's nothing to tessellate an endless surface into, so it isn't exported
+
+    fn materials_mut(&mut self) -> Vec<&mut Arc<Material>> {
+        vec![&mut self.material]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accelerator::AcceleratorKind;
+    use crate::sphere::Sphere;
+
+    #[test]
+    fn test_plane_hit_and_acceleration() {
+        let grey = || Material::Lambertian{albedo: Box::new(crate::texture::SolidTexture::uniform(0.5)), normal_map: None};
+        let ground = Plane::new(Vec3::new(0.0, -1.0, 0.0), Vec3::new(0.0, 1.0, 0.0), grey()).with_uv_size(2.0);
+
+        // far away is as exact as close by, and the uvs repeat every 2 units
+        let ray = Ray::new(Vec3::new(1e5 + 0.5, 10.0, 0.0), Vec3::new(0.0, -1.0, 0.0), None);
+        let record = ground.hit(&ray, 0.001, f64::INFINITY).unwrap();
+        assert!((record.point.y() + 1.0).abs() < 1e-12 && record.front_face);
+        assert!((record.u - 0.25).abs() < 1e-9 || (record.v - 0.25).abs() < 1e-9, "{} {}", record.u, record.v);
+        assert!(ground.hit(&Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0), None), 0.001, f64::INFINITY).is_none());
+
+        // it's still hit from inside an accelerator, next to bounded objects
+        for kind in [AcceleratorKind::Bvh, AcceleratorKind::FlatBvh, AcceleratorKind::Lbvh, AcceleratorKind::KdTree] {
+            let objects: Vec<Box<dyn Hittable>> = vec![
+                Box::new(Plane::new(Vec3::new(0.0, -1.0, 0.0), Vec3::new(0.0, 1.0, 0.0), grey())),
+                Box::new(Sphere::new(Vec3::new(0.0, 0.0, 0.0), 0.5, grey()))
+            ];
+            let world = kind.build(objects, 0.0, 1.0);
+            let down = |x: f64| world.hit(&Ray::new(Vec3::new(x, 10.0, 0.0), Vec3::new(0.0, -1.0, 0.0), None), 0.001, f64::INFINITY).map(|hit| hit.point.y());
+            assert_eq!(down(0.0), Some(0.5), "{:?}", kind);
+            assert_eq!(down(3.0), Some(-1.0), "{:?}", kind);
+        }
+    }
+}
@@ -0,0 +1,237 @@
+use crate::vec3::*;
+use crate::perlin::Perlin;
+use crate::texture::{SolidTexture, Texture};
+use crate::material::Material;
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+// a procedural earth-like planet for spheres, so the textured earth demo
+// doesn't need an image. the continents are fractal noise over the sphere cut
+// off at a sea level, with ice caps towards the poles and a separate noise
+// for clouds. everything is looked up by the sphere's uvs, so it doesn't
+// matter where the sphere is or how big.
+//
+// one Planet drives several textures (the colour, where the ocean is, how
+// rough the surface is, the cloud cover), so they all agree with each other
+
+// the number of noise frequencies summed, each twice the last at half the weight
+const OCTAVES: i32 = 6;
+
+pub struct Planet {
+    noise: Perlin,
+    // noise frequency of the largest continents
+    frequency: f64,
+    // how much of the height range is under water, around 0.5 is half
+    sea_level: f64,
+    // how far from the equator the ice caps start, 0 to 1 (the poles)
+    ice_latitude: f64,
+    // how much of the sky clouds cover, 0 to 1
+    cloud_cover: f64
+}
+
+// which of a planet's maps a PlanetTexture is
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum PlanetMap {
+    Colour,
+    // 1 on open water, 0 on land and ice
+    Ocean,
+    // calm water is smooth, everything else is rough
+    Roughness,
+    // greyscale cloud coverage
+    Clouds
+}
+
+pub struct PlanetTexture {
+    planet: Arc<Planet>,
+    map: PlanetMap
+}
+
+// the direction from the sphere's centre that sphere uvs belong to, see
+// Sphere::get_sphere_uv
+fn direction(u: f64, v: f64) -> Vec3 {
+    let theta = v * PI;
+    let phi = u * 2.0 * PI - PI;
+    Vec3::new(theta.sin() * phi.cos(), -theta.cos(), -theta.sin() * phi.sin())
+}
+
+fn smoothstep(edge_0: f64, edge_1: f64, x: f64) -> f64 {
+    let t = ((x - edge_0) / (edge_1 - edge_0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+impl Planet {
+    // a different seed is a different planet
+    pub fn new(seed: u64) -> Planet {
+        Planet {
+            noise: Perlin::new(seed),
+            frequency: 1.5,
+            sea_level: 0.52,
+            ice_latitude: 0.8,
+            cloud_cover: 0.4
+        }
+    }
+
+    pub fn with_sea_level(mut self, sea_level: f64) -> Planet {
+        self.sea_level = sea_level;
+        self
+    }
+
+    pub fn with_ice_latitude(mut self, ice_latitude: f64) -> Planet {
+        self.ice_latitude = ice_latitude;
+        self
+    }
+
+    pub fn with_cloud_cover(mut self, cloud_cover: f64) -> Planet {
+        self.cloud_cover = cloud_cover;
+        self
+    }
+
+    pub fn with_frequency(mut self, frequency: f64) -> Planet {
+        self.frequency = frequency;
+        self
+    }
+
+    // fractal brownian motion: noise summed over octaves, remapped to about [0, 1]
+    fn fbm(&self, point: &Vec3) -> f64 {
+        let mut total = 0.0;
+        let mut weight = 1.0;
+        let mut weights = 0.0;
+        let mut point = *point;
+        for _ in 0..OCTAVES {
+            total += weight * self.noise.noise(&point);
+            weights += weight;
+            weight *= 0.5;
+            point = point * 2.0;
+        }
+        0.5 * (1.0 + total / weights)
+    }
+
+    // the ground's height at a direction, around 0 to 1
+    fn height(&self, direction: &Vec3) -> f64 {
+        self.fbm(&(*direction * self.frequency))
+    }
+
+    // how icy it is there, 0 to 1. the edge of the caps wanders with the noise
+    fn ice(&self, direction: &Vec3, height: f64) -> f64 {
+        let latitude = direction.y().abs() + 0.6 * (height - 0.5);
+        smoothstep(self.ice_latitude - 0.02, self.ice_latitude + 0.02, latitude)
+    }
+
+    // moved far off so the clouds don't follow the coastlines
+    fn clouds(&self, direction: &Vec3) -> f64 {
+        let density = self.fbm(&(*direction * (2.5 * self.frequency) + Vec3::new(37.0, 11.0, 73.0)));
+        let threshold = 1.0 - self.cloud_cover;
+        // the noise bunches up around 0.5, so squeeze the cut-off towards it
+        let threshold = 0.5 + (threshold - 0.5) * 0.3;
+        smoothstep(threshold - 0.05, threshold + 0.05, density)
+    }
+
+    fn colour(&self, direction: &Vec3) -> Color {
+        let height = self.height(direction);
+        let surface = if height < self.sea_level {
+            // shallow water near the coasts is lighter
+            let depth = smoothstep(self.sea_level - 0.08, self.sea_level, height);
+            Color::new(0.01, 0.04, 0.15).lerp(&Color::new(0.04, 0.18, 0.3), depth)
+        } else {
+            // beaches, then lowland green, highland brown and bare rock
+            let elevation = (height - self.sea_level) / (1.0 - self.sea_level);
+            let stops = [
+                (0.0, Color::new(0.6, 0.55, 0.35)),
+                (0.04, Color::new(0.12, 0.3, 0.06)),
+                (0.25, Color::new(0.3, 0.27, 0.12)),
+                (0.45, Color::new(0.35, 0.3, 0.25))
+            ];
+            match stops.iter().position(|(position, _)| *position > elevation) {
+                Some(0) => stops[0].1,
+                Some(next) => {
+                    let (start, from) = stops[next - 1];
+                    let (end, to) = stops[next];
+                    let f = (elevation - start) / (end - start);
+                    from.lerp(&to, f)
+                },
+                None => stops[stops.len() - 1].1
+            }
+        };
+        let ice = self.ice(direction, height);
+        surface.lerp(&Color::new(0.9, 0.92, 0.95), ice)
+    }
+
+    fn ocean(&self, direction: &Vec3) -> f64 {
+        let height = self.height(direction);
+        if height < self.sea_level { 1.0 - self.ice(direction, height) } else { 0.0 }
+    }
+
+    pub fn texture(self: &Arc<Planet>, map: PlanetMap) -> PlanetTexture {
+        PlanetTexture {
+            planet: self.clone(),
+            map
+        }
+    }
+
+    // rough diffuse land with shiny oceans, whose roughness comes from the
+    // ocean mask so sunlight glints off the water only
+    pub fn surface_material(self: &Arc<Planet>) -> Material {
+        Material::Mix{
+            a: Box::new(Material::Lambertian{albedo: Box::new(self.texture(PlanetMap::Colour)), normal_map: None}),
+            b: Box::new(Material::Metal{
+                albedo: Box::new(self.texture(PlanetMap::Colour)),
+                fuzz: Box::new(self.texture(PlanetMap::Roughness)),
+                normal_map: None
+            }),
+            factor: Box::new(self.texture(PlanetMap::Ocean))
+        }
+    }
+
+    // for a slightly bigger sphere around the surface: white where there are
+    // clouds and see-through (glass that doesn't bend light) elsewhere
+    pub fn cloud_material(self: &Arc<Planet>) -> Material {
+        Material::Mix{
+            a: Box::new(Material::Dielectric{index_of_refraction: 1.0, absorption: Color::new(0.0, 0.0, 0.0)}),
+            b: Box::new(Material::Lambertian{albedo: Box::new(SolidTexture::new(Color::new(0.95, 0.95, 0.95))), normal_map: None}),
+            factor: Box::new(self.texture(PlanetMap::Clouds))
+        }
+    }
+}
+
+impl Texture for PlanetTexture {
+    fn value(&self, u: f64, v: f64, _point: &Vec3) -> Color {
+        let direction = direction(u, v);
+        let grey = |value: f64| Color::new(value, value, value);
+        match self.map {
+            PlanetMap::Colour => self.planet.colour(&direction),
+            PlanetMap::Ocean => grey(self.planet.ocean(&direction)),
+            // a little wind ruffles the water
+            PlanetMap::Roughness => grey(1.0 - self.planet.ocean(&direction) * (0.92 - 0.1 * self.planet.fbm(&(direction * 20.0)))),
+            PlanetMap::Clouds => grey(self.planet.clouds(&direction))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sphere::Sphere;
+
+    #[test]
+    fn test_planet_maps_agree() {
+        // uvs map back to the direction they came from
+        let d = Vec3::new(0.3, -0.5, 0.8).unit_vector();
+        let (u, v) = Sphere::get_sphere_uv(d);
+        assert!((direction(u, v) - d).length() < 1e-9);
+
+        let planet = Arc::new(Planet::new(1).with_ice_latitude(0.9));
+        let (ocean, roughness) = (planet.texture(PlanetMap::Ocean), planet.texture(PlanetMap::Roughness));
+        let mut water = 0;
+        for i in 0..200 {
+            let (u, v) = (i as f64 * 0.618 % 1.0, 0.3 + 0.4 * (i as f64 / 200.0));
+            let point = Vec3::new(0.0, 0.0, 0.0);
+            let is_ocean = ocean.value(u, v, &point).x() == 1.0;
+            // the sea is smooth and land isn't
+            assert_eq!(is_ocean, roughness.value(u, v, &point).x() < 0.5);
+            water += is_ocean as i32;
+        }
+        assert!(water > 20 && water < 180, "{} of 200 samples are ocean", water);
+        // the poles are ice
+        assert!(planet.colour(&Vec3::new(0.0, 1.0, 0.0)).x() > 0.8);
+    }
+}
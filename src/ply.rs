@@ -0,0 +1,343 @@
+use crate::vec3::*;
+use crate::material::Material;
+use crate::mesh::*;
+use crate::point_cloud::PointCloud;
+use crate::mapped::MappedFile;
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+
+// loading stanford PLY files, the usual format for scanned meshes and point clouds.
+// reference: http://paulbourke.net/dataformats/ply/
+// ascii and binary files are supported. only vertex positions, normals, uvs,
+// colours and face indices are read, anything else is skipped
+
+fn invalid_data(message: String) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum ScalarType {
+    Int8,
+    UInt8,
+    Int16,
+    UInt16,
+    Int32,
+    UInt32,
+    Float32,
+    Float64
+}
+
+impl ScalarType {
+    fn parse(name: &str) -> Result<ScalarType> {
+        match name {
+            "char" | "int8" => Ok(ScalarType::Int8),
+            "uchar" | "uint8" => Ok(ScalarType::UInt8),
+            "short" | "int16" => Ok(ScalarType::Int16),
+            "ushort" | "uint16" => Ok(ScalarType::UInt16),
+            "int" | "int32" => Ok(ScalarType::Int32),
+            "uint" | "uint32" => Ok(ScalarType::UInt32),
+            "float" | "float32" => Ok(ScalarType::Float32),
+            "double" | "float64" => Ok(ScalarType::Float64),
+            _ => Err(invalid_data(format!("unknown PLY type '{}'", name)))
+        }
+    }
+
+    fn size(&self) -> usize {
+        match self {
+            ScalarType::Int8 | ScalarType::UInt8 => 1,
+            ScalarType::Int16 | ScalarType::UInt16 => 2,
+            ScalarType::Int32 | ScalarType::UInt32 | ScalarType::Float32 => 4,
+            ScalarType::Float64 => 8
+        }
+    }
+}
+
+enum Property {
+    Scalar{name: String, scalar_type: ScalarType},
+    List{name: String, count_type: ScalarType, item_type: ScalarType}
+}
+
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>
+}
+
+impl Element {
+    // bytes per row in a binary file, if every row is the same size (no lists)
+    fn binary_row_size(&self, format: Format) -> Option<usize> {
+        if format == Format::Ascii {
+            return None
+        }
+        self.properties.iter().map(|property| match property {
+            Property::Scalar{name: _, scalar_type} => Some(scalar_type.size()),
+            Property::List{..} => None
+        }).sum()
+    }
+}
+
+// reads values one at a time from the body of the file
+struct BodyReader<'a> {
+    format: Format,
+    bytes: &'a [u8],
+    position: usize
+}
+
+impl<'a> BodyReader<'a> {
+    fn read(&mut self, scalar_type: ScalarType) -> Result<f64> {
+        if self.format == Format::Ascii {
+            // skip whitespace, then read up to the next whitespace
+            while self.position < self.bytes.len() && self.bytes[self.position].is_ascii_whitespace() {
+                self.position += 1;
+            }
+            let start = self.position;
+            while self.position < self.bytes.len() && !self.bytes[self.position].is_ascii_whitespace() {
+                self.position += 1;
+            }
+            let token = std::str::from_utf8(&self.bytes[start..self.position]).unwrap_or("");
+            return token.parse::<f64>().map_err(|_| invalid_data(format!("'{}' is not a number", token)))
+        }
+
+        let size = scalar_type.size();
+        if self.position + size > self.bytes.len() {
+            return Err(invalid_data(String::from("PLY file ends early")))
+        }
+        let mut raw = [0u8; 8];
+        raw[..size].copy_from_slice(&self.bytes[self.position..self.position + size]);
+        self.position += size;
+        if self.format == Format::BinaryBigEndian {
+            raw[..size].reverse();
+        }
+
+        Ok(match scalar_type {
+            ScalarType::Int8 => raw[0] as i8 as f64,
+            ScalarType::UInt8 => raw[0] as f64,
+            ScalarType::Int16 => i16::from_le_bytes([raw[0], raw[1]]) as f64,
+            ScalarType::UInt16 => u16::from_le_bytes([raw[0], raw[1]]) as f64,
+            ScalarType::Int32 => i32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]) as f64,
+            ScalarType::UInt32 => u32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]) as f64,
+            ScalarType::Float32 => f32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]) as f64,
+            ScalarType::Float64 => f64::from_le_bytes(raw)
+        })
+    }
+}
+
+// the parts of a PLY file used for rendering. normals, uvs and colours are
+// either empty or have one entry per position
+pub struct PlyData {
+    pub positions: Vec<Vec3>,
+    pub normals: Vec<Vec3>,
+    pub uvs: Vec<(f64, f64)>,
+    pub colors: Vec<Color>,
+    // polygons as lists of position indices, empty for point clouds
+    pub faces: Vec<Vec<usize>>
+}
+
+impl PlyData {
+    pub fn parse(bytes: &[u8]) -> Result<PlyData> {
+        // the header is always text, ending with an 'end_header' line
+        let header_end = bytes.windows(10).position(|window| window == b"end_header")
+            .ok_or_else(|| invalid_data(String::from("PLY header has no end_header")))?;
+        let body_start = bytes[header_end..].iter().position(|b| *b == b'\n').map_or(bytes.len(), |i| header_end + i + 1);
+        let header = String::from_utf8_lossy(&bytes[..header_end]);
+
+        let mut lines = header.lines();
+        if lines.next().map(|line| line.trim()) != Some("ply") {
+            return Err(invalid_data(String::from("not a PLY file")))
+        }
+        let mut format = Format::Ascii;
+        let mut elements: Vec<Element> = Vec::new();
+        for line in lines {
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+            match tokens.first() {
+                Some(&"format") if tokens.len() >= 2 => {
+                    format = match tokens[1] {
+                        "ascii" => Format::Ascii,
+                        "binary_little_endian" => Format::BinaryLittleEndian,
+                        "binary_big_endian" => Format::BinaryBigEndian,
+                        other => return Err(invalid_data(format!("unknown PLY format '{}'", other)))
+                    };
+                },
+                Some(&"element") if tokens.len() >= 3 => {
+                    let count = tokens[2].parse::<usize>().map_err(|_| invalid_data(format!("bad element count '{}'", tokens[2])))?;
+                    elements.push(Element{name: tokens[1].to_string(), count, properties: Vec::new()});
+                },
+                Some(&"property") => {
+                    let element = elements.last_mut().ok_or_else(|| invalid_data(String::from("property before element")))?;
+                    let property = if tokens.get(1) == Some(&"list") && tokens.len() >= 5 {
+                        Property::List{name: tokens[4].to_string(), count_type: ScalarType::parse(tokens[2])?, item_type: ScalarType::parse(tokens[3])?}
+                    } else if tokens.len() >= 3 {
+                        Property::Scalar{name: tokens[2].to_string(), scalar_type: ScalarType::parse(tokens[1])?}
+                    } else {
+                        return Err(invalid_data(format!("bad property '{}'", line)))
+                    };
+                    element.properties.push(property);
+                },
+                // comments, obj_info etc.
+                _ => ()
+            }
+        }
+
+        let mut data = PlyData {
+            positions: Vec::new(),
+            normals: Vec::new(),
+            uvs: Vec::new(),
+            colors: Vec::new(),
+            faces: Vec::new()
+        };
+        let mut reader = BodyReader{format, bytes, position: body_start};
+        let is_needed = |element: &Element| element.name == "vertex" || element.name == "face";
+        for (i, element) in elements.iter().enumerate() {
+            if !is_needed(element) {
+                // nothing after this is read, so don't even look at the rest of the file
+                if !elements[i..].iter().any(is_needed) {
+                    break
+                }
+                // binary elements with fixed size rows can be jumped over without
+                // decoding them (with a mapped file, without even reading them)
+                if let Some(row_size) = element.binary_row_size(format) {
+                    reader.position = reader.position.saturating_add(row_size.saturating_mul(element.count)).min(bytes.len());
+                    continue
+                }
+            }
+            for _ in 0..element.count {
+                // values of this element's scalar properties, by name
+                let mut values: Vec<(&str, f64, ScalarType)> = Vec::new();
+                for property in element.properties.iter() {
+                    match property {
+                        Property::Scalar{name, scalar_type} => {
+                            values.push((name, reader.read(*scalar_type)?, *scalar_type));
+                        },
+                        Property::List{name, count_type, item_type} => {
+                            let count = reader.read(*count_type)? as usize;
+                            let mut items = Vec::with_capacity(count);
+                            for _ in 0..count {
+                                items.push(reader.read(*item_type)? as usize);
+                            }
+                            if element.name == "face" && (name == "vertex_indices" || name == "vertex_index") {
+                                data.faces.push(items);
+                            }
+                        }
+                    }
+                }
+                if element.name == "vertex" {
+                    data.add_vertex(&values)?;
+                }
+            }
+        }
+
+        data.check()?;
+        Ok(data)
+    }
+
+    pub fn load(path: &Path) -> Result<PlyData> {
+        PlyData::parse(&MappedFile::open(path)?)
+    }
+
+    fn add_vertex(&mut self, values: &[(&str, f64, ScalarType)]) -> Result<()> {
+        let get = |names: &[&str]| values.iter().find(|(name, _, _)| names.contains(name)).map(|(_, value, scalar_type)| (*value, *scalar_type));
+        match (get(&["x"]), get(&["y"]), get(&["z"])) {
+            (Some((x, _)), Some((y, _)), Some((z, _))) => self.positions.push(Vec3::new(x, y, z)),
+            _ => return Err(invalid_data(String::from("PLY vertex has no x, y and z")))
+        }
+        if let (Some((x, _)), Some((y, _)), Some((z, _))) = (get(&["nx"]), get(&["ny"]), get(&["nz"])) {
+            self.normals.push(Vec3::new(x, y, z));
+        }
+        if let (Some((u, _)), Some((v, _))) = (get(&["u", "s", "texture_u"]), get(&["v", "t", "texture_v"])) {
+            self.uvs.push((u, v));
+        }
+        if let (Some((r, r_type)), Some((g, _)), Some((b, _))) = (get(&["red", "diffuse_red"]), get(&["green", "diffuse_green"]), get(&["blue", "diffuse_blue"])) {
+            // integer colours are 0-255, floating point ones 0-1
+            let scale = match r_type {
+                ScalarType::Float32 | ScalarType::Float64 => 1.0,
+                ScalarType::UInt16 => 1.0 / 65535.0,
+                _ => 1.0 / 255.0
+            };
+            self.colors.push(Color::new(r, g, b) * scale);
+        }
+        Ok(())
+    }
+
+    // optional vertex data is only usable if every vertex has it
+    fn check(&mut self) -> Result<()> {
+        let count = self.positions.len();
+        if !self.normals.is_empty() && self.normals.len() != count {
+            self.normals.clear();
+        }
+        if !self.uvs.is_empty() && self.uvs.len() != count {
+            self.uvs.clear();
+        }
+        if !self.colors.is_empty() && self.colors.len() != count {
+            self.colors.clear();
+        }
+        if self.faces.iter().flatten().any(|index| *index >= count) {
+            return Err(invalid_data(String::from("PLY face references a missing vertex")))
+        }
+        Ok(())
+    }
+
+    // polygons are split into a fan of triangles. all faces use the given material
+    pub fn into_mesh(self, material: Material) -> Mesh {
+        let has_normals = !self.normals.is_empty();
+        let has_uvs = !self.uvs.is_empty();
+        let mut faces = Vec::new();
+        for polygon in self.faces.iter().filter(|polygon| polygon.len() >= 3) {
+            for i in 1..polygon.len() - 1 {
+                let positions = [polygon[0], polygon[i], polygon[i + 1]];
+                faces.push(Face {
+                    positions,
+                    normals: if has_normals { Some(positions) } else { None },
+                    uvs: if has_uvs { Some(positions) } else { None },
+                    material: 0
+                });
+            }
+        }
+
+        let mesh = Mesh::new(self.positions, self.normals, self.uvs, faces, vec![material]);
+        if self.colors.is_empty() {
+            mesh
+        } else {
+            mesh.with_vertex_colors(self.colors)
+        }
+    }
+
+    // every vertex becomes a small sphere, e.g. for scans without faces
+    pub fn into_point_cloud(self, radius: f64, material: Material) -> PointCloud {
+        PointCloud::new(self.positions, self.colors, radius, material)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_binary_vertex_colors() {
+        let mut bytes = b"ply\nformat binary_little_endian 1.0\nelement vertex 3\n\
+            property float x\nproperty float y\nproperty float z\n\
+            property uchar red\nproperty uchar green\nproperty uchar blue\n\
+            element face 1\nproperty list uchar int vertex_indices\nend_header\n".to_vec();
+        for (position, colour) in [([0f32, 0.0, 0.0], [255u8, 0, 0]), ([1.0, 0.0, 0.0], [0, 255, 0]), ([0.0, 1.0, 0.0], [0, 0, 255])].iter() {
+            for value in position.iter() {
+                bytes.extend_from_slice(&value.to_le_bytes());
+            }
+            bytes.extend_from_slice(colour);
+        }
+        bytes.push(3);
+        for index in [0i32, 1, 2].iter() {
+            bytes.extend_from_slice(&index.to_le_bytes());
+        }
+
+        let data = PlyData::parse(&bytes).unwrap();
+        assert_eq!(data.positions.len(), 3);
+        assert_eq!(data.faces, vec![vec![0, 1, 2]]);
+        assert!(data.colors[1].equal_to(&Color::new(0.0, 1.0, 0.0)));
+    }
+}
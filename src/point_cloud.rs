@@ -0,0 +1,81 @@
+use crate::vec3::*;
+use crate::Ray;
+use crate::hittable::*;
+use crate::material::*;
+use crate::aabb::AABB;
+use crate::flat_bvh::FlatTree;
+use crate::sphere::Sphere;
+use std::sync::Arc;
+
+// a set of points (e.g. from a 3D scan) drawn as small spheres of the same
+// size and material. points can carry a colour, which is passed on as the
+// hit's vertex colour (see VertexColorTexture)
+pub struct PointCloud {
+    points: Vec<Vec3>,
+    // one per point, or empty
+    colors: Vec<Color>,
+    radius: f64,
+    material: Arc<Material>,
+    tree: FlatTree
+}
+
+impl PointCloud {
+    pub fn new(points: Vec<Vec3>, colors: Vec<Color>, radius: f64, material: impl Into<Arc<Material>>) -> PointCloud {
+        if !colors.is_empty() && colors.len() != points.len() {
+            panic!("Point cloud has {} points but {} colours", points.len(), colors.len());
+        }
+
+        let extent = Vec3::new(radius, radius, radius);
+        let boxes: Vec<AABB> = points.iter().map(|point| AABB::new(*point - extent, *point + extent)).collect();
+        PointCloud {
+            tree: FlatTree::build(&boxes),
+            points,
+            colors,
+            radius,
+            material: material.into()
+        }
+    }
+
+    fn hit_point(&self, index: usize, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let center = self.points[index];
+        // same as Sphere::hit
+        let origin_to_center = ray.origin - center;
+        let a = ray.direction.length_squared();
+        let half_b = origin_to_center.dot_product(&ray.direction);
+        let c = origin_to_center.length_squared() - self.radius * self.radius;
+        let discriminant = half_b * half_b - a * c;
+        if discriminant < 0.0 {
+            return None
+        }
+        let discrim_sqrt = discriminant.sqrt();
+        let mut t = (-half_b - discrim_sqrt) / a;
+        if t <= t_min || t >= t_max {
+            t = (-half_b + discrim_sqrt) / a;
+            if t <= t_min || t >= t_max {
+                return None
+            }
+        }
+
+        let point = ray.at(t);
+        let outward_normal = (point - center) / self.radius;
+        let (u, v) = Sphere::get_sphere_uv(outward_normal);
+        let mut record = HitRecord::new(point, outward_normal, t, u, v, false, &self.material);
+        record.set_face_normal(ray, &outward_normal);
+        record.vertex_color = self.colors.get(index).copied();
+        Some(record)
+    }
+}
+
+impl Hittable for PointCloud {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        self.tree.hit(ray, t_min, t_max, |index, t_min, t_max| self.hit_point(index, ray, t_min, t_max))
+    }
+
+    fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<AABB> {
+        Some(self.tree.bounding_box())
+    }
+
+    fn materials_mut(&mut self) -> Vec<&mut Arc<Material>> {
+        vec![&mut self.material]
+    }
+}
@@ -0,0 +1,494 @@
+use rays::vec3::*;
+use rays::camera::Camera;
+use rays::hittable::Hittable;
+use rays::material::Material;
+use rays::hittable::HitRecord;
+use rays::texture::{SolidTexture, Texture};
+use rays::utilities::random_float;
+use rays::{camera_sample, CameraSample, ImageConfig, Scene};
+use eframe::egui;
+use rayon::prelude::*;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+
+// a window that keeps rendering one sample per pixel after another and shows
+// the average so far, with a panel for tweaking the scene's materials. every
+// change starts the average again, so look development doesn't need a full
+// render (and restart) per try. the camera can be moved too, which also
+// starts over: dragging turns it around the point it's looking at, w/s move
+// it closer and further, a/d and q/e move it (and that point) sideways and
+// up and down, and [ and ] widen and narrow its field of view. scrolling
+// zooms the view in and out around the pointer, right dragging pans it and
+// a double click goes back to the whole image at one pixel per point. only
+// built with the preview feature:
+//   cargo run --release --features preview -- --preview
+
+// what a render thread and the window share
+struct Shared {
+    scene: RwLock<Scene>,
+    // swapped for a new one when it moves, each pass takes the latest
+    camera: Mutex<Arc<Camera>>,
+    // bumped on every edit, passes started before it are thrown away
+    generation: AtomicU64,
+    accumulation: Mutex<Accumulation>
+}
+
+struct Accumulation {
+    // the sum of every pass, rows from the top down
+    pixels: Vec<Color>,
+    passes: u64,
+    generation: u64
+}
+
+// the parameters the panel can change. textures can't be edited, so a
+// material's colour is read at a single point and becomes a flat colour once
+// it's changed (like exporting it). normal maps are kept as they are
+enum Edit {
+    Lambertian{albedo: [f32; 3]},
+    Metal{albedo: [f32; 3], fuzz: f32},
+    Dielectric{index_of_refraction: f32, absorption: Color},
+    // shown but not editable
+    Other(&'static str)
+}
+
+// one material of the scene and how many surfaces use it
+struct MaterialEntry {
+    material: Arc<Material>,
+    // the material the scene was built with, which edits take its normal map from
+    original: Arc<Material>,
+    uses: usize,
+    edit: Edit
+}
+
+// the normal map of a material from before it was edited. textures can't be
+// copied, so it's looked up through the original material, which is kept
+struct KeptNormalMap(Arc<Material>);
+
+impl KeptNormalMap {
+    fn map(&self) -> &dyn Texture {
+        match self.0.as_ref() {
+            Material::Lambertian{normal_map: Some(map), ..} | Material::Metal{normal_map: Some(map), ..} => map.as_ref(),
+            _ => panic!("KeptNormalMap needs a material with a normal map")
+        }
+    }
+
+    // None when there's no normal map to keep
+    fn of(material: &Arc<Material>) -> Option<Box<dyn Texture>> {
+        match material.as_ref() {
+            Material::Lambertian{normal_map: Some(_), ..} | Material::Metal{normal_map: Some(_), ..} => Some(Box::new(KeptNormalMap(material.clone()))),
+            _ => None
+        }
+    }
+}
+
+impl Texture for KeptNormalMap {
+    fn value(&self, u: f64, v: f64, point: &Vec3) -> Color {
+        self.map().value(u, v, point)
+    }
+
+    fn value_at(&self, record: &HitRecord) -> Color {
+        self.map().value_at(record)
+    }
+}
+
+fn flat_colour(texture: &dyn Texture) -> [f32; 3] {
+    let c = texture.value(0.5, 0.5, &Vec3::new(0.0, 0.0, 0.0));
+    [c.x() as f32, c.y() as f32, c.z() as f32]
+}
+
+fn to_colour(rgb: &[f32; 3]) -> Color {
+    Color::new(rgb[0] as f64, rgb[1] as f64, rgb[2] as f64)
+}
+
+impl Edit {
+    fn from_material(material: &Material) -> Edit {
+        match material {
+            Material::Lambertian{albedo, ..} => Edit::Lambertian{albedo: flat_colour(albedo.as_ref())},
+            Material::Metal{albedo, fuzz, ..} => Edit::Metal{albedo: flat_colour(albedo.as_ref()), fuzz: flat_colour(fuzz.as_ref())[0]},
+            Material::Dielectric{index_of_refraction, absorption} => Edit::Dielectric{index_of_refraction: *index_of_refraction as f32, absorption: *absorption},
+            Material::Principled{..} => Edit::Other("principled"),
+            Material::DiffuseLight{..} => Edit::Other("light"),
+            Material::Mix{..} => Edit::Other("mix"),
+            Material::Isotropic{..} => Edit::Other("isotropic"),
+            Material::ShadowCatcher{..} => Edit::Other("shadow catcher")
+        }
+    }
+
+    // the edited material, with original's normal map
+    fn to_material(&self, original: &Arc<Material>) -> Option<Material> {
+        match self {
+            Edit::Lambertian{albedo} => Some(Material::Lambertian{albedo: Box::new(SolidTexture::new(to_colour(albedo))), normal_map: KeptNormalMap::of(original)}),
+            Edit::Metal{albedo, fuzz} => Some(Material::Metal{
+                albedo: Box::new(SolidTexture::new(to_colour(albedo))),
+                fuzz: Box::new(SolidTexture::uniform(*fuzz as f64)),
+                normal_map: KeptNormalMap::of(original)
+            }),
+            Edit::Dielectric{index_of_refraction, absorption} => Some(Material::Dielectric{index_of_refraction: *index_of_refraction as f64, absorption: *absorption}),
+            Edit::Other(_) => None
+        }
+    }
+
+    // the controls for the parameters, true if any changed
+    fn show(&mut self, ui: &mut egui::Ui) -> bool {
+        match self {
+            Edit::Lambertian{albedo} => ui.horizontal(|ui| {
+                ui.label("albedo");
+                ui.color_edit_button_rgb(albedo).changed()
+            }).inner,
+            Edit::Metal{albedo, fuzz} => {
+                let albedo_changed = ui.horizontal(|ui| {
+                    ui.label("albedo");
+                    ui.color_edit_button_rgb(albedo).changed()
+                }).inner;
+                let fuzz_changed = ui.add(egui::Slider::new(fuzz, 0.0..=1.0).text("fuzz")).changed();
+                albedo_changed || fuzz_changed
+            },
+            Edit::Dielectric{index_of_refraction, ..} => ui.add(egui::Slider::new(index_of_refraction, 1.0..=2.5).text("ior")).changed(),
+            Edit::Other(_) => false
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Edit::Lambertian{..} => "lambertian",
+            Edit::Metal{..} => "metal",
+            Edit::Dielectric{..} => "dielectric",
+            Edit::Other(name) => name
+        }
+    }
+}
+
+// every distinct material in the world, in the order they're first found
+fn list_materials(scene: &mut Scene) -> Vec<MaterialEntry> {
+    let mut entries: Vec<MaterialEntry> = Vec::new();
+    for slot in scene.world.materials_mut() {
+        match entries.iter_mut().find(|entry| Arc::ptr_eq(&entry.material, slot)) {
+            Some(entry) => entry.uses += 1,
+            None => entries.push(MaterialEntry {
+                material: slot.clone(),
+                original: slot.clone(),
+                uses: 1,
+                edit: Edit::from_material(slot)
+            })
+        }
+    }
+    entries
+}
+
+// points every surface using old at new instead
+fn swap_material(scene: &mut Scene, old: &Arc<Material>, new: &Arc<Material>) {
+    for slot in scene.world.materials_mut() {
+        if Arc::ptr_eq(slot, old) {
+            *slot = new.clone();
+        }
+    }
+}
+
+// the camera as the keys and mouse move it: on a sphere around the point it
+// looks at, at an angle around the y axis (yaw) and above the horizon (pitch)
+struct Orbit {
+    target: Vec3,
+    distance: f64,
+    yaw: f64,
+    pitch: f64,
+    vertical_fov: f64
+}
+
+// radians turned per point dragged
+const ORBIT_PER_POINT: f64 = 0.01;
+
+const NAVIGATION_KEYS: [egui::Key; 8] = [
+    egui::Key::W, egui::Key::A, egui::Key::S, egui::Key::D, egui::Key::Q, egui::Key::E,
+    egui::Key::OpenBracket, egui::Key::CloseBracket
+];
+
+impl Orbit {
+    fn new(camera: &Camera) -> Orbit {
+        let (lookfrom, lookat, vertical_fov) = camera.placement();
+        let offset = lookfrom - lookat;
+        let distance = offset.length();
+        Orbit {
+            target: lookat,
+            distance,
+            yaw: offset.z().atan2(offset.x()),
+            pitch: (offset.y() / distance).clamp(-1.0, 1.0).asin(),
+            vertical_fov
+        }
+    }
+
+    fn lookfrom(&self) -> Vec3 {
+        let (sin_pitch, cos_pitch) = self.pitch.sin_cos();
+        self.target + Vec3::new(cos_pitch * self.yaw.cos(), sin_pitch, cos_pitch * self.yaw.sin()) * self.distance
+    }
+
+    // applies this frame's dragging (in points) and held keys (for seconds),
+    // true if the camera moved
+    fn update(&mut self, drag: egui::Vec2, input: &egui::InputState) -> bool {
+        if drag == egui::Vec2::ZERO && !NAVIGATION_KEYS.iter().any(|&key| input.key_down(key)) {
+            return false
+        }
+        self.yaw += drag.x as f64 * ORBIT_PER_POINT;
+        // not quite straight up or down, where which way is up flips
+        self.pitch = (self.pitch + drag.y as f64 * ORBIT_PER_POINT).clamp(-1.55, 1.55);
+
+        let seconds = input.stable_dt.min(0.1) as f64;
+        let held = |key: egui::Key| if input.key_down(key) { 1.0 } else { 0.0 };
+        // moving covers the distance to the target in about a second, so it
+        // suits the scene's size
+        self.distance *= (seconds * (held(egui::Key::S) - held(egui::Key::W))).exp();
+        let forward = (self.target - self.lookfrom()).unit_vector();
+        let right = forward.cross_product(&Vec3::new(0.0, 1.0, 0.0)).unit_vector();
+        let sideways = held(egui::Key::D) - held(egui::Key::A);
+        let upwards = held(egui::Key::E) - held(egui::Key::Q);
+        self.target = self.target + (right * sideways + Vec3::new(0.0, upwards, 0.0)) * (self.distance * seconds);
+        let wider = held(egui::Key::OpenBracket) - held(egui::Key::CloseBracket);
+        self.vertical_fov = (self.vertical_fov * (seconds * wider).exp()).clamp(1.0, 170.0);
+        true
+    }
+}
+
+// renders passes until the process exits
+fn render_passes(shared: &Shared, image: &ImageConfig) {
+    let (width, height) = (image.image_width as usize, image.image_height as usize);
+    loop {
+        let generation = shared.generation.load(Ordering::SeqCst);
+        let camera = shared.camera.lock().unwrap().clone();
+        let camera = camera.as_ref();
+        // the scene is locked a row at a time, so an edit only waits for the
+        // rows being traced rather than the whole pass
+        let pass: Vec<Color> = (0..height).into_par_iter().flat_map_iter(|row| {
+            let j = height - 1 - row;
+            let scene = shared.scene.read().unwrap();
+            (0..width).map(|i| {
+                let u = (i as f64 + random_float()) / (width - 1) as f64;
+                let v = (j as f64 + random_float()) / (height - 1) as f64;
+                camera.get_ray(u, v).map_or_else(CameraSample::blocked, |ray| camera_sample(&ray, &scene, image)).colour
+            }).collect::<Vec<Color>>()
+        }).collect();
+
+        let mut accumulation = shared.accumulation.lock().unwrap();
+        if accumulation.generation != generation {
+            // an edit (or a move) came in since the last pass, start over
+            accumulation.pixels = vec![Color::new(0.0, 0.0, 0.0); width * height];
+            accumulation.passes = 0;
+            accumulation.generation = generation;
+        }
+        // this pass may have seen the scene before the latest edit
+        if shared.generation.load(Ordering::SeqCst) == generation {
+            for (sum, sample) in accumulation.pixels.iter_mut().zip(pass) {
+                *sum = *sum + sample;
+            }
+            accumulation.passes += 1;
+        }
+    }
+}
+
+struct PreviewApp {
+    shared: Arc<Shared>,
+    materials: Vec<MaterialEntry>,
+    width: usize,
+    height: usize,
+    texture: Option<egui::TextureHandle>,
+    orbit: Orbit,
+    // screen points per render pixel, and how far the image's centre is moved
+    // from the middle of the view
+    zoom: f32,
+    pan: egui::Vec2
+}
+
+// how far one notch (or line) of scrolling zooms
+const ZOOM_PER_SCROLL: f32 = 0.002;
+
+impl PreviewApp {
+    fn replace_material(&mut self, index: usize) {
+        let entry = &mut self.materials[index];
+        let material = match entry.edit.to_material(&entry.original) {
+            Some(material) => Arc::new(material),
+            None => return
+        };
+        let mut scene = self.shared.scene.write().unwrap();
+        swap_material(&mut scene, &entry.material, &material);
+        entry.material = material;
+        // before unlocking, so any row that sees the edit belongs to a pass
+        // that's thrown away
+        self.shared.generation.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+impl eframe::App for PreviewApp {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        let mut changed = None;
+        egui::SidePanel::left("materials").show(ctx, |ui| {
+            ui.heading("Materials");
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                for (index, entry) in self.materials.iter_mut().enumerate() {
+                    ui.separator();
+                    ui.label(format!("{} ({}, {} surfaces)", index, entry.edit.name(), entry.uses));
+                    if entry.edit.show(ui) {
+                        changed = Some(index);
+                    }
+                }
+            });
+        });
+        if let Some(index) = changed {
+            self.replace_material(index);
+        }
+
+        let (pixels, passes) = {
+            let accumulation = self.shared.accumulation.lock().unwrap();
+            let passes = accumulation.passes.max(1);
+            let pixels: Vec<u8> = accumulation.pixels.iter().flat_map(|sum| sum.rgb8(passes)).collect();
+            (pixels, accumulation.passes)
+        };
+        if pixels.len() == self.width * self.height * 3 {
+            let frame = egui::ColorImage::from_rgb([self.width, self.height], &pixels);
+            match self.texture.as_mut() {
+                Some(texture) => texture.set(frame, egui::TextureOptions::NEAREST),
+                None => self.texture = Some(ctx.load_texture("render", frame, egui::TextureOptions::NEAREST))
+            }
+        }
+        egui::CentralPanel::default().show(ctx, |ui| {
+            ui.label(format!("{} samples per pixel, {:.0}%", passes, self.zoom * 100.0));
+            let (view, response) = ui.allocate_exact_size(ui.available_size(), egui::Sense::click_and_drag());
+            let size = egui::vec2(self.width as f32, self.height as f32);
+            if response.double_clicked() {
+                self.zoom = 1.0;
+                self.pan = egui::Vec2::ZERO;
+            }
+            if response.dragged_by(egui::PointerButton::Secondary) {
+                self.pan += response.drag_delta();
+            }
+            let orbit_drag = if response.dragged_by(egui::PointerButton::Primary) { response.drag_delta() } else { egui::Vec2::ZERO };
+            // keys typed into the panel aren't for the camera
+            let typing = ctx.wants_keyboard_input();
+            let no_keys = egui::InputState::default();
+            if ui.input(|input| self.orbit.update(orbit_drag, if typing { &no_keys } else { input })) {
+                let camera = self.shared.camera.lock().unwrap().moved(self.orbit.lookfrom(), self.orbit.target, self.orbit.vertical_fov);
+                *self.shared.camera.lock().unwrap() = Arc::new(camera);
+                self.shared.generation.fetch_add(1, Ordering::SeqCst);
+            }
+            if let Some(pointer) = response.hover_pos() {
+                let (scroll, pinch) = ui.input(|input| (input.smooth_scroll_delta.y, input.zoom_delta()));
+                let zoom = (self.zoom * pinch * (scroll * ZOOM_PER_SCROLL).exp()).clamp(0.1, 64.0);
+                if zoom != self.zoom {
+                    // the render pixel under the pointer stays under it
+                    let centre = view.center() + self.pan;
+                    let under_pointer = (pointer - centre) / self.zoom;
+                    self.pan = pointer - under_pointer * zoom - view.center();
+                    self.zoom = zoom;
+                }
+            }
+            if let Some(texture) = &self.texture {
+                let shown = egui::Rect::from_center_size(view.center() + self.pan, size * self.zoom);
+                let whole = egui::Rect::from_min_max(egui::pos2(0.0, 0.0), egui::pos2(1.0, 1.0));
+                ui.painter_at(view).image(texture.id(), shown, whole, egui::Color32::WHITE);
+            }
+        });
+        // keep redrawing while the render refines
+        ctx.request_repaint();
+    }
+}
+
+pub fn run(mut scene: Scene, camera: Camera, image: ImageConfig) {
+    let (width, height) = (image.image_width as usize, image.image_height as usize);
+    let materials = list_materials(&mut scene);
+    let orbit = Orbit::new(&camera);
+    let shared = Arc::new(Shared {
+        scene: RwLock::new(scene),
+        camera: Mutex::new(Arc::new(camera)),
+        generation: AtomicU64::new(0),
+        accumulation: Mutex::new(Accumulation {
+            pixels: vec![Color::new(0.0, 0.0, 0.0); width * height],
+            passes: 0,
+            generation: 0
+        })
+    });
+
+    let renderer = shared.clone();
+    std::thread::spawn(move || render_passes(&renderer, &image));
+
+    let app = PreviewApp {
+        shared,
+        materials,
+        width,
+        height,
+        texture: None,
+        orbit,
+        zoom: 1.0,
+        pan: egui::Vec2::ZERO
+    };
+    let options = eframe::NativeOptions {
+        viewport: egui::ViewportBuilder::default().with_inner_size([width as f32 + 260.0, height as f32 + 40.0]),
+        ..Default::default()
+    };
+    if let Err(e) = eframe::run_native("rays", options, Box::new(|_| Ok(Box::new(app)))) {
+        eprintln!("Couldn't open the preview window: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rays::hittable_list::HittableList;
+    use rays::instance::Instance;
+    use rays::sphere::Sphere;
+    use rays::transform::Transform;
+    use rays::triangle::Triangle;
+    use rays::voxel::VoxelGrid;
+
+    fn matte(albedo: f64) -> Material {
+        Material::Lambertian{albedo: Box::new(SolidTexture::uniform(albedo)), normal_map: None}
+    }
+
+    #[test]
+    fn test_lists_materials_through_wrappers() {
+        let shared = Arc::new(matte(0.5));
+        let mut world = HittableList::new();
+        world.add(Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0, shared.clone()));
+        world.add(Triangle::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0), shared.clone()));
+        world.add(VoxelGrid::new([1, 1, 1], Vec3::new(0.0, 0.0, 0.0), 1.0, vec![matte(0.2)]));
+        // one instance of geometry of its own, and two sharing some
+        world.add(Instance::new(Arc::new(Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0, shared.clone())), Transform::identity()));
+        let common: Arc<dyn Hittable> = Arc::new(Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0, matte(0.8)));
+        world.add(Instance::new(common.clone(), Transform::identity()));
+        world.add(Instance::new(common, Transform::identity()));
+        let mut scene = Scene::from(world);
+
+        let materials = list_materials(&mut scene);
+        assert_eq!(materials.len(), 2);
+        assert!(Arc::ptr_eq(&materials[0].material, &shared));
+        assert_eq!(materials[0].uses, 3);
+        assert_eq!(materials[1].uses, 1);
+    }
+
+    #[test]
+    fn test_editing_swaps_every_use_and_keeps_the_normal_map() {
+        let bumpy = Arc::new(Material::Lambertian{
+            albedo: Box::new(SolidTexture::uniform(0.5)),
+            normal_map: Some(Box::new(SolidTexture::new(Color::new(0.8, 0.5, 0.6))))
+        });
+        let mut world = HittableList::new();
+        world.add(Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0, bumpy.clone()));
+        world.add(Sphere::new(Vec3::new(3.0, 0.0, 0.0), 1.0, bumpy.clone()));
+        world.add(Sphere::new(Vec3::new(6.0, 0.0, 0.0), 1.0, matte(0.5)));
+        let mut scene = Scene::from(world);
+        let mut materials = list_materials(&mut scene);
+
+        let entry = &mut materials[0];
+        entry.edit = Edit::Metal{albedo: [0.9, 0.1, 0.1], fuzz: 0.3};
+        let edited = Arc::new(entry.edit.to_material(&entry.original).unwrap());
+        swap_material(&mut scene, &entry.material, &edited);
+        let uses = scene.world.materials_mut().into_iter().filter(|slot| Arc::ptr_eq(slot, &edited)).count();
+        assert_eq!(uses, 2);
+        match edited.as_ref() {
+            Material::Metal{normal_map: Some(map), ..} => {
+                let value = map.value(0.5, 0.5, &Vec3::new(0.0, 0.0, 0.0));
+                assert!((value - Color::new(0.8, 0.5, 0.6)).near_zero());
+            },
+            _ => panic!("expected a metal with the original's normal map")
+        }
+        // a material without one doesn't get one
+        assert!(matches!(Edit::Lambertian{albedo: [1.0, 1.0, 1.0]}.to_material(&materials[1].original),
+            Some(Material::Lambertian{normal_map: None, ..})));
+    }
+}
@@ -0,0 +1,247 @@
+use crate::vec3::*;
+use crate::utilities::{random_float, PI};
+
+// the maths behind Material::Principled: a lambertian base under a glossy
+// layer using the GGX (trowbridge-reitz) microfacet model, close to what
+// blender's principled bsdf and disney's "principled" brdf do.
+// reference: https://www.cs.cornell.edu/~srm/publications/EGSR07-btdf.pdf and
+// https://media.disneyanimation.com/uploads/production/publication_asset/48/asset/s2012_pbs_disney_brdf_notes_v3.pdf
+//
+// a microfacet surface is made of tiny mirrors. roughness spreads out which
+// way they face (D), some of them hide others (G), and each reflects by the
+// fresnel equations (F). all directions here are unit vectors pointing away
+// from the surface: n the normal, v towards the viewer, l towards the light
+
+pub struct PrincipledParameters {
+    pub base_color: Color,
+    // 0 is a dielectric (plastic, wood...), 1 is a metal tinted by base_color
+    pub metallic: f64,
+    // 0 is a perfect mirror, 1 is very blurry
+    pub roughness: f64,
+    // scales the reflectivity of dielectrics, 0.5 is the usual (4% at ior 1.5)
+    pub specular: f64,
+    pub index_of_refraction: f64,
+    // a measured metal to use instead of tinting by base_color, see Conductor
+    pub conductor: Option<Conductor>
+}
+
+// a metal's complex index of refraction (eta + ik) at red, green and blue
+// (about 650, 550 and 450nm). the tinted schlick most renderers use for metals
+// gets the colour at normal incidence right but not how it shifts towards the
+// edges, e.g. gold going paler and copper going orange at grazing angles
+#[derive(Copy, Clone, Debug)]
+pub struct Conductor {
+    pub eta: Color,
+    pub k: Color
+}
+
+impl Conductor {
+    // measured values, from refractiveindex.info
+    pub fn parse(name: &str) -> Option<Conductor> {
+        let (eta, k) = match name {
+            "gold" => (Color::new(0.143, 0.374, 1.442), Color::new(3.983, 2.385, 1.603)),
+            "silver" => (Color::new(0.155, 0.117, 0.138), Color::new(4.828, 3.122, 2.147)),
+            "copper" => (Color::new(0.200, 0.924, 1.102), Color::new(3.912, 2.452, 2.142)),
+            "aluminum" | "aluminium" => (Color::new(1.657, 0.880, 0.521), Color::new(9.224, 6.270, 4.837)),
+            _ => return None
+        };
+        Some(Conductor {
+            eta,
+            k
+        })
+    }
+
+    // the exact fresnel equations for a conductor, averaged over both
+    // polarisations (as in pbrt's FrConductor)
+    pub fn fresnel(&self, cosine: f64) -> Color {
+        let cos2 = cosine.clamp(0.0, 1.0).powi(2);
+        let sin2 = 1.0 - cos2;
+        let channel = |eta: f64, k: f64| {
+            let t0 = eta * eta - k * k - sin2;
+            let a2_plus_b2 = (t0 * t0 + 4.0 * eta * eta * k * k).sqrt();
+            let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+            let t1 = a2_plus_b2 + cos2;
+            let t2 = 2.0 * cosine.clamp(0.0, 1.0) * a;
+            let perpendicular = (t1 - t2) / (t1 + t2);
+            let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+            let t4 = t2 * sin2;
+            let parallel = perpendicular * (t3 - t4) / (t3 + t4);
+            0.5 * (perpendicular + parallel)
+        };
+        Color::new(channel(self.eta.x(), self.k.x()), channel(self.eta.y(), self.k.y()), channel(self.eta.z(), self.k.z()))
+    }
+}
+
+impl PrincipledParameters {
+    // ggx's alpha. squaring the roughness makes it look more linear to artists,
+    // and a mirror would need a delta distribution so it's kept a little rough
+    fn alpha(&self) -> f64 {
+        (self.roughness * self.roughness).max(1e-3)
+    }
+
+    fn dielectric_f0(&self) -> Color {
+        let r = (self.index_of_refraction - 1.0) / (self.index_of_refraction + 1.0);
+        let dielectric = r * r * self.specular * 2.0;
+        Color::new(dielectric, dielectric, dielectric)
+    }
+
+    // reflectance looking straight at the surface
+    fn f0(&self) -> Color {
+        self.dielectric_f0() * (1.0 - self.metallic) + self.base_color * self.metallic
+    }
+
+    // reflectance at an angle. a conductor's metal part is its own fresnel
+    // (base_color still tints it, white keeps it as measured)
+    fn fresnel(&self, cosine: f64) -> Color {
+        match self.conductor {
+            Some(conductor) => schlick(self.dielectric_f0(), cosine) * (1.0 - self.metallic)
+                + conductor.fresnel(cosine) * self.base_color * self.metallic,
+            None => schlick(self.f0(), cosine)
+        }
+    }
+
+    fn diffuse(&self) -> Color {
+        self.base_color * (1.0 - self.metallic)
+    }
+
+    // the chance of sampling the glossy lobe instead of the diffuse one,
+    // roughly in proportion to how much each reflects
+    fn specular_probability(&self, n_dot_v: f64) -> f64 {
+        let luminance = |c: Color| 0.2126 * c.x() + 0.7152 * c.y() + 0.0722 * c.z();
+        let specular = luminance(self.fresnel(n_dot_v));
+        let diffuse = luminance(self.diffuse());
+        if diffuse <= 0.0 {
+            return 1.0
+        }
+        (specular / (specular + diffuse)).clamp(0.1, 0.9)
+    }
+}
+
+fn schlick(f0: Color, cosine: f64) -> Color {
+    let weight = (1.0 - cosine.clamp(0.0, 1.0)).powi(5);
+    f0 + (Color::new(1.0, 1.0, 1.0) - f0) * weight
+}
+
+// how many microfacets face along h
+fn distribution(alpha: f64, n_dot_h: f64) -> f64 {
+    let a2 = alpha * alpha;
+    let d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    a2 / (PI * d * d)
+}
+
+// smith's masking for one direction: the fraction of facets it can see
+fn masking(alpha: f64, n_dot_x: f64) -> f64 {
+    let a2 = alpha * alpha;
+    2.0 * n_dot_x / (n_dot_x + (a2 + (1.0 - a2) * n_dot_x * n_dot_x).sqrt())
+}
+
+// two vectors that make an orthonormal basis with n
+fn basis(n: &Vec3) -> (Vec3, Vec3) {
+    let helper = if n.x().abs() > 0.9 { Vec3::new(0.0, 1.0, 0.0) } else { Vec3::new(1.0, 0.0, 0.0) };
+    let tangent = helper.cross_product(n).unit_vector();
+    (tangent, n.cross_product(&tangent))
+}
+
+// the brdf times the cosine, and the probability density of sample() picking l
+fn evaluate_with_pdf(parameters: &PrincipledParameters, n: &Vec3, v: &Vec3, l: &Vec3) -> (Color, f64) {
+    let n_dot_v = n.dot_product(v);
+    let n_dot_l = n.dot_product(l);
+    if n_dot_v <= 0.0 || n_dot_l <= 0.0 {
+        return (Color::new(0.0, 0.0, 0.0), 0.0)
+    }
+    let h = (*v + *l).unit_vector();
+    let n_dot_h = n.dot_product(&h).max(0.0);
+    let v_dot_h = v.dot_product(&h).max(1e-8);
+    let alpha = parameters.alpha();
+
+    let d = distribution(alpha, n_dot_h);
+    let g = masking(alpha, n_dot_v) * masking(alpha, n_dot_l);
+    let f = parameters.fresnel(v_dot_h);
+    let specular = f * (d * g / (4.0 * n_dot_v * n_dot_l));
+    let diffuse = parameters.diffuse() / PI;
+
+    let p_specular = parameters.specular_probability(n_dot_v);
+    // sampling h by d * cos(h) turns into this density for l
+    let pdf_specular = d * n_dot_h / (4.0 * v_dot_h);
+    let pdf_diffuse = n_dot_l / PI;
+    let pdf = p_specular * pdf_specular + (1.0 - p_specular) * pdf_diffuse;
+    ((diffuse + specular) * n_dot_l, pdf)
+}
+
+// what Material::evaluate needs: the brdf times the cosine
+pub fn evaluate(parameters: &PrincipledParameters, n: &Vec3, v: &Vec3, l: &Vec3) -> Color {
+    evaluate_with_pdf(parameters, n, v, l).0
+}
+
+// picks a direction to continue in, and the colour the light coming back
+// along it gets multiplied by (brdf * cos / pdf). either lobe can be picked but
+// the weight accounts for both, so the result doesn't depend on the choice
+pub fn sample(parameters: &PrincipledParameters, n: &Vec3, v: &Vec3) -> Option<(Vec3, Color)> {
+    let n_dot_v = n.dot_product(v);
+    if n_dot_v <= 0.0 {
+        return None
+    }
+
+    let l = if random_float() < parameters.specular_probability(n_dot_v) {
+        // pick a microfacet normal, then mirror v about it
+        let alpha = parameters.alpha();
+        let r = random_float();
+        let phi = 2.0 * PI * random_float();
+        let cos_theta = ((1.0 - r) / (1.0 + (alpha * alpha - 1.0) * r)).sqrt();
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let (tangent, bitangent) = basis(n);
+        let h = tangent * (sin_theta * phi.cos()) + bitangent * (sin_theta * phi.sin()) + *n * cos_theta;
+        Vec3::reflect(&(*v * -1.0), &h)
+    } else {
+        let direction = *n + Vec3::random_unit_vector();
+        if direction.near_zero() { *n } else { direction.unit_vector() }
+    };
+
+    let (value, pdf) = evaluate_with_pdf(parameters, n, v, &l);
+    if pdf <= 0.0 {
+        return None
+    }
+    Some((l, value / pdf))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_white_surface_doesnt_create_energy() {
+        // a white surface can at most reflect all of the light it gets
+        for (metallic, roughness) in [(0.0, 0.5), (1.0, 0.3), (0.5, 0.9)] {
+            let parameters = PrincipledParameters {
+                base_color: Color::new(1.0, 1.0, 1.0),
+                metallic,
+                roughness,
+                specular: 0.5,
+                index_of_refraction: 1.5,
+                conductor: None
+            };
+            let n = Vec3::new(0.0, 0.0, 1.0);
+            let v = Vec3::new(0.6, 0.0, 0.8);
+            let samples = 20000;
+            let total = (0..samples).filter_map(|_| sample(&parameters, &n, &v)).fold(0.0, |sum, (_, weight)| sum + weight.y());
+            let albedo = total / samples as f64;
+            assert!(albedo > 0.5 && albedo < 1.1, "metallic {} roughness {} reflected {}", metallic, roughness, albedo);
+        }
+    }
+
+    #[test]
+    fn test_conductor_fresnel() {
+        let gold = Conductor::parse("gold").unwrap();
+        // yellow head on, and every metal turns white at grazing angles
+        let head_on = gold.fresnel(1.0);
+        assert!(head_on.x() > head_on.y() && head_on.y() > head_on.z(), "{:?}", head_on);
+        // which head on is ((eta - 1)^2 + k^2) / ((eta + 1)^2 + k^2)
+        let expected = ((0.143_f64 - 1.0).powi(2) + 3.983_f64.powi(2)) / ((0.143_f64 + 1.0).powi(2) + 3.983_f64.powi(2));
+        assert!((head_on.x() - expected).abs() < 1e-9, "{:?}", head_on);
+        let grazing = gold.fresnel(0.0);
+        assert!(grazing.x() > 0.999 && grazing.z() > 0.999, "{:?}", grazing);
+        let silver = Conductor::parse("silver").unwrap().fresnel(1.0);
+        assert!(silver.x() > 0.9 && silver.z() > 0.9, "{:?}", silver);
+        assert!(Conductor::parse("unobtainium").is_none());
+    }
+}
@@ -0,0 +1,124 @@
+use crate::counters::{count, total, Counter};
+use std::time::{Duration, Instant};
+
+// progress reports while rendering: how far along the render is, how long it's
+// taken, a guess at how long is left and how fast samples and rays are being
+// traced. at most a few reports a second, however fast scanlines finish.
+// `--progress json` prints each report as a json line on stderr instead, for
+// scripts and render farm wrappers to read
+
+const INTERVAL: Duration = Duration::from_millis(250);
+
+pub fn count_ray() {
+    count(Counter::Rays);
+}
+
+pub fn count_sample() {
+    count(Counter::Samples);
+}
+
+// rays and samples counted so far, on every thread
+fn totals() -> (u64, u64) {
+    (total(Counter::Rays), total(Counter::Samples))
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ProgressStyle {
+    Text,
+    Json
+}
+
+impl ProgressStyle {
+    pub fn parse(name: &str) -> Option<ProgressStyle> {
+        match name {
+            "text" => Some(ProgressStyle::Text),
+            "json" => Some(ProgressStyle::Json),
+            _ => None
+        }
+    }
+}
+
+pub struct Progress {
+    style: ProgressStyle,
+    start: Instant,
+    last: Option<Instant>,
+    rays_at_start: u64,
+    samples_at_start: u64
+}
+
+// 75 -> "1:15", 4000 -> "1:06:40"
+fn format_seconds(seconds: f64) -> String {
+    let seconds = seconds.round() as u64;
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    if hours > 0 {
+        format!("{}:{:02}:{:02}", hours, minutes, seconds)
+    } else {
+        format!("{}:{:02}", minutes, seconds)
+    }
+}
+
+impl Progress {
+    pub fn new(style: ProgressStyle) -> Progress {
+        let (rays_at_start, samples_at_start) = totals();
+        Progress {
+            style,
+            start: Instant::now(),
+            last: None,
+            rays_at_start,
+            samples_at_start
+        }
+    }
+
+    // done is the fraction of the render finished, 0 to 1. skipped if the last
+    // report was too recent, unless the render is done
+    pub fn update(&mut self, done: f64) {
+        let now = Instant::now();
+        if done < 1.0 && self.last.is_some_and(|last| now - last < INTERVAL) {
+            return
+        }
+        self.last = Some(now);
+        let report = self.report(done, now - self.start);
+        match self.style {
+            ProgressStyle::Text => {
+                eprint!("\r{}", report);
+                if done >= 1.0 {
+                    eprintln!();
+                }
+            },
+            ProgressStyle::Json => eprintln!("{}", report)
+        }
+    }
+
+    fn report(&self, done: f64, elapsed: Duration) -> String {
+        let elapsed = elapsed.as_secs_f64();
+        // unknown until some of the render is done
+        let eta = if done > 0.0 { Some(elapsed * (1.0 - done) / done) } else { None };
+        let per_second = |count: u64| if elapsed > 0.0 { count as f64 / elapsed } else { 0.0 };
+        let (rays, samples) = totals();
+        let mrays_per_second = per_second(rays - self.rays_at_start) / 1e6;
+        let samples_per_second = per_second(samples - self.samples_at_start);
+        match self.style {
+            ProgressStyle::Text => format!("{:5.1}% | {} elapsed | ETA {} | {:.0} samples/s | {:.2} Mray/s ",
+                100.0 * done, format_seconds(elapsed), eta.map_or("?".to_string(), format_seconds), samples_per_second, mrays_per_second),
+            ProgressStyle::Json => format!("{{\"progress\":{},\"elapsed_seconds\":{},\"eta_seconds\":{},\"samples_per_second\":{},\"mrays_per_second\":{}}}",
+                done, elapsed, eta.map_or("null".to_string(), |eta| eta.to_string()), samples_per_second, mrays_per_second)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reports() {
+        assert_eq!(format_seconds(75.2), "1:15");
+        assert_eq!(format_seconds(4000.0), "1:06:40");
+        let progress = Progress::new(ProgressStyle::Json);
+        let report = progress.report(0.25, Duration::from_secs(10));
+        assert!(report.starts_with("{\"progress\":0.25,\"elapsed_seconds\":10,\"eta_seconds\":30,"), "{}", report);
+        let text = Progress::new(ProgressStyle::Text).report(0.0, Duration::from_secs(3));
+        assert!(text.contains("0:03 elapsed | ETA ? | "), "{}", text);
+        assert!(report.contains("\"samples_per_second\":"), "{}", report);
+    }
+}
@@ -1,27 +1,126 @@
-use crate::Vec3;
+use crate::vec3::{Scalar, Vec3};
+use crate::hittable::{HitRecord, Hittable};
+use crate::utilities::INFINITY;
 
-pub struct Ray {
-    pub origin: Vec3,
-    pub direction: Vec3,
-    pub time: f64
+// origin + t * direction. the renderer's rays are f64, see Scalar
+pub struct Ray<T = f64> {
+    pub origin: Vec3<T>,
+    pub direction: Vec3<T>,
+    pub time: f64,
+    // only set on rays being traced for `--debug-pixel`, see main.rs
+    pub debug: Option<RayDebug>,
+    // only set on camera rays (and their mirror reflections) when ray
+    // differentials are on, see IntegratorSettings
+    pub differential: Option<RayDifferential>
 }
 
-impl Ray {
-    pub fn new(origin: Vec3, direction: Vec3, time: Option<f64>) -> Ray {
-        let unwrapped_time: f64;
-        match time {
-            Some(var) => unwrapped_time = var,
-            None => unwrapped_time = 0.0
+// which camera sample a ray belongs to, so logs (or a conditional breakpoint on
+// ray.debug) can pick out e.g. pixel (312, 190), sample 7 when chasing an artifact
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct RayDebug {
+    // from the top left corner
+    pub pixel: (u32, u32),
+    pub sample: u64,
+    // 0 for the camera ray, 1 after the first bounce...
+    pub bounce: u64
+}
+
+impl RayDebug {
+    pub fn next_bounce(self) -> RayDebug {
+        RayDebug {
+            bounce: self.bounce + 1,
+            ..self
         }
+    }
+}
+
+// the rays through the neighbouring pixels (one to the right, one up), which
+// tell how much of a surface a pixel covers where the ray hits it. textures can
+// then be averaged over that area (see ImageTexture's mipmaps) instead of
+// aliasing when they're far away
+#[derive(Copy, Clone, Debug)]
+pub struct RayDifferential {
+    pub x_origin: Vec3,
+    pub x_direction: Vec3,
+    pub y_origin: Vec3,
+    pub y_direction: Vec3
+}
+
+impl RayDifferential {
+    // where the neighbouring rays cross the plane touching the surface at the hit
+    fn plane_points(&self, record: &HitRecord) -> Option<(Vec3, Vec3)> {
+        let normal = record.normal;
+        let cross = |origin: Vec3, direction: Vec3| {
+            let denominator = direction.dot_product(&normal);
+            if denominator.abs() < 1e-12 {
+                return None
+            }
+            Some(origin + direction * ((record.point - origin).dot_product(&normal) / denominator))
+        };
+        Some((cross(self.x_origin, self.x_direction)?, cross(self.y_origin, self.y_direction)?))
+    }
 
+    // how far the texture coordinates move from one pixel to the next, found by
+    // tracing the neighbouring rays. they only count if they land on the same
+    // surface close to the tangent plane, anything else (an edge, another
+    // object) leaves the footprint unknown, which is 0
+    pub fn uv_footprint(&self, record: &HitRecord, world: &dyn Hittable, time: f64, t_min: f64) -> f64 {
+        let (x_point, y_point) = match self.plane_points(record) {
+            Some(points) => points,
+            None => return 0.0
+        };
+        let spacing = (x_point - record.point).length().max((y_point - record.point).length());
+        let mut footprint: f64 = 0.0;
+        for (origin, direction, expected) in [(self.x_origin, self.x_direction, x_point), (self.y_origin, self.y_direction, y_point)] {
+            match world.hit(&Ray::new(origin, direction, Some(time)), t_min, INFINITY) {
+                Some(hit) if std::ptr::eq(hit.material, record.material) && (hit.point - expected).length() <= 0.5 * spacing => {
+                    // coordinates wrap around on e.g. spheres, where 0.99 to 0.01 is a small step
+                    let step = |a: f64, b: f64| {
+                        let d = (a - b).abs();
+                        d.min((1.0 - d).abs())
+                    };
+                    footprint = footprint.max(step(hit.u, record.u)).max(step(hit.v, record.v));
+                },
+                _ => return 0.0
+            }
+        }
+        footprint
+    }
+
+    // the neighbours after a mirror bounce, reflected off the tangent plane
+    pub fn reflect(&self, record: &HitRecord) -> Option<RayDifferential> {
+        let (x_origin, y_origin) = self.plane_points(record)?;
+        Some(RayDifferential {
+            x_origin,
+            x_direction: Vec3::reflect(&self.x_direction, &record.normal),
+            y_origin,
+            y_direction: Vec3::reflect(&self.y_direction, &record.normal)
+        })
+    }
+}
+
+impl<T: Scalar> Ray<T> {
+    pub fn new(origin: Vec3<T>, direction: Vec3<T>, time: Option<f64>) -> Ray<T> {
         Ray {
             origin,
             direction,
-            time: unwrapped_time
+            time: time.unwrap_or(0.0),
+            debug: None,
+            differential: None
         }
-    } 
+    }
+
+    pub fn with_differential(mut self, differential: Option<RayDifferential>) -> Ray<T> {
+        self.differential = differential;
+        self
+    }
+
+    pub fn with_debug(mut self, debug: Option<RayDebug>) -> Ray<T> {
+        self.debug = debug;
+        self
+    }
 
-    pub fn at(&self, t: f64) -> Vec3 {
+    pub fn at(&self, t: T) -> Vec3<T> {
         self.origin + (self.direction * t)
     }
 }
\ No newline at end of file
@@ -0,0 +1,107 @@
+use crate::counters::{self, total, Counter};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// counts of the work a render does: rays, box tests and primitive tests, for
+// seeing what a change to an accelerator actually did. `--stats` turns it on
+// and prints them when the render is done. off, every count is one check of a
+// flag that's never written to. the counts are kept per thread, see counters.rs
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+fn count(counter: Counter) {
+    if ENABLED.load(Ordering::Relaxed) {
+        counters::count(counter);
+    }
+}
+
+// a ray from the camera
+pub fn count_primary_ray() {
+    count(Counter::PrimaryRays);
+}
+
+// a ray scattered off a surface (shadow rays aren't counted)
+pub fn count_bounce_ray() {
+    count(Counter::BounceRays);
+}
+
+// a ray tested against a bounding box, by an accelerator or anything else
+pub fn count_box_test() {
+    count(Counter::BoxTests);
+}
+
+// a ray tested against an object in an accelerator's leaf. objects kept next
+// to a tree (e.g. planes) are tested by every ray anyway, so aren't counted
+pub fn count_primitive_test() {
+    count(Counter::PrimitiveTests);
+}
+
+#[derive(Copy, Clone, Default, PartialEq, Debug)]
+pub struct RayStats {
+    pub primary_rays: u64,
+    pub bounce_rays: u64,
+    pub box_tests: u64,
+    pub primitive_tests: u64
+}
+
+impl RayStats {
+    // everything counted so far, on every thread
+    pub fn total() -> RayStats {
+        RayStats {
+            primary_rays: total(Counter::PrimaryRays),
+            bounce_rays: total(Counter::BounceRays),
+            box_tests: total(Counter::BoxTests),
+            primitive_tests: total(Counter::PrimitiveTests)
+        }
+    }
+
+    // what was counted between earlier and this, e.g. leaving out building the scene
+    pub fn since(&self, earlier: &RayStats) -> RayStats {
+        RayStats {
+            primary_rays: self.primary_rays - earlier.primary_rays,
+            bounce_rays: self.bounce_rays - earlier.bounce_rays,
+            box_tests: self.box_tests - earlier.box_tests,
+            primitive_tests: self.primitive_tests - earlier.primitive_tests
+        }
+    }
+
+    pub fn rays(&self) -> u64 {
+        self.primary_rays + self.bounce_rays
+    }
+
+    // rays per path, the camera ray included
+    pub fn average_path_length(&self) -> f64 {
+        self.rays() as f64 / self.primary_rays.max(1) as f64
+    }
+
+    pub fn report(&self) -> String {
+        let per_ray = |count: u64| count as f64 / self.rays().max(1) as f64;
+        format!("primary rays: {}\nbounce rays: {}\naverage path length: {:.3}\nbox tests: {} ({:.2} per ray)\nprimitive tests: {} ({:.2} per ray)\n",
+            self.primary_rays, self.bounce_rays, self.average_path_length(),
+            self.box_tests, per_ray(self.box_tests), self.primitive_tests, per_ray(self.primitive_tests))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counted_once_enabled() {
+        enable();
+        let before = RayStats::total();
+        count_primary_ray();
+        count_bounce_ray();
+        count_box_test();
+        // other tests render in parallel, so only check this test's counts are there
+        let counted = RayStats::total().since(&before);
+        assert!(counted.primary_rays >= 1 && counted.bounce_rays >= 1 && counted.box_tests >= 1);
+
+        let stats = RayStats{primary_rays: 4, bounce_rays: 6, box_tests: 30, primitive_tests: 5};
+        assert_eq!(stats.average_path_length(), 2.5);
+        assert!(stats.report().contains("box tests: 30 (3.00 per ray)"), "{}", stats.report());
+    }
+}
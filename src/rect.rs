@@ -0,0 +1,150 @@
+use crate::vec3::*;
+use crate::Ray;
+use crate::hittable::*;
+use crate::material::*;
+use crate::aabb::AABB;
+use crate::export::{ExportMaterial, ExportMesh};
+use std::sync::Arc;
+
+// a rectangle lying flat in a plane perpendicular to one of the axes, e.g. a
+// wall, a floor or a light panel. cheaper to hit than two triangles since the
+// ray only has to be checked against one plane.
+// reference: https://raytracing.github.io/books/RayTracingTheNextWeek.html#rectanglesandlights
+pub struct AxisAlignedRect {
+    // the axis the rectangle faces along (0 -> x, 1 -> y, 2 -> z)
+    axis: usize,
+    // position on that axis
+    k: f64,
+    // bounds along the next two axes, in order (so y, z for x; z, x for y; x, y for z)
+    a: (f64, f64),
+    b: (f64, f64),
+    // 1 if the front faces towards +axis, -1 if towards -axis
+    facing: f64,
+    material: Arc<Material>
+}
+
+impl AxisAlignedRect {
+    pub fn new(axis: usize, k: f64, a: (f64, f64), b: (f64, f64), material: impl Into<Arc<Material>>) -> AxisAlignedRect {
+        if axis > 2 {
+            panic!("A rectangle's axis must be 0, 1 or 2, got {}", axis);
+        }
+        AxisAlignedRect {
+            axis,
+            k,
+            a,
+            b,
+            facing: 1.0,
+            material: material.into()
+        }
+    }
+
+    // faces towards -axis instead. only matters for materials that care which
+    // side is the outside, e.g. glass
+    pub fn flip_normal(mut self) -> AxisAlignedRect {
+        self.facing = -self.facing;
+        self
+    }
+
+    // facing z
+    pub fn xy(x: (f64, f64), y: (f64, f64), k: f64, material: impl Into<Arc<Material>>) -> AxisAlignedRect {
+        AxisAlignedRect::new(2, k, x, y, material)
+    }
+
+    // facing y
+    pub fn xz(x: (f64, f64), z: (f64, f64), k: f64, material: impl Into<Arc<Material>>) -> AxisAlignedRect {
+        AxisAlignedRect::new(1, k, z, x, material)
+    }
+
+    // facing x
+    pub fn yz(y: (f64, f64), z: (f64, f64), k: f64, material: impl Into<Arc<Material>>) -> AxisAlignedRect {
+        AxisAlignedRect::new(0, k, y, z, material)
+    }
+
+    fn axes(&self) -> (usize, usize) {
+        ((self.axis + 1) % 3, (self.axis + 2) % 3)
+    }
+
+    // a point from its position along each of the rectangle's axes
+    fn point(&self, k: f64, a: f64, b: f64) -> Vec3 {
+        let (axis_a, axis_b) = self.axes();
+        let mut point = [0.0; 3];
+        point[self.axis] = k;
+        point[axis_a] = a;
+        point[axis_b] = b;
+        Vec3::new(point[0], point[1], point[2])
+    }
+}
+
+impl Hittable for AxisAlignedRect {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let (axis_a, axis_b) = self.axes();
+        let t = (self.k - ray.origin[self.axis]) / ray.direction[self.axis];
+        // also catches rays parallel to the rectangle, where t isn't a number
+        if !(t > t_min && t < t_max) {
+            return None
+        }
+        let a = ray.origin[axis_a] + t * ray.direction[axis_a];
+        let b = ray.origin[axis_b] + t * ray.direction[axis_b];
+        if a < self.a.0 || a > self.a.1 || b < self.b.0 || b > self.b.1 {
+            return None
+        }
+
+        let u = (a - self.a.0) / (self.a.1 - self.a.0);
+        let v = (b - self.b.0) / (self.b.1 - self.b.0);
+        let outward_normal = self.point(self.facing, 0.0, 0.0);
+        let mut record = HitRecord::new(ray.at(t), outward_normal, t, u, v, false, &self.material);
+        record.set_face_normal(ray, &outward_normal);
+        record.tangent = Some(self.point(0.0, 1.0, 0.0));
+        Some(record)
+    }
+
+    fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<AABB> {
+        // padded since a box can't have 0 thickness
+        let padding = 0.0001;
+        Some(AABB::new(self.point(self.k - padding, self.a.0, self.b.0), self.point(self.k + padding, self.a.1, self.b.1)))
+    }
+
+    fn tessellate(&self) -> Vec<ExportMesh> {
+        let normal = self.point(self.facing, 0.0, 0.0);
+        // counter clockwise seen from the front
+        let triangles = if self.facing > 0.0 { vec![[0, 1, 2], [0, 2, 3]] } else { vec![[0, 2, 1], [0, 3, 2]] };
+        vec![ExportMesh {
+            positions: vec![
+                self.point(self.k, self.a.0, self.b.0),
+                self.point(self.k, self.a.1, self.b.0),
+                self.point(self.k, self.a.1, self.b.1),
+                self.point(self.k, self.a.0, self.b.1)
+            ],
+            normals: vec![normal; 4],
+            uvs: vec![(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)],
+            triangles,
+            material: ExportMaterial::from_material(&self.material)
+        }]
+    }
+
+    fn materials_mut(&mut self) -> Vec<&mut Arc<Material>> {
+        vec![&mut self.material]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rect_hit() {
+        let material = Material::Dielectric{index_of_refraction: 1.5, absorption: Color::new(0.0, 0.0, 0.0)};
+        let floor = AxisAlignedRect::xz((-1.0, 1.0), (0.0, 4.0), 2.0, material);
+
+        let ray = Ray::new(Vec3::new(0.5, 5.0, 1.0), Vec3::new(0.0, -1.0, 0.0), Some(0.0));
+        let record = floor.hit(&ray, 0.001, f64::INFINITY).unwrap();
+        assert!((record.point - Vec3::new(0.5, 2.0, 1.0)).near_zero());
+        assert!((record.normal - Vec3::new(0.0, 1.0, 0.0)).near_zero());
+        // u runs along z and v along x
+        assert!((record.u - 0.25).abs() < 1e-9 && (record.v - 0.75).abs() < 1e-9);
+
+        // outside of the bounds, and parallel
+        assert!(floor.hit(&Ray::new(Vec3::new(1.5, 5.0, 1.0), Vec3::new(0.0, -1.0, 0.0), Some(0.0)), 0.001, f64::INFINITY).is_none());
+        assert!(floor.hit(&Ray::new(Vec3::new(0.0, 2.0, 1.0), Vec3::new(1.0, 0.0, 0.0), Some(0.0)), 0.001, f64::INFINITY).is_none());
+    }
+}
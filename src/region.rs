@@ -0,0 +1,65 @@
+// a rectangle of the image's pixels, from (x0, y0) up to but not including
+// (x1, y1), counting from the top left like the image's rows do
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Region {
+    pub x0: u32,
+    pub y0: u32,
+    pub x1: u32,
+    pub y1: u32
+}
+
+impl Region {
+    pub fn whole(width: u32, height: u32) -> Region {
+        Region {x0: 0, y0: 0, x1: width, y1: height}
+    }
+
+    // "x0,y0,x1,y1". None unless it's four numbers with some pixels between them
+    pub fn parse(text: &str) -> Option<Region> {
+        let numbers: Vec<u32> = text.split(',').map(|part| part.trim().parse().ok()).collect::<Option<_>>()?;
+        match numbers[..] {
+            [x0, y0, x1, y1] if x0 < x1 && y0 < y1 => Some(Region {x0, y0, x1, y1}),
+            _ => None
+        }
+    }
+
+    // the part of the region inside a width by height image, None if that's nothing
+    pub fn within(&self, width: u32, height: u32) -> Option<Region> {
+        let clipped = Region {x0: self.x0, y0: self.y0, x1: self.x1.min(width), y1: self.y1.min(height)};
+        if clipped.x0 < clipped.x1 && clipped.y0 < clipped.y1 { Some(clipped) } else { None }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.x1 - self.x0
+    }
+
+    pub fn height(&self) -> u32 {
+        self.y1 - self.y0
+    }
+
+    pub fn contains_row(&self, row: u32) -> bool {
+        (self.y0..self.y1).contains(&row)
+    }
+
+    pub fn contains(&self, x: u32, row: u32) -> bool {
+        (self.x0..self.x1).contains(&x) && self.contains_row(row)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_clip() {
+        let region = Region::parse("10, 20,110,70").unwrap();
+        assert_eq!((region.width(), region.height()), (100, 50));
+        assert!(region.contains(10, 20) && !region.contains(110, 20) && !region.contains(50, 70));
+        assert_eq!(Region::parse("10,20,10,70"), None);
+        assert_eq!(Region::parse("10,20,30"), None);
+        assert_eq!(Region::parse("a,b,c,d"), None);
+
+        assert_eq!(region.within(64, 64), Some(Region {x0: 10, y0: 20, x1: 64, y1: 64}));
+        assert_eq!(region.within(8, 64), None);
+        assert_eq!(Region::whole(4, 3).within(4, 3), Some(Region::whole(4, 3)));
+    }
+}
@@ -0,0 +1,190 @@
+use crate::vec3::*;
+use crate::adaptive::PixelEstimate;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Error, ErrorKind, Read, Result, Write};
+use std::path::Path;
+
+// picking up a finished render again to refine it. every pixel's running
+// estimate (sum, sample count and brightness variance) is saved, so a restart
+// adds to the old samples instead of throwing them away, and spends the new
+// samples where the old render shows the most noise.
+//
+// file format (little endian): "RSTA", u32 version, u32 width, u32 height, then
+// per pixel from the top row down: sum as 3 f64, count as u64, mean and m2 as
+// f64, alpha as f64, foreground as 3 f64, the filter weight as f64 and the
+// rejected sample count as u64. version 1 files stop after m2, and are taken
+// as fully covered, version 2 files after the foreground, and were box
+// filtered, and version 3 files after the weight, with nothing rejected
+
+const MAGIC: &[u8; 4] = b"RSTA";
+const VERSION: u32 = 4;
+
+pub struct RenderState {
+    pub width: u32,
+    pub height: u32,
+    // rows from the top of the image down
+    pub pixels: Vec<PixelEstimate>
+}
+
+fn invalid_data(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("Bad render state: {}", message))
+}
+
+fn read_array<const N: usize>(reader: &mut impl Read) -> Result<[u8; N]> {
+    let mut bytes = [0; N];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn read_f64(reader: &mut impl Read) -> Result<f64> {
+    Ok(f64::from_le_bytes(read_array(reader)?))
+}
+
+impl RenderState {
+    pub fn new(width: u32, height: u32) -> RenderState {
+        RenderState {
+            width,
+            height,
+            pixels: (0..width as u64 * height as u64).map(|_| PixelEstimate::new()).collect()
+        }
+    }
+
+    // row counts from the top
+    pub fn pixel_mut(&mut self, x: u32, row: u32) -> &mut PixelEstimate {
+        &mut self.pixels[(row * self.width + x) as usize]
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+        self.write(&mut file)?;
+        file.flush()
+    }
+
+    pub fn load(path: &Path) -> Result<RenderState> {
+        RenderState::read(&mut BufReader::new(File::open(path)?))
+    }
+
+    // the same as save and load, for files that hold more than the state (see checkpoint.rs)
+    pub fn write(&self, file: &mut impl Write) -> Result<()> {
+        file.write_all(MAGIC)?;
+        for value in [VERSION, self.width, self.height].iter() {
+            file.write_all(&value.to_le_bytes())?;
+        }
+        for pixel in self.pixels.iter() {
+            for value in [pixel.sum.x(), pixel.sum.y(), pixel.sum.z()].iter() {
+                file.write_all(&value.to_le_bytes())?;
+            }
+            file.write_all(&pixel.count.to_le_bytes())?;
+            file.write_all(&pixel.mean.to_le_bytes())?;
+            file.write_all(&pixel.m2.to_le_bytes())?;
+            for value in [pixel.alpha, pixel.foreground.x(), pixel.foreground.y(), pixel.foreground.z(), pixel.weight].iter() {
+                file.write_all(&value.to_le_bytes())?;
+            }
+            file.write_all(&pixel.rejected.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    pub fn read(file: &mut impl Read) -> Result<RenderState> {
+        if &read_array::<4>(file)? != MAGIC {
+            return Err(invalid_data("missing 'RSTA' header"))
+        }
+        let version = u32::from_le_bytes(read_array(file)?);
+        if !(1..=VERSION).contains(&version) {
+            return Err(invalid_data(&format!("unsupported version {}", version)))
+        }
+        let width = u32::from_le_bytes(read_array(file)?);
+        let height = u32::from_le_bytes(read_array(file)?);
+        let mut pixels = Vec::with_capacity((width as u64 * height as u64) as usize);
+        for _ in 0..width as u64 * height as u64 {
+            let sum = Color::new(read_f64(file)?, read_f64(file)?, read_f64(file)?);
+            let count = u64::from_le_bytes(read_array(file)?);
+            let mean = read_f64(file)?;
+            let m2 = read_f64(file)?;
+            let (alpha, foreground) = if version == 1 {
+                (count as f64, sum)
+            } else {
+                (read_f64(file)?, Color::new(read_f64(file)?, read_f64(file)?, read_f64(file)?))
+            };
+            let weight = if version < 3 { count as f64 } else { read_f64(file)? };
+            let rejected = if version < 4 { 0 } else { u64::from_le_bytes(read_array(file)?) };
+            if rejected > count {
+                return Err(invalid_data("more samples rejected than taken"))
+            }
+            pixels.push(PixelEstimate{sum, count, alpha, foreground, weight, mean, m2, rejected});
+        }
+        Ok(RenderState {
+            width,
+            height,
+            pixels
+        })
+    }
+
+    // how many more samples each pixel gets to bring the render up to
+    // samples_per_pixel on average. the budget is split so each pixel's total
+    // is proportional to how noisy its samples were, which minimizes the
+    // overall error: smooth areas get few (or no) more samples, noisy ones many
+    pub fn sample_budget(&self, samples_per_pixel: u64) -> Vec<u64> {
+        let existing: u64 = self.pixels.iter().map(|pixel| pixel.count).sum();
+        let budget = samples_per_pixel.saturating_mul(self.pixels.len() as u64).saturating_sub(existing);
+        // nothing to spend, which is also the case for an image with no
+        // pixels, so the shares below never divide by a total of nothing
+        if budget == 0 {
+            return vec![0; self.pixels.len()]
+        }
+
+        // pixels with too few samples to know their noise count as average
+        let deviations: Vec<f64> = self.pixels.iter().map(|pixel| pixel.relative_deviation()).collect();
+        let known: Vec<f64> = deviations.iter().copied().filter(|d| d.is_finite()).collect();
+        let average = if known.is_empty() { 1.0 } else { known.iter().sum::<f64>() / known.len() as f64 };
+        let deviations: Vec<f64> = deviations.iter().map(|d| if d.is_finite() { *d } else { average }.max(1e-6)).collect();
+
+        let total_samples = (existing + budget) as f64;
+        let total_deviation: f64 = deviations.iter().sum();
+        let wanted: Vec<f64> = self.pixels.iter().zip(deviations.iter()).map(|(pixel, deviation)| {
+            (total_samples * deviation / total_deviation - pixel.count as f64).max(0.0)
+        }).collect();
+        // pixels that already have more than their share don't give any back,
+        // so scale the rest to spend exactly the budget
+        let total_wanted: f64 = wanted.iter().sum();
+        let scale = if total_wanted > 0.0 { budget as f64 / total_wanted } else { 0.0 };
+        wanted.iter().map(|w| (w * scale).round() as u64).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_budget_favours_noisy_pixels() {
+        let mut state = RenderState::new(2, 1);
+        for i in 0..16 {
+            state.pixel_mut(0, 0).add(Color::new(0.5, 0.5, 0.5));
+            let noisy = if i % 2 == 0 { 0.1 } else { 0.9 };
+            state.pixel_mut(1, 0).add(Color::new(noisy, noisy, noisy));
+        }
+
+        let budget = state.sample_budget(64);
+        assert!(budget[1] > budget[0] && budget[1] >= 90, "{:?}", budget);
+        assert!(((budget[0] + budget[1]) as i64 - 96).abs() <= 1, "{:?}", budget);
+
+        // nothing asked for, or nowhere to spend it
+        assert_eq!(RenderState::new(2, 1).sample_budget(0), vec![0, 0]);
+        assert!(RenderState::new(0, 0).sample_budget(64).is_empty());
+        // a fresh render has no noise to go by, so every pixel gets the same
+        assert_eq!(RenderState::new(2, 2).sample_budget(8), vec![8; 4]);
+
+        // a sample from a negative lobe of the pixel filter
+        state.pixel_mut(0, 0).add_filtered(Color::new(0.5, 0.5, 0.5), Color::new(0.5, 0.5, 0.5), 1.0, -0.5);
+        state.pixel_mut(1, 0).reject();
+        let path = std::env::temp_dir().join("rays_test_state.rsta");
+        state.save(&path).unwrap();
+        let loaded = RenderState::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!((loaded.width, loaded.height, loaded.pixels[1].count), (2, 1, 17));
+        assert_eq!((loaded.pixels[0].rejected, loaded.pixels[1].rejected), (0, 1));
+        assert_eq!(loaded.pixels[1].m2, state.pixels[1].m2);
+        assert_eq!((loaded.pixels[0].weight, loaded.pixels[1].weight), (15.5, 16.0));
+    }
+}
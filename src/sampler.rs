@@ -0,0 +1,329 @@
+use crate::utilities::mix_seed;
+use crate::blue_noise;
+use std::cell::Cell;
+
+// where a sample's random numbers come from. each number a sample takes is a
+// dimension of it: the first two jitter it inside its pixel, the next ones
+// pick its point on the lens and its time, then every bounce takes a few to
+// pick where it goes. a pixel's samples can be spread out evenly along each
+// dimension rather than left to chance, so they clump less and the pixel
+// converges faster (especially at low sample counts).
+// start_sample hands a sample's dimensions to random_float (see utilities.rs)
+// on this thread, so the camera and materials take them without knowing
+pub trait Sampler: Send + Sync {
+    // the index'th sample's coordinate along dimension, in [0, 1). scramble is
+    // different for every pixel, so neighbouring pixels don't repeat the same
+    // pattern. None past the dimensions the sampler covers, which then get
+    // independent random numbers
+    fn sample(&self, scramble: u64, index: u64, dimension: u32) -> Option<f64>;
+}
+
+// every number independently random, as without a sampler
+#[derive(Copy, Clone, Debug)]
+pub struct Independent;
+
+impl Sampler for Independent {
+    fn sample(&self, _scramble: u64, _index: u64, _dimension: u32) -> Option<f64> {
+        None
+    }
+}
+
+// every dimension split into one stratum per sample, each sample taking a
+// random point in its own (latin hypercube sampling). the strata are shuffled
+// differently for every dimension, so dimensions aren't correlated. samples
+// past the count start a fresh round
+#[derive(Copy, Clone, Debug)]
+pub struct Stratified {
+    pub strata: u64
+}
+
+impl Stratified {
+    pub fn new(strata: u64) -> Stratified {
+        Stratified {
+            strata: strata.max(1)
+        }
+    }
+}
+
+impl Sampler for Stratified {
+    fn sample(&self, scramble: u64, index: u64, dimension: u32) -> Option<f64> {
+        let (round, within) = (index / self.strata, index % self.strata);
+        let shuffle = mix_seed(&[scramble, dimension as u64, round]) as u32;
+        let stratum = permute(within as u32, self.strata.min(u32::MAX as u64) as u32, shuffle);
+        let jitter = to_unit(mix_seed(&[scramble, dimension as u64, index]));
+        Some((stratum as f64 + jitter) / self.strata as f64)
+    }
+}
+
+// the halton sequence: dimension d is the sample number's digits in the d'th
+// prime's base, mirrored around the point (the radical inverse). every pixel
+// shifts each dimension by its own random amount, wrapping around
+// (cranley-patterson rotation). a base only gets round the whole range once
+// a pixel has that many samples, so past base 19 (8 dimensions) it does more
+// harm than good at the sample counts renders use, and stops
+#[derive(Copy, Clone, Debug)]
+pub struct Halton;
+
+const PRIMES: [u64; 8] = [2, 3, 5, 7, 11, 13, 17, 19];
+
+fn radical_inverse(base: u64, mut index: u64) -> f64 {
+    let (mut result, mut digit_value) = (0.0, 1.0 / base as f64);
+    while index > 0 {
+        result += (index % base) as f64 * digit_value;
+        index /= base;
+        digit_value /= base as f64;
+    }
+    result
+}
+
+impl Sampler for Halton {
+    fn sample(&self, scramble: u64, index: u64, dimension: u32) -> Option<f64> {
+        let base = *PRIMES.get(dimension as usize)?;
+        let shifted = radical_inverse(base, index) + to_unit(mix_seed(&[scramble, dimension as u64]));
+        Some(shifted.fract())
+    }
+}
+
+// the sobol sequence, in base 2 throughout: any power of two samples fill
+// every dimension's power of two strata exactly, and the first two dimensions
+// together too. every pixel flips its own random bits of each dimension
+// (a digital shift), which keeps that. covers 16 dimensions
+#[derive(Copy, Clone, Debug)]
+pub struct Sobol;
+
+// the polynomial (degree s, coefficients a) and first direction numbers m of
+// dimensions 1 up, from s. joe and f. y. kuo's new-joe-kuo-6.21201.
+// dimension 0 is the van der corput sequence
+const SOBOL_PARAMETERS: [(usize, u32, [u32; 6]); 15] = [
+    (1, 0, [1, 0, 0, 0, 0, 0]),
+    (2, 1, [1, 3, 0, 0, 0, 0]),
+    (3, 1, [1, 3, 1, 0, 0, 0]),
+    (3, 2, [1, 1, 1, 0, 0, 0]),
+    (4, 1, [1, 1, 3, 3, 0, 0]),
+    (4, 4, [1, 3, 5, 13, 0, 0]),
+    (5, 2, [1, 1, 5, 5, 17, 0]),
+    (5, 4, [1, 1, 5, 5, 5, 0]),
+    (5, 7, [1, 1, 7, 11, 19, 0]),
+    (5, 11, [1, 1, 5, 1, 1, 0]),
+    (5, 13, [1, 1, 1, 3, 11, 0]),
+    (5, 14, [1, 3, 5, 5, 31, 0]),
+    (6, 1, [1, 3, 3, 9, 7, 49]),
+    (6, 13, [1, 1, 1, 15, 21, 21]),
+    (6, 16, [1, 3, 1, 13, 27, 49])
+];
+
+// each dimension's direction numbers, as 32 bit binary fractions: the value
+// each set bit of the sample number flips in
+const SOBOL_DIRECTIONS: [[u32; 32]; 16] = sobol_directions();
+
+const fn sobol_directions() -> [[u32; 32]; 16] {
+    let mut directions = [[0; 32]; 16];
+    let mut bit = 0;
+    while bit < 32 {
+        directions[0][bit] = 1 << (31 - bit);
+        bit += 1;
+    }
+    let mut dimension = 1;
+    while dimension < 16 {
+        let (s, a, m) = SOBOL_PARAMETERS[dimension - 1];
+        let v = &mut directions[dimension];
+        let mut bit = 0;
+        while bit < 32 {
+            if bit < s {
+                v[bit] = m[bit] << (31 - bit);
+            } else {
+                v[bit] = v[bit - s] ^ (v[bit - s] >> s);
+                let mut k = 1;
+                while k < s {
+                    if (a >> (s - 1 - k)) & 1 == 1 {
+                        v[bit] ^= v[bit - k];
+                    }
+                    k += 1;
+                }
+            }
+            bit += 1;
+        }
+        dimension += 1;
+    }
+    directions
+}
+
+impl Sampler for Sobol {
+    fn sample(&self, scramble: u64, index: u64, dimension: u32) -> Option<f64> {
+        let directions = SOBOL_DIRECTIONS.get(dimension as usize)?;
+        let (mut bits, mut index) = (mix_seed(&[scramble, dimension as u64]) as u32, index as u32);
+        for direction in directions.iter() {
+            if index == 0 {
+                break
+            }
+            if index & 1 == 1 {
+                bits ^= direction;
+            }
+            index >>= 1;
+        }
+        Some(bits as f64 / 4294967296.0)
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum SamplerKind {
+    Independent(Independent),
+    Stratified(Stratified),
+    Halton(Halton),
+    Sobol(Sobol)
+}
+
+impl Default for SamplerKind {
+    fn default() -> SamplerKind {
+        SamplerKind::Independent(Independent)
+    }
+}
+
+impl SamplerKind {
+    // stratified needs to know how many samples each pixel takes
+    pub fn parse(name: &str, samples_per_pixel: u64) -> Option<SamplerKind> {
+        match name {
+            "independent" | "random" => Some(SamplerKind::Independent(Independent)),
+            "stratified" => Some(SamplerKind::Stratified(Stratified::new(samples_per_pixel))),
+            "halton" => Some(SamplerKind::Halton(Halton)),
+            "sobol" => Some(SamplerKind::Sobol(Sobol)),
+            _ => None
+        }
+    }
+
+    pub fn sampler(&self) -> &dyn Sampler {
+        match self {
+            SamplerKind::Independent(sampler) => sampler,
+            SamplerKind::Stratified(sampler) => sampler,
+            SamplerKind::Halton(sampler) => sampler,
+            SamplerKind::Sobol(sampler) => sampler
+        }
+    }
+}
+
+// a shuffle of 0..length picked by seed, without making a table: a hash that
+// only scrambles the bits below length's, repeated until it lands in range.
+// from a. kensler, "correlated multi-jittered sampling" (2013)
+fn permute(mut i: u32, length: u32, seed: u32) -> u32 {
+    let mut w = length.wrapping_sub(1);
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= seed;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= seed >> 16;
+        i ^= (i & w) >> 4;
+        i ^= seed >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= seed >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | seed >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < length {
+            break
+        }
+    }
+    (i.wrapping_add(seed)) % length
+}
+
+// a hash to [0, 1)
+fn to_unit(hash: u64) -> f64 {
+    (hash >> 11) as f64 / (1u64 << 53) as f64
+}
+
+// the sample this thread is taking, and how many of its dimensions it's used
+#[derive(Copy, Clone)]
+struct SampleStream {
+    kind: SamplerKind,
+    scramble: u64,
+    index: u64,
+    dimension: u32,
+    // the pixel, when its samples are shifted by the blue noise tile
+    dither: Option<(u32, u32)>
+}
+
+thread_local! {
+    static STREAM: Cell<Option<SampleStream>> = const { Cell::new(None) };
+}
+
+// random_float hands out the dimensions of pixel's index'th sample from here
+// on, until the next sample starts (or end_sample). with dither, every
+// dimension is shifted (wrapping around) by that pixel's blue noise (see
+// blue_noise.rs), for pixels that all take the same samples (the same scramble)
+pub fn start_sample(kind: SamplerKind, scramble: u64, index: u64, dither: Option<(u32, u32)>) {
+    let stream = match kind {
+        SamplerKind::Independent(_) => None,
+        _ => Some(SampleStream{kind, scramble, index, dimension: 0, dither})
+    };
+    STREAM.with(|current| current.set(stream));
+}
+
+// back to independent random numbers
+pub fn end_sample() {
+    STREAM.with(|current| current.set(None));
+}
+
+// the current sample's next dimension, if there's one
+pub fn next_dimension() -> Option<f64> {
+    STREAM.with(|current| {
+        let mut stream = current.get()?;
+        let value = stream.kind.sampler().sample(stream.scramble, stream.index, stream.dimension).map(|value| {
+            match stream.dither {
+                Some(pixel) => (value + blue_noise::tile().offset(pixel, stream.dimension)).fract(),
+                None => value
+            }
+        });
+        stream.dimension += 1;
+        current.set(Some(stream));
+        value
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_samples_spread_out() {
+        for length in [1, 5, 16, 33] {
+            let mut shuffled: Vec<u32> = (0..length).map(|i| permute(i, length, 12345)).collect();
+            shuffled.sort_unstable();
+            assert!(shuffled.iter().copied().eq(0..length));
+        }
+
+        // 16 samples of a pixel, in each of the first few dimensions, leave no
+        // gap much bigger than 1/16 (random ones typically leave ~1/5)
+        let samples = 16;
+        for kind in ["stratified", "halton", "sobol"] {
+            let sampler = SamplerKind::parse(kind, samples).unwrap();
+            for dimension in 0..6 {
+                let mut values: Vec<f64> = (0..samples).map(|index| sampler.sampler().sample(99, index, dimension).unwrap()).collect();
+                values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+                let widest = values.windows(2).map(|pair| pair[1] - pair[0]).chain(Some(1.0 - values[15] + values[0])).fold(0.0, f64::max);
+                assert!(values.iter().all(|value| (0.0..1.0).contains(value)) && widest <= 2.0 / 16.0, "{} dimension {}: {:?}", kind, dimension, values);
+            }
+        }
+        // sobol's power of two strata get one sample each
+        let mut strata: Vec<u64> = (0..16).map(|index| (Sobol.sample(7, index, 5).unwrap() * 16.0) as u64).collect();
+        strata.sort_unstable();
+        assert!(strata.into_iter().eq(0..16));
+
+        start_sample(SamplerKind::Sobol(Sobol), 7, 3, None);
+        let first = next_dimension();
+        assert_eq!(first, Sobol.sample(7, 3, 0));
+        assert_eq!(next_dimension(), Sobol.sample(7, 3, 1));
+        end_sample();
+        assert_eq!(next_dimension(), None);
+    }
+}
@@ -0,0 +1,181 @@
+use crate::vec3::*;
+use crate::camera::Camera;
+use crate::hittable::Hittable;
+use crate::hittable_list::HittableList;
+use crate::sphere::Sphere;
+use crate::material::Material;
+use crate::mesh::Mesh;
+use crate::light::Light;
+use crate::sky::PreethamSky;
+use crate::accelerator::AcceleratorKind;
+use crate::animation::{CameraPose, Keyframes};
+use crate::{ImageConfig, Scene};
+use std::sync::Arc;
+
+// puts together everything a render needs (the image settings, a camera and a
+// scene) without wiring up a HittableList, an acceleration structure and a
+// Camera by hand each time:
+//
+//     let (image, camera, scene) = SceneBuilder::new(ImageConfig::new(16.0 / 9.0, 400, 10, 50))
+//         .add_sphere(Vec3::new(0.0, -1000.0, 0.0), 1000.0, ground)
+//         .add_sphere(Vec3::new(0.0, 1.0, 0.0), 1.0, glass)
+//         .set_camera(Vec3::new(13.0, 2.0, 3.0), Vec3::new(0.0, 0.0, 0.0), 20.0)
+//         .build_with_bvh();
+//
+// what's added goes into one acceleration structure when it's built, so it's
+// only worth adding objects one at a time; a world with its own (e.g. a list
+// of thousands of instances) can be added whole like any other object
+
+// what rays that leave the scene see
+pub enum Background {
+    // the blue to white gradient, multiplied by a colour, e.g. dark for night
+    Gradient(Color),
+    // a physical sky, whose sun also lights the scene
+    Sky(PreethamSky)
+}
+
+// where the camera is, as for Camera::new with up being +y
+struct View {
+    lookfrom: Vec3,
+    lookat: Vec3,
+    vertical_fov: f64
+}
+
+pub struct SceneBuilder {
+    image: ImageConfig,
+    objects: Vec<Box<dyn Hittable>>,
+    // what from_scene started from, and the lights and background
+    scene: Scene,
+    view: Option<View>,
+    aperture: f64,
+    // none to focus on what the camera's looking at
+    focus_distance: Option<f64>
+}
+
+impl SceneBuilder {
+    pub fn new(image: ImageConfig) -> SceneBuilder {
+        SceneBuilder::from_scene(image, Scene::from(HittableList::new()))
+    }
+
+    // starting from a scene put together by hand, kept as it is
+    pub fn from_scene(image: ImageConfig, scene: Scene) -> SceneBuilder {
+        SceneBuilder {
+            image,
+            objects: Vec::new(),
+            scene,
+            view: None,
+            aperture: 0.0,
+            focus_distance: None
+        }
+    }
+
+    pub fn add_object(mut self, object: impl Hittable + 'static) -> SceneBuilder {
+        self.objects.push(Box::new(object));
+        self
+    }
+
+    pub fn add_sphere(self, center: Vec3, radius: f64, material: impl Into<Arc<Material>>) -> SceneBuilder {
+        self.add_object(Sphere::new(center, radius, material))
+    }
+
+    pub fn add_mesh(self, mesh: Mesh) -> SceneBuilder {
+        self.add_object(mesh)
+    }
+
+    pub fn add_light(mut self, light: Light) -> SceneBuilder {
+        self.scene.lights.push(light);
+        self
+    }
+
+    pub fn set_background(mut self, background: Background) -> SceneBuilder {
+        match background {
+            Background::Gradient(tint) => {
+                self.scene.sky = None;
+                self.scene.sky_tint = tint;
+            },
+            Background::Sky(sky) => {
+                self.scene.lights.push(sky.sun());
+                self.scene.sky = Some(sky);
+                self.scene.sky_tint = Color::new(1.0, 1.0, 1.0);
+            }
+        }
+        self
+    }
+
+    pub fn set_camera(mut self, lookfrom: Vec3, lookat: Vec3, vertical_fov: f64) -> SceneBuilder {
+        self.view = Some(View{lookfrom, lookat, vertical_fov});
+        self
+    }
+
+    // depth of field: how wide the lens is, 0 (the default) having everything in focus
+    pub fn set_aperture(mut self, aperture: f64) -> SceneBuilder {
+        self.aperture = aperture;
+        self
+    }
+
+    // how far away things are in focus, rather than at what the camera's looking at
+    pub fn set_focus_distance(mut self, distance: f64) -> SceneBuilder {
+        self.focus_distance = Some(distance);
+        self
+    }
+
+    // the camera follows the path over time (see --frames). without
+    // set_camera it starts where the path does
+    pub fn set_camera_path(mut self, path: Keyframes<CameraPose>) -> SceneBuilder {
+        self.scene.camera_path = Some(path);
+        self
+    }
+
+    pub fn build_with(self, accelerator: AcceleratorKind) -> (ImageConfig, Camera, Scene) {
+        let SceneBuilder {image, objects, mut scene, view, aperture, focus_distance} = self;
+        // a camera that isn't given opens and closes its shutter at the start
+        // of its path, so stills of an animation show its first moment
+        let (view, shutter) = match (view, &scene.camera_path) {
+            (Some(view), _) => (view, (0.0, 1.0)),
+            (None, Some(path)) => {
+                let start = path.span().0;
+                let pose = path.at(start);
+                (View{lookfrom: pose.lookfrom, lookat: pose.lookat, vertical_fov: pose.vertical_fov}, (start, start))
+            },
+            (None, None) => panic!("A scene needs a camera, see set_camera")
+        };
+        let focus_distance = focus_distance.unwrap_or_else(|| (view.lookat - view.lookfrom).length());
+        let camera = Camera::new(view.lookfrom, view.lookat, Vec3::new(0.0, 1.0, 0.0), view.vertical_fov, image.aspect_ratio.into(),
+            aperture, focus_distance, shutter.0, shutter.1);
+        if !objects.is_empty() {
+            scene.world.add_boxed(accelerator.build(objects, shutter.0, shutter.1));
+        }
+        (image, camera, scene)
+    }
+
+    pub fn build_with_bvh(self) -> (ImageConfig, Camera, Scene) {
+        self.build_with(AcceleratorKind::FlatBvh)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ray::Ray;
+    use crate::texture::SolidTexture;
+
+    #[test]
+    fn test_built_scene_is_ready_to_render() {
+        let matte = || Material::Lambertian{albedo: Box::new(SolidTexture::new(Color::new(0.5, 0.5, 0.5))), normal_map: None};
+        let (image, camera, scene) = SceneBuilder::new(ImageConfig::new(2.0, 20, 1, 5))
+            .add_sphere(Vec3::new(0.0, 0.0, -5.0), 1.0, matte())
+            .add_sphere(Vec3::new(3.0, 0.0, -5.0), 1.0, matte())
+            .set_camera(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -5.0), 40.0)
+            .set_background(Background::Gradient(Color::new(0.1, 0.1, 0.1)))
+            .build_with_bvh();
+        assert_eq!((image.image_width, image.image_height), (20, 10));
+        // both spheres went into one tree
+        assert_eq!(scene.world.objects.len(), 1);
+        assert!((scene.sky_tint - Color::new(0.1, 0.1, 0.1)).length() < 1e-12 && scene.sky.is_none());
+        let centre = camera.get_ray(0.5, 0.5).unwrap();
+        let hit = scene.world.hit(&centre, 0.001, f64::INFINITY).unwrap();
+        assert!((hit.point - Vec3::new(0.0, 0.0, -4.0)).length() < 1e-9);
+        let aside = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(3.0, 0.0, -4.0), Some(0.5));
+        assert!(scene.world.hit(&aside, 0.001, f64::INFINITY).is_some());
+    }
+}
@@ -0,0 +1,190 @@
+use crate::vec3::*;
+use crate::Ray;
+use crate::hittable::*;
+use crate::material::*;
+use crate::sphere::Sphere;
+use crate::aabb::AABB;
+use std::sync::Arc;
+
+// a shape given by a signed distance function: how far a point is from the
+// surface, negative inside. anything that can say that much can be rendered,
+// e.g. shapes blended into each other with smooth_min or fractals like the
+// mandelbulb, which have no closed form intersection.
+// rays are sphere traced (john c. hart, "sphere tracing", 1996): the distance
+// is a step the ray can always take without passing through the surface, so it
+// steps until the distance is under epsilon. the distance only has to be a
+// lower bound, so a function that's a little off still works, just slower
+// reference: https://iquilezles.org/articles/distfunctions/
+
+pub type DistanceFunction = dyn Fn(&Vec3) -> f64 + Send + Sync;
+
+pub struct SdfObject {
+    distance: Box<DistanceFunction>,
+    // the function is only marched inside these, so they have to hold the whole shape
+    bounds: AABB,
+    // how close to the surface counts as a hit
+    epsilon: f64,
+    // gives up on rays that creep along the surface without reaching it
+    max_steps: u32,
+    material: Arc<Material>
+}
+
+impl SdfObject {
+    pub fn new(distance: impl Fn(&Vec3) -> f64 + Send + Sync + 'static, bounds: AABB, material: impl Into<Arc<Material>>) -> SdfObject {
+        SdfObject {
+            distance: Box::new(distance),
+            bounds,
+            epsilon: 1e-4,
+            max_steps: 256,
+            material: material.into()
+        }
+    }
+
+    pub fn with_epsilon(mut self, epsilon: f64) -> SdfObject {
+        self.epsilon = epsilon;
+        self
+    }
+
+    pub fn with_max_steps(mut self, max_steps: u32) -> SdfObject {
+        self.max_steps = max_steps;
+        self
+    }
+
+    // the direction the distance grows fastest in (central differences), which
+    // is the outward normal on the surface
+    fn normal(&self, point: &Vec3) -> Vec3 {
+        let h = self.epsilon;
+        let along = |offset: Vec3| (self.distance)(&(*point + offset)) - (self.distance)(&(*point - offset));
+        let gradient = Vec3::new(along(Vec3::new(h, 0.0, 0.0)), along(Vec3::new(0.0, h, 0.0)), along(Vec3::new(0.0, 0.0, h)));
+        if gradient.near_zero() { Vec3::new(0.0, 1.0, 0.0) } else { gradient.unit_vector() }
+    }
+}
+
+impl Hittable for SdfObject {
+    // the uvs wrap around the bounds' center like a sphere's
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let (start, end) = self.bounds.hit_range(ray, t_min, t_max)?;
+        let speed = ray.direction.length();
+        let mut t = start;
+        let mut steps = 0;
+
+        // coming in from outside the bounds is coming in from outside the
+        // shape. a ray starting inside them might be leaving the surface, in
+        // which case it starts within epsilon of it, so first move off it to
+        // find out which side the ray is heading into. inside (e.g. refracted
+        // into glass) the surface is where the distance comes back up to 0
+        let mut side = 1.0;
+        if start <= t_min {
+            let mut distance = (self.distance)(&ray.at(t));
+            while distance.abs() < self.epsilon {
+                t += self.epsilon / speed;
+                steps += 1;
+                if t > end || steps >= self.max_steps {
+                    return None
+                }
+                distance = (self.distance)(&ray.at(t));
+            }
+            side = distance.signum();
+        }
+
+        loop {
+            let distance = side * (self.distance)(&ray.at(t));
+            if distance < self.epsilon {
+                break
+            }
+            t += distance / speed;
+            steps += 1;
+            if t > end || steps >= self.max_steps {
+                return None
+            }
+        }
+
+        let point = ray.at(t);
+        let outward_normal = self.normal(&point);
+        let center = (self.bounds.minimum + self.bounds.maximum) * 0.5;
+        let (u, v) = if (point - center).near_zero() { (0.0, 0.0) } else { Sphere::get_sphere_uv((point - center).unit_vector()) };
+        let mut record = HitRecord::new(point, outward_normal, t, u, v, false, &self.material);
+        record.set_face_normal(ray, &outward_normal);
+        Some(record)
+    }
+
+    fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<AABB> {
+        Some(self.bounds)
+    }
+
+    fn materials_mut(&mut self) -> Vec<&mut Arc<Material>> {
+        vec![&mut self.material]
+    }
+}
+
+// some distance functions to build shapes from
+
+pub fn sphere_distance(point: &Vec3, center: &Vec3, radius: f64) -> f64 {
+    (*point - *center).length() - radius
+}
+
+// a ring lying flat around the y axis. major is the ring's radius, minor the tube's
+pub fn torus_distance(point: &Vec3, center: &Vec3, major: f64, minor: f64) -> f64 {
+    let offset = *point - *center;
+    let around = (offset.x() * offset.x() + offset.z() * offset.z()).sqrt() - major;
+    (around * around + offset.y() * offset.y()).sqrt() - minor
+}
+
+// the union of two shapes (min of their distances) with the seam rounded off
+// over about smoothness units, so they melt into each other
+pub fn smooth_min(a: f64, b: f64, smoothness: f64) -> f64 {
+    if smoothness <= 0.0 {
+        return a.min(b)
+    }
+    let h = (0.5 + 0.5 * (b - a) / smoothness).clamp(0.0, 1.0);
+    b * (1.0 - h) + a * h - smoothness * h * (1.0 - h)
+}
+
+// the power 8 mandelbulb fractal, about 1.2 across around the origin. more
+// iterations give finer detail (and slower renders)
+pub fn mandelbulb_distance(point: &Vec3, iterations: u32) -> f64 {
+    let power = 8.0;
+    let mut z = *point;
+    let mut derivative = 1.0;
+    let mut radius = z.length();
+    for _ in 0..iterations {
+        if radius > 2.0 {
+            break
+        }
+        // z -> z^power + point, in spherical coordinates
+        let theta = (z.z() / radius).acos() * power;
+        let phi = z.y().atan2(z.x()) * power;
+        derivative = power * radius.powf(power - 1.0) * derivative + 1.0;
+        let scaled = radius.powf(power);
+        z = Vec3::new(theta.sin() * phi.cos(), theta.sin() * phi.sin(), theta.cos()) * scaled + *point;
+        radius = z.length();
+    }
+    0.5 * radius.ln() * radius / derivative
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sdf_sphere_matches_sphere() {
+        let glass = Material::Dielectric{index_of_refraction: 1.5, absorption: Color::new(0.0, 0.0, 0.0)};
+        let center = Vec3::new(0.0, 1.0, 0.0);
+        let bounds = AABB::new(Vec3::new(-1.0, 0.0, -1.0), Vec3::new(1.0, 2.0, 1.0));
+        let ball = SdfObject::new(move |point: &Vec3| sphere_distance(point, &center, 1.0), bounds, glass).with_epsilon(1e-6);
+
+        let ray = Ray::new(Vec3::new(0.0, 1.0, 5.0), Vec3::new(0.0, 0.0, -2.0), None);
+        let record = ball.hit(&ray, 0.001, f64::INFINITY).unwrap();
+        assert!((record.point - Vec3::new(0.0, 1.0, 1.0)).length() < 1e-5 && record.front_face, "{:?}", record.point);
+        assert!((record.normal - Vec3::new(0.0, 0.0, 1.0)).length() < 1e-4, "{:?}", record.normal);
+
+        // from the surface into it (refracted), it finds the way out on the far side
+        let inside = Ray::new(record.point, Vec3::new(0.0, 0.0, -1.0), None);
+        let exit = ball.hit(&inside, 1e-9, f64::INFINITY).unwrap();
+        assert!((exit.point.z() + 1.0).abs() < 1e-5 && !exit.front_face, "{:?}", exit.point);
+
+        assert!(ball.hit(&Ray::new(Vec3::new(1.5, 1.0, 5.0), Vec3::new(0.0, 0.0, -1.0), None), 0.001, f64::INFINITY).is_none());
+        // blending two shapes never leaves a gap between them
+        assert!(smooth_min(0.3, 0.3, 0.5) < 0.3 && smooth_min(0.1, 5.0, 0.5) == 0.1);
+    }
+}
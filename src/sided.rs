@@ -0,0 +1,97 @@
+use crate::vec3::*;
+use crate::Ray;
+use crate::hittable::*;
+use crate::material::Material;
+use crate::texture::SolidTexture;
+use crate::aabb::AABB;
+use crate::export::ExportMesh;
+use std::sync::Arc;
+
+// every surface is double sided by default: the back of a wall shades the same
+// as its front, and a light panel glows both ways. wrapping objects in a
+// OneSided changes what happens when a ray reaches their back instead
+
+// the distance a ray moves past a culled back face before looking again, so it
+// doesn't find the same face
+const CULL_OFFSET: f64 = 1e-7;
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum BackFace {
+    // back faces aren't there at all, rays (and shadows) go straight through
+    Cull,
+    // back faces absorb everything and give off nothing, so e.g. a light panel
+    // only lights what's in front of it
+    Black
+}
+
+pub struct OneSided {
+    object: Box<dyn Hittable>,
+    back_face: BackFace,
+    black: Material
+}
+
+impl OneSided {
+    pub fn new(object: impl Hittable + 'static, back_face: BackFace) -> OneSided {
+        OneSided {
+            object: Box::new(object),
+            back_face,
+            black: Material::DiffuseLight{emit: Box::new(SolidTexture::new(Color::new(0.0, 0.0, 0.0)))}
+        }
+    }
+}
+
+impl Hittable for OneSided {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let mut t_min = t_min;
+        loop {
+            let mut record = self.object.hit(ray, t_min, t_max)?;
+            if record.front_face {
+                return Some(record)
+            }
+            match self.back_face {
+                // keep looking behind it, e.g. for the far side of a sphere
+                BackFace::Cull => t_min = record.t + CULL_OFFSET,
+                BackFace::Black => {
+                    record.material = &self.black;
+                    return Some(record)
+                }
+            }
+        }
+    }
+
+    fn bounding_box(&self, t0: f64, t1: f64) -> Option<AABB> {
+        self.object.bounding_box(t0, t1)
+    }
+
+    fn tessellate(&self) -> Vec<ExportMesh> {
+        self.object.tessellate()
+    }
+
+    fn materials_mut(&mut self) -> Vec<&mut Arc<Material>> {
+        self.object.materials_mut()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rect::AxisAlignedRect;
+    use crate::material::MaterialScattering;
+
+    #[test]
+    fn test_back_faces() {
+        let panel = || AxisAlignedRect::xy((-1.0, 1.0), (-1.0, 1.0), 0.0, Material::DiffuseLight{emit: Box::new(SolidTexture::new(Color::new(4.0, 4.0, 4.0)))});
+        let from_front = Ray::new(Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 0.0, -1.0), None);
+        let from_back = Ray::new(Vec3::new(0.0, 0.0, -1.0), Vec3::new(0.0, 0.0, 1.0), None);
+
+        let culled = OneSided::new(panel(), BackFace::Cull);
+        assert!(culled.hit(&from_front, 1e-3, f64::INFINITY).is_some());
+        assert!(culled.hit(&from_back, 1e-3, f64::INFINITY).is_none());
+
+        let black = OneSided::new(panel(), BackFace::Black);
+        let front = black.hit(&from_front, 1e-3, f64::INFINITY).unwrap();
+        assert_eq!(front.material.emitted(&front).x(), 4.0);
+        let back = black.hit(&from_back, 1e-3, f64::INFINITY).unwrap();
+        assert!(back.material.emitted(&back).near_zero() && back.material.scatter(&from_back, &back).is_none());
+    }
+}
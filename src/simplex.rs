@@ -0,0 +1,145 @@
+use crate::Vec3;
+use crate::perlin::Noise;
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+
+// simplex noise (Ken Perlin's successor to his original noise, following
+// Stefan Gustavson's "Simplex noise demystified"). it blends between the
+// corners of simplices (tetrahedra in 3d) instead of cubes, which hides the
+// grid: perlin noise's axis aligned streaks show up on big surfaces like a
+// ground sphere, simplex noise's don't. it also extends cheaply to 4d, where
+// the 4th dimension is time, so an animated texture changes smoothly from one
+// frame to the next instead of sliding through 3d noise
+
+const GRADIENTS_3: [[f64; 3]; 12] = [
+    [1.0, 1.0, 0.0], [-1.0, 1.0, 0.0], [1.0, -1.0, 0.0], [-1.0, -1.0, 0.0],
+    [1.0, 0.0, 1.0], [-1.0, 0.0, 1.0], [1.0, 0.0, -1.0], [-1.0, 0.0, -1.0],
+    [0.0, 1.0, 1.0], [0.0, -1.0, 1.0], [0.0, 1.0, -1.0], [0.0, -1.0, -1.0]
+];
+
+// the midpoints of the edges of a 4d hypercube
+const GRADIENTS_4: [[f64; 4]; 32] = [
+    [0.0, 1.0, 1.0, 1.0], [0.0, 1.0, 1.0, -1.0], [0.0, 1.0, -1.0, 1.0], [0.0, 1.0, -1.0, -1.0],
+    [0.0, -1.0, 1.0, 1.0], [0.0, -1.0, 1.0, -1.0], [0.0, -1.0, -1.0, 1.0], [0.0, -1.0, -1.0, -1.0],
+    [1.0, 0.0, 1.0, 1.0], [1.0, 0.0, 1.0, -1.0], [1.0, 0.0, -1.0, 1.0], [1.0, 0.0, -1.0, -1.0],
+    [-1.0, 0.0, 1.0, 1.0], [-1.0, 0.0, 1.0, -1.0], [-1.0, 0.0, -1.0, 1.0], [-1.0, 0.0, -1.0, -1.0],
+    [1.0, 1.0, 0.0, 1.0], [1.0, 1.0, 0.0, -1.0], [1.0, -1.0, 0.0, 1.0], [1.0, -1.0, 0.0, -1.0],
+    [-1.0, 1.0, 0.0, 1.0], [-1.0, 1.0, 0.0, -1.0], [-1.0, -1.0, 0.0, 1.0], [-1.0, -1.0, 0.0, -1.0],
+    [1.0, 1.0, 1.0, 0.0], [1.0, 1.0, -1.0, 0.0], [1.0, -1.0, 1.0, 0.0], [1.0, -1.0, -1.0, 0.0],
+    [-1.0, 1.0, 1.0, 0.0], [-1.0, 1.0, -1.0, 0.0], [-1.0, -1.0, 1.0, 0.0], [-1.0, -1.0, -1.0, 0.0]
+];
+
+pub struct Simplex {
+    // a shuffle of 0..256 twice over, so lookups can add offsets without wrapping
+    perms: Vec<usize>
+}
+
+impl Simplex {
+    pub fn new(seed: u64) -> Simplex {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut perms: Vec<usize> = (0..256).collect();
+        for i in (0..perms.len()).rev() {
+            let target = rng.gen_range(0..i + 1);
+            perms.swap(i, target);
+        }
+        perms.extend_from_within(..);
+        Simplex {
+            perms
+        }
+    }
+
+    // about -1 to 1
+    pub fn noise(&self, point: &Vec3) -> f64 {
+        // skew space so the simplices become cubes, to find the point's cube
+        let skew = (point.x() + point.y() + point.z()) / 3.0;
+        let cell = [(point.x() + skew).floor(), (point.y() + skew).floor(), (point.z() + skew).floor()];
+        let unskew = (cell[0] + cell[1] + cell[2]) / 6.0;
+        let offset = [point.x() - cell[0] + unskew, point.y() - cell[1] + unskew, point.z() - cell[2] + unskew];
+
+        // which of the cube's 6 tetrahedra the point is in: the corners are
+        // reached by stepping along the axes from the largest offset down
+        let mut order = [0, 1, 2];
+        order.sort_by(|a, b| offset[*b].total_cmp(&offset[*a]));
+        let mut corner = [0usize; 3];
+        let mut corners = [[0usize; 3]; 4];
+        for (step, axis) in order.iter().enumerate() {
+            corner[*axis] = 1;
+            corners[step + 1] = corner;
+        }
+
+        let cell = [cell[0] as i64 & 255, cell[1] as i64 & 255, cell[2] as i64 & 255].map(|c| c as usize);
+        let mut total = 0.0;
+        for (n, corner) in corners.iter().enumerate() {
+            let d: [f64; 3] = std::array::from_fn(|axis| offset[axis] - corner[axis] as f64 + n as f64 / 6.0);
+            let falloff = 0.6 - d[0] * d[0] - d[1] * d[1] - d[2] * d[2];
+            if falloff > 0.0 {
+                let p = &self.perms;
+                let gradient = GRADIENTS_3[p[cell[0] + corner[0] + p[cell[1] + corner[1] + p[cell[2] + corner[2]]]] % 12];
+                total += falloff.powi(4) * (gradient[0] * d[0] + gradient[1] * d[1] + gradient[2] * d[2]);
+            }
+        }
+        32.0 * total
+    }
+
+    // noise over space and time, about -1 to 1
+    pub fn noise4(&self, point: &Vec3, time: f64) -> f64 {
+        let sqrt_5 = 5.0_f64.sqrt();
+        let (skew_factor, unskew_factor) = ((sqrt_5 - 1.0) / 4.0, (5.0 - sqrt_5) / 20.0);
+        let position = [point.x(), point.y(), point.z(), time];
+        let skew = position.iter().sum::<f64>() * skew_factor;
+        let cell = position.map(|x| (x + skew).floor());
+        let unskew = cell.iter().sum::<f64>() * unskew_factor;
+        let offset: [f64; 4] = std::array::from_fn(|axis| position[axis] - cell[axis] + unskew);
+
+        // same as 3d, the largest offset is stepped along first
+        let mut order = [0, 1, 2, 3];
+        order.sort_by(|a, b| offset[*b].total_cmp(&offset[*a]));
+        let mut corner = [0usize; 4];
+        let mut corners = [[0usize; 4]; 5];
+        for (step, axis) in order.iter().enumerate() {
+            corner[*axis] = 1;
+            corners[step + 1] = corner;
+        }
+
+        let cell = cell.map(|c| (c as i64 & 255) as usize);
+        let mut total = 0.0;
+        for (n, corner) in corners.iter().enumerate() {
+            let d: [f64; 4] = std::array::from_fn(|axis| offset[axis] - corner[axis] as f64 + n as f64 * unskew_factor);
+            let falloff = 0.6 - d.iter().map(|x| x * x).sum::<f64>();
+            if falloff > 0.0 {
+                let p = &self.perms;
+                let hash = p[cell[0] + corner[0] + p[cell[1] + corner[1] + p[cell[2] + corner[2] + p[cell[3] + corner[3]]]]];
+                let gradient = GRADIENTS_4[hash % 32];
+                total += falloff.powi(4) * (0..4).map(|axis| gradient[axis] * d[axis]).sum::<f64>();
+            }
+        }
+        27.0 * total
+    }
+}
+
+impl Noise for Simplex {
+    fn noise_at(&self, point: &Vec3, time: f64) -> f64 {
+        self.noise4(point, time)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_simplex_range_and_continuity() {
+        let simplex = Simplex::new(3);
+        let mut largest: f64 = 0.0;
+        for i in 0..2000 {
+            let point = Vec3::new(i as f64 * 0.173, i as f64 * -0.091, 2.0 + i as f64 * 0.057);
+            let (value, animated) = (simplex.noise(&point), simplex.noise4(&point, i as f64 * 0.031));
+            assert!(value.abs() <= 1.0 && animated.abs() <= 1.0, "{} {}", value, animated);
+            largest = largest.max(value.abs()).max(animated.abs());
+            // a small step in time is a small change
+            assert!((simplex.noise4(&point, 0.5) - simplex.noise4(&point, 0.501)).abs() < 0.05);
+        }
+        // it isn't all squashed near 0 either
+        assert!(largest > 0.5, "{}", largest);
+    }
+}
@@ -0,0 +1,166 @@
+use crate::vec3::*;
+use crate::light::Light;
+use std::f64::consts::PI;
+
+// a clear sky lit by the sun, from a.j. preetham, p. shirley and b. smits, "a
+// practical analytic model for daylight" (1999). a fit to simulated skies gives
+// the brightness and colour of any direction from how far it is from the
+// zenith and from the sun, for a sun anywhere from overhead down to the horizon
+// and a turbidity (haze) from about 2 (very clear) to 10 (hazy). low suns give
+// orange horizons and hazy ones a whiter sky.
+// radiance comes out in kcd/m^2 times scale, which by default makes a white
+// surface under the midday sun and sky come out about 1 (a real exposure is the same
+// idea). the sun itself isn't in the sky's radiance, it's a directional light
+// (see sun) so every hit finds it rather than the odd bounce hitting its disc
+
+// perez et al's formula for how brightness varies over the sky, relative to
+// the zenith. theta is the angle from the zenith, gamma from the sun
+struct Perez {
+    a: f64,
+    b: f64,
+    c: f64,
+    d: f64,
+    e: f64
+}
+
+impl Perez {
+    fn new(turbidity: f64, coefficients: [[f64; 2]; 5]) -> Perez {
+        let [a, b, c, d, e] = coefficients.map(|[slope, offset]| slope * turbidity + offset);
+        Perez {a, b, c, d, e}
+    }
+
+    fn value(&self, cos_theta: f64, gamma: f64) -> f64 {
+        (1.0 + self.a * (self.b / cos_theta).exp()) * (1.0 + self.c * (self.d * gamma).exp() + self.e * gamma.cos().powi(2))
+    }
+}
+
+// what's left of the sun's light after it comes through air_mass times the
+// atmosphere overhead, per channel (at 680, 550 and 440nm): rayleigh scattering
+// by the air and mie scattering by haze, both stronger for blue
+fn transmittance(turbidity: f64, air_mass: f64) -> Color {
+    let beta = 0.04608 * turbidity - 0.04586;
+    let through = |wavelength: f64| {
+        let rayleigh = 0.008735 * wavelength.powf(-4.08);
+        let aerosol = beta * wavelength.powf(-1.3);
+        (-air_mass * (rayleigh + aerosol)).exp()
+    };
+    Color::new(through(0.68), through(0.55), through(0.44))
+}
+
+// the sun's illuminance above the atmosphere, in klux
+const SOLAR_ILLUMINANCE: f64 = 128.0;
+
+pub struct PreethamSky {
+    // towards the sun, unit length
+    sun_direction: Vec3,
+    turbidity: f64,
+    scale: f64,
+    // the zenith's luminance (kcd/m^2) and chromaticity, and the formula for
+    // each relative to it
+    zenith: (f64, f64, f64),
+    perez: [Perez; 3]
+}
+
+impl PreethamSky {
+    pub fn new(sun_direction: Vec3, turbidity: f64) -> PreethamSky {
+        if sun_direction.near_zero() || sun_direction.y() < 0.0 {
+            panic!("The sun has to be above the horizon, got {:?}", sun_direction);
+        }
+        if !(1.0..=20.0).contains(&turbidity) {
+            panic!("Turbidity has to be between 1 and 20, got {}", turbidity);
+        }
+        let sun_direction = sun_direction.unit_vector();
+        let t = turbidity;
+        let theta = sun_direction.y().clamp(0.0, 1.0).acos();
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta);
+        let luminance = ((4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192).max(0.0);
+        let chromaticity = |m: [[f64; 4]; 3]| {
+            let thetas = [theta.powi(3), theta * theta, theta, 1.0];
+            let row = |r: [f64; 4]| r.iter().zip(thetas.iter()).map(|(a, b)| a * b).sum::<f64>();
+            t * t * row(m[0]) + t * row(m[1]) + row(m[2])
+        };
+        let x = chromaticity([[0.00166, -0.00375, 0.00209, 0.0], [-0.02903, 0.06377, -0.03202, 0.00394], [0.11693, -0.21196, 0.06052, 0.25886]]);
+        let y = chromaticity([[0.00275, -0.00610, 0.00317, 0.0], [-0.04214, 0.08970, -0.04153, 0.00516], [0.15346, -0.26756, 0.06670, 0.26688]]);
+
+        PreethamSky {
+            sun_direction,
+            turbidity,
+            scale: 1.0 / 40.0,
+            zenith: (luminance, x, y),
+            perez: [
+                Perez::new(t, [[0.1787, -1.4630], [-0.3554, 0.4275], [-0.0227, 5.3251], [0.1206, -2.5771], [-0.0670, 0.3703]]),
+                Perez::new(t, [[-0.0193, -0.2592], [-0.0665, 0.0008], [-0.0004, 0.2125], [-0.0641, -0.8989], [-0.0033, 0.0452]]),
+                Perez::new(t, [[-0.0167, -0.2608], [-0.0950, 0.0092], [-0.0079, 0.2102], [-0.0441, -1.6537], [-0.0109, 0.0529]])
+            ]
+        }
+    }
+
+    // multiplies the sky's and sun's brightness, like an exposure
+    pub fn with_scale(mut self, scale: f64) -> PreethamSky {
+        self.scale = scale;
+        self
+    }
+
+    // the light coming from the given direction (doesn't need to be a unit
+    // vector). below the horizon is the horizon, as if the ground were a mirror
+    pub fn radiance(&self, direction: &Vec3) -> Color {
+        let direction = direction.unit_vector();
+        let cos_theta = direction.y().max(1e-3);
+        let gamma = direction.dot_product(&self.sun_direction).clamp(-1.0, 1.0).acos();
+        let sun_theta = self.sun_direction.y().clamp(0.0, 1.0).acos();
+        let relative = |perez: &Perez| perez.value(cos_theta, gamma) / perez.value(1.0, sun_theta);
+
+        let (zenith_luminance, zenith_x, zenith_y) = self.zenith;
+        let luminance = zenith_luminance * relative(&self.perez[0]);
+        let x = zenith_x * relative(&self.perez[1]);
+        let y = zenith_y * relative(&self.perez[2]);
+        xyy_to_rgb(x, y, luminance) * self.scale
+    }
+
+    // the sun as a light, dimmed and reddened by the air it comes through
+    pub fn sun(&self) -> Light {
+        // kasten and young's air mass, which stays finite at the horizon
+        let elevation = self.sun_direction.y().clamp(0.0, 1.0).asin().to_degrees();
+        let air_mass = 1.0 / (self.sun_direction.y().max(0.0) + 0.50572 * (elevation + 6.07995).powf(-1.6364));
+        Light::Directional {
+            direction: self.sun_direction * -1.0,
+            irradiance: transmittance(self.turbidity, air_mass) * (SOLAR_ILLUMINANCE * self.scale)
+        }
+    }
+}
+
+// a colour given by its chromaticity (x, y) and luminance, to linear srgb
+fn xyy_to_rgb(x: f64, y: f64, luminance: f64) -> Color {
+    if y <= 0.0 {
+        return Color::new(0.0, 0.0, 0.0)
+    }
+    let (cx, cz) = (x / y * luminance, (1.0 - x - y) / y * luminance);
+    Color::new(
+        3.2406 * cx - 1.5372 * luminance - 0.4986 * cz,
+        -0.9689 * cx + 1.8758 * luminance + 0.0415 * cz,
+        0.0557 * cx - 0.2040 * luminance + 1.0570 * cz
+    ).max_components(&Color::new(0.0, 0.0, 0.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sky_colours() {
+        let noon = PreethamSky::new(Vec3::new(0.0, 1.0, 0.3), 3.0);
+        // blue overhead, brightest near the sun, brighter at the horizon than
+        // overhead away from it
+        let zenith = noon.radiance(&Vec3::new(0.0, 1.0, 0.0));
+        assert!(zenith.z() > zenith.x(), "{:?}", zenith);
+        assert!(noon.radiance(&Vec3::new(0.0, 1.0, 0.35)).y() > noon.radiance(&Vec3::new(0.0, 1.0, -1.0)).y());
+        assert!(noon.radiance(&Vec3::new(0.0, 0.05, -1.0)).y() > noon.radiance(&Vec3::new(0.0, 1.0, -1.0)).y());
+
+        // the midday sun is white and a lot brighter than the sky, a setting sun is dim and orange
+        let Light::Directional{irradiance: midday, ..} = noon.sun() else { panic!() };
+        let Light::Directional{irradiance: evening, ..} = PreethamSky::new(Vec3::new(1.0, 0.02, 0.0), 3.0).sun() else { panic!() };
+        assert!(midday.z() > 0.6 * midday.x() && midday.y() > PI * zenith.y(), "{:?} {:?}", midday, zenith);
+        assert!(evening.x() > 2.0 * evening.z() && evening.y() < 0.5 * midday.y(), "{:?}", evening);
+    }
+}
@@ -0,0 +1,116 @@
+use crate::vec3::Color;
+use crate::checkpoint::parse_duration;
+use crate::restart::RenderState;
+use std::fs::File;
+use std::io::{BufWriter, Result, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+// progressive rendering: instead of finishing each pixel before moving on,
+// the whole image is rendered a few samples per pixel at a time (a pass), so
+// it's all there, noisy, early on and gets cleaner with every pass. a snapshot
+// of the render so far is written every so often to look at, and the render
+// can be stopped once it's good enough
+
+// how many samples each pixel should have after each pass, per_pass at a time
+// up to samples_per_pixel
+pub fn pass_targets(samples_per_pixel: u64, per_pass: u64) -> Vec<u64> {
+    if per_pass == 0 {
+        panic!("A pass needs at least one sample per pixel");
+    }
+    let mut targets: Vec<u64> = (1..).map(|pass| pass * per_pass).take_while(|&target| target < samples_per_pixel).collect();
+    targets.push(samples_per_pixel);
+    targets
+}
+
+pub enum SnapshotInterval {
+    Passes(u64),
+    Time(Duration)
+}
+
+impl SnapshotInterval {
+    // "4" is every 4 passes, "30s" (or "5m"...) every 30 seconds
+    pub fn parse(text: &str) -> std::result::Result<SnapshotInterval, String> {
+        if let Ok(passes) = text.parse::<u64>() {
+            if passes == 0 {
+                return Err("Snapshots have to be at least 1 pass apart".to_string())
+            }
+            return Ok(SnapshotInterval::Passes(passes))
+        }
+        parse_duration(text).map(SnapshotInterval::Time)
+    }
+}
+
+// writes the render so far to the same file every interval
+pub struct SnapshotSchedule {
+    path: PathBuf,
+    interval: SnapshotInterval,
+    last: Instant,
+    passes: u64
+}
+
+impl SnapshotSchedule {
+    pub fn new(path: &Path, interval: SnapshotInterval) -> SnapshotSchedule {
+        SnapshotSchedule {
+            path: path.to_path_buf(),
+            interval,
+            last: Instant::now(),
+            passes: 0
+        }
+    }
+
+    // called after every pass, true if it's time for a snapshot
+    pub fn pass_done(&mut self) -> bool {
+        self.passes += 1;
+        match self.interval {
+            SnapshotInterval::Passes(passes) => self.passes.is_multiple_of(passes),
+            SnapshotInterval::Time(interval) => self.last.elapsed() >= interval
+        }
+    }
+
+    // writes state as a ppm, with each pixel's average turned into 8 bits by
+    // rgb8 (given the pixel's x and y too). it's written next to the path
+    // first and moved over it, so something watching the file never sees half an image
+    pub fn write(&mut self, state: &RenderState, rgb8: impl Fn(Color, u32, u32) -> [u8; 3]) -> Result<()> {
+        let partial = self.path.with_extension("partial");
+        let mut output = BufWriter::new(File::create(&partial)?);
+        writeln!(output, "P3\n{0} {1}\n255", state.width, state.height)?;
+        for (pixel, estimate) in state.pixels.iter().enumerate() {
+            let (x, y) = (pixel as u32 % state.width, pixel as u32 / state.width);
+            let [r, g, b] = rgb8(estimate.colour(), x, y);
+            writeln!(output, "{0} {1} {2}", r, g, b)?;
+        }
+        output.flush()?;
+        drop(output);
+        std::fs::rename(&partial, &self.path)?;
+        self.last = Instant::now();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_passes_and_snapshots() {
+        assert_eq!(pass_targets(20, 8), vec![8, 16, 20]);
+        assert_eq!(pass_targets(16, 8), vec![8, 16]);
+        assert_eq!(pass_targets(4, 8), vec![4]);
+
+        let mut every_other = SnapshotSchedule::new(Path::new("unused.ppm"), SnapshotInterval::parse("2").unwrap());
+        let due: Vec<bool> = (0..4).map(|_| every_other.pass_done()).collect();
+        assert_eq!(due, vec![false, true, false, true]);
+        assert!(matches!(SnapshotInterval::parse("30s"), Ok(SnapshotInterval::Time(_))));
+        assert!(SnapshotInterval::parse("0").is_err());
+
+        let path = std::env::temp_dir().join(format!("rays-snapshot-{}.ppm", std::process::id()));
+        let mut state = RenderState::new(2, 1);
+        state.pixel_mut(1, 0).add(Color::new(1.0, 0.5, 0.0));
+        SnapshotSchedule::new(&path, SnapshotInterval::Passes(1)).write(&state, |colour: Color, _, _| colour.rgb8(1)).unwrap();
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(text.starts_with("P3\n2 1\n255\n0 0 0\n"), "{}", text);
+        assert_eq!(text.lines().count(), 5);
+    }
+}
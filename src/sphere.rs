@@ -3,34 +3,65 @@ use crate::Ray;
 use crate::hittable::*;
 use crate::material::*;
 use crate::aabb::AABB;
-use crate::utilities::PI;
+use crate::utilities::{PI, clamp};
+use crate::export::*;
+use std::sync::Arc;
 
 pub struct Sphere {
     center: Vec3,
     radius: f64,
-    material: Material
+    material: Arc<Material>,
+    // turns the texture around the y axis, as a fraction of a full turn
+    uv_rotation: f64
 }
 
 impl Sphere {
-    pub fn new(center: Vec3, radius: f64, material: Material) -> Sphere {
+    // takes a Material, or an Arc<Material> to share one between many objects
+    pub fn new(center: Vec3, radius: f64, material: impl Into<Arc<Material>>) -> Sphere {
         Sphere {
             center,
             radius,
-            material
+            material: material.into(),
+            uv_rotation: 0.0
         }
     }
 
+    pub fn material(&self) -> &Arc<Material> {
+        &self.material
+    }
+
+    // swaps the material after the scene is built, e.g. for look development
+    pub fn set_material(&mut self, material: impl Into<Arc<Material>>) {
+        self.material = material.into();
+    }
+
+    // rotates the texture mapping around the y axis (e.g. to turn a globe to
+    // face the camera) without moving the sphere
+    pub fn with_uv_rotation(mut self, degrees: f64) -> Sphere {
+        self.uv_rotation = degrees / 360.0;
+        self
+    }
+
     // convert the point from cartesian to spherical coordinates
     // point: a point on a unit sphere centered at the origin
     pub fn get_sphere_uv(point: Vec3) -> (f64, f64) {
-        // represents the angle from the south pole upwards (-Y to +Y)
-        let theta = f64::acos(point.y() * -1.0);
+        // represents the angle from the south pole upwards (-Y to +Y).
+        // rounding can push y slightly past +-1 at the poles, which acos turns into NaN
+        let theta = f64::acos(clamp(-point.y(), -1.0, 1.0));
         // represents the value from -X to +X (-X -> +Z -> +X -> -Z -> -X)
-        let phi = (-1.0 * point.z()).atan2(point.x()) + PI;
+        let phi = (-point.z()).atan2(point.x()) + PI;
         // returning (u, v) where:
         // u is [0, 1], value of angle around y axis
         // v is [0, 1] value of angle from south to north pole (-Y to +Y)
-        (phi / (2.0 * PI), theta / PI)
+        // phi can come out as exactly 2 * PI, which is the same place as u = 0
+        let u = phi / (2.0 * PI);
+        (if u >= 1.0 { u - 1.0 } else { u }, theta / PI)
+    }
+
+    // the direction u grows in at a point on a unit sphere, i.e. the derivative
+    // of the point with respect to phi in get_sphere_uv (zero at the poles)
+    pub fn get_sphere_tangent(point: Vec3) -> Vec3 {
+        Vec3::new(point.z(), 0.0, -point.x())
     }
 
     // using an (optimized) quadratic formula (let b = 2h so the '2a' becomes an 'a' etc.)
@@ -59,26 +90,22 @@ impl Sphere {
 }
 
 impl Hittable for Sphere {
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
         let origin_to_center = ray.origin - self.center;
         // using an (optimized) quadratic formula (let b = 2h so the '2a' becomes an 'a')
         // to see if ray intersects sphere
         let a: f64 = ray.direction.length_squared();
         let half_b: f64 = origin_to_center.dot_product(&ray.direction);
         let c: f64 = origin_to_center.length_squared() - self.radius * self.radius;
-        let root = Sphere::get_first_root_in_range(a, half_b, c, t_min, t_max);
-        match root {
-            None => return None,
-            Some(_) => ()
-        }
-
-        let t = root.unwrap();
+        let t = Sphere::get_first_root_in_range(a, half_b, c, t_min, t_max)?;
         let point = ray.at(t);
         let outward_normal = (point - self.center) / self.radius;
         let (u, v): (f64, f64) = Sphere::get_sphere_uv(outward_normal);
+        let u = (u + self.uv_rotation).rem_euclid(1.0);
         let mut record = HitRecord::new(point, outward_normal, t, u, v, false, &self.material);
         // adjust normal so that it's always pointing away from the ray
         record.set_face_normal(ray, &outward_normal);
+        record.tangent = Some(Sphere::get_sphere_tangent(outward_normal));
         Some(record)
     }
 
@@ -86,4 +113,12 @@ impl Hittable for Sphere {
         let radius = Vec3::new(self.radius, self.radius, self.radius);
         Some(AABB::new(self.center - radius, self.center + radius))
     }
+
+    fn tessellate(&self) -> Vec<ExportMesh> {
+        vec![ExportMesh::uv_sphere(self.center, self.radius, ExportMaterial::from_material(&self.material))]
+    }
+
+    fn materials_mut(&mut self) -> Vec<&mut Arc<Material>> {
+        vec![&mut self.material]
+    }
 }
\ No newline at end of file
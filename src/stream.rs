@@ -0,0 +1,82 @@
+use rays::vec3::Color;
+use std::io::{Result, Write};
+
+// a small binary protocol for watching a render while it happens: pipe the
+// output of `rays --stream` into a viewer that understands it (or a bridge to
+// one, e.g. something that forwards tiles to tev over its socket).
+// everything is little endian:
+//
+//   header:  b"RAYS"  u32 version  u32 width  u32 height  u32 channels
+//   tile:    b'T'  u32 x  u32 y  u32 width  u32 height
+//            then width * height * channels f32s, row by row from the tile's top left
+//   end:     b'E'
+//
+// (0, 0) is the top left of the image. pixels are linear (no gamma, no clamping)
+// so the viewer can apply its own exposure. a pixel may be sent more than once,
+// the latest tile wins
+
+pub const MAGIC: &[u8; 4] = b"RAYS";
+pub const VERSION: u32 = 1;
+pub const CHANNELS: u32 = 3;
+
+pub struct StreamWriter<W: Write> {
+    output: W,
+    width: u32,
+    height: u32
+}
+
+impl<W: Write> StreamWriter<W> {
+    pub fn new(mut output: W, width: u32, height: u32) -> Result<StreamWriter<W>> {
+        output.write_all(MAGIC)?;
+        for value in [VERSION, width, height, CHANNELS] {
+            output.write_all(&value.to_le_bytes())?;
+        }
+        output.flush()?;
+        Ok(StreamWriter {
+            output,
+            width,
+            height
+        })
+    }
+
+    pub fn write_tile(&mut self, x: u32, y: u32, width: u32, height: u32, pixels: &[Color]) -> Result<()> {
+        if x + width > self.width || y + height > self.height || pixels.len() != (width * height) as usize {
+            panic!("Tile {}x{} at ({}, {}) doesn't fit the {}x{} image", width, height, x, y, self.width, self.height);
+        }
+        self.output.write_all(b"T")?;
+        for value in [x, y, width, height] {
+            self.output.write_all(&value.to_le_bytes())?;
+        }
+        for pixel in pixels {
+            for value in [pixel.x(), pixel.y(), pixel.z()] {
+                self.output.write_all(&(value as f32).to_le_bytes())?;
+            }
+        }
+        // the whole point is for the viewer to see it now
+        self.output.flush()
+    }
+
+    pub fn finish(mut self) -> Result<W> {
+        self.output.write_all(b"E")?;
+        self.output.flush()?;
+        Ok(self.output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stream_layout() {
+        let mut writer = StreamWriter::new(Vec::new(), 2, 1).unwrap();
+        writer.write_tile(1, 0, 1, 1, &[Color::new(1.0, 0.5, 0.25)]).unwrap();
+        let bytes = writer.finish().unwrap();
+        // header, tile header, 3 floats, end marker
+        assert_eq!(bytes.len(), 20 + 17 + 12 + 1);
+        assert_eq!(&bytes[0..4], MAGIC);
+        assert_eq!(bytes[20], b'T');
+        assert_eq!(f32::from_le_bytes([bytes[41], bytes[42], bytes[43], bytes[44]]), 0.5);
+        assert_eq!(*bytes.last().unwrap(), b'E');
+    }
+}
@@ -0,0 +1,117 @@
+use crate::vec3::*;
+use crate::Ray;
+use crate::hittable::*;
+use crate::material::Material;
+use crate::texture::SolidTexture;
+use crate::aabb::AABB;
+use crate::export::ExportMesh;
+use crate::utilities::{random_float, INFINITY};
+use std::sync::Arc;
+
+// subsurface scattering (skin, wax, marble, milk...) as a random walk.
+// the boundary's own material is the surface, usually a Dielectric so light
+// refracts in and out. inside, light travels a random distance (exponentially
+// distributed around the mean free path) before bouncing off in a random
+// direction, and keeps doing so until it finds its way out. a short mean free
+// path looks nearly opaque, a long one lets light glow through thin parts
+pub struct Subsurface {
+    boundary: Box<dyn Hittable>,
+    mean_free_path: f64,
+    medium: Material
+}
+
+// the colour a thick block of the medium looks under white light comes from
+// many bounces, each of which keeps a little less than the one before, so the
+// albedo of a single bounce has to be brighter than the colour wanted.
+// the fit is from "practical and controllable subsurface scattering for
+// production path tracing" (chiang et al., 2016)
+fn single_scattering_albedo(colour: Color) -> Color {
+    let invert = |a: f64| {
+        let a = a.clamp(0.0, 1.0);
+        let s = 4.09712 + 4.20863 * a - (9.59217 + 41.6808 * a + 17.7126 * a * a).sqrt();
+        (1.0 - s * s).clamp(0.0, 1.0)
+    };
+    Color::new(invert(colour.x()), invert(colour.y()), invert(colour.z()))
+}
+
+impl Subsurface {
+    // colour is roughly how the object looks, the mean free path (in scene
+    // units) how far light gets between bounces, i.e. how translucent it is
+    pub fn new(boundary: impl Hittable + 'static, colour: Color, mean_free_path: f64) -> Subsurface {
+        Subsurface {
+            boundary: Box::new(boundary),
+            mean_free_path,
+            medium: Material::Isotropic{albedo: Box::new(SolidTexture::new(single_scattering_albedo(colour)))}
+        }
+    }
+}
+
+impl Hittable for Subsurface {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        // looked up without t_max, since a ray inside needs to know where it
+        // would leave to tell whether it scatters before that
+        let surface = self.boundary.hit(ray, t_min, INFINITY)?;
+        // hitting the front is coming from outside, so the surface decides
+        if surface.front_face {
+            return if surface.t < t_max { Some(surface) } else { None }
+        }
+
+        let distance = -self.mean_free_path * (1.0 - random_float()).ln();
+        let t = (distance / ray.direction.length()).max(t_min);
+        if t >= surface.t {
+            return if surface.t < t_max { Some(surface) } else { None }
+        }
+        if t >= t_max {
+            return None
+        }
+        // the normal doesn't mean anything in a volume, it just faces the ray
+        let point = ray.at(t);
+        let normal = ray.direction.unit_vector() * -1.0;
+        Some(HitRecord::new(point, normal, t, 0.0, 0.0, true, &self.medium))
+    }
+
+    fn bounding_box(&self, t0: f64, t1: f64) -> Option<AABB> {
+        self.boundary.bounding_box(t0, t1)
+    }
+
+    // the medium can't be exported, only its surface
+    fn tessellate(&self) -> Vec<ExportMesh> {
+        self.boundary.tessellate()
+    }
+
+    fn materials_mut(&mut self) -> Vec<&mut Arc<Material>> {
+        self.boundary.materials_mut()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sphere::Sphere;
+
+    #[test]
+    fn test_walk_scatters_inside_boundary() {
+        let glass = || Material::Dielectric{index_of_refraction: 1.5, absorption: Color::new(0.0, 0.0, 0.0)};
+        let boundary = || Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0, glass());
+        let from_center = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0), None);
+
+        // dense: every ray scatters well before the surface
+        let dense = Subsurface::new(boundary(), Color::new(0.8, 0.8, 0.8), 1e-3);
+        for _ in 0..32 {
+            let record = dense.hit(&from_center, 1e-4, INFINITY).unwrap();
+            assert!(record.t < 0.1 && matches!(record.material, Material::Isotropic{..}));
+        }
+        // thin: every ray gets out
+        let thin = Subsurface::new(boundary(), Color::new(0.8, 0.8, 0.8), 1e6);
+        let record = thin.hit(&from_center, 1e-4, INFINITY).unwrap();
+        assert!((record.t - 1.0).abs() < 1e-9 && !record.front_face);
+        // from outside the surface comes first
+        let outside = Ray::new(Vec3::new(-3.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0), None);
+        assert!(dense.hit(&outside, 1e-4, INFINITY).unwrap().front_face);
+
+        // black stays black, white never loses anything
+        assert!(single_scattering_albedo(Color::new(0.0, 0.0, 0.0)).x().abs() < 1e-4);
+        assert!(single_scattering_albedo(Color::new(1.0, 1.0, 1.0)).x() > 0.9999);
+        assert!(single_scattering_albedo(Color::new(0.5, 0.5, 0.5)).x() > 0.5);
+    }
+}
@@ -0,0 +1,118 @@
+use std::io::{Result, Write};
+use std::net::TcpStream;
+
+// pushes images to a running tev viewer (https://github.com/Tom94/tev) over its
+// tcp protocol, so a render can be watched (and compared to earlier ones) as it
+// progresses. start tev first, it listens on 127.0.0.1:14158 by default.
+//
+// every packet is a u32 total length (including itself), a u8 type and a payload.
+// numbers are little endian and strings are null terminated. channels are named
+// like exr channels: "R", "G", "B" for the image and "<layer>.<channel>" for
+// extra layers (AOVs) shown in tev's layer list
+
+pub const DEFAULT_ADDRESS: &str = "127.0.0.1:14158";
+
+const CREATE_IMAGE: u8 = 4;
+const UPDATE_IMAGE: u8 = 6;
+
+fn push_string(packet: &mut Vec<u8>, value: &str) {
+    packet.extend_from_slice(value.as_bytes());
+    packet.push(0);
+}
+
+pub struct TevClient {
+    stream: TcpStream
+}
+
+impl TevClient {
+    pub fn connect(address: &str) -> Result<TevClient> {
+        let stream = TcpStream::connect(address)?;
+        stream.set_nodelay(true)?;
+        Ok(TevClient {
+            stream
+        })
+    }
+
+    fn send(&mut self, packet_type: u8, payload: &[u8]) -> Result<()> {
+        let length = (payload.len() + 5) as u32;
+        self.stream.write_all(&length.to_le_bytes())?;
+        self.stream.write_all(&[packet_type])?;
+        self.stream.write_all(payload)
+    }
+
+    // makes an empty (black) image, replacing any open image with the same name
+    pub fn create_image(&mut self, name: &str, width: u32, height: u32, channels: &[&str]) -> Result<()> {
+        let mut payload = Vec::new();
+        // don't steal focus from whatever image the user is looking at
+        payload.push(0);
+        push_string(&mut payload, name);
+        for value in [width as i32, height as i32, channels.len() as i32] {
+            payload.extend_from_slice(&value.to_le_bytes());
+        }
+        for channel in channels {
+            push_string(&mut payload, channel);
+        }
+        self.send(CREATE_IMAGE, &payload)
+    }
+
+    // replaces a width x height tile at (x, y) (the top left is (0, 0)).
+    // pixels are interleaved: all of the channels of a pixel, then the next pixel
+    #[allow(clippy::too_many_arguments)]
+    pub fn update_image(&mut self, name: &str, channels: &[&str], x: u32, y: u32, width: u32, height: u32, pixels: &[f32]) -> Result<()> {
+        if pixels.len() != (width * height) as usize * channels.len() {
+            panic!("Tile of {}x{} with {} channels needs {} values, got {}",
+                width, height, channels.len(), (width * height) as usize * channels.len(), pixels.len());
+        }
+        let mut payload = Vec::with_capacity(64 + pixels.len() * 4);
+        payload.push(0);
+        push_string(&mut payload, name);
+        payload.extend_from_slice(&(channels.len() as i32).to_le_bytes());
+        for channel in channels {
+            push_string(&mut payload, channel);
+        }
+        for value in [x as i32, y as i32, width as i32, height as i32] {
+            payload.extend_from_slice(&value.to_le_bytes());
+        }
+        // where each channel's first value is and how far apart its values are
+        for offset in 0..channels.len() {
+            payload.extend_from_slice(&(offset as i64).to_le_bytes());
+        }
+        for _ in 0..channels.len() {
+            payload.extend_from_slice(&(channels.len() as i64).to_le_bytes());
+        }
+        for value in pixels {
+            payload.extend_from_slice(&value.to_le_bytes());
+        }
+        self.send(UPDATE_IMAGE, &payload)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_packets_are_length_prefixed() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let mut client = TevClient::connect(&address).unwrap();
+        let (mut server, _) = listener.accept().unwrap();
+
+        client.create_image("render", 2, 1, &["R"]).unwrap();
+        client.update_image("render", &["R"], 0, 0, 2, 1, &[0.25, 0.5]).unwrap();
+        drop(client);
+        let mut received = Vec::new();
+        server.read_to_end(&mut received).unwrap();
+
+        // type, focus, name + null, width, height, channel count, "R" + null
+        let create_length = 4 + 1 + 1 + 7 + 12 + 2;
+        assert_eq!(u32::from_le_bytes([received[0], received[1], received[2], received[3]]) as usize, create_length);
+        assert_eq!(received[4], CREATE_IMAGE);
+        assert_eq!(received[create_length + 4], UPDATE_IMAGE);
+        let update_length = u32::from_le_bytes([received[create_length], received[create_length + 1], received[create_length + 2], received[create_length + 3]]) as usize;
+        assert_eq!(create_length + update_length, received.len());
+        assert_eq!(&received[received.len() - 4..], &0.5f32.to_le_bytes());
+    }
+}
@@ -1,8 +1,22 @@
 use crate::vec3::*;
-use crate::perlin::Perlin;
+use crate::perlin::{Noise, Perlin};
+use crate::worley::Worley;
+use crate::hittable::HitRecord;
+use crate::utilities::{clamp, degrees_to_radians};
+use crate::mapped::MappedFile;
+use crate::color_space::ColorSpace;
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+use std::sync::Arc;
 
-pub trait Texture {
+pub trait Texture: Send + Sync {
     fn value(&self, u: f64, v: f64, point: &Vec3) -> Color;
+
+    // the texture's colour where a ray hit. most textures only need the surface
+    // coordinates and point, but some (e.g. vertex colours) need more of the hit
+    fn value_at(&self, record: &HitRecord) -> Color {
+        self.value(record.u, record.v, &record.point)
+    }
 }
 
 pub struct SolidTexture {
@@ -15,6 +29,11 @@ impl SolidTexture {
             color_val: color
         }
     }
+
+    // the same value in every channel, for textures read as a single number
+    pub fn uniform(value: f64) -> SolidTexture {
+        SolidTexture::new(Color::new(value, value, value))
+    }
 }
 
 impl Texture for SolidTexture {
@@ -24,31 +43,60 @@ impl Texture for SolidTexture {
     }
 }
 
+// where a checker's squares come from
+#[derive(Copy, Clone, PartialEq)]
+pub enum CheckerMapping {
+    // the sign of sin(10x)sin(10y)sin(10z) at the hit point. needs no texture
+    // coordinates but the squares stretch and warp with the surface's curvature
+    Solid,
+    // tiles_u by tiles_v squares over the surface's (u, v), so they follow it
+    // (and move with it) like an image would
+    Uv{tiles_u: f64, tiles_v: f64}
+}
+
 pub struct CheckeredTexture {
     odd: Box<dyn Texture>,
-    even: Box<dyn Texture>
+    even: Box<dyn Texture>,
+    mapping: CheckerMapping
 }
 
 impl CheckeredTexture {
     pub fn new_with_texture(odd: impl Texture + 'static, even: impl Texture + 'static) -> CheckeredTexture {
         CheckeredTexture {
             odd: Box::new(odd),
-            even: Box::new(even)
+            even: Box::new(even),
+            mapping: CheckerMapping::Solid
         }
     }
 
     pub fn new_with_solid(odd: Color, even: Color) -> CheckeredTexture {
         CheckeredTexture {
             odd: Box::new(SolidTexture::new(odd)),
-            even: Box::new(SolidTexture::new(even))
+            even: Box::new(SolidTexture::new(even)),
+            mapping: CheckerMapping::Solid
         }
     }
+
+    // squares laid out over the texture coordinates instead, see CheckerMapping::Uv
+    pub fn with_uv_tiles(mut self, tiles_u: f64, tiles_v: f64) -> CheckeredTexture {
+        self.mapping = CheckerMapping::Uv{tiles_u, tiles_v};
+        self
+    }
 }
 
 impl Texture for CheckeredTexture {
     fn value(&self, u: f64, v: f64, point: &Vec3) -> Color {
-        let sines = f64::sin(10.0 * point.x()) * f64::sin(10.0 * point.y()) * f64::sin(10.0 * point.z());
-        if sines < 0.0 {
+        let odd = match self.mapping {
+            CheckerMapping::Solid => {
+                let sines = f64::sin(10.0 * point.x()) * f64::sin(10.0 * point.y()) * f64::sin(10.0 * point.z());
+                sines < 0.0
+            },
+            CheckerMapping::Uv{tiles_u, tiles_v} => {
+                let square = (u * tiles_u).floor() as i64 + (v * tiles_v).floor() as i64;
+                square.rem_euclid(2) == 1
+            }
+        };
+        if odd {
             self.odd.value(u, v, point)
         } else {
             self.even.value(u, v, point)            
@@ -56,27 +104,679 @@ impl Texture for CheckeredTexture {
     }
 }
 
+// the look a NoiseTexture makes of its noise
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum NoisePattern {
+    // turbulence bending sine stripes along z
+    Marble,
+    // the magnitude of the noise, a "net-like" web of dark lines
+    Net,
+    // the noise itself remapped to [0, 1], soft clouds
+    Smooth
+}
+
+// how the noise frequencies are layered
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Fbm {
+    pub octaves: i32,
+    // how much the frequency grows from one octave to the next
+    pub lacunarity: f64,
+    // how much the weight shrinks from one octave to the next
+    pub gain: f64
+}
+
+impl Default for Fbm {
+    fn default() -> Fbm {
+        Fbm {
+            octaves: 7,
+            lacunarity: 2.0,
+            gain: 0.5
+        }
+    }
+}
+
 pub struct NoiseTexture {
-    noise: Perlin,
-    frequency: f64
+    noise: Arc<dyn Noise>,
+    frequency: f64,
+    pattern: NoisePattern,
+    fbm: Fbm
 }
 
 impl NoiseTexture {
-    pub fn new(frequency: f64) -> NoiseTexture {
+    pub fn new(noise: Arc<dyn Noise>, frequency: f64, pattern: NoisePattern, fbm: Fbm) -> NoiseTexture {
         NoiseTexture {
-            noise: Perlin::new(),
-            frequency
+            noise,
+            frequency,
+            pattern,
+            fbm
         }
-    }    
+    }
+
+    fn fbm(&self, point: &Vec3, time: f64) -> f64 {
+        self.noise.fbm_at(point, time, self.fbm.octaves, self.fbm.lacunarity, self.fbm.gain)
+    }
+
+    // the texture at point, time seconds into an animation. generators with
+    // a time dimension (e.g. simplex) change smoothly from frame to frame
+    fn value_in_time(&self, point: &Vec3, time: f64) -> Color {
+        let value = match self.pattern {
+            // correlate turbulences with a sine function to give a 'marble-like' texture
+            NoisePattern::Marble => 0.5 * (1.0 + f64::sin(self.frequency * point.z() + 10.0 * self.fbm(point, time).abs())),
+            NoisePattern::Net => self.fbm(&(*point * self.frequency), time).abs().min(1.0),
+            NoisePattern::Smooth => clamp(0.5 * (1.0 + self.fbm(&(*point * self.frequency), time)), 0.0, 1.0)
+        };
+        Color::new(1.0, 1.0, 1.0) * value
+    }
 }
 
 impl Texture for NoiseTexture {
     fn value(&self, _u: f64, _v: f64, point: &Vec3) -> Color {
-        // correlate turbulences with a sine function to give a 'marble-like' texture
-        Color::new(1.0, 1.0, 1.0) * 0.5 * (1.0 + f64::sin(self.frequency * point.z() + 10.0 * self.noise.turbulence(point, 7)))
-        // this gives a "net-like" texture
-        // Color::new(1.0, 1.0, 1.0) * self.noise.turbulence(&(*point * self.frequency), 7)
-        // this gives a kind of smoothened blocky texture
-        // Color::new(1.0, 1.0, 1.0) * 0.5 * (1.0 + self.noise.noise(&(*point * self.frequency)))
-    }
-}
\ No newline at end of file
+        self.value_in_time(point, 0.0)
+    }
+
+    // when the ray was traced, which is the frame's time with --frame
+    fn value_at(&self, record: &HitRecord) -> Color {
+        self.value_in_time(&record.point, record.time)
+    }
+}
+
+// what a WorleyTexture makes of the distances to the nearest cells
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum WorleyPattern {
+    // dark at each cell's centre, brighter towards its edges
+    Distance,
+    // flat cells with dark seams between them, like a stone wall
+    Stones,
+    // every cell its own flat grey, e.g. for tiles or scales
+    Cells,
+    // thin bright lines where cells meet, like light on the bottom of a pool
+    Caustics
+}
+
+pub struct WorleyTexture {
+    noise: Worley,
+    frequency: f64,
+    pattern: WorleyPattern
+}
+
+impl WorleyTexture {
+    pub fn new(frequency: f64, pattern: WorleyPattern) -> WorleyTexture {
+        WorleyTexture {
+            noise: Worley::new(),
+            frequency,
+            pattern
+        }
+    }
+}
+
+impl Texture for WorleyTexture {
+    fn value(&self, _u: f64, _v: f64, point: &Vec3) -> Color {
+        let sample = self.noise.sample(&(*point * self.frequency));
+        // halfway between two cells f1 and f2 are equal
+        let edge = sample.f2 - sample.f1;
+        let value = match self.pattern {
+            WorleyPattern::Distance => sample.f1.min(1.0),
+            WorleyPattern::Stones => clamp(edge / 0.1, 0.0, 1.0) * (0.6 + 0.4 * sample.cell),
+            WorleyPattern::Cells => sample.cell,
+            WorleyPattern::Caustics => (1.0 - edge / 0.25).max(0.0).powi(3)
+        };
+        Color::new(value, value, value)
+    }
+}
+
+// moves the texture coordinates before looking them up in another texture, to
+// tile, turn or slide e.g. an image or checker without touching the geometry's
+// uvs. the steps are applied in the order they're added, each one to the
+// result of the ones before
+pub struct TransformedTexture {
+    inner: Box<dyn Texture>,
+    // (u, v) -> (m[0][0] u + m[0][1] v + m[0][2], m[1][0] u + m[1][1] v + m[1][2])
+    matrix: [[f64; 3]; 2]
+}
+
+impl TransformedTexture {
+    pub fn new(inner: impl Texture + 'static) -> TransformedTexture {
+        TransformedTexture {
+            inner: Box::new(inner),
+            matrix: [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0]]
+        }
+    }
+
+    // applies step after the current transform
+    fn then(mut self, step: [[f64; 3]; 2]) -> TransformedTexture {
+        let m = self.matrix;
+        let row = |r: usize| [
+            step[r][0] * m[0][0] + step[r][1] * m[1][0],
+            step[r][0] * m[0][1] + step[r][1] * m[1][1],
+            step[r][0] * m[0][2] + step[r][1] * m[1][2] + step[r][2]
+        ];
+        self.matrix = [row(0), row(1)];
+        self
+    }
+
+    // the texture repeats this many times across the surface (with a
+    // repeating inner texture), i.e. the coordinates are multiplied
+    pub fn with_scale(self, u: f64, v: f64) -> TransformedTexture {
+        self.then([[u, 0.0, 0.0], [0.0, v, 0.0]])
+    }
+
+    // turns the coordinates counterclockwise around (0.5, 0.5), the middle of the texture
+    pub fn with_rotation(self, degrees: f64) -> TransformedTexture {
+        let (sin, cos) = degrees_to_radians(degrees).sin_cos();
+        self.then([[1.0, 0.0, -0.5], [0.0, 1.0, -0.5]])
+            .then([[cos, -sin, 0.0], [sin, cos, 0.0]])
+            .then([[1.0, 0.0, 0.5], [0.0, 1.0, 0.5]])
+    }
+
+    pub fn with_offset(self, u: f64, v: f64) -> TransformedTexture {
+        self.then([[1.0, 0.0, u], [0.0, 1.0, v]])
+    }
+}
+
+impl Texture for TransformedTexture {
+    fn value(&self, u: f64, v: f64, point: &Vec3) -> Color {
+        let m = &self.matrix;
+        self.inner.value(m[0][0] * u + m[0][1] * v + m[0][2], m[1][0] * u + m[1][1] * v + m[1][2], point)
+    }
+}
+
+// the number a RampTexture looks up its colour by
+pub enum RampDriver {
+    // the surface's v coordinate, e.g. from the bottom to the top of a sky sphere
+    V,
+    // the hit point's height, 0 at bottom and 1 at top
+    Height{bottom: f64, top: f64},
+    // perlin noise at the point times frequency, remapped to [0, 1]
+    Noise{noise: Arc<Perlin>, frequency: f64},
+    // the red channel of another texture
+    Texture(Box<dyn Texture>)
+}
+
+// a colour ramp (gradient): the driver's value picks a colour between the
+// stops, blending linearly between neighbours. values past the first or last
+// stop get that stop's colour
+pub struct RampTexture {
+    driver: RampDriver,
+    // (position, colour), sorted by position
+    stops: Vec<(f64, Color)>
+}
+
+impl RampTexture {
+    pub fn new(driver: RampDriver, stops: Vec<(f64, Color)>) -> RampTexture {
+        if stops.is_empty() {
+            panic!("A ramp needs at least one colour stop");
+        }
+        let mut stops = stops;
+        stops.sort_by(|a, b| a.0.total_cmp(&b.0));
+        RampTexture {
+            driver,
+            stops
+        }
+    }
+
+    fn driver_value(&self, u: f64, v: f64, point: &Vec3) -> f64 {
+        match &self.driver {
+            RampDriver::V => v,
+            RampDriver::Height{bottom, top} => (point.y() - bottom) / (top - bottom),
+            RampDriver::Noise{noise, frequency} => 0.5 * (1.0 + noise.noise(&(*point * *frequency))),
+            RampDriver::Texture(texture) => texture.value(u, v, point).x()
+        }
+    }
+
+    pub fn colour_at(&self, t: f64) -> Color {
+        // the first stop past t, blended with the one before it
+        match self.stops.iter().position(|(position, _)| *position > t) {
+            Some(0) => self.stops[0].1,
+            Some(next) => {
+                let (start, from) = self.stops[next - 1];
+                let (end, to) = self.stops[next];
+                let f = (t - start) / (end - start);
+                from.lerp(&to, f)
+            },
+            None => self.stops[self.stops.len() - 1].1
+        }
+    }
+}
+
+impl Texture for RampTexture {
+    fn value(&self, u: f64, v: f64, point: &Vec3) -> Color {
+        self.colour_at(self.driver_value(u, v, point))
+    }
+}
+
+// the colours stored on the vertices of the object that was hit (e.g. a
+// scanned mesh or point cloud), interpolated across its faces. objects
+// without vertex colours get the fallback colour
+pub struct VertexColorTexture {
+    fallback: Color
+}
+
+impl VertexColorTexture {
+    pub fn new(fallback: Color) -> VertexColorTexture {
+        VertexColorTexture {
+            fallback
+        }
+    }
+}
+
+impl Texture for VertexColorTexture {
+    fn value(&self, _u: f64, _v: f64, _point: &Vec3) -> Color {
+        self.fallback
+    }
+
+    fn value_at(&self, record: &HitRecord) -> Color {
+        record.vertex_color.unwrap_or(self.fallback)
+    }
+}
+
+// bump mapping: turns a greyscale height texture into a tangent space normal
+// map (use it as a material's normal_map), for when there's only height data.
+// the slope is found numerically by sampling the height a small step along u
+// and v. the step moves both the texture coordinates and the point (along the
+// surface's tangents), so image heights and solid (3D) textures both work
+pub struct BumpMap {
+    height: Box<dyn Texture>,
+    // how high a height of 1 raises the surface, in texture coordinate units
+    // (0.01 is 1% of the texture's width)
+    strength: f64,
+    // the step used for the slope, in texture coordinate (and world) units
+    delta: f64
+}
+
+impl BumpMap {
+    pub fn new(height: Box<dyn Texture>, strength: f64) -> BumpMap {
+        BumpMap {
+            height,
+            strength,
+            delta: 1e-3
+        }
+    }
+
+    // smaller steps pick up finer detail but alias more
+    pub fn with_delta(mut self, delta: f64) -> BumpMap {
+        self.delta = delta;
+        self
+    }
+
+    fn height(&self, u: f64, v: f64, point: &Vec3) -> f64 {
+        let c = self.height.value(u, v, point);
+        (c.x() + c.y() + c.z()) / 3.0
+    }
+
+    // the normal for the given slopes, encoded as a colour like a normal map's
+    fn encode(&self, height: f64, height_u: f64, height_v: f64) -> Color {
+        let slope_u = (height_u - height) / self.delta;
+        let slope_v = (height_v - height) / self.delta;
+        let normal = Vec3::new(-self.strength * slope_u, -self.strength * slope_v, 1.0).unit_vector();
+        (normal + Vec3::new(1.0, 1.0, 1.0)) * 0.5
+    }
+}
+
+impl Texture for BumpMap {
+    // without a hit there are no tangents, so only the texture coordinates move
+    fn value(&self, u: f64, v: f64, point: &Vec3) -> Color {
+        let d = self.delta;
+        self.encode(self.height(u, v, point), self.height(u + d, v, point), self.height(u, v + d, point))
+    }
+
+    fn value_at(&self, record: &HitRecord) -> Color {
+        let (tangent, bitangent, _) = record.tangent_frame();
+        let (u, v, d) = (record.u, record.v, self.delta);
+        self.encode(
+            self.height(u, v, &record.point),
+            self.height(u + d, v, &(record.point + tangent * d)),
+            self.height(u, v + d, &(record.point + bitangent * d))
+        )
+    }
+}
+
+// what happens to texture coordinates outside of [0, 1]
+#[derive(Copy, Clone, PartialEq)]
+pub enum WrapMode {
+    // stick to the nearest edge
+    Clamp,
+    // tile the image, so the left edge continues on from the right one
+    Repeat
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum Filter {
+    // the closest pixel, blocky when magnified
+    Nearest,
+    // a weighted blend of the 4 closest pixels
+    Bilinear,
+    // bilinear in the two mipmap levels (see MipLevel) closest to the size of
+    // the pixel's footprint, blended between them. the same as bilinear when
+    // the footprint isn't known, see RayDifferential
+    Trilinear
+}
+
+// a smaller copy of an image, each level half the size of the one before (down
+// to 1x1) with every pixel the average of 4 in the level above. far away, a
+// pixel covers lots of texels, and reading a level where it covers about one
+// averages them instead of picking a few at random (aliasing)
+struct MipLevel {
+    width: usize,
+    height: usize,
+    pixels: Vec<Color>
+}
+
+impl MipLevel {
+    // odd sizes round down, dropping the last row or column
+    fn downsample(width: usize, height: usize, pixels: &[Color]) -> MipLevel {
+        let (half_width, half_height) = ((width / 2).max(1), (height / 2).max(1));
+        let mut half = Vec::with_capacity(half_width * half_height);
+        for j in 0..half_height {
+            for i in 0..half_width {
+                let at = |di: usize, dj: usize| pixels[(2 * j + dj).min(height - 1) * width + (2 * i + di).min(width - 1)];
+                half.push((at(0, 0) + at(1, 0) + at(0, 1) + at(1, 1)) * 0.25);
+            }
+        }
+        MipLevel {
+            width: half_width,
+            height: half_height,
+            pixels: half
+        }
+    }
+}
+
+// a texture read from an image file (png or jpeg)
+pub struct ImageTexture {
+    width: usize,
+    height: usize,
+    // row by row, starting at the top left
+    pixels: Vec<Color>,
+    wrap_u: WrapMode,
+    wrap_v: WrapMode,
+    filter: Filter,
+    // the average of the top and bottom rows, see spherical()
+    poles: Option<(Color, Color)>,
+    // the levels after the image itself, from half its size down
+    mips: Vec<MipLevel>
+}
+
+impl ImageTexture {
+    // keeps the values as stored, which is right for data (normal, roughness
+    // maps...). colour images are usually srgb, see load_with_color_space
+    pub fn load(path: &Path) -> Result<ImageTexture> {
+        ImageTexture::load_with_color_space(path, ColorSpace::LinearRec709)
+    }
+
+    // converts the image from the colour space it's in to the working space
+    pub fn load_with_color_space(path: &Path, space: ColorSpace) -> Result<ImageTexture> {
+        // decoded straight from the mapped file, without reading it into a buffer first
+        let image = image::load_from_memory(&MappedFile::open(path)?)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, format!("{}: {}", path.display(), e)))?
+            .into_rgb8();
+        let (width, height) = image.dimensions();
+        let pixels = image.pixels().map(|p| {
+            space.decode(Color::new(p[0] as f64 / 255.0, p[1] as f64 / 255.0, p[2] as f64 / 255.0))
+        }).collect();
+
+        Ok(ImageTexture::from_pixels(width as usize, height as usize, pixels))
+    }
+
+    pub fn from_pixels(width: usize, height: usize, pixels: Vec<Color>) -> ImageTexture {
+        if width == 0 || height == 0 || pixels.len() != width * height {
+            panic!("Image needs {}x{} pixels, got {}", width, height, pixels.len());
+        }
+
+        let mut mips: Vec<MipLevel> = Vec::new();
+        let (mut level_width, mut level_height) = (width, height);
+        while level_width > 1 || level_height > 1 {
+            let level = MipLevel::downsample(level_width, level_height, mips.last().map_or(&pixels, |level| &level.pixels));
+            level_width = level.width;
+            level_height = level.height;
+            mips.push(level);
+        }
+
+        ImageTexture {
+            width,
+            height,
+            pixels,
+            wrap_u: WrapMode::Clamp,
+            wrap_v: WrapMode::Clamp,
+            filter: Filter::Trilinear,
+            poles: None,
+            mips
+        }
+    }
+
+    // set up for equirectangular (latitude/longitude) images wrapped around a
+    // sphere, like an earth texture:
+    // - u wraps around so there's no visible seam where u goes from 1 back to 0
+    // - every pixel in the top (bottom) row is squeezed into the north (south)
+    //   pole, so blend towards their average there instead of a pinched swirl
+    pub fn spherical(mut self) -> ImageTexture {
+        let average = |row: usize| {
+            let pixels = &self.pixels[row * self.width..(row + 1) * self.width];
+            pixels.iter().fold(Color::new(0.0, 0.0, 0.0), |sum, p| sum + *p) / self.width as f64
+        };
+        self.poles = Some((average(0), average(self.height - 1)));
+        self.wrap_u = WrapMode::Repeat;
+        self.wrap_v = WrapMode::Clamp;
+        self
+    }
+
+    pub fn with_wrap(mut self, wrap_u: WrapMode, wrap_v: WrapMode) -> ImageTexture {
+        self.wrap_u = wrap_u;
+        self.wrap_v = wrap_v;
+        self
+    }
+
+    pub fn with_filter(mut self, filter: Filter) -> ImageTexture {
+        self.filter = filter;
+        self
+    }
+
+    // resolves a (possibly out of range) pixel index
+    fn wrap(index: i64, size: usize, mode: WrapMode) -> usize {
+        match mode {
+            WrapMode::Clamp => index.clamp(0, size as i64 - 1) as usize,
+            WrapMode::Repeat => index.rem_euclid(size as i64) as usize
+        }
+    }
+
+    // level 0 is the image itself
+    fn level(&self, level: usize) -> (usize, usize, &[Color]) {
+        match level {
+            0 => (self.width, self.height, &self.pixels),
+            _ => {
+                let mip = &self.mips[level - 1];
+                (mip.width, mip.height, &mip.pixels)
+            }
+        }
+    }
+
+    fn pixel(&self, level: usize, i: i64, j: i64) -> Color {
+        let (width, height, pixels) = self.level(level);
+        let i = ImageTexture::wrap(i, width, self.wrap_u);
+        let j = ImageTexture::wrap(j, height, self.wrap_v);
+        pixels[j * width + i]
+    }
+
+    // v is flipped because images are stored top to bottom
+    fn bilinear(&self, level: usize, u: f64, v: f64) -> Color {
+        let (width, height, _) = self.level(level);
+        // pixel centers are at +0.5, so blend between the 4 centers around (x, y)
+        let x = u * width as f64 - 0.5;
+        let y = (1.0 - v) * height as f64 - 0.5;
+        let (i, j) = (x.floor() as i64, y.floor() as i64);
+        let (s, t) = (x - x.floor(), y - y.floor());
+        let top = self.pixel(level, i, j).lerp(&self.pixel(level, i + 1, j), s);
+        let bottom = self.pixel(level, i, j + 1).lerp(&self.pixel(level, i + 1, j + 1), s);
+        top.lerp(&bottom, t)
+    }
+
+    // footprint is how far apart neighbouring pixels' coordinates are, 0 if unknown
+    fn lookup(&self, u: f64, v: f64, footprint: f64) -> Color {
+        let u = if self.wrap_u == WrapMode::Clamp { clamp(u, 0.0, 1.0) } else { u };
+        let v = if self.wrap_v == WrapMode::Clamp { clamp(v, 0.0, 1.0) } else { v };
+        let x = u * self.width as f64;
+        let y = (1.0 - v) * self.height as f64;
+
+        let colour = match self.filter {
+            Filter::Nearest => self.pixel(0, x.floor() as i64, y.floor() as i64),
+            Filter::Bilinear => self.bilinear(0, u, v),
+            Filter::Trilinear => {
+                // the level where the footprint is about one texel
+                let texels = footprint * self.width.max(self.height) as f64;
+                let lod = if texels > 1.0 { texels.log2().min(self.mips.len() as f64) } else { 0.0 };
+                let level = lod.floor() as usize;
+                if level == self.mips.len() {
+                    self.bilinear(level, u, v)
+                } else {
+                    self.bilinear(level, u, v).lerp(&self.bilinear(level + 1, u, v), lod - lod.floor())
+                }
+            }
+        };
+
+        match self.poles {
+            // within the outermost row, fade to the pole's average colour
+            Some((north, south)) => {
+                if y < 1.0 {
+                    north.lerp(&colour, y.max(0.0))
+                } else if y > self.height as f64 - 1.0 {
+                    let distance = (self.height as f64 - y).max(0.0);
+                    south.lerp(&colour, distance)
+                } else {
+                    colour
+                }
+            },
+            None => colour
+        }
+    }
+}
+
+impl Texture for ImageTexture {
+    fn value(&self, u: f64, v: f64, _point: &Vec3) -> Color {
+        self.lookup(u, v, 0.0)
+    }
+
+    fn value_at(&self, record: &HitRecord) -> Color {
+        self.lookup(record.u, record.v, record.uv_footprint)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_uv_checker_tiles() {
+        let black = Color::new(0.0, 0.0, 0.0);
+        let white = Color::new(1.0, 1.0, 1.0);
+        let checker = CheckeredTexture::new_with_solid(white, black).with_uv_tiles(4.0, 2.0);
+        let at = |u: f64, v: f64| checker.value(u, v, &Vec3::new(0.3, 0.7, 0.1)).x();
+        assert_eq!(at(0.1, 0.1), 0.0);
+        assert_eq!(at(0.3, 0.1), 1.0);
+        assert_eq!(at(0.3, 0.6), 0.0);
+        // and the point doesn't matter
+        assert_eq!(checker.value(0.1, 0.1, &Vec3::new(5.0, -2.0, 1.0)).x(), 0.0);
+    }
+
+    #[test]
+    fn test_noise_patterns() {
+        let noise = Arc::new(Perlin::new(7));
+        let marble = NoiseTexture::new(noise.clone(), 4.0, NoisePattern::Marble, Fbm::default());
+        let smooth = NoiseTexture::new(noise, 4.0, NoisePattern::Smooth, Fbm{octaves: 3, lacunarity: 2.5, gain: 0.6});
+        for i in 0..50 {
+            let point = Vec3::new(i as f64 * 0.13, 1.0 - i as f64 * 0.07, i as f64 * 0.29);
+            // the default settings are the original hard coded ones
+            let expected = 0.5 * (1.0 + f64::sin(4.0 * point.z() + 10.0 * Perlin::new(7).turbulence(&point, 7)));
+            assert!((marble.value(0.0, 0.0, &point).x() - expected).abs() < 1e-12);
+            assert!((0.0..=1.0).contains(&smooth.value(0.0, 0.0, &point).x()));
+        }
+    }
+
+    #[test]
+    fn test_noise_follows_the_hit_time() {
+        use crate::material::Material;
+        use crate::simplex::Simplex;
+        let texture = NoiseTexture::new(Arc::new(Simplex::new(3)), 2.0, NoisePattern::Smooth, Fbm::default());
+        let material = Material::Lambertian{albedo: Box::new(SolidTexture::uniform(0.5)), normal_map: None};
+        let point = Vec3::new(0.3, 1.2, -0.7);
+        let mut record = HitRecord::new(point, Vec3::new(0.0, 1.0, 0.0), 1.0, 0.0, 0.0, true, &material);
+        assert_eq!(texture.value_at(&record).x(), texture.value(0.0, 0.0, &point).x());
+        // a frame later the simplex noise has moved on, a little
+        record.time = 1.0 / 24.0;
+        let later = texture.value_at(&record).x();
+        assert!(later != texture.value(0.0, 0.0, &point).x());
+        assert!((later - texture.value(0.0, 0.0, &point).x()).abs() < 0.2);
+    }
+
+    #[test]
+    fn test_transformed_texture() {
+        let checker = || CheckeredTexture::new_with_solid(Color::new(1.0, 1.0, 1.0), Color::new(0.0, 0.0, 0.0)).with_uv_tiles(2.0, 2.0);
+        let origin = Vec3::new(0.0, 0.0, 0.0);
+        // scaled 2x, (0.3, 0.1) looks up (0.6, 0.2): the second tile along u
+        let scaled = TransformedTexture::new(checker()).with_scale(2.0, 2.0);
+        assert_eq!(scaled.value(0.3, 0.1, &origin).x(), 1.0);
+        // then moved by 0.5, (0.3, 0.1) looks up (1.1, 0.7)
+        let moved = TransformedTexture::new(checker()).with_scale(2.0, 2.0).with_offset(0.5, 0.5);
+        assert_eq!(moved.value(0.3, 0.1, &origin).x(), 1.0);
+        // a quarter turn around the middle takes (0.9, 0.5) to (0.5, 0.9)
+        let turned = TransformedTexture::new(checker()).with_rotation(90.0);
+        let m = turned.matrix;
+        assert!((m[0][0] * 0.9 + m[0][1] * 0.5 + m[0][2] - 0.5).abs() < 1e-9);
+        assert!((m[1][0] * 0.9 + m[1][1] * 0.5 + m[1][2] - 0.9).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_ramp_blends_between_stops() {
+        let red = Color::new(1.0, 0.0, 0.0);
+        let blue = Color::new(0.0, 0.0, 1.0);
+        let white = Color::new(1.0, 1.0, 1.0);
+        // given out of order
+        let ramp = RampTexture::new(RampDriver::Height{bottom: 0.0, top: 10.0}, vec![(1.0, white), (0.0, red), (0.5, blue)]);
+        let at = |y: f64| ramp.value(0.0, 0.0, &Vec3::new(0.0, y, 0.0));
+        assert!(at(-5.0).equal_to(&red));
+        assert!(at(2.5).equal_to(&Color::new(0.5, 0.0, 0.5)));
+        assert!(at(5.0).equal_to(&blue));
+        assert!(at(20.0).equal_to(&white));
+    }
+
+    #[test]
+    fn test_repeat_wrap_blends_across_seam() {
+        let black = Color::new(0.0, 0.0, 0.0);
+        let white = Color::new(1.0, 1.0, 1.0);
+        let texture = ImageTexture::from_pixels(2, 1, vec![black, white]).with_wrap(WrapMode::Repeat, WrapMode::Clamp);
+        // u = 0 is exactly between the last and first pixel's centers
+        let seam = texture.value(0.0, 0.5, &Vec3::new(0.0, 0.0, 0.0));
+        assert!((seam.x() - 0.5).abs() < 1e-9);
+        // and u = 1 is the same place
+        let seam = texture.value(1.0, 0.5, &Vec3::new(0.0, 0.0, 0.0));
+        assert!((seam.x() - 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_mipmaps_average_large_footprints() {
+        let black = Color::new(0.0, 0.0, 0.0);
+        let white = Color::new(1.0, 1.0, 1.0);
+        let checker: Vec<Color> = (0..64).map(|n| if (n % 8 + n / 8) % 2 == 0 { black } else { white }).collect();
+        let texture = ImageTexture::from_pixels(8, 8, checker).with_wrap(WrapMode::Repeat, WrapMode::Repeat);
+        // a pixel covering the whole image sees it as grey
+        let far = texture.lookup(0.3, 0.6, 1.0);
+        assert!((far.x() - 0.5).abs() < 1e-9, "{:?}", far);
+        // and an unknown footprint is the image at full size
+        let near = texture.lookup(1.0 / 16.0, 1.0 - 1.0 / 16.0, 0.0);
+        assert!(near.equal_to(&black), "{:?}", near);
+    }
+
+    struct RampU;
+
+    impl Texture for RampU {
+        fn value(&self, u: f64, _v: f64, _point: &Vec3) -> Color {
+            Color::new(u, u, u)
+        }
+    }
+
+    #[test]
+    fn test_bump_map_tilts_against_slope() {
+        // the surface rises along u, so the normal leans back towards -u
+        let bump = BumpMap::new(Box::new(RampU), 1.0);
+        let normal = bump.value(0.5, 0.5, &Vec3::new(0.0, 0.0, 0.0)) * 2.0 - Vec3::new(1.0, 1.0, 1.0);
+        let expected = Vec3::new(-1.0, 0.0, 1.0).unit_vector();
+        assert!((normal - expected).length() < 1e-6);
+    }
+}
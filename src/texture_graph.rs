@@ -0,0 +1,313 @@
+use crate::vec3::*;
+use crate::perlin::Perlin;
+use crate::texture::Texture;
+use std::sync::Arc;
+
+// textures described as a graph of small nodes instead of a fixed type per look.
+// nodes are stored in evaluation order and can only reference nodes added
+// before them, so evaluating the graph is a single pass over the list.
+//
+// a graph can be written to/read from a line based text format, one node per
+// line. nodes are numbered from 0 in the order they're given; blank lines, #
+// comments and the output line (which node is the texture's colour, the last
+// one if there isn't one) don't count. e.g. the marble texture:
+//   position
+//   turbulence 0 7
+//   scale 1 10
+//   component 0 2
+//   scale 3 4
+//   add 2 4
+//   sin 5
+//   const 0.5 0.5 0.5
+//   mul 6 7
+//   add 8 7
+//   output 9
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum MathOp {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Min,
+    Max
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum Node {
+    // inputs
+    Constant(Color),
+    // (u, v, 0)
+    Uv,
+    // the hit point in world space
+    Position,
+    // component-wise math between two nodes
+    Math{op: MathOp, a: usize, b: usize},
+    Scale{input: usize, factor: f64},
+    Sin{input: usize},
+    // one component (0 -> x, 1 -> y, 2 -> z) of input copied to all three
+    Component{input: usize, axis: usize},
+    // perlin noise at the point given by input, remapped to [0, 1]
+    Noise{input: usize},
+    // sum of multiple noise frequencies at the point given by input
+    Turbulence{input: usize, depth: i32},
+    // a * (1 - factor) + b * factor, per component
+    Mix{a: usize, b: usize, factor: usize}
+}
+
+impl Node {
+    fn inputs(&self) -> Vec<usize> {
+        match *self {
+            Node::Constant(_) | Node::Uv | Node::Position => Vec::new(),
+            Node::Math{op: _, a, b} => vec![a, b],
+            Node::Scale{input, factor: _} | Node::Sin{input} | Node::Noise{input} => vec![input],
+            Node::Component{input, axis: _} => vec![input],
+            Node::Turbulence{input, depth: _} => vec![input],
+            Node::Mix{a, b, factor} => vec![a, b, factor]
+        }
+    }
+}
+
+pub struct TextureGraph {
+    nodes: Vec<Node>,
+    output: usize,
+    noise: Arc<Perlin>
+}
+
+impl TextureGraph {
+    // noise is what the noise and turbulence nodes sample, e.g. shared with
+    // the scene's NoiseTextures
+    pub fn new(noise: Arc<Perlin>) -> TextureGraph {
+        TextureGraph {
+            nodes: Vec::new(),
+            output: 0,
+            noise
+        }
+    }
+
+    // adds a node and returns its index. the output defaults to the last node added
+    pub fn add(&mut self, node: Node) -> usize {
+        let index = self.nodes.len();
+        for input in node.inputs() {
+            if input >= index {
+                panic!("Node {} can only reference earlier nodes, got {}", index, input);
+            }
+        }
+        self.nodes.push(node);
+        self.output = index;
+        index
+    }
+
+    pub fn set_output(&mut self, output: usize) {
+        if output >= self.nodes.len() {
+            panic!("Output node {} does not exist", output);
+        }
+        self.output = output;
+    }
+
+    // the same 'marble-like' pattern as NoiseTexture
+    pub fn marble(noise: Arc<Perlin>, frequency: f64) -> TextureGraph {
+        let mut graph = TextureGraph::new(noise);
+        let position = graph.add(Node::Position);
+        let turbulence = graph.add(Node::Turbulence{input: position, depth: 7});
+        let scaled_turbulence = graph.add(Node::Scale{input: turbulence, factor: 10.0});
+        let z = graph.add(Node::Component{input: position, axis: 2});
+        let scaled_position = graph.add(Node::Scale{input: z, factor: frequency});
+        let phase = graph.add(Node::Math{op: MathOp::Add, a: scaled_turbulence, b: scaled_position});
+        let wave = graph.add(Node::Sin{input: phase});
+        let half = graph.add(Node::Constant(Color::new(0.5, 0.5, 0.5)));
+        let scaled_wave = graph.add(Node::Math{op: MathOp::Multiply, a: wave, b: half});
+        graph.add(Node::Math{op: MathOp::Add, a: scaled_wave, b: half});
+        graph
+    }
+
+    pub fn parse(text: &str, noise: Arc<Perlin>) -> Result<TextureGraph, String> {
+        let mut graph = TextureGraph::new(noise);
+        let mut output = None;
+
+        for (line_number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut tokens = line.split_whitespace();
+            let name = tokens.next().unwrap();
+            let args: Vec<&str> = tokens.collect();
+            let index = |i: usize| -> Result<usize, String> {
+                args.get(i).ok_or(format!("line {}: missing argument {}", line_number + 1, i + 1))?
+                    .parse::<usize>().map_err(|e| format!("line {}: {}", line_number + 1, e))
+            };
+            let number = |i: usize| -> Result<f64, String> {
+                args.get(i).ok_or(format!("line {}: missing argument {}", line_number + 1, i + 1))?
+                    .parse::<f64>().map_err(|e| format!("line {}: {}", line_number + 1, e))
+            };
+
+            let node = match name {
+                "const" => Node::Constant(Color::new(number(0)?, number(1)?, number(2)?)),
+                "uv" => Node::Uv,
+                "position" => Node::Position,
+                "add" => Node::Math{op: MathOp::Add, a: index(0)?, b: index(1)?},
+                "sub" => Node::Math{op: MathOp::Subtract, a: index(0)?, b: index(1)?},
+                "mul" => Node::Math{op: MathOp::Multiply, a: index(0)?, b: index(1)?},
+                "div" => Node::Math{op: MathOp::Divide, a: index(0)?, b: index(1)?},
+                "min" => Node::Math{op: MathOp::Min, a: index(0)?, b: index(1)?},
+                "max" => Node::Math{op: MathOp::Max, a: index(0)?, b: index(1)?},
+                "scale" => Node::Scale{input: index(0)?, factor: number(1)?},
+                "sin" => Node::Sin{input: index(0)?},
+                "component" => {
+                    let axis = index(1)?;
+                    if axis > 2 {
+                        return Err(format!("line {}: axis must be 0, 1 or 2", line_number + 1));
+                    }
+                    Node::Component{input: index(0)?, axis}
+                },
+                "noise" => Node::Noise{input: index(0)?},
+                "turbulence" => Node::Turbulence{input: index(0)?, depth: number(1)? as i32},
+                "mix" => Node::Mix{a: index(0)?, b: index(1)?, factor: index(2)?},
+                "output" => {
+                    output = Some(index(0)?);
+                    continue;
+                },
+                _ => return Err(format!("line {}: unknown node '{}'", line_number + 1, name))
+            };
+
+            let own_index = graph.nodes.len();
+            if node.inputs().iter().any(|input| *input >= own_index) {
+                return Err(format!("line {}: nodes can only reference earlier nodes", line_number + 1));
+            }
+            graph.add(node);
+        }
+
+        if graph.nodes.is_empty() {
+            return Err(String::from("texture graph has no nodes"));
+        }
+        if let Some(output) = output {
+            if output >= graph.nodes.len() {
+                return Err(format!("output node {} does not exist", output));
+            }
+            graph.output = output;
+        }
+        Ok(graph)
+    }
+
+    pub fn serialize(&self) -> String {
+        let mut text = String::new();
+        for node in self.nodes.iter() {
+            let line = match *node {
+                Node::Constant(c) => format!("const {} {} {}", c.x(), c.y(), c.z()),
+                Node::Uv => String::from("uv"),
+                Node::Position => String::from("position"),
+                Node::Math{op, a, b} => {
+                    let name = match op {
+                        MathOp::Add => "add",
+                        MathOp::Subtract => "sub",
+                        MathOp::Multiply => "mul",
+                        MathOp::Divide => "div",
+                        MathOp::Min => "min",
+                        MathOp::Max => "max"
+                    };
+                    format!("{} {} {}", name, a, b)
+                },
+                Node::Scale{input, factor} => format!("scale {} {}", input, factor),
+                Node::Sin{input} => format!("sin {}", input),
+                Node::Component{input, axis} => format!("component {} {}", input, axis),
+                Node::Noise{input} => format!("noise {}", input),
+                Node::Turbulence{input, depth} => format!("turbulence {} {}", input, depth),
+                Node::Mix{a, b, factor} => format!("mix {} {} {}", a, b, factor)
+            };
+            text.push_str(&line);
+            text.push('\n');
+        }
+        text.push_str(&format!("output {}\n", self.output));
+        text
+    }
+}
+
+impl Texture for TextureGraph {
+    fn value(&self, u: f64, v: f64, point: &Vec3) -> Color {
+        // only nodes up to the output can affect it
+        let mut values: Vec<Color> = Vec::with_capacity(self.output + 1);
+        for node in self.nodes[..=self.output].iter() {
+            let value = match *node {
+                Node::Constant(c) => c,
+                Node::Uv => Color::new(u, v, 0.0),
+                Node::Position => *point,
+                Node::Math{op, a, b} => {
+                    let (a, b) = (values[a], values[b]);
+                    match op {
+                        MathOp::Add => a + b,
+                        MathOp::Subtract => a - b,
+                        MathOp::Multiply => a * b,
+                        MathOp::Divide => Color::new(a.x() / b.x(), a.y() / b.y(), a.z() / b.z()),
+                        MathOp::Min => a.min_components(&b),
+                        MathOp::Max => a.max_components(&b)
+                    }
+                },
+                Node::Scale{input, factor} => values[input] * factor,
+                Node::Sin{input} => {
+                    let x = values[input];
+                    Color::new(x.x().sin(), x.y().sin(), x.z().sin())
+                },
+                Node::Component{input, axis} => {
+                    let x = values[input];
+                    let c = [x.x(), x.y(), x.z()][axis];
+                    Color::new(c, c, c)
+                },
+                Node::Noise{input} => {
+                    let n = 0.5 * (1.0 + self.noise.noise(&values[input]));
+                    Color::new(n, n, n)
+                },
+                Node::Turbulence{input, depth} => {
+                    let n = self.noise.turbulence(&values[input], depth);
+                    Color::new(n, n, n)
+                },
+                Node::Mix{a, b, factor} => {
+                    let f = values[factor];
+                    values[a] * (Color::new(1.0, 1.0, 1.0) - f) + values[b] * f
+                }
+            };
+            values.push(value);
+        }
+        values[self.output]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serialize_round_trip() {
+        let text = "const 1 0 0\nconst 0 0 1\nuv\ncomponent 2 0\nmix 0 1 3\noutput 4\n";
+        let graph = TextureGraph::parse(text, Arc::new(Perlin::new(0))).unwrap();
+        assert_eq!(graph.serialize(), text);
+
+        // the mix factor is u, so halfway across blends red and blue evenly
+        let colour = graph.value(0.5, 0.0, &Vec3::new(0.0, 0.0, 0.0));
+        assert!(colour.equal_to(&Color::new(0.5, 0.0, 0.5)));
+    }
+
+    #[test]
+    fn test_rejects_forward_references() {
+        assert!(TextureGraph::parse("add 0 1\nuv\n", Arc::new(Perlin::new(0))).is_err());
+    }
+
+    #[test]
+    fn test_indices_skip_comments_and_output() {
+        // node 1 is the second node, past the comment and the blank line
+        let text = "# red and blue\nconst 1 0 0\n\noutput 1\nconst 0 0 1\nadd 0 1\n";
+        let graph = TextureGraph::parse(text, Arc::new(Perlin::new(0))).unwrap();
+        assert!(graph.value(0.0, 0.0, &Vec3::new(0.0, 0.0, 0.0)).equal_to(&Color::new(0.0, 0.0, 1.0)));
+    }
+
+    #[test]
+    fn test_marble_matches_noise_texture() {
+        use crate::texture::{Fbm, NoisePattern, NoiseTexture};
+        let noise = Arc::new(Perlin::new(3));
+        let graph = TextureGraph::marble(noise.clone(), 4.0);
+        let texture = NoiseTexture::new(noise, 4.0, NoisePattern::Marble, Fbm::default());
+        for point in [Vec3::new(0.3, 1.7, -2.2), Vec3::new(5.0, 0.1, 0.9)] {
+            assert!((graph.value(0.0, 0.0, &point) - texture.value(0.0, 0.0, &point)).length() < 1e-9);
+        }
+    }
+}
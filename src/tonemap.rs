@@ -0,0 +1,241 @@
+use crate::vec3::*;
+
+// turning the light a render found, which can be any brightness, into what a
+// screen can show. exposure scales it first, like a camera's, in stops (EV):
+// +1 is twice as bright. then a tone mapper squeezes it into [0, 1). clamping
+// is how renders have always come out: fine for scenes lit by the sky, but a
+// scene with lamps in it has them (and everything they light brightly) clip
+// to flat white. the curves instead roll bright values off towards white, so
+// lights keep some shape and highlights some colour
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ToneMapper {
+    // left as is, to be clipped at 1 (see HighlightSettings)
+    Clamp,
+    // erik reinhard's l / (1 + l) on the luminance, with the colour scaled to
+    // match so its hue stays. soft, but washes out highlights a little. very
+    // saturated colours can still have a channel over 1 after it
+    Reinhard,
+    // the aces filmic curve, as fitted by krzysztof narkowicz (2016): a
+    // contrasty s-curve per channel, so very bright colours desaturate
+    // towards white the way film does
+    Aces
+}
+
+impl ToneMapper {
+    pub fn parse(name: &str) -> Option<ToneMapper> {
+        match name {
+            "clamp" | "linear" => Some(ToneMapper::Clamp),
+            "reinhard" => Some(ToneMapper::Reinhard),
+            "aces" => Some(ToneMapper::Aces),
+            _ => None
+        }
+    }
+}
+
+pub struct ToneMapping {
+    pub mapper: ToneMapper,
+    // in stops, 0 leaves the render as it is
+    pub exposure: f64
+}
+
+impl ToneMapping {
+    pub fn new(mapper: ToneMapper) -> ToneMapping {
+        ToneMapping {
+            mapper,
+            exposure: 0.0
+        }
+    }
+
+    pub fn with_exposure(mut self, exposure: f64) -> ToneMapping {
+        self.exposure = exposure;
+        self
+    }
+
+    // a linear colour, exposed and mapped, still linear
+    pub fn apply(&self, colour: Color) -> Color {
+        let colour = colour * 2f64.powf(self.exposure);
+        match self.mapper {
+            ToneMapper::Clamp => colour,
+            ToneMapper::Reinhard => {
+                let luminance = colour.luminance();
+                if luminance <= 0.0 {
+                    return Color::new(0.0, 0.0, 0.0)
+                }
+                colour * (1.0 / (1.0 + luminance))
+            },
+            ToneMapper::Aces => {
+                // the fit takes values exposed a little darker than the curve
+                // it approximates, so mid grey stays about where it was
+                let curve = |x: f64| {
+                    let x = (x * 0.6).max(0.0);
+                    (x * (2.51 * x + 0.03) / (x * (2.43 * x + 0.59) + 0.14)).clamp(0.0, 1.0)
+                };
+                Color::new(curve(colour.x()), curve(colour.y()), curve(colour.z()))
+            }
+        }
+    }
+}
+
+impl Default for ToneMapping {
+    fn default() -> ToneMapping {
+        ToneMapping::new(ToneMapper::Clamp)
+    }
+}
+
+// how auto exposure judges how bright the render is
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Metering {
+    // the log average of the luminance (its geometric mean), so a few very
+    // bright pixels don't count for much. the darkest and brightest pixels
+    // (see AutoExposure) are left out altogether
+    Average,
+    // the luminance this fraction of the pixels are darker than, e.g. 0.5 for
+    // the median
+    Percentile(f64)
+}
+
+impl Metering {
+    // "average", "median", or a percentile from 0 to 100
+    pub fn parse(name: &str) -> Option<Metering> {
+        match name {
+            "average" => Some(Metering::Average),
+            "median" => Some(Metering::Percentile(0.5)),
+            _ => name.parse::<f64>().ok().filter(|percentile| (0.0..=100.0).contains(percentile))
+                .map(|percentile| Metering::Percentile(percentile / 100.0))
+        }
+    }
+}
+
+// the histogram covers luminances from 2^-20 to 2^12 (about a millionth to
+// 4000), in 1/8 stop bins. anything darker is taken as black and left out
+const HISTOGRAM_BINS: usize = 256;
+const DARKEST_STOP: f64 = -20.0;
+const STOPS_PER_BIN: f64 = 0.125;
+
+// picks the exposure from the render itself, so dim interiors and bright
+// outdoor scenes both come out looking right without scaling their lights
+pub struct AutoExposure {
+    pub metering: Metering,
+    // the luminance the metered brightness is brought to: middle grey, like a
+    // camera's light meter
+    pub key: f64,
+    // for Average, the fractions of the pixels left out at each end, so a black
+    // border or a lamp in view don't pull the exposure their way
+    pub ignore_darkest: f64,
+    pub ignore_brightest: f64
+}
+
+impl AutoExposure {
+    pub fn new(metering: Metering) -> AutoExposure {
+        AutoExposure {
+            metering,
+            key: 0.18,
+            ignore_darkest: 0.1,
+            ignore_brightest: 0.05
+        }
+    }
+
+    pub fn with_key(mut self, key: f64) -> AutoExposure {
+        if key <= 0.0 {
+            panic!("Auto exposure needs a key over 0, got {}", key);
+        }
+        self.key = key;
+        self
+    }
+
+    // the exposure for the pixels, in stops, 0 if they're all black
+    pub fn exposure(&self, pixels: &[Color]) -> f64 {
+        let mut histogram = [0u64; HISTOGRAM_BINS];
+        for colour in pixels {
+            let luminance = colour.luminance();
+            if luminance > 0.0 {
+                let bin = ((luminance.log2() - DARKEST_STOP) / STOPS_PER_BIN).floor();
+                if bin >= 0.0 {
+                    histogram[(bin as usize).min(HISTOGRAM_BINS - 1)] += 1;
+                }
+            }
+        }
+        let total: u64 = histogram.iter().sum();
+        if total == 0 {
+            return 0.0
+        }
+        // the middle of each bin, in stops
+        let stop = |bin: usize| DARKEST_STOP + (bin as f64 + 0.5) * STOPS_PER_BIN;
+        let metered = match self.metering {
+            Metering::Average => {
+                // the pixels from the first to the last of the ones kept, a
+                // bin at a time, counting only the part of a bin that's inside
+                let (first, last) = (self.ignore_darkest * total as f64, (1.0 - self.ignore_brightest) * total as f64);
+                let (mut below, mut sum, mut count) = (0.0, 0.0, 0.0);
+                for (bin, pixels) in histogram.iter().enumerate() {
+                    let above = below + *pixels as f64;
+                    let kept = above.min(last) - below.max(first);
+                    if kept > 0.0 {
+                        sum += kept * stop(bin);
+                        count += kept;
+                    }
+                    below = above;
+                }
+                if count > 0.0 { sum / count } else { stop(0) }
+            },
+            Metering::Percentile(fraction) => {
+                let wanted = (fraction * total as f64).ceil().max(1.0) as u64;
+                let mut below = 0;
+                let bin = histogram.iter().position(|pixels| {
+                    below += pixels;
+                    below >= wanted
+                }).unwrap_or(HISTOGRAM_BINS - 1);
+                stop(bin)
+            }
+        };
+        self.key.log2() - metered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_curves_keep_bright_lights_under_white() {
+        let lamp = Color::new(4.0, 2.0, 0.8);
+        let grey = Color::new(0.18, 0.18, 0.18);
+        let clamp = ToneMapping::default();
+        assert!(clamp.apply(lamp).equal_to(&lamp));
+        assert!(ToneMapping::new(ToneMapper::Clamp).with_exposure(1.0).apply(grey).equal_to(&Color::new(0.36, 0.36, 0.36)));
+
+        for name in ["reinhard", "aces"].iter() {
+            let mapping = ToneMapping::new(ToneMapper::parse(name).unwrap());
+            let mapped = mapping.apply(lamp);
+            assert!(mapped.luminance() < 1.0, "{} {:?}", name, mapped);
+            // still orange, not white
+            assert!(mapped.x() > mapped.y() && mapped.y() > mapped.z(), "{} {:?}", name, mapped);
+            // brighter in, brighter out
+            assert!(mapping.apply(grey * 2.0).x() > mapping.apply(grey).x());
+        }
+        assert!(ToneMapper::parse("filmic").is_none());
+    }
+
+    #[test]
+    fn test_auto_exposure_brings_scenes_to_middle_grey() {
+        // a dim room with a bright window, 16 times brighter than it
+        let mut room = vec![Color::new(0.01, 0.01, 0.01); 90];
+        room.extend(vec![Color::new(0.16, 0.16, 0.16); 10]);
+        let outdoors: Vec<Color> = room.iter().map(|colour| *colour * 64.0).collect();
+        let average = AutoExposure::new(Metering::Average);
+        // the window is about the brightest 5% left out, and the darkest 10%
+        let exposure = average.exposure(&room);
+        let expected = (0.18f64 / 0.01).log2() - 4.0 * 5.0 / 85.0;
+        assert!((exposure - expected).abs() < 0.1, "{} {}", exposure, expected);
+        // 6 stops brighter outside, so 6 stops less exposure
+        assert!((average.exposure(&outdoors) - (exposure - 6.0)).abs() < 0.1);
+
+        let median = AutoExposure::new(Metering::parse("median").unwrap());
+        assert!((median.exposure(&room) - (0.18f64 / 0.01).log2()).abs() < 0.1);
+        let window = AutoExposure::new(Metering::parse("95").unwrap()).with_key(1.0);
+        assert!((window.exposure(&room) - (1.0f64 / 0.16).log2()).abs() < 0.1);
+        assert_eq!(average.exposure(&[Color::new(0.0, 0.0, 0.0)]), 0.0);
+        assert!(Metering::parse("120").is_none());
+    }
+}
@@ -0,0 +1,185 @@
+use crate::Vec3;
+use crate::aabb::AABB;
+use crate::utilities::degrees_to_radians;
+
+// an affine transformation (rotation, scaling, translation etc.), stored as the
+// top 3 rows of a 4x4 matrix since the last row is always (0, 0, 0, 1).
+// the inverse is kept alongside it since rays are transformed by the inverse
+#[derive(Copy, Clone, Debug)]
+pub struct Transform {
+    matrix: [[f64; 4]; 3],
+    inverse: [[f64; 4]; 3]
+}
+
+impl Transform {
+    pub fn identity() -> Transform {
+        let identity = [[1.0, 0.0, 0.0, 0.0], [0.0, 1.0, 0.0, 0.0], [0.0, 0.0, 1.0, 0.0]];
+        Transform {
+            matrix: identity,
+            inverse: identity
+        }
+    }
+
+    // panics if the matrix can't be inverted (e.g. a scale of 0)
+    pub fn from_matrix(matrix: [[f64; 4]; 3]) -> Transform {
+        Transform {
+            matrix,
+            inverse: Transform::invert(&matrix)
+        }
+    }
+
+    pub fn translation(offset: Vec3) -> Transform {
+        Transform::from_matrix([
+            [1.0, 0.0, 0.0, offset.x()],
+            [0.0, 1.0, 0.0, offset.y()],
+            [0.0, 0.0, 1.0, offset.z()]
+        ])
+    }
+
+    pub fn scaling(factors: Vec3) -> Transform {
+        Transform::from_matrix([
+            [factors.x(), 0.0, 0.0, 0.0],
+            [0.0, factors.y(), 0.0, 0.0],
+            [0.0, 0.0, factors.z(), 0.0]
+        ])
+    }
+
+    // rotations are counter clockwise (looking down the axis) in degrees
+    pub fn rotation_x(degrees: f64) -> Transform {
+        let (sin, cos) = degrees_to_radians(degrees).sin_cos();
+        Transform::from_matrix([[1.0, 0.0, 0.0, 0.0], [0.0, cos, -sin, 0.0], [0.0, sin, cos, 0.0]])
+    }
+
+    pub fn rotation_y(degrees: f64) -> Transform {
+        let (sin, cos) = degrees_to_radians(degrees).sin_cos();
+        Transform::from_matrix([[cos, 0.0, sin, 0.0], [0.0, 1.0, 0.0, 0.0], [-sin, 0.0, cos, 0.0]])
+    }
+
+    pub fn rotation_z(degrees: f64) -> Transform {
+        let (sin, cos) = degrees_to_radians(degrees).sin_cos();
+        Transform::from_matrix([[cos, -sin, 0.0, 0.0], [sin, cos, 0.0, 0.0], [0.0, 0.0, 1.0, 0.0]])
+    }
+
+    // applies self first, then other
+    pub fn then(&self, other: &Transform) -> Transform {
+        Transform {
+            matrix: Transform::multiply(&other.matrix, &self.matrix),
+            inverse: Transform::multiply(&self.inverse, &other.inverse)
+        }
+    }
+
+    pub fn inverse(&self) -> Transform {
+        Transform {
+            matrix: self.inverse,
+            inverse: self.matrix
+        }
+    }
+
+    pub fn matrix(&self) -> [[f64; 4]; 3] {
+        self.matrix
+    }
+
+    pub fn point(&self, point: &Vec3) -> Vec3 {
+        Transform::apply(&self.matrix, point, 1.0)
+    }
+
+    // directions aren't affected by translation
+    pub fn vector(&self, vector: &Vec3) -> Vec3 {
+        Transform::apply(&self.matrix, vector, 0.0)
+    }
+
+    // normals have to be transformed by the inverse transpose to stay
+    // perpendicular to the surface (e.g. under non-uniform scaling)
+    pub fn normal(&self, normal: &Vec3) -> Vec3 {
+        let m = &self.inverse;
+        Vec3::new(
+            m[0][0] * normal.x() + m[1][0] * normal.y() + m[2][0] * normal.z(),
+            m[0][1] * normal.x() + m[1][1] * normal.y() + m[2][1] * normal.z(),
+            m[0][2] * normal.x() + m[1][2] * normal.y() + m[2][2] * normal.z()
+        )
+    }
+
+    pub fn inverse_point(&self, point: &Vec3) -> Vec3 {
+        Transform::apply(&self.inverse, point, 1.0)
+    }
+
+    pub fn inverse_vector(&self, vector: &Vec3) -> Vec3 {
+        Transform::apply(&self.inverse, vector, 0.0)
+    }
+
+    // the box around all 8 transformed corners of the given box
+    pub fn bounding_box(&self, bounding_box: &AABB) -> AABB {
+        let mut minimum = Vec3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY);
+        let mut maximum = Vec3::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY);
+        for corner in 0..8 {
+            let x = if corner & 1 == 0 { bounding_box.minimum.x() } else { bounding_box.maximum.x() };
+            let y = if corner & 2 == 0 { bounding_box.minimum.y() } else { bounding_box.maximum.y() };
+            let z = if corner & 4 == 0 { bounding_box.minimum.z() } else { bounding_box.maximum.z() };
+            let point = self.point(&Vec3::new(x, y, z));
+            minimum = minimum.min_components(&point);
+            maximum = maximum.max_components(&point);
+        }
+        AABB::new(minimum, maximum)
+    }
+
+    fn apply(m: &[[f64; 4]; 3], v: &Vec3, w: f64) -> Vec3 {
+        Vec3::new(
+            m[0][0] * v.x() + m[0][1] * v.y() + m[0][2] * v.z() + m[0][3] * w,
+            m[1][0] * v.x() + m[1][1] * v.y() + m[1][2] * v.z() + m[1][3] * w,
+            m[2][0] * v.x() + m[2][1] * v.y() + m[2][2] * v.z() + m[2][3] * w
+        )
+    }
+
+    // a * b, treating both as 4x4 matrices with a (0, 0, 0, 1) last row
+    fn multiply(a: &[[f64; 4]; 3], b: &[[f64; 4]; 3]) -> [[f64; 4]; 3] {
+        let mut result = [[0.0; 4]; 3];
+        for (i, row) in result.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = a[i][0] * b[0][j] + a[i][1] * b[1][j] + a[i][2] * b[2][j];
+            }
+            row[3] += a[i][3];
+        }
+        result
+    }
+
+    fn invert(m: &[[f64; 4]; 3]) -> [[f64; 4]; 3] {
+        // invert the 3x3 part using the adjugate, then undo the translation
+        let determinant = m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0]);
+        if determinant.abs() < 1e-12 {
+            panic!("Transform can't be inverted");
+        }
+        let inv_det = 1.0 / determinant;
+
+        let mut result = [[0.0; 4]; 3];
+        result[0][0] = (m[1][1] * m[2][2] - m[1][2] * m[2][1]) * inv_det;
+        result[0][1] = (m[0][2] * m[2][1] - m[0][1] * m[2][2]) * inv_det;
+        result[0][2] = (m[0][1] * m[1][2] - m[0][2] * m[1][1]) * inv_det;
+        result[1][0] = (m[1][2] * m[2][0] - m[1][0] * m[2][2]) * inv_det;
+        result[1][1] = (m[0][0] * m[2][2] - m[0][2] * m[2][0]) * inv_det;
+        result[1][2] = (m[0][2] * m[1][0] - m[0][0] * m[1][2]) * inv_det;
+        result[2][0] = (m[1][0] * m[2][1] - m[1][1] * m[2][0]) * inv_det;
+        result[2][1] = (m[0][1] * m[2][0] - m[0][0] * m[2][1]) * inv_det;
+        result[2][2] = (m[0][0] * m[1][1] - m[0][1] * m[1][0]) * inv_det;
+        for row in result.iter_mut() {
+            row[3] = -(row[0] * m[0][3] + row[1] * m[1][3] + row[2] * m[2][3]);
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inverse_round_trip() {
+        let transform = Transform::scaling(Vec3::new(2.0, 3.0, 0.5))
+            .then(&Transform::rotation_y(30.0))
+            .then(&Transform::translation(Vec3::new(1.0, -2.0, 5.0)));
+        let point = Vec3::new(0.3, 4.0, -1.5);
+        let round_trip = transform.inverse_point(&transform.point(&point));
+        assert!((round_trip - point).length() < 1e-9);
+    }
+}
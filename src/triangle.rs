@@ -0,0 +1,106 @@
+use crate::Vec3;
+use crate::Ray;
+use crate::hittable::*;
+use crate::material::*;
+use crate::aabb::AABB;
+use crate::export::*;
+use std::sync::Arc;
+
+// how much to pad a triangle's bounding box by. a triangle lying in an axis
+// plane would otherwise have a box with no thickness
+const BOX_PADDING: f64 = 0.0001;
+
+// Möller–Trumbore ray-triangle intersection:
+// https://www.scratchapixel.com/lessons/3d-basic-rendering/ray-tracing-rendering-a-triangle/moller-trumbore-ray-triangle-intersection
+// returns (t, b1, b2) where b1 and b2 are the barycentric weights of p1 and p2
+// (p0's weight is 1 - b1 - b2)
+pub fn intersect(ray: &Ray, p0: Vec3, p1: Vec3, p2: Vec3, t_min: f64, t_max: f64) -> Option<(f64, f64, f64)> {
+    let edge_1 = p1 - p0;
+    let edge_2 = p2 - p0;
+    let p_vec = ray.direction.cross_product(&edge_2);
+    let determinant = edge_1.dot_product(&p_vec);
+    // ray is parallel to the triangle
+    if determinant.abs() < 1e-12 {
+        return None
+    }
+
+    let inv_determinant = 1.0 / determinant;
+    let t_vec = ray.origin - p0;
+    let b1 = t_vec.dot_product(&p_vec) * inv_determinant;
+    if !(0.0..=1.0).contains(&b1) {
+        return None
+    }
+
+    let q_vec = t_vec.cross_product(&edge_1);
+    let b2 = ray.direction.dot_product(&q_vec) * inv_determinant;
+    if b2 < 0.0 || b1 + b2 > 1.0 {
+        return None
+    }
+
+    let t = edge_2.dot_product(&q_vec) * inv_determinant;
+    if t > t_min && t < t_max {
+        Some((t, b1, b2))
+    } else {
+        None
+    }
+}
+
+// bounding box of three points
+pub fn bounding_box(p0: Vec3, p1: Vec3, p2: Vec3) -> AABB {
+    let padding = Vec3::new(BOX_PADDING, BOX_PADDING, BOX_PADDING);
+    let minimum = p0.min_components(&p1).min_components(&p2);
+    let maximum = p0.max_components(&p1).max_components(&p2);
+    AABB::new(minimum - padding, maximum + padding)
+}
+
+// a single flat triangle. the front face is the one where p0 -> p1 -> p2 is
+// counter clockwise. for many triangles sharing materials use a Mesh instead
+pub struct Triangle {
+    p0: Vec3,
+    p1: Vec3,
+    p2: Vec3,
+    material: Arc<Material>
+}
+
+impl Triangle {
+    pub fn new(p0: Vec3, p1: Vec3, p2: Vec3, material: impl Into<Arc<Material>>) -> Triangle {
+        Triangle {
+            p0,
+            p1,
+            p2,
+            material: material.into()
+        }
+    }
+}
+
+impl Hittable for Triangle {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let (t, u, v) = intersect(ray, self.p0, self.p1, self.p2, t_min, t_max)?;
+        let outward_normal = (self.p1 - self.p0).cross_product(&(self.p2 - self.p0)).unit_vector();
+        // without texture coordinates, use the barycentric coordinates as uvs
+        let mut record = HitRecord::new(ray.at(t), outward_normal, t, u, v, false, &self.material);
+        record.set_face_normal(ray, &outward_normal);
+        // u is the barycentric weight of p1
+        record.tangent = Some(self.p1 - self.p0);
+        Some(record)
+    }
+
+    fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<AABB> {
+        Some(bounding_box(self.p0, self.p1, self.p2))
+    }
+
+    fn tessellate(&self) -> Vec<ExportMesh> {
+        let normal = (self.p1 - self.p0).cross_product(&(self.p2 - self.p0)).unit_vector();
+        vec![ExportMesh {
+            positions: vec![self.p0, self.p1, self.p2],
+            normals: vec![normal, normal, normal],
+            uvs: vec![(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)],
+            triangles: vec![[0, 1, 2]],
+            material: ExportMaterial::from_material(&self.material)
+        }]
+    }
+
+    fn materials_mut(&mut self) -> Vec<&mut Arc<Material>> {
+        vec![&mut self.material]
+    }
+}
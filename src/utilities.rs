@@ -1,24 +1,61 @@
-use rand::Rng;
+use crate::sampler;
+use rand::{Rng, SeedableRng};
+use rand_xoshiro::Xoshiro256PlusPlus;
+use std::cell::RefCell;
 
 pub const INFINITY: f64 = f64::INFINITY;
-pub const PI: f64 = 3.1415926535897932385;
+pub const PI: f64 = std::f64::consts::PI;
 
 pub fn degrees_to_radians(degrees: f64) -> f64{
     degrees * PI / 180.0
 }
 
+thread_local! {
+    // every thread (e.g. each rayon worker) has its own generator, so taking
+    // a number never waits on another thread. a sample takes dozens of them,
+    // so it's a small fast one (xoshiro256++) rather than rand's thread_rng,
+    // which is cryptographically secure and reseeds itself along the way.
+    // it's named rather than SmallRng, which is a different generator on 32
+    // bit targets (e.g. wasm32) and may change between rand versions.
+    // it starts from entropy, seed_rng makes what follows repeatable
+    static RNG: RefCell<Xoshiro256PlusPlus> = RefCell::new(Xoshiro256PlusPlus::from_entropy());
+}
+
+// runs f with this thread's generator, for code that takes an Rng, e.g. to
+// shuffle or to draw from a distribution
+pub fn with_rng<R>(f: impl FnOnce(&mut Xoshiro256PlusPlus) -> R) -> R {
+    RNG.with(|rng| f(&mut rng.borrow_mut()))
+}
+
+// restarts this thread's random numbers from seed
+pub fn seed_rng(seed: u64) {
+    RNG.with(|rng| *rng.borrow_mut() = Xoshiro256PlusPlus::seed_from_u64(seed));
+}
+
+// one seed from several numbers (e.g. a render's seed, a pixel and a sample
+// number), mixed so that neighbouring pixels don't get related sequences.
+// this is splitmix64's step
+pub fn mix_seed(parts: &[u64]) -> u64 {
+    parts.iter().fold(0x9e37_79b9_7f4a_7c15, |hash: u64, part| {
+        let mut z = (hash ^ part).wrapping_add(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    })
+}
+
 pub fn random_int_in_range(min: u32, max: u32) -> u32 {
-    let mut rng = rand::thread_rng();
-    rng.gen_range(min..max)}
+    with_rng(|rng| rng.gen_range(min..max))
+}
 
+// in [0, 1). while a sample is being taken this is its next dimension, from
+// the image's sampler (see sampler.rs)
 pub fn random_float() -> f64 {
-    // Rng::gen_range(0.0..1.0,)
-    rand::random::<f64>()
+    sampler::next_dimension().unwrap_or_else(|| with_rng(|rng| rng.gen()))
 }
 
 pub fn random_float_in_range(min: f64, max: f64) -> f64 {
-    let mut rng = rand::thread_rng();
-    rng.gen_range(min..max)
+    min + (max - min) * random_float()
 }
 
 // fix given x to be in [min, max]
@@ -29,5 +66,35 @@ pub fn clamp(x: f64, min: f64, max: f64) -> f64 {
     if x > max {
         return max
     }
-    return x
-}
\ No newline at end of file
+    x
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seeded_numbers_repeat() {
+        let draw = || (0..8).map(|_| random_float() + random_int_in_range(0, 10) as f64).collect::<Vec<f64>>();
+        seed_rng(42);
+        let first = draw();
+        seed_rng(42);
+        assert_eq!(draw(), first);
+        seed_rng(43);
+        assert_ne!(draw(), first);
+        assert!(first.iter().all(|&number| (0.0..10.0).contains(&number)));
+
+        // the order of the parts matters
+        assert_eq!(mix_seed(&[1, 2, 3]), mix_seed(&[1, 2, 3]));
+        assert_ne!(mix_seed(&[1, 2, 3]), mix_seed(&[1, 3, 2]));
+    }
+
+    #[test]
+    fn test_seeded_numbers_are_pinned() {
+        // the same on every platform, so a --seed render on one machine can be
+        // compared with (or finished on) another
+        seed_rng(42);
+        // xoshiro256++ seeded through splitmix64
+        assert_eq!(with_rng(|rng| rng.gen::<u64>()), 15021278609987233951);
+    }
+}
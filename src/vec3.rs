@@ -1,15 +1,86 @@
 use std::ops::*;
+use std::fmt::Debug;
 use crate::utilities::*;
+use crate::color_space::ColorSpace;
+
+// the number type the math core (Vec3, Ray, AABB) works in. only those three
+// are generic: hittables, materials, textures and the integrators take the f64
+// default, so every render is in f64, which scenes with far apart coordinates
+// (e.g. kilometres of terrain around millimetre details) need anyway. f32
+// halves the memory and doubles the simd lanes, for code of its own that can
+// live with ~7 significant digits, e.g. a large mesh kept as Vec3<f32> until
+// it's needed. there's no f32 render
+pub trait Scalar: Copy + PartialOrd + Debug + Send + Sync + 'static
+    + Add<Output = Self> + Sub<Output = Self> + Mul<Output = Self> + Div<Output = Self> + Neg<Output = Self> {
+    fn from_f64(value: f64) -> Self;
+    fn to_f64(self) -> f64;
+    fn sqrt(self) -> Self;
+    fn abs(self) -> Self;
+    fn min(self, other: Self) -> Self;
+    fn max(self, other: Self) -> Self;
+}
+
+impl Scalar for f64 {
+    fn from_f64(value: f64) -> f64 {
+        value
+    }
+
+    fn to_f64(self) -> f64 {
+        self
+    }
+
+    fn sqrt(self) -> f64 {
+        f64::sqrt(self)
+    }
+
+    fn abs(self) -> f64 {
+        f64::abs(self)
+    }
+
+    fn min(self, other: f64) -> f64 {
+        f64::min(self, other)
+    }
+
+    fn max(self, other: f64) -> f64 {
+        f64::max(self, other)
+    }
+}
+
+impl Scalar for f32 {
+    fn from_f64(value: f64) -> f32 {
+        value as f32
+    }
+
+    fn to_f64(self) -> f64 {
+        self as f64
+    }
+
+    fn sqrt(self) -> f32 {
+        f32::sqrt(self)
+    }
+
+    fn abs(self) -> f32 {
+        f32::abs(self)
+    }
+
+    fn min(self, other: f32) -> f32 {
+        f32::min(self, other)
+    }
+
+    fn max(self, other: f32) -> f32 {
+        f32::max(self, other)
+    }
+}
 
 #[derive(Debug, Copy, Clone)]
-pub struct Vec3 {
-    x: f64,
-    y: f64,
-    z: f64,
+pub struct Vec3<T = f64> {
+    x: T,
+    y: T,
+    z: T,
 }
 
-impl Vec3 {
-    pub fn new(x: f64, y: f64, z: f64) -> Vec3 {
+impl<T: Scalar> Vec3<T> {
+    pub fn new(x: T, y: T, z: T) -> Vec3<T> {
         Vec3 {
             x,
             y,
@@ -17,31 +88,31 @@ impl Vec3 {
         }
     }
 
-    pub fn x(&self) -> f64 {
+    pub fn x(&self) -> T {
         self.x
     }
 
-    pub fn y(&self) -> f64 {
+    pub fn y(&self) -> T {
         self.y
     }
 
-    pub fn z(&self) -> f64 {
+    pub fn z(&self) -> T {
         self.z
     }
 
-    pub fn length(&self) -> f64 {
+    pub fn length(&self) -> T {
         self.length_squared().sqrt()
     }
 
-    pub fn length_squared(&self) -> f64 {
+    pub fn length_squared(&self) -> T {
         self.x * self.x + self.y * self.y + self.z * self.z
     }
 
-    pub fn dot_product(&self, other: &Vec3) -> f64 {
+    pub fn dot_product(&self, other: &Vec3<T>) -> T {
         self.x * other.x + self.y * other.y + self.z * other.z
     }
 
-    pub fn cross_product(&self, other: &Vec3) -> Vec3 {
+    pub fn cross_product(&self, other: &Vec3<T>) -> Vec3<T> {
         Vec3 {
             x: self.y * other.z - self.z * other.y,
             y: self.z * other.x - self.x * other.z,
@@ -49,28 +120,95 @@ impl Vec3 {
         }
     }
 
-    pub fn unit_vector(&self) -> Vec3 {
+    pub fn unit_vector(&self) -> Vec3<T> {
         Vec3::new(self.x, self.y, self.z) / self.length()
     }
 
+    // the smaller of each pair of components, e.g. for the corner of a box around both
+    pub fn min_components(&self, other: &Vec3<T>) -> Vec3<T> {
+        Vec3::new(self.x.min(other.x), self.y.min(other.y), self.z.min(other.z))
+    }
+
+    pub fn max_components(&self, other: &Vec3<T>) -> Vec3<T> {
+        Vec3::new(self.x.max(other.x), self.y.max(other.y), self.z.max(other.z))
+    }
+
+    pub fn abs(&self) -> Vec3<T> {
+        Vec3::new(self.x.abs(), self.y.abs(), self.z.abs())
+    }
+
+    // the largest of x, y and z, e.g. a colour's brightest channel
+    pub fn max_component(&self) -> T {
+        self.x.max(self.y).max(self.z)
+    }
+
+    // per component
+    pub fn sqrt(&self) -> Vec3<T> {
+        Vec3::new(self.x.sqrt(), self.y.sqrt(), self.z.sqrt())
+    }
+
+    // self at t = 0, other at t = 1 and a straight line in between
+    pub fn lerp(&self, other: &Vec3<T>, t: T) -> Vec3<T> {
+        *self * (T::from_f64(1.0) - t) + *other * t
+    }
+
+    pub fn equal_to(&self, second: &Vec3<T>) -> bool {
+        self.x == second. x && self.y == second.y && self.z == second.z
+    }
+
+    // the same vector in another precision
+    pub fn cast<U: Scalar>(&self) -> Vec3<U> {
+        Vec3::new(U::from_f64(self.x.to_f64()), U::from_f64(self.y.to_f64()), U::from_f64(self.z.to_f64()))
+    }
+
+    pub fn near_zero(&self) -> bool {
+        let min = T::from_f64(1e-8);
+        self.x().abs() < min && self.y().abs() < min && self.z().abs() < min
+    }
+
+    // v       ^
+    //  \  n  /
+    //   \ | /
+    // ___\ /___
+    // vector v is reflected off the surface, n is the norma
+    pub fn reflect(vector: &Vec3<T>, normal: &Vec3<T>) -> Vec3<T> {
+        let x = vector.dot_product(normal) * T::from_f64(2.0);
+        let reflected_vector = *vector - *normal * x;
+        Vec3::new(reflected_vector.x(), reflected_vector.y(), reflected_vector.z())
+    }
+
+    // using snell's law
+    pub fn refract(uv: &Vec3<T>, normal: &Vec3<T>, etai_over_etat: T) -> Vec3<T> {
+        let one = T::from_f64(1.0);
+        let cos_theta: T = uv.dot_product(&-*normal).min(one);
+        let r_out_perp: Vec3<T> = (*uv + (*normal * cos_theta)) * etai_over_etat;
+        let r_out_paralel: Vec3<T> = -(*normal * ((one - r_out_perp.length_squared()).abs().sqrt()));
+        r_out_perp + r_out_paralel
+    }
+}
+
+// colours and random directions, which the renderer only needs in f64
+impl Vec3 {
     pub fn write_colour(&self, samples_per_pixel: u64) {
-        let mut r = self.x();
-        let mut g = self.y();
-        let mut b = self.z();
+        let [r, g, b] = self.rgb8(samples_per_pixel);
+        println!("{0} {1} {2}", r, g, b);
+    }
 
+    // the averaged, gamma corrected colour as 8 bit values
+    pub fn rgb8(&self, samples_per_pixel: u64) -> [u8; 3] {
         let scale = 1.0 / samples_per_pixel as f64;
 
         // perform gamma correction because of how light is perceived/displayed
-        // (adjusts ligting due to the non-linearity of light perception)
-        r = (r * scale).sqrt();
-        g = (g * scale).sqrt();
-        b = (b * scale).sqrt();
-        
-        println!("{0} {1} {2}", 256.0 * clamp(r, 0.0, 0.999), 256.0 * clamp(g, 0.0, 0.999), 256.0 * clamp(b, 0.0, 0.999));
+        // (adjusts ligting due to the non-linearity of light perception). this
+        // is the srgb curve screens expect. a square root used to stand in for
+        // it, but that's a gamma of 2.0 rather than about 2.2, so midtones came
+        // out too dark
+        ColorSpace::Srgb.encode_rgb8(*self * scale)
     }
 
-    pub fn equal_to(&self, second: &Vec3) -> bool {
-        self.x == second. x && self.y == second.y && self.z == second.z
+    // how bright a linear (rec.709) colour is to the eye
+    pub fn luminance(&self) -> f64 {
+        0.2126 * self.x + 0.7152 * self.y + 0.0722 * self.z
     }
 
     pub fn random() -> Vec3 {
@@ -81,18 +219,43 @@ impl Vec3 {
         Vec3::new(random_float_in_range(min, max), random_float_in_range(min, max), random_float_in_range(min, max))
     }
 
+    // uniformly, from exactly two random numbers. squares around the middle of
+    // [-1, 1]^2 are squashed onto circles (shirley and chiu's concentric map),
+    // so samples spread evenly over the square (see sampler.rs) stay spread
+    // evenly over the disk, and a sample's later dimensions stay where they are
     pub fn random_in_unit_disk() -> Vec3 {
-        loop {
-            let point = Vec3::new(
-                random_float_in_range(-1.0, 1.0),
-                random_float_in_range(-1.0, 1.0),
-                0.0
-            );
-
-            if point.length_squared() < 1.0 {
-                return point;
-            }
+        let a = random_float_in_range(-1.0, 1.0);
+        let b = random_float_in_range(-1.0, 1.0);
+        if a == 0.0 && b == 0.0 {
+            return Vec3::new(0.0, 0.0, 0.0)
+        }
+        let (radius, angle) = if a.abs() > b.abs() {
+            (a, PI / 4.0 * (b / a))
+        } else {
+            (b, PI / 2.0 - PI / 4.0 * (a / b))
+        };
+        Vec3::new(radius * angle.cos(), radius * angle.sin(), 0.0)
+    }
+
+    // uniformly sample a regular polygon inscribed in the unit disk (like the
+    // opening formed by a lens' aperture blades). rotation is in radians
+    pub fn random_in_unit_polygon(blades: u32, rotation: f64) -> Vec3 {
+        // the polygon is a fan of 'blades' triangles around the center, all of
+        // equal area, so pick one at random then sample a point inside it
+        let step = 2.0 * PI / blades as f64;
+        let blade = ((random_float() * blades as f64) as u32).min(blades - 1) as f64;
+        let first_angle = rotation + blade * step;
+        let first = Vec3::new(first_angle.cos(), first_angle.sin(), 0.0);
+        let second = Vec3::new((first_angle + step).cos(), (first_angle + step).sin(), 0.0);
+
+        // fold points outside the triangle back inside it
+        let mut a = random_float();
+        let mut b = random_float();
+        if a + b > 1.0 {
+            a = 1.0 - a;
+            b = 1.0 - b;
         }
+        first * a + second * b
     }
 
     // basic diffusion (this + normal)
@@ -121,37 +284,13 @@ impl Vec3 {
         }
     }
 
-    pub fn near_zero(&self) -> bool {
-        let min = 1e-8;
-        self.x().abs() < min && self.y().abs() < min && self.z().abs() < min
-    }
-
-    // v       ^
-    //  \  n  /
-    //   \ | /
-    // ___\ /___
-    // vector v is reflected off the surface, n is the norma
-    pub fn reflect(vector: &Vec3, normal: &Vec3) -> Vec3 {
-        let x = vector.dot_product(normal) * 2.0;
-        let y = *normal * 2.0;
-        let reflected_vector = *vector - *normal * x;
-        Vec3::new(reflected_vector.x(), reflected_vector.y(), reflected_vector.z())
-    }
-
-    // using snell's law
-    pub fn refract(uv: &Vec3, normal: &Vec3, etai_over_etat: f64) -> Vec3 {
-        let cos_theta: f64 = uv.dot_product(&(*normal * -1.0)).min(1.0);
-        let r_out_perp: Vec3 = (*uv + (*normal * cos_theta)) * etai_over_etat;
-        let r_out_paralel: Vec3 = *normal * ((1.0 - r_out_perp.length_squared()).abs().sqrt()) * -1.0;
-        r_out_perp + r_out_paralel
-    }
 }
 
 // vec3 + vec3
-impl Add<Vec3> for Vec3 {
-    type Output = Vec3;
+impl<T: Scalar> Add<Vec3<T>> for Vec3<T> {
+    type Output = Vec3<T>;
 
-    fn add(self, other: Vec3) -> Vec3 {
+    fn add(self, other: Vec3<T>) -> Vec3<T> {
         Vec3 {
             x: self.x() + other.x(),
             y: self.y() + other.y(),
@@ -161,10 +300,10 @@ impl Add<Vec3> for Vec3 {
 }
 
 // vec3 - vec3
-impl Sub<Vec3> for Vec3 {
-    type Output = Vec3;
+impl<T: Scalar> Sub<Vec3<T>> for Vec3<T> {
+    type Output = Vec3<T>;
 
-    fn sub(self, other: Vec3) -> Vec3 {
+    fn sub(self, other: Vec3<T>) -> Vec3<T> {
         Vec3 {
             x: self.x() - other.x(),
             y: self.y() - other.y(),
@@ -173,11 +312,24 @@ impl Sub<Vec3> for Vec3 {
     }
 }
 
-// vec3 * f64
-impl Mul<f64> for Vec3 {
-    type Output = Vec3;
+// -vec3
+impl<T: Scalar> Neg for Vec3<T> {
+    type Output = Vec3<T>;
+
+    fn neg(self) -> Vec3<T> {
+        Vec3 {
+            x: -self.x(),
+            y: -self.y(),
+            z: -self.z()
+        }
+    }
+}
+
+// vec3 * scalar
+impl<T: Scalar> Mul<T> for Vec3<T> {
+    type Output = Vec3<T>;
 
-    fn mul(self, factor: f64) -> Vec3 {
+    fn mul(self, factor: T) -> Vec3<T> {
         Vec3 {
             x: self.x() * factor,
             y: self.y() * factor,
@@ -187,10 +339,10 @@ impl Mul<f64> for Vec3 {
 }
 
 // vec3 * vec3
-impl Mul<Vec3> for Vec3 {
-    type Output = Vec3;
+impl<T: Scalar> Mul<Vec3<T>> for Vec3<T> {
+    type Output = Vec3<T>;
 
-    fn mul(self, vector: Vec3) -> Vec3 {
+    fn mul(self, vector: Vec3<T>) -> Vec3<T> {
         Vec3 {
             x: self.x() * vector.x(),
             y: self.y() * vector.y(),
@@ -199,11 +351,11 @@ impl Mul<Vec3> for Vec3 {
     }
 }
 
-// vec3 / f64
-impl Div<f64> for Vec3 {
-    type Output = Vec3;
+// vec3 / scalar
+impl<T: Scalar> Div<T> for Vec3<T> {
+    type Output = Vec3<T>;
 
-    fn div(self, factor: f64) -> Vec3 {
+    fn div(self, factor: T) -> Vec3<T> {
         Vec3 {
             x: self.x() / factor,
             y: self.y() / factor,
@@ -212,6 +364,20 @@ impl Div<f64> for Vec3 {
     }
 }
 
+// vec3[0] is x, vec3[1] is y, vec3[2] is z. for code that works along any axis
+impl<T: Scalar> Index<usize> for Vec3<T> {
+    type Output = T;
+
+    fn index(&self, axis: usize) -> &T {
+        match axis {
+            0 => &self.x,
+            1 => &self.y,
+            2 => &self.z,
+            _ => panic!("Vec3 has no axis {}", axis)
+        }
+    }
+}
+
 pub type Color = Vec3;
 
 #[cfg(test)]
@@ -249,4 +415,59 @@ mod tests {
         let actual = first.cross_product(&second);
         assert!(expected.equal_to(&actual));
     }
+
+    #[test]
+    fn test_component_helpers() {
+        let first = Vec3::new(1.0, -4.0, 9.0);
+        let second = Vec3::new(2.0, -5.0, 0.0);
+        assert!(first.min_components(&second).equal_to(&Vec3::new(1.0, -5.0, 0.0)));
+        assert!(first.max_components(&second).equal_to(&Vec3::new(2.0, -4.0, 9.0)));
+        assert!(first.abs().sqrt().equal_to(&Vec3::new(1.0, 2.0, 3.0)));
+        assert_eq!(second.max_component(), 2.0);
+        assert!(first.lerp(&second, 0.5).equal_to(&Vec3::new(1.5, -4.5, 4.5)));
+    }
+
+    #[test]
+    fn test_random_in_unit_polygon() {
+        let (blades, rotation) = (6, 0.3);
+        let step = 2.0 * PI / blades as f64;
+        let mut per_blade = vec![0; blades as usize];
+        for _ in 0..6000 {
+            let point = Vec3::random_in_unit_polygon(blades, rotation);
+            assert_eq!(point.z(), 0.0);
+            // inside every edge, each as far from the middle as a corner's cos(step / 2)
+            for edge in 0..blades {
+                let angle = rotation + (edge as f64 + 0.5) * step;
+                assert!(point.x() * angle.cos() + point.y() * angle.sin() <= (step / 2.0).cos() + 1e-12);
+            }
+            let angle = (point.y().atan2(point.x()) - rotation).rem_euclid(2.0 * PI);
+            per_blade[(angle / step) as usize % blades as usize] += 1;
+        }
+        // spread evenly between the blades, 1000 each on average
+        assert!(per_blade.iter().all(|count| (850..1150).contains(count)), "{:?}", per_blade);
+    }
+
+    #[test]
+    fn test_lens_samples_take_fixed_dimensions() {
+        use crate::sampler::{end_sample, next_dimension, start_sample, Sampler, SamplerKind, Sobol};
+        // whatever the numbers, so the dimensions after (time, bounces) are
+        // always the same ones
+        for index in 0..64 {
+            start_sample(SamplerKind::Sobol(Sobol), 5, index, None);
+            assert!(Vec3::random_in_unit_disk().length_squared() <= 1.0);
+            assert_eq!(next_dimension(), Sobol.sample(5, index, 2));
+            start_sample(SamplerKind::Sobol(Sobol), 5, index, None);
+            Vec3::random_in_unit_polygon(5, 0.0);
+            assert_eq!(next_dimension(), Sobol.sample(5, index, 3));
+        }
+        end_sample();
+
+        // and the concentric map spreads the disk evenly: a quarter of the
+        // points in each quadrant, and within half the radius
+        let points: Vec<Vec3> = (0..4000).map(|_| Vec3::random_in_unit_disk()).collect();
+        assert!(points.iter().all(|point| point.length_squared() < 1.0 && point.z() == 0.0));
+        let share = |inside: &dyn Fn(&Vec3) -> bool| points.iter().filter(|point| inside(point)).count() as f64 / 4000.0;
+        assert!((share(&|point| point.x() > 0.0 && point.y() > 0.0) - 0.25).abs() < 0.03);
+        assert!((share(&|point| point.length() < 0.5) - 0.25).abs() < 0.03);
+    }
 }
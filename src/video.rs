@@ -0,0 +1,99 @@
+use std::io::{Error, ErrorKind, Result, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+
+// an animation's frames encoded straight into a video by ffmpeg, instead of
+// being left as an image per frame. frames are piped to it as raw 8 bit rgb,
+// so nothing is written but the video. ffmpeg has to be installed (and on the
+// path); the codec comes from the file's extension: vp9 for .webm, h.264
+// (yuv420p, which every player can show) for anything else, e.g. .mp4
+
+pub struct VideoWriter {
+    path: PathBuf,
+    fps: f64,
+    // started with the first frame, since that's when the size is known
+    ffmpeg: Option<Encoder>
+}
+
+struct Encoder {
+    child: Child,
+    input: ChildStdin,
+    width: u32,
+    height: u32
+}
+
+impl VideoWriter {
+    pub fn new(path: &Path, fps: f64) -> VideoWriter {
+        VideoWriter {
+            path: path.to_path_buf(),
+            fps,
+            ffmpeg: None
+        }
+    }
+
+    // the next frame's pixels, rows from the top
+    pub fn write_frame(&mut self, width: u32, height: u32, pixels: impl Iterator<Item = [u8; 3]>) -> Result<()> {
+        if self.ffmpeg.is_none() {
+            let mut child = Command::new("ffmpeg").args(ffmpeg_args(&self.path, width, height, self.fps))
+                .stdin(Stdio::piped()).stdout(Stdio::null()).stderr(Stdio::inherit()).spawn()
+                .map_err(|e| Error::new(e.kind(), format!("Couldn't start ffmpeg (is it installed?): {}", e)))?;
+            let input = child.stdin.take().expect("ffmpeg's input is piped");
+            self.ffmpeg = Some(Encoder{child, input, width, height});
+        }
+        let encoder = self.ffmpeg.as_mut().unwrap();
+        if (width, height) != (encoder.width, encoder.height) {
+            return Err(Error::new(ErrorKind::InvalidInput,
+                format!("Frames have to be the same size, {}x{} after {}x{}", width, height, encoder.width, encoder.height)))
+        }
+        let bytes: Vec<u8> = pixels.flatten().collect();
+        if bytes.len() != (width * height * 3) as usize {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("A {}x{} frame needs {} pixels, got {}", width, height, width * height, bytes.len() / 3)))
+        }
+        encoder.input.write_all(&bytes)
+    }
+
+    // closes ffmpeg's input and waits for it to finish the file
+    pub fn finish(self) -> Result<()> {
+        let Some(Encoder{mut child, input, ..}) = self.ffmpeg else {
+            return Ok(())
+        };
+        drop(input);
+        let status = child.wait()?;
+        if !status.success() {
+            return Err(Error::other(format!("ffmpeg failed ({})", status)))
+        }
+        Ok(())
+    }
+}
+
+fn ffmpeg_args(path: &Path, width: u32, height: u32, fps: f64) -> Vec<String> {
+    let webm = path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("webm"));
+    let mut args: Vec<String> = ["-y", "-loglevel", "error", "-f", "rawvideo", "-pix_fmt", "rgb24"].iter().map(|arg| arg.to_string()).collect();
+    args.extend(["-s".to_string(), format!("{}x{}", width, height), "-framerate".to_string(), fps.to_string(), "-i".to_string(), "-".to_string()]);
+    let codec: &[&str] = if webm {
+        &["-c:v", "libvpx-vp9", "-b:v", "0", "-crf", "30", "-pix_fmt", "yuv420p"]
+    } else {
+        // yuv420p needs an even width and height, so odd ones get a black
+        // line added
+        &["-c:v", "libx264", "-crf", "18", "-pix_fmt", "yuv420p", "-vf", "pad=ceil(iw/2)*2:ceil(ih/2)*2"]
+    };
+    args.extend(codec.iter().map(|arg| arg.to_string()));
+    args.push(path.to_string_lossy().into_owned());
+    args
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_codec_follows_the_extension() {
+        let mp4 = ffmpeg_args(Path::new("out/ball.mp4"), 400, 225, 24.0).join(" ");
+        assert!(mp4.contains("-s 400x225 -framerate 24 -i -") && mp4.contains("libx264") && mp4.ends_with("out/ball.mp4"), "{}", mp4);
+        let webm = ffmpeg_args(Path::new("ball.WEBM"), 400, 225, 29.97).join(" ");
+        assert!(webm.contains("-framerate 29.97") && webm.contains("libvpx-vp9"), "{}", webm);
+
+        // nothing written, nothing to finish
+        assert!(VideoWriter::new(Path::new("unused.mp4"), 24.0).finish().is_ok());
+    }
+}
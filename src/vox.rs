@@ -0,0 +1,131 @@
+use crate::vec3::*;
+use crate::material::Material;
+use crate::texture::SolidTexture;
+use crate::voxel::VoxelGrid;
+use crate::mapped::MappedFile;
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+
+// loading MagicaVoxel .vox files.
+// reference: https://github.com/ephtracy/voxel-model/blob/master/MagicaVoxel-file-format-vox.txt
+// only the first model is read (scene graph chunks are ignored) and every
+// colour becomes a lambertian material. magicavoxel's z is up, which becomes y
+
+fn invalid_data(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("Bad vox file: {}", message))
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Result<u32> {
+    match bytes.get(offset..offset + 4) {
+        Some(b) => Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]])),
+        None => Err(invalid_data("unexpected end of file"))
+    }
+}
+
+// a chunk's id, content and the offset of the chunk after it
+fn read_chunk(bytes: &[u8], offset: usize) -> Result<(&[u8], &[u8], usize)> {
+    let id = bytes.get(offset..offset + 4).ok_or_else(|| invalid_data("unexpected end of file"))?;
+    let content_size = read_u32(bytes, offset + 4)? as usize;
+    let children_size = read_u32(bytes, offset + 8)? as usize;
+    let content_start = offset + 12;
+    let content = bytes.get(content_start..content_start + content_size).ok_or_else(|| invalid_data("chunk runs past the end of the file"))?;
+    Ok((id, content, content_start + content_size + children_size))
+}
+
+pub fn load_vox(path: &Path, origin: Vec3, voxel_size: f64) -> Result<VoxelGrid> {
+    let file = MappedFile::open(path)?;
+    parse_vox(&file, origin, voxel_size)
+}
+
+pub fn parse_vox(bytes: &[u8], origin: Vec3, voxel_size: f64) -> Result<VoxelGrid> {
+    if bytes.get(0..4) != Some(b"VOX ") {
+        return Err(invalid_data("missing 'VOX ' header"))
+    }
+    let (id, _, _) = read_chunk(bytes, 8)?;
+    if id != b"MAIN" {
+        return Err(invalid_data("expected a MAIN chunk"))
+    }
+
+    // MAIN's children follow its (empty) content
+    let mut size: Option<[usize; 3]> = None;
+    let mut voxels: Option<&[u8]> = None;
+    // files without an RGBA chunk use magicavoxel's built in palette, which
+    // isn't copied here. grey at least keeps the shapes visible
+    let mut palette = vec![Color::new(0.75, 0.75, 0.75); 255];
+    let mut offset = 20;
+    while offset < bytes.len() {
+        let (id, content, next) = read_chunk(bytes, offset)?;
+        match id {
+            b"SIZE" if size.is_none() => {
+                size = Some([read_u32(content, 0)? as usize, read_u32(content, 4)? as usize, read_u32(content, 8)? as usize]);
+            },
+            b"XYZI" if voxels.is_none() => {
+                let count = read_u32(content, 0)? as usize;
+                voxels = Some(content.get(4..4 + 4 * count).ok_or_else(|| invalid_data("XYZI chunk is too short"))?);
+            },
+            b"RGBA" => {
+                // colour index i (1 - 255) is entry i - 1
+                for (i, entry) in palette.iter_mut().enumerate() {
+                    let rgba = content.get(4 * i..4 * i + 4).ok_or_else(|| invalid_data("RGBA chunk is too short"))?;
+                    *entry = Color::new(rgba[0] as f64 / 255.0, rgba[1] as f64 / 255.0, rgba[2] as f64 / 255.0);
+                }
+            },
+            _ => ()
+        }
+        offset = next;
+    }
+
+    let size = size.ok_or_else(|| invalid_data("no SIZE chunk"))?;
+    let voxels = voxels.ok_or_else(|| invalid_data("no XYZI chunk"))?;
+    if size.contains(&0) {
+        return Err(invalid_data("model is empty"))
+    }
+
+    let materials: Vec<Material> = palette.into_iter().map(|colour| {
+        Material::Lambertian{albedo: Box::new(SolidTexture::new(colour)), normal_map: None}
+    }).collect();
+    // (x, y, z) with z up -> (x, z, -y) with y up
+    let mut grid = VoxelGrid::new([size[0], size[2], size[1]], origin, voxel_size, materials);
+    for voxel in voxels.chunks_exact(4) {
+        let (x, y, z, colour) = (voxel[0] as usize, voxel[1] as usize, voxel[2] as usize, voxel[3]);
+        if x >= size[0] || y >= size[1] || z >= size[2] {
+            return Err(invalid_data(&format!("voxel ({}, {}, {}) is outside of the model", x, y, z)))
+        }
+        if colour != 0 {
+            grid.set([x, z, size[1] - 1 - y], colour);
+        }
+    }
+    Ok(grid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(id: &[u8], content: &[u8], children: &[u8]) -> Vec<u8> {
+        let mut bytes = id.to_vec();
+        bytes.extend_from_slice(&(content.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&(children.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(content);
+        bytes.extend_from_slice(children);
+        bytes
+    }
+
+    #[test]
+    fn test_parse_vox() {
+        let size: Vec<u8> = [2u32, 3, 4].iter().flat_map(|v| v.to_le_bytes()).collect();
+        // one voxel at (1, 0, 3) with colour 5
+        let xyzi = [1u32.to_le_bytes().to_vec(), vec![1, 0, 3, 5]].concat();
+        let mut rgba = vec![0u8; 1024];
+        rgba[16..20].copy_from_slice(&[255, 0, 51, 255]);
+        let children = [chunk(b"SIZE", &size, &[]), chunk(b"XYZI", &xyzi, &[]), chunk(b"RGBA", &rgba, &[])].concat();
+        let bytes = [b"VOX ".to_vec(), 150u32.to_le_bytes().to_vec(), chunk(b"MAIN", &[], &children)].concat();
+
+        let grid = parse_vox(&bytes, Vec3::new(0.0, 0.0, 0.0), 1.0).unwrap();
+        assert_eq!(grid.dims(), [2, 4, 3]);
+        assert_eq!(grid.get([1, 3, 2]), 5);
+        assert_eq!(grid.get([1, 0, 0]), 0);
+
+        assert!(parse_vox(b"VOX \x96\x00\x00\x00", Vec3::new(0.0, 0.0, 0.0), 1.0).is_err());
+    }
+}
@@ -0,0 +1,335 @@
+use crate::vec3::*;
+use crate::Ray;
+use crate::hittable::*;
+use crate::material::*;
+use crate::aabb::AABB;
+use crate::export::{ExportMaterial, ExportMesh};
+use std::sync::Arc;
+
+// a grid of cubes, each empty or made of one of a list of materials, for
+// blocky (minecraft-like) scenes. rays step through the grid a cell at a time
+// (a 3D DDA) so only cells along the ray are ever looked at.
+// reference: http://www.cse.yorku.ca/~amana/research/grid.pdf
+//
+// the grid is stored sparsely in bricks of BRICK^3 voxels. bricks with nothing
+// in them aren't allocated, and rays walk over the coarse grid of bricks first
+// so big empty areas (usually most of the world) are skipped in a few steps
+
+// voxels along each side of a brick
+const BRICK: usize = 8;
+const BRICK_VOLUME: usize = BRICK * BRICK * BRICK;
+
+pub struct VoxelGrid {
+    // voxels along each axis
+    dims: [usize; 3],
+    // bricks along each axis
+    brick_dims: [usize; 3],
+    // None for bricks that are entirely empty
+    bricks: Vec<Option<Box<[u8; BRICK_VOLUME]>>>,
+    // a voxel's value is 0 for empty, otherwise it's made of materials[value - 1]
+    materials: Vec<Arc<Material>>,
+    // the corner of voxel (0, 0, 0)
+    origin: Vec3,
+    voxel_size: f64
+}
+
+impl VoxelGrid {
+    pub fn new(dims: [usize; 3], origin: Vec3, voxel_size: f64, materials: Vec<impl Into<Arc<Material>>>) -> VoxelGrid {
+        if dims.contains(&0) {
+            panic!("A voxel grid needs at least 1 voxel along each axis, got {:?}", dims);
+        }
+        if materials.len() > u8::MAX as usize {
+            panic!("A voxel grid supports at most {} materials, got {}", u8::MAX, materials.len());
+        }
+        let brick_dims = [dims[0].div_ceil(BRICK), dims[1].div_ceil(BRICK), dims[2].div_ceil(BRICK)];
+        VoxelGrid {
+            dims,
+            brick_dims,
+            bricks: (0..brick_dims[0] * brick_dims[1] * brick_dims[2]).map(|_| None).collect(),
+            materials: materials.into_iter().map(|material| material.into()).collect(),
+            origin,
+            voxel_size
+        }
+    }
+
+    pub fn dims(&self) -> [usize; 3] {
+        self.dims
+    }
+
+    fn brick_index(&self, brick: [usize; 3]) -> usize {
+        (brick[2] * self.brick_dims[1] + brick[1]) * self.brick_dims[0] + brick[0]
+    }
+
+    fn index_in_brick(voxel: [usize; 3]) -> usize {
+        ((voxel[2] % BRICK) * BRICK + voxel[1] % BRICK) * BRICK + voxel[0] % BRICK
+    }
+
+    fn brick_of(voxel: [usize; 3]) -> [usize; 3] {
+        [voxel[0] / BRICK, voxel[1] / BRICK, voxel[2] / BRICK]
+    }
+
+    pub fn get(&self, voxel: [usize; 3]) -> u8 {
+        match &self.bricks[self.brick_index(VoxelGrid::brick_of(voxel))] {
+            Some(brick) => brick[VoxelGrid::index_in_brick(voxel)],
+            None => 0
+        }
+    }
+
+    // 0 empties the voxel, anything else is 1 + the index of its material
+    pub fn set(&mut self, voxel: [usize; 3], value: u8) {
+        if (0..3).any(|axis| voxel[axis] >= self.dims[axis]) {
+            panic!("Voxel {:?} is outside of the grid {:?}", voxel, self.dims);
+        }
+        if value as usize > self.materials.len() {
+            panic!("Voxel value {} has no material, there are {}", value, self.materials.len());
+        }
+        let index = self.brick_index(VoxelGrid::brick_of(voxel));
+        if value == 0 && self.bricks[index].is_none() {
+            return
+        }
+        let brick = self.bricks[index].get_or_insert_with(|| Box::new([0; BRICK_VOLUME]));
+        brick[VoxelGrid::index_in_brick(voxel)] = value;
+    }
+
+    fn is_filled(&self, voxel: [i64; 3]) -> bool {
+        if (0..3).any(|axis| voxel[axis] < 0 || voxel[axis] >= self.dims[axis] as i64) {
+            return false
+        }
+        self.get([voxel[0] as usize, voxel[1] as usize, voxel[2] as usize]) != 0
+    }
+
+    fn bounds(&self) -> AABB {
+        let size = Vec3::new(self.dims[0] as f64, self.dims[1] as f64, self.dims[2] as f64) * self.voxel_size;
+        AABB::new(self.origin, self.origin + size)
+    }
+}
+
+// a ray in grid units, where voxels are 1 wide and the grid starts at 0. t is unchanged
+struct GridRay {
+    origin: [f64; 3],
+    direction: [f64; 3]
+}
+
+// walks the cells (cell_size grid units wide) a ray passes through between
+// t_start and t_end in order, until visit returns something. visit gets the
+// cell, the t range the ray is inside it for and the axis it was entered across
+#[allow(clippy::too_many_arguments)]
+fn walk<R>(ray: &GridRay, cell_size: f64, count: [usize; 3], t_start: f64, t_end: f64, entered: Option<usize>,
+    mut visit: impl FnMut([usize; 3], f64, f64, Option<usize>) -> Option<R>) -> Option<R> {
+    let mut cell = [0usize; 3];
+    let mut step = [0i64; 3];
+    // the t at which the ray crosses into the next cell along each axis
+    let mut t_next = [f64::INFINITY; 3];
+    // how much t it takes to cross a whole cell along each axis
+    let mut t_delta = [f64::INFINITY; 3];
+    for axis in 0..3 {
+        let position = ray.origin[axis] + ray.direction[axis] * t_start;
+        // clamped since the start is on the grid's edge, where rounding can land either side
+        cell[axis] = ((position / cell_size).floor() as i64).clamp(0, count[axis] as i64 - 1) as usize;
+        if ray.direction[axis] > 0.0 {
+            step[axis] = 1;
+            t_next[axis] = ((cell[axis] + 1) as f64 * cell_size - ray.origin[axis]) / ray.direction[axis];
+            t_delta[axis] = cell_size / ray.direction[axis];
+        } else if ray.direction[axis] < 0.0 {
+            step[axis] = -1;
+            t_next[axis] = (cell[axis] as f64 * cell_size - ray.origin[axis]) / ray.direction[axis];
+            t_delta[axis] = -cell_size / ray.direction[axis];
+        }
+    }
+
+    let mut t = t_start;
+    let mut entered = entered;
+    loop {
+        let axis = if t_next[0] < t_next[1] {
+            if t_next[0] < t_next[2] { 0 } else { 2 }
+        } else if t_next[1] < t_next[2] { 1 } else { 2 };
+        let exit = t_next[axis].min(t_end);
+        if let Some(result) = visit(cell, t, exit, entered) {
+            return Some(result)
+        }
+        if t_next[axis] >= t_end {
+            return None
+        }
+        let next = cell[axis] as i64 + step[axis];
+        if next < 0 || next >= count[axis] as i64 {
+            return None
+        }
+        cell[axis] = next as usize;
+        t = t_next[axis];
+        t_next[axis] += t_delta[axis];
+        entered = Some(axis);
+    }
+}
+
+impl Hittable for VoxelGrid {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let (t_start, t_end) = self.bounds().hit_range(ray, t_min, t_max)?;
+        let grid_ray = GridRay {
+            origin: [
+                (ray.origin.x() - self.origin.x()) / self.voxel_size,
+                (ray.origin.y() - self.origin.y()) / self.voxel_size,
+                (ray.origin.z() - self.origin.z()) / self.voxel_size
+            ],
+            direction: [ray.direction.x() / self.voxel_size, ray.direction.y() / self.voxel_size, ray.direction.z() / self.voxel_size]
+        };
+
+        // a ray starting outside enters the grid across the side it hits last
+        let entered = if t_start > t_min {
+            (0..3).filter(|axis| grid_ray.direction[*axis] != 0.0).max_by(|a, b| {
+                let slab_entry = |axis: usize| {
+                    let side = if grid_ray.direction[axis] > 0.0 { 0.0 } else { self.dims[axis] as f64 };
+                    (side - grid_ray.origin[axis]) / grid_ray.direction[axis]
+                };
+                slab_entry(*a).total_cmp(&slab_entry(*b))
+            })
+        } else {
+            None
+        };
+
+        let (voxel, t, axis) = walk(&grid_ray, BRICK as f64, self.brick_dims, t_start, t_end, entered, |brick, brick_start, brick_end, entered| {
+            let voxels = self.bricks[self.brick_index(brick)].as_ref()?;
+            // the voxels of this brick only, in grid units
+            let local = GridRay {
+                origin: [
+                    grid_ray.origin[0] - (brick[0] * BRICK) as f64,
+                    grid_ray.origin[1] - (brick[1] * BRICK) as f64,
+                    grid_ray.origin[2] - (brick[2] * BRICK) as f64
+                ],
+                direction: grid_ray.direction
+            };
+            let count = [
+                BRICK.min(self.dims[0] - brick[0] * BRICK),
+                BRICK.min(self.dims[1] - brick[1] * BRICK),
+                BRICK.min(self.dims[2] - brick[2] * BRICK)
+            ];
+            walk(&local, 1.0, count, brick_start, brick_end, entered, |voxel, voxel_start, _, entered| {
+                if voxels[VoxelGrid::index_in_brick(voxel)] == 0 {
+                    return None
+                }
+                // a ray starting inside a filled voxel has no side to hit, it
+                // carries on until it finds another one
+                let axis = entered?;
+                Some(([voxel[0] + brick[0] * BRICK, voxel[1] + brick[1] * BRICK, voxel[2] + brick[2] * BRICK], voxel_start, axis))
+            })
+        })?;
+
+        let value = self.get(voxel);
+        let material = &self.materials[value as usize - 1];
+        let point = ray.at(t);
+        let sign = if grid_ray.direction[axis] > 0.0 { -1.0 } else { 1.0 };
+        let mut axes = [0.0; 3];
+        axes[axis] = sign;
+        let outward_normal = Vec3::new(axes[0], axes[1], axes[2]);
+
+        // uvs across the face, from the other two axes
+        let local = (point - self.origin) / self.voxel_size;
+        let local = [local.x(), local.y(), local.z()];
+        let (a, b) = match axis {
+            0 => (2, 1),
+            1 => (0, 2),
+            _ => (0, 1)
+        };
+        let u = local[a] - voxel[a] as f64;
+        let v = local[b] - voxel[b] as f64;
+        let mut record = HitRecord::new(point, outward_normal, t, u.clamp(0.0, 1.0), v.clamp(0.0, 1.0), false, material);
+        record.set_face_normal(ray, &outward_normal);
+        let mut tangent = [0.0; 3];
+        tangent[a] = 1.0;
+        record.tangent = Some(Vec3::new(tangent[0], tangent[1], tangent[2]));
+        Some(record)
+    }
+
+    fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<AABB> {
+        Some(self.bounds())
+    }
+
+    // only the sides of voxels facing an empty neighbour, one mesh per material
+    fn tessellate(&self) -> Vec<ExportMesh> {
+        let mut meshes: Vec<ExportMesh> = self.materials.iter().map(|material| ExportMesh {
+            positions: Vec::new(),
+            normals: Vec::new(),
+            uvs: Vec::new(),
+            triangles: Vec::new(),
+            material: ExportMaterial::from_material(material)
+        }).collect();
+
+        for z in 0..self.dims[2] {
+            for y in 0..self.dims[1] {
+                for x in 0..self.dims[0] {
+                    let value = self.get([x, y, z]);
+                    if value == 0 {
+                        continue
+                    }
+                    let mesh = &mut meshes[value as usize - 1];
+                    let corner = [x as i64, y as i64, z as i64];
+                    for axis in 0..3 {
+                        for sign in [-1i64, 1] {
+                            let mut neighbour = corner;
+                            neighbour[axis] += sign;
+                            if self.is_filled(neighbour) {
+                                continue
+                            }
+                            // the face's corners, counter clockwise seen from outside
+                            let (a, b) = ((axis + 1) % 3, (axis + 2) % 3);
+                            let offsets: [(f64, f64); 4] = if sign > 0 {
+                                [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)]
+                            } else {
+                                [(0.0, 0.0), (0.0, 1.0), (1.0, 1.0), (1.0, 0.0)]
+                            };
+                            let first = mesh.positions.len();
+                            let mut normal = [0.0; 3];
+                            normal[axis] = sign as f64;
+                            for (offset_a, offset_b) in offsets.iter() {
+                                let mut position = [x as f64, y as f64, z as f64];
+                                position[axis] += if sign > 0 { 1.0 } else { 0.0 };
+                                position[a] += offset_a;
+                                position[b] += offset_b;
+                                mesh.positions.push(self.origin + Vec3::new(position[0], position[1], position[2]) * self.voxel_size);
+                                mesh.normals.push(Vec3::new(normal[0], normal[1], normal[2]));
+                                mesh.uvs.push((*offset_a, *offset_b));
+                            }
+                            mesh.triangles.push([first, first + 1, first + 2]);
+                            mesh.triangles.push([first, first + 2, first + 3]);
+                        }
+                    }
+                }
+            }
+        }
+        meshes.into_iter().filter(|mesh| !mesh.triangles.is_empty()).collect()
+    }
+
+    fn materials_mut(&mut self) -> Vec<&mut Arc<Material>> {
+        self.materials.iter_mut().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ray_stops_at_first_filled_voxel() {
+        let red = Material::Lambertian{albedo: Box::new(crate::texture::SolidTexture::new(Color::new(1.0, 0.0, 0.0))), normal_map: None};
+        let mut grid = VoxelGrid::new([20, 4, 4], Vec3::new(0.0, 0.0, 0.0), 0.5, vec![red]);
+        // two voxels in different bricks, with empty bricks around them
+        grid.set([13, 1, 2], 1);
+        grid.set([18, 1, 2], 1);
+        assert!(grid.bricks.iter().filter(|brick| brick.is_some()).count() == 2);
+
+        // along +x at the height of voxel row 1 and column 2
+        let ray = Ray::new(Vec3::new(-3.0, 0.75, 1.25), Vec3::new(1.0, 0.0, 0.0), Some(0.0));
+        let record = grid.hit(&ray, 0.001, f64::INFINITY).unwrap();
+        assert!((record.t - 9.5).abs() < 1e-9);
+        assert!((record.normal - Vec3::new(-1.0, 0.0, 0.0)).near_zero());
+
+        // the same ray from just past the first voxel finds the second
+        let ray = Ray::new(Vec3::new(7.0, 0.75, 1.25), Vec3::new(1.0, 0.0, 0.0), Some(0.0));
+        assert!((grid.hit(&ray, 0.001, f64::INFINITY).unwrap().t - 2.0).abs() < 1e-9);
+        // and misses going the other way
+        let ray = Ray::new(Vec3::new(7.0, 0.75, 1.25), Vec3::new(-1.0, 0.0, 0.0), Some(0.0));
+        assert!(grid.hit(&ray, 0.001, f64::INFINITY).is_none());
+
+        // a lone voxel's 6 sides, twice
+        assert_eq!(grid.tessellate()[0].triangles.len(), 24);
+    }
+}
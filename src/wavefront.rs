@@ -0,0 +1,328 @@
+use crate::vec3::*;
+use crate::ray::Ray;
+use crate::hittable::*;
+use crate::material::*;
+use crate::light::LightSample;
+use crate::integrator::{Integrator, IntegratorKind};
+use crate::sampler;
+use crate::aovs::FirstHit;
+use crate::utilities::{mix_seed, random_float, seed_rng, with_rng, INFINITY};
+use crate::{camera_sample, glass_coverage, progress, ray_stats, shadow_amount, with_footprint, CameraSample, ImageConfig, Scene};
+use rand::Rng;
+use rayon::prelude::*;
+
+// path tracing a batch of camera rays a bounce at a time, rather than one path
+// at a time to the end (ray_colour). each bounce of every path goes through
+// the same steps in turn: find all the hits, shade them all (queueing shadow
+// rays towards the lights and the bounced rays), then trace all the shadow
+// rays. each step is one loop over a queue, which spreads over threads, keeps
+// the same code and data hot in the cache and is the shape simd or a gpu wants.
+// it gives the same result as the PathTracer, only in a different order.
+// other integrators (and --debug-pixel logging) still trace each ray on its own.
+// the image's sampler only places the camera rays, the bounces get independent
+// random numbers, since a thread takes many samples' bounces in turn
+
+// rays waiting to be traced, as a structure of arrays: one array per field, so
+// a step only reads the fields it needs
+#[derive(Default)]
+struct RayQueue {
+    origins: Vec<Vec3>,
+    directions: Vec<Vec3>,
+    times: Vec<f64>,
+    // what the light found along each ray is multiplied by on its way to the camera
+    throughputs: Vec<Color>,
+    // which camera sample the light adds to
+    samples: Vec<usize>
+}
+
+impl RayQueue {
+    fn push(&mut self, ray: &Ray, throughput: Color, sample: usize) {
+        self.origins.push(ray.origin);
+        self.directions.push(ray.direction);
+        self.times.push(ray.time);
+        self.throughputs.push(throughput);
+        self.samples.push(sample);
+    }
+
+    fn len(&self) -> usize {
+        self.origins.len()
+    }
+
+    fn ray(&self, index: usize) -> Ray {
+        Ray::new(self.origins[index], self.directions[index], Some(self.times[index]))
+    }
+}
+
+// shadow rays towards the lights, each adding its light if nothing's in the way
+#[derive(Default)]
+struct ShadowQueue {
+    origins: Vec<Vec3>,
+    directions: Vec<Vec3>,
+    times: Vec<f64>,
+    // how far along the ray the light is (less the shadow epsilon)
+    distances: Vec<f64>,
+    // the light that arrives at the camera if the ray gets through
+    contributions: Vec<Color>,
+    samples: Vec<usize>
+}
+
+impl ShadowQueue {
+    fn push(&mut self, origin: Vec3, light: &LightSample, max_distance: f64, time: f64, contribution: Color, sample: usize) {
+        self.origins.push(origin);
+        self.directions.push(light.direction);
+        self.times.push(time);
+        self.distances.push(max_distance);
+        self.contributions.push(contribution);
+        self.samples.push(sample);
+    }
+}
+
+// a sample for each camera ray, as camera_sample would give
+pub fn trace(rays: &[Ray], scene: &Scene, image: &ImageConfig) -> Vec<CameraSample> {
+    sampler::end_sample();
+    // a seeded render has its rays traced on whichever thread, so each one gets
+    // a generator of its own (e.g. for volumes) from this, which the caller's
+    // seeded generator makes the same each time
+    let trace_seed = image.seed.map(|_| with_rng(|rng| rng.gen::<u64>()));
+    let path_tracer = match image.integrator.kind {
+        IntegratorKind::PathTracer(path_tracer) => path_tracer,
+        _ => return rays.par_iter().enumerate().map(|(index, ray)| {
+            if let Some(trace_seed) = trace_seed {
+                seed_rng(mix_seed(&[trace_seed, index as u64]));
+            }
+            camera_sample(ray, scene, image)
+        }).collect()
+    };
+    let settings = &image.integrator;
+    let nothing = Color::new(0.0, 0.0, 0.0);
+    let mut results: Vec<CameraSample> = rays.iter().map(|_| CameraSample{colour: nothing, foreground: nothing, alpha: 0.0, layer: None, first_hit: None}).collect();
+    // whether each sample's camera ray hit something that's kept in the
+    // foreground (not the sky or a shadow catcher)
+    let mut opaque = vec![false; rays.len()];
+    // samples whose camera ray hit glass, for the alpha channel
+    let mut glass = Vec::new();
+
+    let mut queue = RayQueue::default();
+    for (sample, ray) in rays.iter().enumerate() {
+        queue.push(ray, Color::new(1.0, 1.0, 1.0), sample);
+    }
+
+    for bounce in 0..image.max_depth {
+        if queue.len() == 0 {
+            break
+        }
+        let depth = image.max_depth - bounce;
+        let primary = bounce == 0;
+
+        // find every hit at once
+        let hits: Vec<Option<HitRecord>> = (0..queue.len()).into_par_iter().map(|index| {
+            progress::count_ray();
+            if primary {
+                progress::count_sample();
+                ray_stats::count_primary_ray();
+            } else {
+                ray_stats::count_bounce_ray();
+            }
+            if let Some(trace_seed) = trace_seed {
+                seed_rng(mix_seed(&[trace_seed, bounce, index as u64]));
+            }
+            let bounced;
+            // only camera rays carry differentials
+            let ray = if primary { &rays[queue.samples[index]] } else { bounced = queue.ray(index); &bounced };
+            scene.world.hit(ray, settings.continuation_epsilon, INFINITY)
+                .map(|record| with_footprint(record, ray, scene, settings))
+        }).collect();
+
+        // shade them, queueing up what needs tracing next
+        let mut next = RayQueue::default();
+        let mut shadows = ShadowQueue::default();
+        for (index, hit) in hits.iter().enumerate() {
+            let (sample, throughput) = (queue.samples[index], queue.throughputs[index]);
+            let bounced;
+            let ray = if primary { &rays[sample] } else { bounced = queue.ray(index); &bounced };
+            let record = match hit {
+                Some(record) => record,
+                None => {
+                    let sky = throughput * path_tracer.background(ray, scene);
+                    results[sample].colour = results[sample].colour + sky;
+                    continue
+                }
+            };
+            if primary {
+                results[sample].layer = Some(record.layer);
+                results[sample].first_hit = Some(record).filter(|_| image.aovs).map(|record| FirstHit::new(ray, record));
+                results[sample].alpha = match record.material {
+                    Material::ShadowCatcher{..} => shadow_amount(record, scene, ray.time, settings),
+                    Material::Dielectric{..} if image.glass_alpha => {
+                        glass.push(sample);
+                        opaque[sample] = true;
+                        1.0
+                    },
+                    _ => {
+                        opaque[sample] = true;
+                        1.0
+                    }
+                };
+            }
+
+            let emitted = throughput * record.material.emitted(record);
+            results[sample].colour = results[sample].colour + emitted;
+            if path_tracer.next_event_estimation {
+                for light in scene.lights.iter() {
+                    if let Some(light_sample) = light.sample(&record.point) {
+                        let reflected = record.material.evaluate(ray, record, &light_sample.direction);
+                        if !reflected.near_zero() {
+                            let origin = settings.offset_origin(record, &light_sample.direction, settings.shadow_epsilon);
+                            let contribution = throughput * reflected * light_sample.radiance;
+                            shadows.push(origin, &light_sample, light_sample.distance - settings.shadow_epsilon, ray.time, contribution, sample);
+                        }
+                    }
+                }
+            }
+
+            // a path with no bounces left would only find black
+            if depth <= 1 {
+                continue
+            }
+            if let Some(scattering) = record.material.scatter(ray, record) {
+                let mut attenuation = scattering.attenuation();
+                if let Some(survival) = settings.survival_probability(bounce, (throughput * attenuation).max_component()) {
+                    if random_float() > survival {
+                        continue
+                    }
+                    attenuation = attenuation / survival;
+                }
+                let mut scattered = scattering.scattered();
+                scattered.origin = settings.offset_origin(record, &scattered.direction, 0.0);
+                next.push(&scattered, throughput * attenuation, sample);
+            }
+        }
+
+        // then every shadow ray
+        let unblocked: Vec<bool> = (0..shadows.origins.len()).into_par_iter().map(|index| {
+            let shadow_ray = Ray::new(shadows.origins[index], shadows.directions[index], Some(shadows.times[index]));
+            scene.world.hit(&shadow_ray, 0.0, shadows.distances[index]).is_none()
+        }).collect();
+        for (index, unblocked) in unblocked.into_iter().enumerate() {
+            if unblocked {
+                let sample = shadows.samples[index];
+                results[sample].colour = results[sample].colour + shadows.contributions[index];
+            }
+        }
+        queue = next;
+    }
+
+    // once the colours are done, so they're the same either way
+    for sample in glass {
+        let ray = &rays[sample];
+        if let Some(record) = scene.world.hit(ray, settings.continuation_epsilon, INFINITY) {
+            results[sample].alpha = glass_coverage(ray, &record, scene, settings, image.max_depth);
+        }
+    }
+
+    // shadow catchers only keep their shadow, as black, and glass what it covers
+    for (result, opaque) in results.iter_mut().zip(opaque) {
+        if opaque {
+            result.foreground = result.colour * result.alpha;
+        }
+    }
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittable_list::HittableList;
+    use crate::sphere::Sphere;
+    use crate::light::Light;
+    use crate::texture::SolidTexture;
+    use crate::subsurface::Subsurface;
+    use crate::integrator::AmbientOcclusion;
+
+    #[test]
+    fn test_matches_path_tracer() {
+        // a ball on the ground under the sky and a light, seen from above
+        let mut world = HittableList::new();
+        let grey = || Material::Lambertian{albedo: Box::new(SolidTexture::uniform(0.5)), normal_map: None};
+        world.add(Sphere::new(Vec3::new(0.0, -1000.0, 0.0), 1000.0, grey()));
+        world.add(Sphere::new(Vec3::new(0.0, 1.0, 0.0), 1.0, grey()));
+        let mut scene = Scene::from(world);
+        scene.lights.push(Light::Point{position: Vec3::new(3.0, 5.0, 0.0), intensity: Color::new(20.0, 20.0, 20.0)});
+        let image = ImageConfig::new(1.0, 10, 1, 8);
+
+        // the ground right next to the ball, and the sky
+        let towards = |direction: Vec3| (0..20000).map(|_| Ray::new(Vec3::new(0.0, 6.0, 6.0), direction, None)).collect::<Vec<Ray>>();
+        for direction in [Vec3::new(1.2, -6.0, -6.0), Vec3::new(0.0, 1.0, -1.0)] {
+            let rays = towards(direction);
+            let wavefront = trace(&rays, &scene, &image);
+            let mean = |colours: &mut dyn Iterator<Item = Color>| colours.fold(Color::new(0.0, 0.0, 0.0), |a, b| a + b) / rays.len() as f64;
+            let batched = mean(&mut wavefront.iter().map(|sample| sample.colour));
+            let one_by_one = mean(&mut rays.iter().map(|ray| camera_sample(ray, &scene, &image).colour));
+            assert!((batched - one_by_one).length() < 0.01 * one_by_one.length(), "{:?} {:?}", batched, one_by_one);
+            let covered = direction.y() < 0.0;
+            assert!(wavefront.iter().all(|sample| (sample.alpha == 1.0) == covered && sample.layer.is_some() == covered));
+        }
+    }
+
+    #[test]
+    fn test_clear_glass_lets_the_background_through() {
+        // a clear ball and a grey one, in front of the sky
+        let mut world = HittableList::new();
+        let glass = Material::Dielectric{index_of_refraction: 1.5, absorption: Color::new(0.0, 0.0, 0.0)};
+        world.add(Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0, glass));
+        world.add(Sphere::new(Vec3::new(5.0, 0.0, 0.0), 1.0, Material::Lambertian{albedo: Box::new(SolidTexture::uniform(0.5)), normal_map: None}));
+        let scene = Scene::from(world);
+        let mut image = ImageConfig::new(1.0, 10, 1, 8);
+        image.glass_alpha = true;
+
+        let towards = |x: f64| (0..2000).map(|_| Ray::new(Vec3::new(x, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0), None)).collect::<Vec<Ray>>();
+        let rays = towards(0.0);
+        let mean_alpha = |samples: &mut dyn Iterator<Item = CameraSample>| samples.map(|sample| sample.alpha).sum::<f64>() / rays.len() as f64;
+        // straight through the middle, most of it is transmitted
+        let batched = mean_alpha(&mut trace(&rays, &scene, &image).into_iter());
+        let one_by_one = mean_alpha(&mut rays.iter().map(|ray| camera_sample(ray, &scene, &image)));
+        assert!(batched < 0.3 && one_by_one < 0.3, "{} {}", batched, one_by_one);
+        assert!((batched - one_by_one).abs() < 0.05, "{} {}", batched, one_by_one);
+        assert!(trace(&rays, &scene, &image).iter().all(|sample| sample.foreground.equal_to(&(sample.colour * sample.alpha))));
+
+        assert!(trace(&towards(5.0), &scene, &image).iter().all(|sample| sample.alpha == 1.0));
+        // and without it asked for, glass is opaque as before
+        image.glass_alpha = false;
+        assert!(trace(&rays, &scene, &image).iter().all(|sample| sample.alpha == 1.0));
+    }
+
+    #[test]
+    fn test_seeded_traces_repeat() {
+        // a ball the rays wander through at random, whose hits are found on
+        // several threads
+        let mut world = HittableList::new();
+        let glass = Material::Dielectric{index_of_refraction: 1.5, absorption: Color::new(0.0, 0.0, 0.0)};
+        world.add(Subsurface::new(Sphere::new(Vec3::new(0.0, 0.0, 0.0), 2.0, glass), Color::new(0.8, 0.8, 0.8), 0.5));
+        let scene = Scene::from(world);
+        let mut image = ImageConfig::new(1.0, 10, 1, 8);
+        image.seed = Some(7);
+
+        let rays: Vec<Ray> = (0..2000).map(|_| Ray::new(Vec3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0), None)).collect();
+        let repeats = |rays: &[Ray], scene: &Scene, image: &ImageConfig| {
+            let render = || {
+                seed_rng(7);
+                trace(rays, scene, image).iter().map(|sample| sample.colour).collect::<Vec<Color>>()
+            };
+            let (first, second) = (render(), render());
+            assert!(first.iter().zip(second.iter()).all(|(a, b)| a.equal_to(b)));
+            // and it isn't the same for every ray
+            assert!(first.iter().any(|colour| !colour.equal_to(&first[0])));
+        };
+        repeats(&rays, &scene, &image);
+
+        // nor with an integrator that traces each ray on its own, here ambient
+        // occlusion on the ground beside a ball
+        let mut world = HittableList::new();
+        let grey = || Material::Lambertian{albedo: Box::new(SolidTexture::uniform(0.5)), normal_map: None};
+        world.add(Sphere::new(Vec3::new(0.0, -1000.0, 0.0), 1000.0, grey()));
+        world.add(Sphere::new(Vec3::new(0.0, 1.0, 0.0), 1.0, grey()));
+        image.integrator.kind = IntegratorKind::AmbientOcclusion(AmbientOcclusion::new(4));
+        let rays: Vec<Ray> = (0..2000).map(|_| Ray::new(Vec3::new(0.0, 6.0, 6.0), Vec3::new(1.2, -6.0, -6.0), None)).collect();
+        repeats(&rays, &Scene::from(world), &image);
+    }
+}
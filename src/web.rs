@@ -0,0 +1,62 @@
+use crate::adaptive::PixelEstimate;
+use crate::color_space::ColorSpace;
+use crate::highlights::{ClampMode, HighlightSettings};
+use crate::tonemap::ToneMapping;
+use crate::utilities::random_float;
+use crate::{camera_sample, find_scene, CameraSample, SceneOptions};
+use rayon::prelude::*;
+
+// the tracer in a browser. built for wasm32-unknown-unknown, there's no stdout
+// to write a ppm to, no files and no clock, so instead of going through the
+// binary's render a scene comes straight back as rgba bytes, rows from the top,
+// ready for a canvas's ImageData (see web/index.html). there are no threads
+// either: rayon falls back on running everything on the calling one, and
+// getrandom seeds the random numbers from the browser's crypto api.
+// build with `wasm-pack build --target web --out-dir web/pkg`
+
+// scene, by name (see SCENES), width pixels across, at its own aspect
+// ratio, with samples_per_pixel samples each. tone mapped and encoded as the
+// binary does with no options
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen::prelude::wasm_bindgen)]
+pub fn render_rgba(scene: &str, width: u32, samples_per_pixel: u32) -> Vec<u8> {
+    if width < 2 || samples_per_pixel == 0 {
+        panic!("A render needs to be at least 2 pixels across with a sample each, got {} and {}", width, samples_per_pixel);
+    }
+    let entry = find_scene(scene).unwrap_or_else(|| panic!("Unknown scene {}", scene));
+    let (image, camera, scene) = entry.build(&SceneOptions::default());
+    let height = ((width as f32 / image.aspect_ratio) as u32).max(2);
+    let (tone_mapping, highlights) = (ToneMapping::default(), HighlightSettings::new(ClampMode::PerChannel));
+    (0..height).into_par_iter().flat_map_iter(|row| {
+        let j = height - 1 - row;
+        let (image, camera, scene, tone_mapping, highlights) = (&image, &camera, &scene, &tone_mapping, &highlights);
+        (0..width).flat_map(move |i| {
+            let mut estimate = PixelEstimate::new();
+            for _ in 0..samples_per_pixel {
+                let (x, x_weight) = image.filter.sample(random_float());
+                let (y, y_weight) = image.filter.sample(random_float());
+                let ray = camera.get_ray((i as f64 + x) / (width - 1) as f64, (j as f64 + y) / (height - 1) as f64);
+                let sample = ray.map_or_else(CameraSample::blocked, |ray| camera_sample(&ray, scene, image));
+                estimate.add_filtered(sample.colour, sample.foreground, sample.alpha, x_weight * y_weight);
+            }
+            let [r, g, b] = ColorSpace::Srgb.encode_rgb8(highlights.apply(tone_mapping.apply(estimate.colour())));
+            [r, g, b, 255]
+        })
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_renders_to_rgba_rows() {
+        // the checkered spheres at 16:9, 32 pixels across
+        let pixels = render_rgba("checkered-spheres", 32, 8);
+        assert_eq!(pixels.len(), 32 * 18 * 4);
+        assert!(pixels.chunks(4).all(|pixel| pixel[3] == 255));
+        // the top sphere, lit by the sky above it, is brighter than the
+        // bottom one, which the top sphere shades
+        let brightness = |rows: std::ops::Range<usize>| pixels[rows.start * 32 * 4..rows.end * 32 * 4].iter().map(|value| *value as u32).sum::<u32>();
+        assert!(brightness(0..9) > brightness(9..18), "{} {}", brightness(0..9), brightness(9..18));
+    }
+}
@@ -0,0 +1,104 @@
+use crate::Vec3;
+use crate::utilities::*;
+
+// worley (cellular) noise: space is split into unit cells with one random
+// feature point in each, and the noise is the distance to the nearest
+// points. where perlin gives smooth blobs, this gives cells like stones,
+// scales or the bright network of light on the bottom of a pool
+pub struct Worley {
+    // where the feature point sits inside its cell, per hashed cell
+    offsets: Vec<Vec3>,
+    x_perms: Vec<usize>,
+    y_perms: Vec<usize>,
+    z_perms: Vec<usize>
+}
+
+// the distances to the nearest and second nearest feature point, and a
+// random number in [0, 1) that's the same everywhere in the nearest one's cell
+#[derive(Copy, Clone, Debug)]
+pub struct WorleySample {
+    pub f1: f64,
+    pub f2: f64,
+    pub cell: f64
+}
+
+const POINT_COUNT: u32 = 256;
+
+impl Worley {
+    pub fn new() -> Worley {
+        Worley {
+            offsets: (0..POINT_COUNT).map(|_| Vec3::random()).collect(),
+            x_perms: Worley::generate_perm(),
+            y_perms: Worley::generate_perm(),
+            z_perms: Worley::generate_perm()
+        }
+    }
+
+    fn hash(&self, i: i64, j: i64, k: i64) -> usize {
+        self.x_perms[(i & 255) as usize] ^ self.y_perms[(j & 255) as usize] ^ self.z_perms[(k & 255) as usize]
+    }
+
+    pub fn sample(&self, point: &Vec3) -> WorleySample {
+        let i = point.x().floor() as i64;
+        let j = point.y().floor() as i64;
+        let k = point.z().floor() as i64;
+        let mut nearest = (f64::INFINITY, 0);
+        let mut second = f64::INFINITY;
+        // a feature point further than the neighbouring cells is always
+        // further than the one in the point's own cell
+        for di in -1..=1 {
+            for dj in -1..=1 {
+                for dk in -1..=1 {
+                    let index = self.hash(i + di, j + dj, k + dk);
+                    let feature = Vec3::new((i + di) as f64, (j + dj) as f64, (k + dk) as f64) + self.offsets[index];
+                    let distance = (feature - *point).length();
+                    if distance < nearest.0 {
+                        second = nearest.0;
+                        nearest = (distance, index);
+                    } else if distance < second {
+                        second = distance;
+                    }
+                }
+            }
+        }
+        WorleySample {
+            f1: nearest.0,
+            f2: second,
+            cell: nearest.1 as f64 / POINT_COUNT as f64
+        }
+    }
+
+    fn generate_perm() -> Vec<usize> {
+        let mut result: Vec<usize> = (0..POINT_COUNT as usize).collect();
+        for i in (0..result.len()).rev() {
+            let target = random_int_in_range(0, (i + 1) as u32);
+            result.swap(i, target as usize);
+        }
+        result
+    }
+}
+
+impl Default for Worley {
+    fn default() -> Worley {
+        Worley::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_distances_are_ordered() {
+        let worley = Worley::new();
+        for i in 0..100 {
+            let point = Vec3::new(i as f64 * 0.37, i as f64 * -0.11, 3.0 + i as f64 * 0.05);
+            let sample = worley.sample(&point);
+            assert!(sample.f1 <= sample.f2 && sample.f2 < 3.0_f64.sqrt() * 2.0);
+            assert!((0.0..1.0).contains(&sample.cell));
+        }
+        // a feature point is exactly 0 away from itself
+        let sample = worley.sample(&(Vec3::new(5.0, -2.0, 7.0) + worley.offsets[worley.hash(5, -2, 7)]));
+        assert!(sample.f1 < 1e-12);
+    }
+}
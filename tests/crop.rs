@@ -0,0 +1,34 @@
+use std::process::Command;
+
+// --crop writes only the region, to every output and not just the ppm
+#[test]
+fn test_crop_applies_to_every_output() {
+    let directory = std::env::temp_dir().join(format!("rays-crop-{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    let (rgba_path, png16_path) = (directory.join("render.png"), directory.join("render16.png"));
+    let output = Command::new(env!("CARGO_BIN_EXE_rays"))
+        .args(["--scene", "zoomed-in", "--spp", "2", "--seed", "1", "--region", "190,100,206,108", "--crop",
+            "--rgba", rgba_path.to_str().unwrap(), "--png16", png16_path.to_str().unwrap()])
+        .output().unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let text = String::from_utf8(output.stdout).unwrap();
+    let ppm: Vec<u8> = text.lines().skip(3).flat_map(|line| line.split_whitespace()).map(|value| value.parse().unwrap()).collect();
+
+    // the same pixels as the ppm, where they're opaque
+    let rgba = image::open(&rgba_path).unwrap().to_rgba8();
+    assert_eq!(rgba.dimensions(), (16, 8));
+    let opaque = rgba.pixels().zip(ppm.chunks(3)).filter(|(pixel, _)| pixel[3] == 255).map(|(pixel, rgb)| {
+        assert_eq!(&pixel.0[..3], rgb);
+    }).count();
+    assert!(opaque > 0);
+
+    // the 16 bit png rounds to the ppm's 8 bits
+    let png16 = image::open(&png16_path).unwrap().to_rgb16();
+    assert_eq!(png16.dimensions(), (16, 8));
+    for (pixel, rgb) in png16.pixels().zip(ppm.chunks(3)) {
+        for (deep, shallow) in pixel.0.iter().zip(rgb) {
+            assert!((*deep as f64 / 257.0 - *shallow as f64).abs() <= 1.0, "{:?} {:?}", pixel, rgb);
+        }
+    }
+    std::fs::remove_dir_all(&directory).unwrap();
+}
@@ -0,0 +1,20 @@
+use std::process::Command;
+
+// a ppm's pixels are whole numbers from 0 to 255. the colour writer used to
+// print the scaled floats as they were (e.g. 213.738...), which image viewers
+// reject
+#[test]
+fn test_ppm_has_whole_numbers() {
+    let output = Command::new(env!("CARGO_BIN_EXE_rays"))
+        .args(["--scene", "zoomed-in", "--spp", "2", "--seed", "1", "--region", "190,100,206,108", "--crop"])
+        .output().unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let text = String::from_utf8(output.stdout).unwrap();
+    let mut lines = text.lines();
+    assert_eq!((lines.next(), lines.next(), lines.next()), (Some("P3"), Some("16 8"), Some("255")));
+    let values: Vec<&str> = lines.flat_map(|line| line.split_whitespace()).collect();
+    assert_eq!(values.len(), 16 * 8 * 3);
+    for value in values {
+        assert!(value.parse::<u8>().is_ok(), "{} isn't a whole number from 0 to 255", value);
+    }
+}
@@ -0,0 +1,40 @@
+use rays::checkpoint::read_checkpoint;
+use std::path::PathBuf;
+use std::process::Command;
+
+// a render stopped at a checkpoint and resumed comes out the same, to the
+// byte, as the render carrying on. runs the binary, since the render loop
+// and checkpoints live in main.rs
+#[test]
+fn test_resumed_render_matches_continuous() {
+    let directory = std::env::temp_dir().join(format!("rays-resume-{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    let name = directory.join("render");
+    // no --seed, so the checkpoints have to carry one of their own. the
+    // checkpoints come about every scanline
+    let output = Command::new(env!("CARGO_BIN_EXE_rays"))
+        .args(["--scene", "zoomed-in", "--spp", "4", "--region", "180,100,220,116", "--crop",
+            "--checkpoint-every", "0.001s", "--keep", "1000", "--checkpoint-name", name.to_str().unwrap()])
+        .output().unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let continuous = output.stdout;
+
+    // one from partway through the region
+    let mut checkpoints: Vec<PathBuf> = std::fs::read_dir(&directory).unwrap().map(|entry| entry.unwrap().path()).collect();
+    checkpoints.sort();
+    let full = 40 * 16 * 4;
+    let partway = checkpoints.iter().find(|path| {
+        let (_, state) = read_checkpoint(path).unwrap();
+        let samples: u64 = state.pixels.iter().map(|pixel| pixel.count).sum();
+        samples > 0 && samples < full
+    }).expect("no checkpoint from partway through the render").clone();
+    let (args, _) = read_checkpoint(&partway).unwrap();
+    assert!(args.iter().any(|arg| arg == "--seed"));
+
+    let output = Command::new(env!("CARGO_BIN_EXE_rays")).arg("resume").arg(&partway).output().unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let resumed = output.stdout;
+    std::fs::remove_dir_all(&directory).unwrap();
+    assert!(!continuous.is_empty());
+    assert!(resumed == continuous, "the resumed render differs from the continuous one");
+}